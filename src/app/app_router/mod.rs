@@ -1,12 +1,13 @@
 use super::{
     ActivePane,
     components::{
-        Component, ComponentRender, Props, chains_list::ChainsList, edit_page::EditPage,
-        help_page::HelpPage, packet_log::PacketLog, tables_list::TableList,
+        Component, ComponentRender, Props, chains_list::ChainsList, conntrack_list::ConntrackList,
+        edit_page::EditPage, help_page::HelpPage, packet_log::PacketLog, tables_list::TableList,
     },
-    context::AppContext,
+    context::{AppContext, Status},
     ui::Action,
 };
+use crate::netlink::QueueStats;
 use cli_log::debug;
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
 use ratatui::{
@@ -15,6 +16,7 @@ use ratatui::{
     style::{Color, Style},
     widgets::{Block, Paragraph},
 };
+use std::sync::Arc;
 use tokio::sync::mpsc::{self};
 
 pub struct AppRouter<'a, T> {
@@ -23,7 +25,10 @@ pub struct AppRouter<'a, T> {
     chains_list: ChainsList,
     edit_page: EditPage,
     packet_log: PacketLog<'a>,
+    conntrack_list: ConntrackList,
     help_page: HelpPage,
+    status: Option<Status>,
+    queue_stats: Option<Arc<QueueStats>>,
     action_tx: mpsc::UnboundedSender<Action>,
 }
 
@@ -37,8 +42,11 @@ impl<'a> Component for AppRouter<'a, usize> {
             table_list: TableList::new(context, action_tx.clone()),
             chains_list: ChainsList::new(context, action_tx.clone()),
             packet_log: PacketLog::new(context, action_tx.clone()),
+            conntrack_list: ConntrackList::new(context, action_tx.clone()),
             edit_page: EditPage::new(context, action_tx.clone()),
             help_page: HelpPage::new(context, action_tx.clone()),
+            status: context.status.clone(),
+            queue_stats: context.queue_stats.clone(),
             action_tx,
         }
     }
@@ -52,8 +60,11 @@ impl<'a> Component for AppRouter<'a, usize> {
             table_list: self.table_list.update(context),
             chains_list: self.chains_list.update(context),
             packet_log: self.packet_log.update(context),
+            conntrack_list: self.conntrack_list.update(context),
             help_page: self.help_page.update(context),
             edit_page: self.edit_page.update(context),
+            status: context.status.clone(),
+            queue_stats: context.queue_stats.clone(),
             action_tx: self.action_tx,
         }
     }
@@ -75,6 +86,9 @@ impl<'a> Component for AppRouter<'a, usize> {
             ActivePane::PacketLog => {
                 self.packet_log.handle_key_event(key);
             }
+            ActivePane::Conntrack => {
+                self.conntrack_list.handle_key_event(key);
+            }
             ActivePane::HelpPage => {
                 self.help_page.handle_key_event(key);
             }
@@ -96,6 +110,10 @@ impl<'a> Component for AppRouter<'a, usize> {
                     let _ = self.action_tx.send(Action::SelectTableList);
                     debug!("Sending {:?}", Action::SelectTableList);
                 }
+                KeyCode::Char('c') => {
+                    let _ = self.action_tx.send(Action::SelectConntrack);
+                    debug!("Sending {:?}", Action::SelectConntrack);
+                }
                 _ => {}
             },
         }
@@ -121,16 +139,26 @@ impl<'a> ComponentRender<()> for AppRouter<'a, usize> {
                 text.push_str(" ? - help ");
                 text.push_str(" r - firewall rules ");
                 text.push_str(" p - packet log ");
+                text.push_str(" c - connections ");
             }
             ActivePane::PacketLog => {
                 text.push_str(" esc - back ");
                 text.push_str(" ? - help ");
                 text.push_str(" i - select interface");
             }
+            ActivePane::Conntrack => {
+                text.push_str(" esc - back ");
+                text.push_str(" / - filter ");
+                text.push_str(" d - delete ");
+                text.push_str(" D - delete shown ");
+            }
             ActivePane::TableList => {
                 text.push_str(" esc - back ");
                 text.push_str(" enter - expand ");
                 text.push_str(" e - edit ");
+                text.push_str(" z - reset counters ");
+                text.push_str(" n - nft syntax ");
+                text.push_str(" pgup/pgdn - tree/sets ");
                 text.push_str(" ? - help ");
             }
             ActivePane::ChainsList => {
@@ -139,14 +167,30 @@ impl<'a> ComponentRender<()> for AppRouter<'a, usize> {
             }
             ActivePane::EditPage => {
                 text.push_str(" esc - back ");
-                text.push_str(" ? - help ");
+                text.push_str(" a - apply ");
+                text.push_str(" y - confirm ");
+                text.push_str(" n - roll back ");
             }
             ActivePane::HelpPage => {
                 text.push_str(" esc - back ");
             }
         }
 
-        let footer = Paragraph::new(text)
+        let mut footer_line = vec![Span::raw(text)];
+        match &self.status {
+            Some(Status::Ok(message)) => {
+                footer_line.push(Span::styled(format!(" {message} "), Color::Green));
+            }
+            Some(Status::Error(message)) => {
+                footer_line.push(Span::styled(format!(" {message} "), Color::Red));
+            }
+            None => {}
+        }
+        if let Some(stats) = &self.queue_stats {
+            footer_line.push(Span::styled(format!(" queued: {stats} "), Color::Cyan));
+        }
+
+        let footer = Paragraph::new(Line::from(footer_line))
             .style(Style::new().bold())
             .block(block)
             .alignment(Alignment::Center);
@@ -176,6 +220,13 @@ impl<'a> ComponentRender<()> for AppRouter<'a, usize> {
 
         // We either display the rules list or the pane to edit the rules
         if self.active_pane == ActivePane::EditPage {
+            self.edit_page.render(
+                frame,
+                Props {
+                    area: nested_layout[0],
+                    border_color: Color::Green,
+                },
+            );
         } else {
             self.table_list.render(
                 frame,
@@ -190,10 +241,15 @@ impl<'a> ComponentRender<()> for AppRouter<'a, usize> {
             );
         }
 
+        // The right pane is shared by the packet log and the connections
+        let right_layout = Layout::default()
+            .constraints(Constraint::from_percentages([50, 50]))
+            .split(nested_layout[1]);
+
         self.packet_log.render(
             frame,
             Props {
-                area: nested_layout[1],
+                area: right_layout[0],
                 border_color: if self.active_pane == ActivePane::PacketLog {
                     Color::Green
                 } else {
@@ -201,5 +257,17 @@ impl<'a> ComponentRender<()> for AppRouter<'a, usize> {
                 },
             },
         );
+
+        self.conntrack_list.render(
+            frame,
+            Props {
+                area: right_layout[1],
+                border_color: if self.active_pane == ActivePane::Conntrack {
+                    Color::Green
+                } else {
+                    Color::White
+                },
+            },
+        );
     }
 }
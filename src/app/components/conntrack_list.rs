@@ -0,0 +1,240 @@
+use super::{Action, AppContext, Component, ComponentRender, Props};
+use crate::{
+    app::ActivePane,
+    netlink::{self, Connection, ConntrackFilter, Counters},
+};
+use cli_log::debug;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{
+    Frame,
+    layout::Constraint,
+    prelude::*,
+    style::{Color, Style},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
+};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self};
+
+// How often the table is read again while the pane is selected
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ConntrackList {
+    action_tx: mpsc::UnboundedSender<Action>,
+    connections: netlink::Result<Vec<Connection>>,
+    last_read: Instant,
+    filter: ConntrackFilter,
+    filter_text: String,
+    // Filter being typed, applied with enter
+    filter_input: Option<String>,
+    table_state: TableState,
+    status: Option<String>,
+}
+
+impl ConntrackList {
+    fn refresh(&mut self) {
+        self.connections = netlink::list_connections();
+        self.last_read = Instant::now();
+    }
+
+    fn visible(&self) -> Vec<&Connection> {
+        match &self.connections {
+            Ok(connections) => connections
+                .iter()
+                .filter(|c| self.filter.matches(c))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn apply_filter(&mut self, text: String) {
+        match ConntrackFilter::parse(&[&text]) {
+            Ok(filter) => {
+                self.filter = filter;
+                self.filter_text = text;
+                self.table_state.select(None);
+                self.status = None;
+            }
+            Err(e) => self.status = Some(e.to_string()),
+        }
+    }
+
+    // Deletes the selected connection, or every connection the filter shows
+    fn delete(&mut self, all: bool) {
+        let connections: Vec<Connection> = match (all, self.table_state.selected()) {
+            (true, _) if !self.filter.is_empty() => self.visible().into_iter().cloned().collect(),
+            (false, Some(i)) => self
+                .visible()
+                .get(i)
+                .map(|c| (*c).clone())
+                .into_iter()
+                .collect(),
+            _ => return,
+        };
+
+        self.status = match netlink::delete_connections(&connections) {
+            Ok(()) => Some(format!("Deleted {} connections", connections.len())),
+            Err(e) => {
+                debug!("Unable to delete connections: {e:?}");
+                Some(format!("Unable to delete connections: {e}"))
+            }
+        };
+        self.refresh();
+    }
+}
+
+impl Component for ConntrackList {
+    fn new(_context: &AppContext, action_tx: mpsc::UnboundedSender<Action>) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            action_tx,
+            connections: netlink::list_connections(),
+            last_read: Instant::now(),
+            filter: ConntrackFilter::default(),
+            filter_text: String::new(),
+            filter_input: None,
+            table_state: TableState::default(),
+            status: None,
+        }
+    }
+
+    fn update(mut self, context: &AppContext) -> Self
+    where
+        Self: Sized,
+    {
+        // Connections come and go too often to follow them all the time, the
+        // table is only kept up to date while it is looked at
+        if context.active_box == ActivePane::Conntrack
+            && self.last_read.elapsed() >= REFRESH_INTERVAL
+        {
+            self.refresh();
+        }
+
+        Self {
+            action_tx: self.action_tx,
+            connections: self.connections,
+            last_read: self.last_read,
+            filter: self.filter,
+            filter_text: self.filter_text,
+            filter_input: self.filter_input,
+            table_state: self.table_state,
+            status: self.status,
+        }
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if let Some(input) = &mut self.filter_input {
+            match key.code {
+                KeyCode::Esc => self.filter_input = None,
+                KeyCode::Enter => {
+                    if let Some(text) = self.filter_input.take() {
+                        self.apply_filter(text);
+                    }
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Esc => {
+                let _ = self.action_tx.send(Action::Return);
+            }
+            KeyCode::Down => self.table_state.select_next(),
+            KeyCode::Up => self.table_state.select_previous(),
+            KeyCode::Char('/') => self.filter_input = Some(self.filter_text.clone()),
+            KeyCode::Char('r') => self.refresh(),
+            KeyCode::Char('d') => self.delete(false),
+            KeyCode::Char('D') => self.delete(true),
+            _ => {}
+        }
+    }
+}
+
+impl ComponentRender<Props> for ConntrackList {
+    fn render(&mut self, frame: &mut Frame, props: Props) {
+        let visible = self.visible();
+        let mut title = match &self.connections {
+            Ok(connections) => format!("Connections ({} of {})", visible.len(), connections.len()),
+            Err(_) => "Connections".to_string(),
+        };
+        if !self.filter_text.is_empty() {
+            title.push_str(&format!(" - filter: {}", self.filter_text));
+        }
+
+        let mut block = Block::default()
+            .title(title)
+            .borders(Borders::all())
+            .border_style(props.border_color);
+        if let Some(input) = &self.filter_input {
+            block = block.title_bottom(format!(" filter: {input}_ "));
+        } else if let Some(status) = &self.status {
+            block = block.title_bottom(format!(" {status} "));
+        }
+
+        if let Err(e) = &self.connections {
+            let text = Paragraph::new(format!("Unable to read the conntrack table: {e}"))
+                .wrap(Wrap { trim: true })
+                .block(block);
+            frame.render_widget(text, props.area);
+            return;
+        }
+
+        let header = [
+            "Proto", "State", "Original", "Reply", "Timeout", "Mark", "Packets", "Bytes",
+        ]
+        .into_iter()
+        .map(Cell::from)
+        .collect::<Row>();
+
+        let rows: Vec<Row> = visible
+            .iter()
+            .map(|c| {
+                let optional =
+                    |value: Option<u32>| value.map(|v| v.to_string()).unwrap_or_default();
+                let counters =
+                    |counter: fn(&Counters) -> u64| match (c.original_counters, c.reply_counters) {
+                        (Some(original), Some(reply)) => {
+                            format!("{}/{}", counter(&original), counter(&reply))
+                        }
+                        _ => String::new(),
+                    };
+
+                Row::new([
+                    Cell::from(c.protocol_name()),
+                    Cell::from(c.state_name().unwrap_or_default()),
+                    Cell::from(c.original.to_string()),
+                    Cell::from(c.reply.to_string()),
+                    Cell::from(optional(c.timeout)),
+                    Cell::from(optional(c.mark)),
+                    Cell::from(counters(|n| n.packets)),
+                    Cell::from(counters(|n| n.bytes)),
+                ])
+            })
+            .collect();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Length(11),
+                Constraint::Fill(3),
+                Constraint::Fill(3),
+                Constraint::Length(7),
+                Constraint::Length(6),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+            ],
+        )
+        .block(block)
+        .row_highlight_style(Style::new().bold().bg(Color::White).fg(Color::Black))
+        .header(header);
+
+        frame.render_stateful_widget(table, props.area, &mut self.table_state);
+    }
+}
@@ -1,15 +1,73 @@
 use super::{Action, AppContext, Component, ComponentRender, Props};
+use crate::{
+    netlink::{self, PendingApply},
+    rules::{self, RulesFile},
+};
+use cli_log::debug;
 use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{
     Frame,
     prelude::*,
     style::{Color, Style},
-    widgets::{Block, Borders, Padding, Paragraph},
+    widgets::{Block, Borders, Padding, Paragraph, Wrap},
 };
 use tokio::sync::mpsc::{self};
 
 pub struct EditPage {
     action_tx: mpsc::UnboundedSender<Action>,
+    rules_file: String,
+    // Rules applied from this page that still need to be confirmed. They are
+    // rolled back when the page goes away with the interface, which quits on
+    // SIGINT and SIGTERM too.
+    pending: Option<(PendingApply, RulesFile)>,
+    status: Option<String>,
+}
+
+impl EditPage {
+    fn apply(&mut self) {
+        if self.pending.is_some() {
+            return;
+        }
+
+        let res = rules::load(&self.rules_file).and_then(|rules| {
+            let pending = netlink::apply_with_rollback(&rules, netlink::DEFAULT_CONFIRM_TIMEOUT)?;
+            Ok((pending, rules))
+        });
+        match res {
+            Ok(pending) => {
+                self.pending = Some(pending);
+                self.status = None;
+                let _ = self.action_tx.send(Action::RulesApplied);
+            }
+            Err(e) => {
+                debug!("Unable to apply rules: {e:?}");
+                self.status = Some(format!("Unable to apply rules: {e}"));
+            }
+        }
+    }
+
+    fn confirm(&mut self) {
+        if let Some((pending, rules)) = self.pending.take() {
+            pending.confirm();
+            self.status = Some("New rules confirmed".to_string());
+            let _ = self.action_tx.send(Action::RulesConfirmed(Box::new(rules)));
+        }
+    }
+
+    fn rollback(&mut self, reason: &str) {
+        let Some((pending, _)) = self.pending.take() else {
+            return;
+        };
+        let _ = self.action_tx.send(Action::RulesRolledBack);
+
+        self.status = match pending.rollback() {
+            Ok(()) => Some(format!("{reason}, previous ruleset restored")),
+            Err(e) => {
+                debug!("Unable to roll back: {e:?}");
+                Some(format!("{reason}, unable to restore previous ruleset: {e}"))
+            }
+        };
+    }
 }
 
 impl Component for EditPage {
@@ -17,35 +75,87 @@ impl Component for EditPage {
     where
         Self: Sized,
     {
-        Self { action_tx }
+        Self {
+            action_tx,
+            rules_file: context.rules_file.clone(),
+            pending: None,
+            status: None,
+        }
     }
 
-    fn update(self, context: &AppContext) -> Self
+    fn update(mut self, _context: &AppContext) -> Self
     where
         Self: Sized,
     {
+        // Called on every tick, so unconfirmed rules are reverted about a
+        // second after their deadline at most
+        if self.pending.as_ref().is_some_and(|(p, _)| p.expired()) {
+            self.rollback("Not confirmed in time");
+        }
+
         Self {
             action_tx: self.action_tx,
+            rules_file: self.rules_file,
+            pending: self.pending,
+            status: self.status,
         }
     }
 
-    fn handle_key_event(&mut self, key: crossterm::event::KeyEvent) {
+    fn handle_key_event(&mut self, key: KeyEvent) {
         match key.code {
             KeyCode::Esc => {
                 let _ = self.action_tx.send(Action::Return);
             }
+            KeyCode::Char('a') => {
+                self.apply();
+            }
+            KeyCode::Char('y') => {
+                self.confirm();
+            }
+            KeyCode::Char('n') => {
+                self.rollback("Rejected");
+            }
             _ => {}
         }
     }
 }
 
 impl ComponentRender<Props> for EditPage {
-    fn render(&mut self, frame: &mut ratatui::Frame, props: Props) {
+    fn render(&mut self, frame: &mut Frame, props: Props) {
         let block = Block::new()
+            .title("Edit Firewall Rules")
             .borders(Borders::all())
             .border_style(props.border_color)
             .padding(Padding::uniform(1));
 
-        frame.render_widget(block, props.area);
+        let mut lines = vec![
+            Line::from(format!("Rules file: {}", self.rules_file)),
+            Line::from(""),
+        ];
+
+        match &self.pending {
+            Some((pending, _)) => {
+                lines.push(Line::styled(
+                    format!(
+                        "New rules applied, confirm within {}s or they are rolled back",
+                        pending.remaining().as_secs()
+                    ),
+                    Style::new().fg(Color::Yellow).bold(),
+                ));
+                lines.push(Line::from("y - keep the new rules, n - roll back now"));
+            }
+            None => {
+                lines.push(Line::from("a - apply the rules file"));
+            }
+        }
+
+        if let Some(status) = &self.status {
+            lines.push(Line::from(""));
+            lines.push(Line::from(status.as_str()));
+        }
+
+        let text = Paragraph::new(lines).wrap(Wrap { trim: true }).block(block);
+
+        frame.render_widget(text, props.area);
     }
 }
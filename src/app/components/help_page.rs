@@ -58,12 +58,48 @@ impl ComponentRender<Props> for HelpPage {
                     To look at the rules for a chain press 'Enter'.
 
                     e - Edit the exising netfilter tables and rules
+                    r - Read the active ruleset again
+                    z - Reset the counters of the firewall-rs rules
+                    n - Show the rules in nft syntax, with their handles
+
+                    The rules of the firewall-rs table show their packet and
+                    byte rates, a sparkline of the recent rates and their
+                    totals, read every second.
+
+                    PageUp and PageDown switch between the rule tree and the
+                    sets and maps of every table. In the sets tab the right
+                    and left arrows move between a set and its elements.
+
+                    a - Add an element, like 10.0.0.5 or 22 : 10.0.0.5 for
+                        maps, optionally followed by timeout <seconds>
+                    d - Delete the selected element
+
+                    The ruleset is refreshed whenever it changes, the pane
+                    title names the process when the change came from
+                    outside firewall-rs.
+
+                    From the edit page, 'a' applies the rules file. The new
+                    rules have to be confirmed with 'y' within 30 seconds or
+                    the previous ruleset is restored.
                 
                 Packet Log of Incoming Packets:
-                    The log displays incoming packets and their status for the
-                    selected network device.
+                    The log displays the packets logged by rules with
+                    log = true and the rule that logged them, along with the
+                    packets of the selected network device.
 
                     i - List and select the network device
+                    c - Clear the log
+
+                Tracked Connections:
+                    c - Select the connections pane
+                    The pane lists the connection tracking table, read again
+                    every second while it is selected.
+
+                    / - Filter by protocol, tcp state, assured, unreplied,
+                        mark=N, port, address or network
+                    d - Delete the selected connection
+                    D - Delete every connection the filter shows
+                    r - Read the table again
                 
                 This page can be displayed by pressing '?'
             "#,
@@ -15,6 +15,7 @@ use ratatui::{
         Scrollbar, ScrollbarOrientation, ScrollbarState, Table, TableState,
     },
 };
+use std::net::{IpAddr, SocketAddr};
 use tokio::sync::broadcast::{self};
 use tokio::sync::mpsc::{self};
 
@@ -30,7 +31,10 @@ pub struct PacketLog<'a> {
     table_state: TableState,
     list_state: ListState,
     packet_collector: PacketCollector,
-    packets_tx: mpsc::Sender<PacketInfo>,
+    packets_tx: mpsc::UnboundedSender<PacketInfo>,
+    // NFLOG group read, with the error when binding it failed
+    log_group: u16,
+    log_error: Option<String>,
 }
 
 impl<'a> Component for PacketLog<'a> {
@@ -39,7 +43,7 @@ impl<'a> Component for PacketLog<'a> {
         Self: Sized,
     {
         let (packet_collector, packets_tx) = PacketCollector::new();
-        Self {
+        let mut packet_log = Self {
             target_if: None,
             list_interfaces: false,
             network_ifs: netlink::get_interfaces(),
@@ -52,12 +56,23 @@ impl<'a> Component for PacketLog<'a> {
             shutdown_channel: context.shutdown_channel.resubscribe(),
             packet_collector,
             packets_tx,
-        }
+            log_group: context.log_group,
+            log_error: None,
+        };
+        packet_log.read_log(context.log_group);
+
+        packet_log
     }
-    fn update(self, _: &AppContext) -> Self
+    fn update(mut self, context: &AppContext) -> Self
     where
         Self: Sized,
     {
+        // The group changes when the rules file is reloaded
+        if self.log_group != context.log_group {
+            self.read_log(context.log_group);
+        }
+        self.packet_collector.collect();
+
         Self {
             target_if: self.target_if,
             network_ifs: self.network_ifs,
@@ -71,6 +86,8 @@ impl<'a> Component for PacketLog<'a> {
             shutdown_channel: self.shutdown_channel,
             packet_collector: self.packet_collector,
             packets_tx: self.packets_tx,
+            log_group: self.log_group,
+            log_error: self.log_error,
         }
     }
 
@@ -86,6 +103,12 @@ impl<'a> Component for PacketLog<'a> {
             KeyCode::Char('i') => {
                 self.list_interfaces = true;
             }
+            KeyCode::Char('c') => {
+                self.packet_collector.clear();
+                self.table_state.select(None);
+                self.scroll = 0;
+                self.scrollbar_state = self.scrollbar_state.position(0);
+            }
             KeyCode::Down => {
                 if self.list_interfaces {
                     self.list_state.select_next();
@@ -117,7 +140,8 @@ impl<'a> Component for PacketLog<'a> {
                             .unwrap()
                             .clone();
 
-                        self.action_tx
+                        let _ = self
+                            .action_tx
                             .send(Action::StartListener(target_if, self.packets_tx.clone()));
 
                         let mut shutdown_rx = self.shutdown_channel.resubscribe();
@@ -134,8 +158,11 @@ impl<'a> Component for PacketLog<'a> {
 impl<'a> ComponentRender<Props> for PacketLog<'a> {
     fn render(&mut self, frame: &mut ratatui::Frame, props: Props) {
         let block_title = match self.target_if.clone() {
-            Some(interface) => format!("Packet Log - from [{interface}]"),
-            None => String::from("Packet Log"),
+            Some(interface) => format!(
+                "Packet Log - NFLOG group {} and [{interface}]",
+                self.log_group
+            ),
+            None => format!("Packet Log - NFLOG group {}", self.log_group),
         };
 
         let block = Block::default()
@@ -154,27 +181,49 @@ impl<'a> ComponentRender<Props> for PacketLog<'a> {
                     .border_style(props.border_color),
             );
 
-        let header = ["Protocol", "Source", "Destination"]
-            .into_iter()
-            .map(Cell::from)
-            .collect::<Row>();
+        let header = [
+            "Time",
+            "Rule",
+            "In",
+            "Out",
+            "Protocol",
+            "Source",
+            "Destination",
+        ]
+        .into_iter()
+        .map(Cell::from)
+        .collect::<Row>();
 
         let rows = self.packet_collector.packets.iter().map(|entry| {
-            let protocol_cell = Cell::from(Text::from(format!("{}", entry.proto)));
-            let src_cell = Cell::from(Text::from(format!("{}", entry.src)));
-            let dst_cell = Cell::from(Text::from(format!("{}", entry.dst)));
+            let time_cell = Cell::from(Text::from(entry.time.format("%H:%M:%S").to_string()));
+            let rule_cell = Cell::from(Text::from(entry.rule.clone().unwrap_or_default()));
+            let iif_cell = Cell::from(Text::from(entry.iifname.clone().unwrap_or_default()));
+            let oif_cell = Cell::from(Text::from(entry.oifname.clone().unwrap_or_default()));
+            let protocol_cell = Cell::from(Text::from(entry.proto.clone()));
+            let src_cell = Cell::from(Text::from(endpoint(entry.src, entry.sport)));
+            let dst_cell = Cell::from(Text::from(endpoint(entry.dst, entry.dport)));
 
-            Row::new([protocol_cell, src_cell, dst_cell].into_iter())
+            Row::new([
+                time_cell,
+                rule_cell,
+                iif_cell,
+                oif_cell,
+                protocol_cell,
+                src_cell,
+                dst_cell,
+            ])
         });
 
         let table = Table::new(
             rows,
             [
-                Constraint::Length(5),
-                Constraint::Length(10),
-                Constraint::Length(15),
-                Constraint::Length(15),
-                Constraint::Length(15),
+                Constraint::Length(8),
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Fill(2),
+                Constraint::Fill(2),
             ],
         )
         .block(block.clone())
@@ -183,8 +232,13 @@ impl<'a> ComponentRender<Props> for PacketLog<'a> {
 
         if self.list_interfaces {
             frame.render_stateful_widget(if_list, props.area, &mut self.list_state);
-        } else if self.target_if.is_none() {
-            let text = Text::from("---- No device selected ----").style(Style::new().bold());
+        } else if let Some(error) = &self.log_error
+            && self.target_if.is_none()
+        {
+            let text = Text::from(vec![
+                Line::from(format!("---- {error} ----")).bold(),
+                Line::from("Press 'i' to capture from a network device instead"),
+            ]);
             let paragraph = Paragraph::new(text).block(block).centered();
             frame.render_widget(paragraph, props.area);
         } else {
@@ -197,3 +251,29 @@ impl<'a> ComponentRender<Props> for PacketLog<'a> {
         }
     }
 }
+
+impl PacketLog<'_> {
+    // Logged packets show up next to the ones captured from the device
+    fn read_log(&mut self, group: u16) {
+        self.log_group = group;
+        match netlink::nflog(group) {
+            Ok(log_rx) => {
+                self.packet_collector.read_log(log_rx);
+                self.log_error = None;
+            }
+            Err(e) => {
+                debug!("Unable to read NFLOG group {group}: {e:?}");
+                self.log_error = Some(format!("Unable to read NFLOG group {group}: {e}"));
+            }
+        }
+    }
+}
+
+// Address with the port when the protocol has one
+fn endpoint(addr: Option<IpAddr>, port: Option<u16>) -> String {
+    match (addr, port) {
+        (Some(addr), Some(port)) => SocketAddr::new(addr, port).to_string(),
+        (Some(addr), None) => addr.to_string(),
+        (None, _) => String::new(),
+    }
+}
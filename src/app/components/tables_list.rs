@@ -1,20 +1,47 @@
 use super::{Action, AppContext, Component, ComponentRender, Props};
-use crate::netlink::{self};
+use crate::netlink::{self, CounterStats, RuleSyntax, Ruleset, RulesetChange, Set, Table};
 use cli_log::debug;
 use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{
     prelude::*,
     style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Tabs},
+    widgets::{
+        Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table as TableWidget,
+        TableState, Tabs, Wrap,
+    },
 };
 use tokio::sync::mpsc::{self};
-use tui_tree_widget::{Tree, TreeState};
+use tui_tree_widget::{Tree, TreeItem, TreeState};
+
+const TABS: [&str; 2] = ["Tree", "Sets"];
+const SETS_TAB: usize = 1;
+
+// Which list of the sets tab the arrow keys move in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetsFocus {
+    Sets,
+    Elements,
+}
 
 pub struct TableList<T> {
     current_tab: usize,
     total_tabs: usize,
     action_tx: mpsc::UnboundedSender<Action>,
     tree_state: TreeState<T>,
+    // Ruleset as of ruleset_generation, only read again when it changes
+    ruleset: netlink::Result<Ruleset>,
+    // Built again from the ruleset when the counters are polled
+    tree: Vec<TreeItem<'static, T>>,
+    syntax: RuleSyntax,
+    ruleset_generation: u64,
+    counters_generation: u64,
+    external_change: Option<RulesetChange>,
+    sets_state: TableState,
+    elements_state: ListState,
+    sets_focus: SetsFocus,
+    // Element being typed, added with enter
+    element_input: Option<String>,
+    status: Option<String>,
 }
 
 impl TableList<usize> {
@@ -31,42 +58,315 @@ impl TableList<usize> {
     fn clamp_tab(&mut self) {
         self.current_tab = self.current_tab.clamp(0, self.total_tabs - 1);
     }
+
+    fn refresh(&mut self, counters: &CounterStats) {
+        debug!("Reading the active ruleset");
+        self.ruleset = Ruleset::read();
+        self.build(counters);
+    }
+
+    fn build(&mut self, counters: &CounterStats) {
+        self.tree = match &self.ruleset {
+            Ok(ruleset) => netlink::build_tree(ruleset, counters, self.syntax),
+            Err(_) => vec![],
+        };
+        self.counters_generation = counters.generation;
+    }
+
+    // Sets and maps of every table, in the order nft lists them
+    fn sets(&self) -> Vec<(&Table, &Set)> {
+        match &self.ruleset {
+            Ok(ruleset) => ruleset
+                .tables
+                .iter()
+                .flat_map(|table| table.sets.iter().map(move |set| (table, set)))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn selected_set(&self) -> Option<(&Table, &Set)> {
+        self.sets().into_iter().nth(self.sets_state.selected()?)
+    }
+
+    // Elements are written like `set add` takes them, optionally followed
+    // by `timeout <seconds>`
+    fn add_element(&mut self, text: &str) {
+        let Some((table, set)) = self.selected_set() else {
+            return;
+        };
+
+        let (element, timeout) = match text.rsplit_once(" timeout ") {
+            Some((element, timeout)) => (element, Some(timeout.trim().trim_end_matches('s'))),
+            None => (text, None),
+        };
+        let result = timeout
+            .map(|timeout| {
+                timeout
+                    .parse::<u32>()
+                    .map_err(|e| netlink::Error::rejected(format!("timeout {timeout}"), e))
+            })
+            .transpose()
+            .and_then(|timeout| netlink::parse_element(element, timeout))
+            .and_then(|element| {
+                netlink::add_elements(table.family, &table.name, &set.name, &[element])
+            });
+
+        let status = match result {
+            Ok(()) => format!("Added {element} to {}", set.name),
+            Err(e) => {
+                debug!("Unable to add element: {e:?}");
+                format!("Unable to add {element}: {e}")
+            }
+        };
+        self.status = Some(status);
+        self.ruleset = Ruleset::read();
+    }
+
+    fn delete_element(&mut self) {
+        let Some((table, set)) = self.selected_set() else {
+            return;
+        };
+        let Some(element) = self
+            .elements_state
+            .selected()
+            .and_then(|i| set.elements.get(i))
+        else {
+            return;
+        };
+
+        let key = netlink::element_text(netlink::element_key(element));
+        let status = match netlink::delete_elements(
+            table.family,
+            &table.name,
+            &set.name,
+            std::slice::from_ref(element),
+        ) {
+            Ok(()) => format!("Deleted {key} from {}", set.name),
+            Err(e) => {
+                debug!("Unable to delete element: {e:?}");
+                format!("Unable to delete {key}: {e}")
+            }
+        };
+        self.status = Some(status);
+        self.ruleset = Ruleset::read();
+    }
+
+    fn handle_sets_key(&mut self, key: KeyEvent) {
+        match (self.sets_focus, key.code) {
+            (SetsFocus::Sets, KeyCode::Down) => {
+                self.sets_state.select_next();
+                self.elements_state.select(None);
+            }
+            (SetsFocus::Sets, KeyCode::Up) => {
+                self.sets_state.select_previous();
+                self.elements_state.select(None);
+            }
+            (SetsFocus::Sets, KeyCode::Right | KeyCode::Enter) if self.selected_set().is_some() => {
+                self.sets_focus = SetsFocus::Elements;
+                self.elements_state.select_first();
+            }
+            (SetsFocus::Elements, KeyCode::Down) => self.elements_state.select_next(),
+            (SetsFocus::Elements, KeyCode::Up) => self.elements_state.select_previous(),
+            (SetsFocus::Elements, KeyCode::Left) => self.sets_focus = SetsFocus::Sets,
+            (SetsFocus::Elements, KeyCode::Char('d')) => self.delete_element(),
+            (_, KeyCode::Char('a')) if self.selected_set().is_some() => {
+                self.element_input = Some(String::new());
+            }
+            _ => {}
+        }
+    }
+
+    fn render_sets(&mut self, frame: &mut Frame, area: Rect, props: &Props) {
+        let layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(area);
+
+        let focused = |focus| {
+            if self.sets_focus == focus {
+                props.border_color
+            } else {
+                Color::default()
+            }
+        };
+        let sets = self.sets();
+
+        let header = ["Table", "Name", "Type", "Flags", "Timeout", "Elements"]
+            .into_iter()
+            .map(Cell::from)
+            .collect::<Row>();
+        let rows: Vec<Row> = sets
+            .iter()
+            .map(|(table, set)| {
+                Row::new([
+                    Cell::from(format!(
+                        "{} {}",
+                        netlink::family_name(table.family),
+                        table.name
+                    )),
+                    Cell::from(set.name.clone()),
+                    Cell::from(set.type_text()),
+                    Cell::from(set.flags_text().join(",")),
+                    Cell::from(set.timeout.map(|t| format!("{t}s")).unwrap_or_default()),
+                    Cell::from(set.elements.len().to_string()),
+                ])
+            })
+            .collect();
+        let sets_table = TableWidget::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Fill(2),
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+            ],
+        )
+        .header(header)
+        .block(
+            Block::default()
+                .title(format!("Sets and maps ({})", sets.len()))
+                .borders(Borders::all())
+                .border_style(focused(SetsFocus::Sets)),
+        )
+        .row_highlight_style(Style::new().bold().bg(Color::White).fg(Color::Black));
+
+        let selected = self.sets_state.selected().and_then(|i| sets.get(i));
+        let (title, items): (String, Vec<ListItem>) = match selected {
+            Some((_, set)) => (
+                format!("Elements of {}", set.name),
+                set.elements
+                    .iter()
+                    .map(|e| ListItem::new(netlink::element_text(e)))
+                    .collect(),
+            ),
+            None => ("Elements".to_string(), vec![]),
+        };
+        let mut block = Block::default()
+            .title(title)
+            .borders(Borders::all())
+            .border_style(focused(SetsFocus::Elements));
+        if let Some(input) = &self.element_input {
+            block = block.title_bottom(format!(" add: {input}_ "));
+        } else if let Some(status) = &self.status {
+            block = block.title_bottom(format!(" {status} "));
+        }
+        let elements = List::new(items)
+            .block(block)
+            .highlight_style(Style::new().bold().bg(Color::White).fg(Color::Black));
+
+        frame.render_stateful_widget(sets_table, layout[0], &mut self.sets_state);
+        frame.render_stateful_widget(elements, layout[1], &mut self.elements_state);
+    }
 }
 
 impl Component for TableList<usize> {
-    fn new(_context: &AppContext, action_tx: mpsc::UnboundedSender<Action>) -> Self
+    fn new(context: &AppContext, action_tx: mpsc::UnboundedSender<Action>) -> Self
     where
         Self: Sized,
     {
-        Self {
+        let mut table_list = Self {
             current_tab: 0,
-            total_tabs: 1,
+            total_tabs: TABS.len(),
             action_tx,
             tree_state: TreeState::default(),
-        }
+            ruleset: Ruleset::read(),
+            tree: vec![],
+            syntax: RuleSyntax::default(),
+            ruleset_generation: context.ruleset_generation,
+            counters_generation: context.counters.generation,
+            external_change: context.external_change.clone(),
+            sets_state: TableState::default(),
+            elements_state: ListState::default(),
+            sets_focus: SetsFocus::Sets,
+            element_input: None,
+            status: None,
+        };
+        table_list.build(&context.counters);
+
+        table_list
     }
 
-    fn update(self, _context: &AppContext) -> Self
+    fn update(mut self, context: &AppContext) -> Self
     where
         Self: Sized,
     {
+        if self.ruleset_generation != context.ruleset_generation {
+            self.refresh(&context.counters);
+        } else if self.counters_generation != context.counters.generation {
+            self.build(&context.counters);
+        }
+
         Self {
             current_tab: self.current_tab,
             total_tabs: self.total_tabs,
             action_tx: self.action_tx,
             tree_state: self.tree_state,
+            ruleset: self.ruleset,
+            tree: self.tree,
+            syntax: self.syntax,
+            ruleset_generation: context.ruleset_generation,
+            counters_generation: self.counters_generation,
+            external_change: context.external_change.clone(),
+            sets_state: self.sets_state,
+            elements_state: self.elements_state,
+            sets_focus: self.sets_focus,
+            element_input: self.element_input,
+            status: self.status,
         }
     }
 
     fn handle_key_event(&mut self, key: KeyEvent) {
+        if let Some(input) = &mut self.element_input {
+            match key.code {
+                KeyCode::Esc => self.element_input = None,
+                KeyCode::Enter => {
+                    if let Some(text) = self.element_input.take() {
+                        self.add_element(&text);
+                    }
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return;
+        }
+
         match key.code {
             KeyCode::Esc => {
                 let _ = self.action_tx.send(Action::Return);
             }
-            KeyCode::Enter => {}
             KeyCode::Char('e') => {
                 let _ = self.action_tx.send(Action::EditRules);
             }
+            KeyCode::Char('r') => {
+                // The tree follows on the next tick, along with the counters
+                debug!("Reading the active ruleset");
+                self.ruleset = Ruleset::read();
+            }
+            KeyCode::Char('z') => {
+                let _ = self.action_tx.send(Action::ResetCounters);
+            }
+            KeyCode::PageDown => {
+                self.previous_tab();
+            }
+            KeyCode::PageUp => {
+                self.next_tab();
+            }
+            KeyCode::Char('n') if self.current_tab != SETS_TAB => {
+                self.syntax = match self.syntax {
+                    RuleSyntax::English => RuleSyntax::Nft,
+                    RuleSyntax::Nft => RuleSyntax::English,
+                };
+                // Built again on the next tick, as if the counters changed
+                self.counters_generation = u64::MAX;
+            }
+            _ if self.current_tab == SETS_TAB => self.handle_sets_key(key),
+            KeyCode::Enter => {}
             KeyCode::Down => {
                 self.tree_state.key_down();
             }
@@ -79,12 +379,6 @@ impl Component for TableList<usize> {
             KeyCode::Right => {
                 self.tree_state.key_right();
             }
-            KeyCode::PageDown => {
-                self.previous_tab();
-            }
-            KeyCode::PageUp => {
-                self.next_tab();
-            }
             _ => {}
         }
     }
@@ -97,12 +391,12 @@ impl ComponentRender<Props> for TableList<usize> {
             .constraints([Constraint::Length(3), Constraint::Min(0)])
             .split(props.area);
 
-        let tabs = Tabs::new(vec!["Tree"])
-            .block(
-                Block::default()
-                    .borders(Borders::all())
-                    .title("Active Firewall Rules"),
-            )
+        let title = match &self.external_change {
+            Some(change) => format!("Active Firewall Rules (changed by {change})"),
+            None => "Active Firewall Rules".to_string(),
+        };
+        let tabs = Tabs::new(TABS.to_vec())
+            .block(Block::default().borders(Borders::all()).title(title))
             .highlight_style(
                 Style::default()
                     .fg(Color::Black)
@@ -115,9 +409,31 @@ impl ComponentRender<Props> for TableList<usize> {
 
         frame.render_widget(tabs, layout[0]);
 
-        let tree_nodes = netlink::build_tree();
+        if self.current_tab == SETS_TAB && self.ruleset.is_ok() {
+            self.render_sets(frame, layout[1], &props);
+            return;
+        }
+
+        let tree_nodes = match &self.ruleset {
+            Ok(_) => &self.tree,
+            Err(e) => {
+                debug!("Unable to list the ruleset: {e:?}");
+                let text = Paragraph::new(format!("Unable to list the active ruleset: {e}"))
+                    .style(Style::default().fg(Color::Red))
+                    .wrap(Wrap { trim: true })
+                    .block(
+                        Block::default()
+                            .borders(Borders::all())
+                            .border_style(props.border_color),
+                    )
+                    .alignment(Alignment::Center);
+
+                frame.render_widget(text, layout[1]);
+                return;
+            }
+        };
         if !tree_nodes.is_empty() {
-            let tree = Tree::new(&tree_nodes)
+            let tree = Tree::new(tree_nodes)
                 .unwrap()
                 .block(
                     Block::default()
@@ -1,16 +1,45 @@
 use super::ActivePane;
+use crate::netlink::{CounterStats, QueueStats, RulesetChange};
+use std::sync::Arc;
 use tokio::sync::broadcast::{self};
 
+// Outcome of the last background operation, shown in the footer
+#[derive(Debug, Clone)]
+pub enum Status {
+    Ok(String),
+    Error(String),
+}
+
 #[derive(Debug)]
 pub struct AppContext {
     pub active_box: ActivePane,
+    pub rules_file: String,
+    pub status: Option<Status>,
+    // Bumped every time the live ruleset changes, components showing it
+    // read it again when it differs from what they last saw
+    pub ruleset_generation: u64,
+    // Last change made to the ruleset by another process
+    pub external_change: Option<RulesetChange>,
+    // NFLOG group the packet log reads
+    pub log_group: u16,
+    // Counters of the managed rules, polled on every tick
+    pub counters: CounterStats,
+    // Packets handled by the queue worker, when the rules use the queue
+    pub queue_stats: Option<Arc<QueueStats>>,
     pub shutdown_channel: broadcast::Receiver<()>,
 }
 
 impl AppContext {
-    pub fn new(shutdown_channel: broadcast::Receiver<()>) -> Self {
+    pub fn new(rules_file: String, shutdown_channel: broadcast::Receiver<()>) -> Self {
         Self {
             active_box: ActivePane::None,
+            rules_file,
+            status: None,
+            ruleset_generation: 0,
+            external_change: None,
+            log_group: 0,
+            counters: CounterStats::default(),
+            queue_stats: None,
             shutdown_channel,
         }
     }
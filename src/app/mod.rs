@@ -1,7 +1,7 @@
 use self::{
     app_router::AppRouter,
     components::{Component, ComponentRender},
-    context::AppContext,
+    context::{AppContext, Status},
     event_handler::{Event, EventHandler},
     ui::{Action, UserInterface},
 };
@@ -9,10 +9,14 @@ use crate::{
     display,
     netlink::{self},
     packetcap::packet::PacketInfo,
+    rules::{self, RulesChanges, RulesFile},
 };
 use anyhow::Result;
 use cli_log::debug;
-use tokio::sync::mpsc::{self};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::mpsc::{self},
+};
 
 mod app_router;
 mod components;
@@ -28,6 +32,7 @@ pub enum ActivePane {
     HelpPage,
     EditPage,
     ChainsList,
+    Conntrack,
 }
 
 pub struct App {
@@ -35,29 +40,91 @@ pub struct App {
     ui: UserInterface,
     action_rx: mpsc::UnboundedReceiver<Action>,
     event_handler: EventHandler,
+    // Rules currently applied from the rules file
+    rules: RulesFile,
+    // Rules applied from the edit page still await confirmation. A reload
+    // meanwhile would be undone by their rollback, so it waits until then.
+    rollback_pending: bool,
+    reload_deferred: bool,
 }
 
 impl App {
-    pub fn new() -> Result<Self> {
+    pub fn new(rules: RulesFile) -> Result<Self> {
         let (ui, action_rx, event_handler) = UserInterface::new();
 
-        let _ = netlink::create_test_table();
+        if let Err(e) = netlink::create_test_table() {
+            debug!("Unable to create test table: {e:?}");
+        }
 
         Ok(Self {
             quit: false,
             ui,
             action_rx,
             event_handler,
+            rules,
+            rollback_pending: false,
+            reload_deferred: false,
         })
     }
 
+    // Loads the rules file again after it changed on disk and applies it if
+    // the rules are different. The old rules stay in place on any error.
+    fn reload_rules(&mut self, rules_file: &str) -> Status {
+        let rules = match rules::load(rules_file) {
+            Ok(rules) => rules,
+            Err(e) => return Status::Error(one_line(&format!("{e:#}"))),
+        };
+
+        let changes = RulesChanges::between(&self.rules, &rules);
+        if changes.is_empty() {
+            return Status::Ok(format!("Reloaded {rules_file}, no changes"));
+        }
+
+        if let Err(e) = netlink::apply_rules(&rules) {
+            debug!("Unable to apply reloaded rules: {e:?}");
+            return Status::Error(format!("Unable to apply {rules_file}: {e}"));
+        }
+        self.rules = rules;
+
+        Status::Ok(format!("Reloaded {rules_file}: {changes}"))
+    }
+
+    // Runs the reload held back while applied rules awaited confirmation
+    fn end_pending(&mut self, context: &mut AppContext) {
+        self.rollback_pending = false;
+        if std::mem::take(&mut self.reload_deferred) {
+            context.status = Some(self.reload_rules(&context.rules_file));
+            context.log_group = self.rules.log_settings().group;
+        }
+    }
+
     pub async fn run(&mut self, mut context: AppContext) -> Result<()> {
         debug!("Running app");
+        context.log_group = self.rules.log_settings().group;
 
         let mut app_router = AppRouter::new(&context, self.ui.action_tx.clone());
         let mut terminal = display::setup_terminal();
         terminal.clear()?;
 
+        // Without a watcher the channel is simply closed and never fires
+        let mut reload_rx = rules::watch(&context.rules_file).unwrap_or_else(|e| {
+            debug!("Unable to watch rules file: {e:?}");
+            context.status = Some(Status::Error(format!("Hot reload disabled: {e}")));
+            mpsc::unbounded_channel().1
+        });
+
+        // Without the monitor the ruleset shown is only read again on demand
+        let mut ruleset_rx = netlink::monitor().unwrap_or_else(|e| {
+            debug!("Unable to monitor the ruleset: {e:?}");
+            context.status = Some(Status::Error(format!("Ruleset monitor disabled: {e}")));
+            mpsc::unbounded_channel().1
+        });
+
+        // Signals quit like `q` does, rules applied from the edit page and not
+        // confirmed yet are rolled back as the page goes away
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+
         loop {
             if self.quit {
                 display::teardown_terminal(&mut terminal);
@@ -72,6 +139,11 @@ impl App {
                         },
                         Ok(Event::Error) => {},
                         Ok(Event::Tick) => {
+                            // Rates are left as they were when the table
+                            // can't be read, the tree says why
+                            if let Err(e) = context.counters.poll() {
+                                debug!("Unable to read the rule counters: {e:?}");
+                            }
                             app_router = app_router.update(&context);
                         },
                         Err(_) => {},
@@ -95,27 +167,50 @@ impl App {
                         Some(Action::SelectPacketLog) => {
                             context.active_box = ActivePane::PacketLog;
                         },
+                        Some(Action::SelectConntrack) => {
+                            context.active_box = ActivePane::Conntrack;
+                        },
                         Some(Action::EditRules) => {
                             context.active_box = ActivePane::EditPage;
                         },
+                        Some(Action::ResetCounters) => {
+                            context.status = Some(match context.counters.reset() {
+                                Ok(()) => Status::Ok("Rule counters reset".to_string()),
+                                Err(e) => Status::Error(format!(
+                                    "Unable to reset the rule counters: {e}"
+                                )),
+                            });
+                        },
+                        Some(Action::RulesApplied) => {
+                            self.rollback_pending = true;
+                        },
+                        Some(Action::RulesConfirmed(rules)) => {
+                            self.rules = *rules;
+                            context.log_group = self.rules.log_settings().group;
+                            self.end_pending(&mut context);
+                        },
+                        Some(Action::RulesRolledBack) => {
+                            self.end_pending(&mut context);
+                        },
                         Some(Action::StartListener(target_if, packet_tx)) => {
-                        let _ = tokio::task::spawn_blocking(move || {
-                            let mut cap = pcap::Capture::from_device(target_if)
-                                .unwrap()
-                                .open()
-                                .unwrap();
+                        // Captures until the packet log goes away, the loop
+                        // doesn't wait for it
+                        tokio::task::spawn_blocking(move || {
+                            let mut cap = match pcap::Capture::from_device(target_if)
+                                .and_then(|cap| cap.open())
+                            {
+                                Ok(cap) => cap,
+                                Err(e) => {
+                                    debug!("Unable to capture packets: {e}");
+                                    return;
+                                }
+                            };
                             while let Ok(packet) = cap.next_packet() {
-                                //if let Ok(_) = shutdown_rx.try_recv() {
-                                //    break;
-                                //}
-                                let packet_info = PacketInfo::build(&packet);
-                                let _ = packet_tx.send(packet_info);
-
-                                debug!("PACKET SENT");
+                                if packet_tx.send(PacketInfo::build(&packet)).is_err() {
+                                    break;
+                                }
                             }
-
-                            std::thread::sleep(std::time::Duration::from_millis(1));
-                        }).await;
+                        });
                         },
                         None => {},
                     }
@@ -123,13 +218,61 @@ impl App {
                     app_router = app_router.update(&context);
 
                 },
+                Some(change) = ruleset_rx.recv() => {
+                    debug!("Ruleset changed: {change:?}");
+                    context.ruleset_generation += 1;
+                    if change.is_external() {
+                        context.status = Some(Status::Ok(format!(
+                            "Ruleset changed outside firewall-rs by {change}"
+                        )));
+                        context.external_change = Some(change);
+                    } else {
+                        context.external_change = None;
+                    }
+                    app_router = app_router.update(&context);
+                },
+                Some(()) = reload_rx.recv() => {
+                    if self.rollback_pending {
+                        debug!("Rules file changed, reloading after confirmation");
+                        self.reload_deferred = true;
+                        context.status = Some(Status::Ok(
+                            "Rules file changed, reloading once the applied rules are confirmed or rolled back".to_string(),
+                        ));
+                    } else {
+                        debug!("Rules file changed, reloading");
+                        context.status = Some(self.reload_rules(&context.rules_file));
+                        context.log_group = self.rules.log_settings().group;
+                    }
+                    app_router = app_router.update(&context);
+                },
+                _ = interrupt.recv() => {
+                    debug!("Interrupted, quitting app");
+                    self.quit = true;
+                },
+                _ = terminate.recv() => {
+                    debug!("Terminated, quitting app");
+                    self.quit = true;
+                },
             }
 
             let _ = terminal.draw(|f| app_router.render(f, ()));
         }
 
-        netlink::cleanup_test_tables();
+        if let Err(e) = netlink::cleanup_test_tables() {
+            debug!("Unable to remove test table: {e:?}");
+        }
 
         Ok(())
     }
 }
+
+// TOML errors point at the offending line with a multi-line snippet, only the
+// location and message fit in the footer
+fn one_line(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('|') && !line.contains(" | "))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
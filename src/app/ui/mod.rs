@@ -1,5 +1,5 @@
 use super::EventHandler;
-use crate::packetcap::packet::PacketInfo;
+use crate::{packetcap::packet::PacketInfo, rules::RulesFile};
 use pcap::Device;
 use tokio::sync::mpsc::{self};
 
@@ -9,9 +9,15 @@ pub enum Action {
     Return,
     SelectTableList,
     SelectPacketLog,
-    StartListener(Device, mpsc::Sender<PacketInfo>),
+    SelectConntrack,
+    StartListener(Device, mpsc::UnboundedSender<PacketInfo>),
     DisplayHelp,
     EditRules,
+    ResetCounters,
+    // Rules applied from the edit page, waiting to be confirmed
+    RulesApplied,
+    RulesConfirmed(Box<RulesFile>),
+    RulesRolledBack,
 }
 
 pub struct UserInterface {
@@ -1,17 +1,46 @@
-use crate::app::{App, context::AppContext};
-use anyhow::Result;
+use crate::{
+    app::{App, context::AppContext},
+    netlink::{self, QueueStats, ScriptFormat},
+    rules::{self, QueueSettings, RulesFile, Severity},
+};
+use anyhow::{Context, Result, anyhow, bail};
 use cli_log::debug;
+use nftables::types::NfFamily;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    sync::broadcast::{self},
+    signal::unix::{SignalKind, signal},
+    sync::{
+        broadcast::{self},
+        oneshot,
+    },
     task::JoinSet,
 };
 
 // Runs the main logic of the application
-pub async fn run() -> Result<()> {
+pub async fn run(rules_file: String) -> Result<()> {
+    let rules = rules::load(&rules_file)?;
+    netlink::apply_rules(&rules)?;
+    debug!("Applied rules from {rules_file}");
+
+    // Without a worker queued packets would wait for a program reading the
+    // queue, this one gives them the decision of the rules file
+    let queue_stats = if rules.uses_queue() {
+        Some(spawn_queue_worker(rules.queue_settings())?)
+    } else {
+        None
+    };
+
     let (shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
 
-    let context = AppContext::new(shutdown_rx.resubscribe());
-    let mut app = App::new().unwrap();
+    let mut context = AppContext::new(rules_file, shutdown_rx.resubscribe());
+    context.queue_stats = queue_stats;
+    let mut app = App::new(rules).unwrap();
 
     let mut task_set = JoinSet::new();
 
@@ -28,3 +57,331 @@ pub async fn run() -> Result<()> {
 
     Ok(())
 }
+
+// Applies the rules file once and exits. With dry_run the generated ruleset
+// is printed instead, which doesn't need root. With a confirm timeout the
+// previous ruleset is restored unless the operator confirms in time.
+pub async fn apply(
+    rules_file: &str,
+    dry_run: bool,
+    format: ScriptFormat,
+    confirm_timeout: Option<Duration>,
+) -> Result<()> {
+    let rules = rules::load(rules_file)?;
+
+    if dry_run {
+        let script = netlink::render_script(&netlink::compile(&rules), format)?;
+        print!("{script}");
+        return Ok(());
+    }
+
+    warn_unread_queue(&rules);
+
+    let Some(timeout) = confirm_timeout else {
+        let diff = netlink::apply_rules(&rules)?;
+        print!("{diff}");
+        if diff.is_empty() {
+            println!("The active ruleset already matches {rules_file}");
+        } else {
+            println!("Applied rules from {rules_file}");
+        }
+        return Ok(());
+    };
+
+    // Losing the session is exactly what the rollback protects against, so
+    // the process has to outlive the terminal it was started from
+    unsafe { libc::signal(libc::SIGHUP, libc::SIG_IGN) };
+    // Interrupting or terminating the process would otherwise end it without
+    // rolling back, the handlers are in place before anything is applied
+    let mut interrupt = signal(SignalKind::interrupt()).context("Unable to handle SIGINT")?;
+    let mut terminate = signal(SignalKind::terminate()).context("Unable to handle SIGTERM")?;
+
+    // Nothing written to the terminal may fail the command before the rules
+    // are confirmed or rolled back, the terminal might be gone
+    let pending = netlink::apply_with_rollback(&rules, timeout)?;
+    write!(
+        io::stdout(),
+        "Applied rules from {rules_file}, can you still establish new connections? (y/N, {}s) ",
+        timeout.as_secs()
+    )
+    .ok();
+    io::stdout().flush().ok();
+
+    // Stdin is read on its own thread, a blocking tokio read would keep the
+    // runtime from shutting down after a timeout
+    let (answer_tx, answer_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        let _ = answer_tx.send(answer);
+    });
+
+    let answer = tokio::select! {
+        answer = tokio::time::timeout(pending.remaining(), answer_rx) => Some(answer),
+        _ = interrupt.recv() => None,
+        _ = terminate.recv() => None,
+    };
+    let Some(answer) = answer else {
+        pending.rollback()?;
+        // The prompt is still waiting for an answer
+        writeln!(io::stderr()).ok();
+        bail!(
+            "interrupted before the new rules were confirmed, rolled back to the previous ruleset"
+        );
+    };
+    let confirmed = matches!(&answer, Ok(Ok(answer)) if matches!(answer.trim(), "y" | "Y" | "yes"));
+
+    if confirmed {
+        pending.confirm();
+        writeln!(io::stdout(), "New rules kept").ok();
+    } else {
+        pending.rollback()?;
+        // The prompt is still waiting for an answer after a timeout
+        let newline = if answer.is_err() { "\n" } else { "" };
+        writeln!(io::stderr(), "{newline}Rolled back to the previous ruleset").ok();
+    }
+
+    Ok(())
+}
+
+// Reads the queue of the rules file without starting the interface, printing
+// what became of the queued packets every interval and when stopped
+pub async fn queue(rules_file: &str, interval: Option<Duration>) -> Result<()> {
+    let rules = rules::load(rules_file)?;
+    if !rules.uses_queue() {
+        eprintln!("No rule of {rules_file} has the queue verdict");
+    }
+
+    let settings = rules.queue_settings();
+    let stats = spawn_queue_worker(settings)?;
+    eprintln!(
+        "Reading netfilter queue {}, stop with Ctrl-C",
+        settings.number
+    );
+
+    let mut interrupt = signal(SignalKind::interrupt()).context("Unable to handle SIGINT")?;
+    let mut terminate = signal(SignalKind::terminate()).context("Unable to handle SIGTERM")?;
+    // Without an interval the stats are only printed when stopping
+    let mut ticks = interval
+        .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+
+    loop {
+        tokio::select! {
+            _ = tick(&mut ticks) => println!("{stats}"),
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+    println!("{stats}");
+
+    Ok(())
+}
+
+async fn tick(ticks: &mut Option<tokio::time::Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn spawn_queue_worker(settings: QueueSettings) -> Result<Arc<QueueStats>> {
+    let decider = netlink::Always(settings.decision.into());
+    netlink::spawn_queue_worker(settings, decider)
+        .with_context(|| format!("Unable to read netfilter queue {}", settings.number))
+}
+
+// Queued packets wait for a worker, which only runs along with the interface
+// or the queue command
+fn warn_unread_queue(rules: &RulesFile) {
+    let settings = rules.queue_settings();
+    if rules.uses_queue() && !settings.fail_open {
+        eprintln!(
+            "warning: packets sent to queue {} are dropped until firewall-rs or firewall-rs queue reads it",
+            settings.number
+        );
+    }
+}
+
+// Prints the changes applying the rules file would make to the active ruleset
+pub fn diff(rules_file: &str) -> Result<()> {
+    let rules = rules::load(rules_file)?;
+    let diff = netlink::diff_ruleset(&rules)?;
+
+    if diff.is_empty() {
+        println!("The active ruleset already matches {rules_file}");
+    } else {
+        print!("{diff}");
+    }
+
+    Ok(())
+}
+
+// Prints every problem found in the rules file, only errors make it fail
+pub fn validate(rules_file: &str) -> Result<()> {
+    let findings = rules::lint_file(rules_file)?;
+    for finding in &findings {
+        println!("{finding}");
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("{rules_file} has {errors} error(s)");
+    }
+    if findings.is_empty() {
+        println!("{rules_file}: no problems found");
+    }
+
+    Ok(())
+}
+
+// Writes the active ruleset to rules_file, reporting the rules that can't be
+// expressed in a rules file
+pub fn import(rules_file: &str, force: bool) -> Result<()> {
+    let path = Path::new(rules_file);
+    if path.exists() && !force {
+        bail!("{rules_file} already exists, use --force to overwrite it");
+    }
+
+    let import = netlink::import_ruleset()?;
+    for skipped in &import.skipped {
+        eprintln!("skipped {skipped}");
+    }
+
+    let contents = rules::to_toml(&import.rules)?;
+    // Make sure the written file loads back to the same rules
+    rules::parse(path, &contents).context("Imported rules don't form a valid rules file")?;
+    fs::write(path, contents).with_context(|| format!("Unable to write {rules_file}"))?;
+
+    println!(
+        "Imported {} of {} rules into {rules_file}",
+        import.rules.rules.len(),
+        import.total
+    );
+
+    Ok(())
+}
+
+// Prints the tracked connections matching the filter like `conntrack -L`
+pub fn conntrack_list(filter: &[String]) -> Result<()> {
+    let filter = netlink::ConntrackFilter::parse(filter)?;
+    let connections: Vec<_> = netlink::list_connections()?
+        .into_iter()
+        .filter(|c| filter.matches(c))
+        .collect();
+
+    for connection in &connections {
+        println!("{connection}");
+    }
+    eprintln!("{} connections listed", connections.len());
+
+    Ok(())
+}
+
+pub fn conntrack_delete(filter: &[String]) -> Result<()> {
+    let filter = netlink::ConntrackFilter::parse(filter)?;
+    let connections: Vec<_> = netlink::list_connections()?
+        .into_iter()
+        .filter(|c| filter.matches(c))
+        .collect();
+
+    netlink::delete_connections(&connections)?;
+    for connection in &connections {
+        println!("{connection}");
+    }
+    eprintln!("{} connections deleted", connections.len());
+
+    Ok(())
+}
+
+pub fn set_list(family: Option<&str>, table: Option<&str>, set: Option<&str>) -> Result<()> {
+    let family = family.map(parse_family).transpose()?;
+    let ruleset = netlink::Ruleset::read()?;
+
+    let mut listed = 0;
+    let tables = ruleset
+        .tables
+        .iter()
+        .filter(|t| family.is_none_or(|family| t.family == family))
+        .filter(|t| table.is_none_or(|table| t.name == table));
+    for t in tables {
+        for s in t
+            .sets
+            .iter()
+            .filter(|s| set.is_none_or(|set| s.name == set))
+        {
+            let mut header = format!(
+                "{} {} {} {} type {}",
+                if s.is_map() { "map" } else { "set" },
+                netlink::family_name(t.family),
+                t.name,
+                s.name,
+                s.type_text()
+            );
+            let flags = s.flags_text();
+            if !flags.is_empty() {
+                header.push_str(&format!(" flags {}", flags.join(",")));
+            }
+            if let Some(timeout) = s.timeout {
+                header.push_str(&format!(" timeout {timeout}s"));
+            }
+
+            println!("{header}");
+            for element in &s.elements {
+                println!("\t{}", netlink::element_text(element));
+            }
+            listed += 1;
+        }
+    }
+
+    if listed == 0
+        && let Some(set) = set
+    {
+        bail!("No set named {set} was found");
+    }
+    eprintln!("{listed} sets and maps listed");
+
+    Ok(())
+}
+
+pub fn set_add(
+    family: Option<&str>,
+    table: &str,
+    set: &str,
+    elements: &[String],
+    timeout: Option<u32>,
+) -> Result<()> {
+    let family = family.map_or(Ok(NfFamily::INet), parse_family)?;
+    let elements = elements
+        .iter()
+        .map(|e| netlink::parse_element(e, timeout))
+        .collect::<netlink::Result<Vec<_>>>()?;
+
+    netlink::add_elements(family, table, set, &elements)?;
+    eprintln!("{} elements added to {set}", elements.len());
+
+    Ok(())
+}
+
+pub fn set_delete(family: Option<&str>, table: &str, set: &str, elements: &[String]) -> Result<()> {
+    let family = family.map_or(Ok(NfFamily::INet), parse_family)?;
+    let elements = elements
+        .iter()
+        .map(|e| netlink::parse_element(e, None))
+        .collect::<netlink::Result<Vec<_>>>()?;
+
+    netlink::delete_elements(family, table, set, &elements)?;
+    eprintln!("{} elements deleted from {set}", elements.len());
+
+    Ok(())
+}
+
+fn parse_family(name: &str) -> Result<NfFamily> {
+    netlink::parse_family(name).ok_or_else(|| {
+        anyhow!("Unknown family {name}, expected ip, ip6, inet, arp, bridge or netdev")
+    })
+}
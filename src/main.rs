@@ -1,13 +1,119 @@
 use anyhow::{Result, anyhow};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use cli_log::*;
-use firewall_rs::cli;
-use serde::{Deserialize, Serialize};
+use firewall_rs::{cli, netlink::ScriptFormat};
+use std::time::Duration;
 
-#[derive(Parser, Serialize, Deserialize)]
+#[derive(Parser)]
 struct Config {
-    #[arg(short)]
-    rules_file: String,
+    #[arg(short, global = true)]
+    rules_file: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply the rules file without starting the interface
+    Apply {
+        /// Print the generated ruleset instead of applying it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Output format used with --dry-run
+        #[arg(long, value_enum, default_value_t)]
+        format: ScriptFormat,
+
+        /// Roll back to the previous ruleset unless the new rules are
+        /// confirmed within this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        confirm_timeout: Option<u64>,
+    },
+    /// Give queued packets the decision of the rules file without starting
+    /// the interface
+    Queue {
+        /// Print the number of packets handled every this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        interval: Option<u64>,
+    },
+    /// Show what applying the rules file would change in the active ruleset
+    Diff,
+    /// Check the rules file for invalid, redundant and conflicting entries
+    Validate,
+    /// Write the active ruleset to the rules file, skipping what it can't express
+    Import {
+        /// Overwrite the rules file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show or delete entries of the connection tracking table
+    Conntrack {
+        #[command(subcommand)]
+        command: ConntrackCommand,
+    },
+    /// List named sets and maps, or add and delete their elements
+    Set {
+        #[command(subcommand)]
+        command: SetCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConntrackCommand {
+    /// List the tracked connections matching every filter term
+    List {
+        /// Protocols, tcp states, assured, unreplied, mark=N, ports,
+        /// addresses or networks
+        filter: Vec<String>,
+    },
+    /// Delete the tracked connections matching every filter term
+    Delete {
+        /// Protocols, tcp states, assured, unreplied, mark=N, ports,
+        /// addresses or networks
+        #[arg(required = true)]
+        filter: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SetCommand {
+    /// List the sets and maps of every table, of one table or a single one
+    List {
+        table: Option<String>,
+        set: Option<String>,
+
+        /// Only list tables of this family
+        #[arg(long)]
+        family: Option<String>,
+    },
+    /// Add elements to a set or map, map elements are written "key : value"
+    Add {
+        table: String,
+        set: String,
+        #[arg(required = true)]
+        elements: Vec<String>,
+
+        /// Family of the table, inet when not given
+        #[arg(long)]
+        family: Option<String>,
+
+        /// Drop the elements after this many seconds, the set needs the
+        /// timeout flag
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<u32>,
+    },
+    /// Delete elements from a set or map by their key
+    Del {
+        table: String,
+        set: String,
+        #[arg(required = true)]
+        elements: Vec<String>,
+
+        /// Family of the table, inet when not given
+        #[arg(long)]
+        family: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -15,13 +121,64 @@ async fn main() -> Result<()> {
     init_cli_log!();
     debug!("Starting CLI");
 
-    match cli::run().await {
+    let config = Config::parse();
+    // Only the conntrack and set commands work without a rules file
+    let rules_file = match &config.command {
+        Some(Command::Conntrack { .. } | Command::Set { .. }) => {
+            config.rules_file.unwrap_or_default()
+        }
+        _ => config
+            .rules_file
+            .ok_or_else(|| anyhow!("A rules file must be given with -r <RULES_FILE>"))?,
+    };
+
+    let res = match config.command {
+        Some(Command::Apply {
+            dry_run,
+            format,
+            confirm_timeout,
+        }) => {
+            let confirm_timeout = confirm_timeout.map(Duration::from_secs);
+            cli::apply(&rules_file, dry_run, format, confirm_timeout).await
+        }
+        Some(Command::Queue { interval }) => {
+            cli::queue(&rules_file, interval.map(Duration::from_secs)).await
+        }
+        Some(Command::Diff) => cli::diff(&rules_file),
+        Some(Command::Validate) => cli::validate(&rules_file),
+        Some(Command::Import { force }) => cli::import(&rules_file, force),
+        Some(Command::Conntrack { command }) => match command {
+            ConntrackCommand::List { filter } => cli::conntrack_list(&filter),
+            ConntrackCommand::Delete { filter } => cli::conntrack_delete(&filter),
+        },
+        Some(Command::Set { command }) => match command {
+            SetCommand::List { table, set, family } => {
+                cli::set_list(family.as_deref(), table.as_deref(), set.as_deref())
+            }
+            SetCommand::Add {
+                table,
+                set,
+                elements,
+                family,
+                timeout,
+            } => cli::set_add(family.as_deref(), &table, &set, &elements, timeout),
+            SetCommand::Del {
+                table,
+                set,
+                elements,
+                family,
+            } => cli::set_delete(family.as_deref(), &table, &set, &elements),
+        },
+        None => cli::run(rules_file).await,
+    };
+
+    match res {
         Ok(()) => {
             debug!("CLI exited");
         }
         Err(e) => {
-            error!("Error running CLI: {e}");
-            return Err(anyhow!("Error running app: {e}"));
+            error!("Error running CLI: {e:#}");
+            return Err(anyhow!("Error running app: {e:#}"));
         }
     };
 
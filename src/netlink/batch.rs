@@ -0,0 +1,529 @@
+use super::{Error, Result};
+
+use cli_log::debug;
+use nftnl::{Batch, FinalizedBatch, MsgType, NlMsg};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    ffi::{CStr, c_void},
+    io,
+    os::fd::AsRawFd,
+};
+
+pub(super) const NLMSG_HDRLEN: usize = 16;
+// Size of the nfgenmsg header in front of the attributes of netfilter messages
+pub(super) const NFGENMSG_LEN: usize = 4;
+const NLA_HDRLEN: usize = 4;
+// Human readable message attribute of extended ACKs, missing from libc
+const NLMSGERR_ATTR_MSG: u16 = 1;
+
+// A batch remembering which object each of its messages is about, so the
+// errors the kernel sends back can say what it rejected
+pub struct TrackedBatch {
+    batch: Batch,
+    objects: BTreeMap<u32, String>,
+}
+
+impl TrackedBatch {
+    pub fn new() -> Self {
+        Self {
+            batch: Batch::new(),
+            objects: BTreeMap::new(),
+        }
+    }
+
+    // Every message asks for an ACK, nftnl leaves it out for some of them
+    pub fn add<T: NlMsg>(&mut self, msg: &T, msg_type: MsgType, object: impl Into<String>) {
+        let msg = Acked {
+            msg,
+            seq: Cell::new(0),
+        };
+        self.batch.add(&msg, msg_type);
+        self.objects.insert(msg.seq.get(), object.into());
+    }
+}
+
+impl Default for TrackedBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Acked<'a, T> {
+    msg: &'a T,
+    seq: Cell<u32>,
+}
+
+unsafe impl<T: NlMsg> NlMsg for Acked<'_, T> {
+    unsafe fn write(&self, buf: *mut c_void, seq: u32, msg_type: MsgType) {
+        unsafe {
+            self.msg.write(buf, seq, msg_type);
+            let header = buf as *mut libc::nlmsghdr;
+            (*header).nlmsg_flags |= libc::NLM_F_ACK as u16;
+        }
+        self.seq.set(seq);
+    }
+}
+
+// Reply of the kernel to one message of the batch
+struct Ack {
+    seq: u32,
+    errno: i32,
+    // Extended ACK message explaining the error
+    message: Option<String>,
+}
+
+// Sends the batch and waits for the ACK of every message. The kernel rejects
+// the whole batch if any message fails, the first failure is returned.
+pub fn send_and_process_batch(batch: TrackedBatch) -> Result<()> {
+    let TrackedBatch { batch, objects } = batch;
+    let batch = batch.finalize();
+
+    let netlink = |e| Error::from_io("netfilter socket", e);
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(netlink)?;
+    enable_extended_acks(&socket);
+    reserve_acks(&socket, objects.len());
+
+    send_batch(&socket, &batch).map_err(netlink)?;
+
+    let mut pending = objects.len();
+    let mut failed = vec![];
+    let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+    // The kernel handles the batch while it is sent, every reply is already
+    // queued once sendmsg returns. Anything missing was lost.
+    while pending > 0 {
+        if !has_replies(&socket).map_err(netlink)? {
+            return Err(Error::rejected(
+                "batch",
+                format!("no reply to {pending} of its messages"),
+            ));
+        }
+        let len = socket.recv(&mut buffer).map_err(netlink)?;
+        for ack in parse_acks(&buffer[..len]) {
+            pending = pending.saturating_sub(1);
+            if ack.errno != 0 {
+                failed.push(ack);
+            }
+        }
+    }
+
+    for ack in &failed {
+        debug!(
+            "Kernel rejected message {} ({}): errno {} {:?}",
+            ack.seq,
+            objects.get(&ack.seq).map_or("batch", String::as_str),
+            ack.errno,
+            ack.message
+        );
+    }
+
+    match failed.iter().min_by_key(|ack| ack.seq) {
+        Some(ack) => {
+            let object = objects.get(&ack.seq).map_or("batch", String::as_str);
+            Err(ack_error(object, ack))
+        }
+        None => Ok(()),
+    }
+}
+
+// Like nft, every page of the batch goes out in a single sendmsg so the
+// kernel gets the begin and end messages of the batch together
+fn send_batch(socket: &mnl::Socket, batch: &FinalizedBatch) -> io::Result<()> {
+    let mut iovecs: Vec<libc::iovec> = batch
+        .iter()
+        .map(|page| libc::iovec {
+            iov_base: page.as_ptr() as *mut c_void,
+            iov_len: page.len(),
+        })
+        .collect();
+    let len: usize = iovecs.iter().map(|iovec| iovec.iov_len).sum();
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_nl as *mut c_void;
+    msg.msg_namelen = size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+    msg.msg_iov = iovecs.as_mut_ptr();
+    msg.msg_iovlen = iovecs.len();
+
+    match unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) } {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        ret if ret as usize != len => Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!("only {ret} of {len} bytes of the batch were sent"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// nlmsghdr and nfgenmsg of a netfilter message sent on its own, the length
+// is filled in by finish_msg once the attributes are added
+pub(super) fn nfnl_msg(
+    msg_type: libc::c_int,
+    flags: libc::c_int,
+    family: u8,
+    res_id: u16,
+) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(64);
+    msg.extend(0u32.to_ne_bytes());
+    msg.extend((msg_type as u16).to_ne_bytes());
+    msg.extend((flags as u16).to_ne_bytes());
+    // Sequence number and port id
+    msg.extend(0u32.to_ne_bytes());
+    msg.extend(0u32.to_ne_bytes());
+
+    msg.push(family);
+    msg.push(libc::NFNETLINK_V0 as u8);
+    msg.extend(res_id.to_be_bytes());
+
+    msg
+}
+
+pub(super) fn push_attr(msg: &mut Vec<u8>, kind: u16, value: &[u8]) {
+    let len = (NLA_HDRLEN + value.len()) as u16;
+    msg.extend(len.to_ne_bytes());
+    msg.extend(kind.to_ne_bytes());
+    msg.extend(value);
+    msg.resize(align(msg.len()), 0);
+}
+
+pub(super) fn finish_msg(msg: &mut [u8]) {
+    let len = msg.len() as u32;
+    msg[..4].copy_from_slice(&len.to_ne_bytes());
+}
+
+// Waits for the ACK of a single message sent on its own, before anything
+// else is received on the socket
+pub(super) fn recv_ack(socket: &mnl::Socket, object: &str) -> Result<()> {
+    let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+    loop {
+        let len = socket
+            .recv(&mut buffer)
+            .map_err(|e| Error::from_io(object, e))?;
+        if let Some(ack) = parse_acks(&buffer[..len]).first() {
+            return match ack.errno {
+                0 => Ok(()),
+                _ => Err(ack_error(object, ack)),
+            };
+        }
+    }
+}
+
+fn ack_error(object: &str, ack: &Ack) -> Error {
+    match Error::from_io(object, io::Error::from_raw_os_error(ack.errno)) {
+        Error::Rejected { object, reason } => Error::Rejected {
+            object,
+            reason: match &ack.message {
+                Some(message) => format!("{reason}, {message}"),
+                None => reason,
+            },
+        },
+        e => e,
+    }
+}
+
+// Older kernels don't support extended ACKs, errors are then reported
+// without a message
+fn enable_extended_acks(socket: &mnl::Socket) {
+    for option in [libc::NETLINK_EXT_ACK, libc::NETLINK_CAP_ACK] {
+        if let Err(e) = set_option(socket, option, 1) {
+            debug!("Unable to set netlink option {option}: {e}");
+        }
+    }
+}
+
+// Every message of the batch is ACKed and the ACKs are only read once the
+// whole batch is sent. Like nft, the receive buffer is grown to hold them,
+// with the default one a large batch fails with ENOBUFS.
+fn reserve_acks(socket: &mnl::Socket, messages: usize) {
+    const ACK_SIZE: usize = 1024;
+
+    let size = libc::c_int::try_from(messages.saturating_mul(ACK_SIZE)).unwrap_or(libc::c_int::MAX);
+    let current = socket_option(socket, libc::SOL_SOCKET, libc::SO_RCVBUF).unwrap_or_default();
+    if size <= current {
+        return;
+    }
+
+    // Only root can go above net.core.rmem_max
+    let result = set_socket_option(socket, libc::SOL_SOCKET, libc::SO_RCVBUFFORCE, size)
+        .or_else(|_| set_socket_option(socket, libc::SOL_SOCKET, libc::SO_RCVBUF, size));
+    if let Err(e) = result {
+        debug!("Unable to grow the receive buffer to {size} bytes: {e}");
+    }
+}
+
+pub(super) fn set_option(
+    socket: &mnl::Socket,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    set_socket_option(socket, libc::SOL_NETLINK, option, value)
+}
+
+fn set_socket_option(
+    socket: &mnl::Socket,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    match ret {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn socket_option(
+    socket: &mnl::Socket,
+    level: libc::c_int,
+    option: libc::c_int,
+) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &mut value as *mut libc::c_int as *mut c_void,
+            &mut len,
+        )
+    };
+
+    match ret {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        _ => Ok(value),
+    }
+}
+
+pub(super) fn has_replies(socket: &mnl::Socket) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    match unsafe { libc::poll(&mut fd, 1, 0) } {
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        ret => Ok(ret > 0),
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub(super) fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub(super) fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+// NLMSG_ERROR replies hold the error, the header of the message they are for
+// and, with extended ACKs, attributes after the (capped) original message
+fn parse_acks(buf: &[u8]) -> Vec<Ack> {
+    let mut acks = vec![];
+
+    for msg in messages(buf) {
+        let msg_type = read_u16(msg, 4);
+        let flags = read_u16(msg, 6) as libc::c_int;
+        if msg_type != libc::NLMSG_ERROR as u16 || msg.len() < NLMSG_HDRLEN * 2 + 4 {
+            continue;
+        }
+
+        let error = read_u32(msg, NLMSG_HDRLEN) as i32;
+        let original = &msg[NLMSG_HDRLEN + 4..];
+        let seq = read_u32(original, 8);
+
+        let mut message = None;
+        if flags & libc::NLM_F_ACK_TLVS != 0 {
+            let attrs = match flags & libc::NLM_F_CAPPED {
+                0 => align(read_u32(original, 0) as usize),
+                _ => NLMSG_HDRLEN,
+            };
+            message = original.get(attrs..).and_then(ack_message);
+        }
+
+        acks.push(Ack {
+            seq,
+            errno: -error,
+            message,
+        });
+    }
+
+    acks
+}
+
+fn ack_message(attrs: &[u8]) -> Option<String> {
+    attributes(attrs)
+        .find(|(kind, _)| *kind == NLMSGERR_ATTR_MSG)
+        .and_then(|(_, value)| attr_string(value))
+}
+
+// Netlink messages received in a single read, including their header
+pub(super) fn messages(mut buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if buf.len() < NLMSG_HDRLEN {
+            return None;
+        }
+        let len = read_u32(buf, 0) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return None;
+        }
+
+        let msg = &buf[..len];
+        buf = &buf[align(len).min(buf.len())..];
+
+        Some(msg)
+    })
+}
+
+// Netlink attributes as (type, value) pairs
+pub(super) fn attributes(mut attrs: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if attrs.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = read_u16(attrs, 0) as usize;
+        if len < NLA_HDRLEN || len > attrs.len() {
+            return None;
+        }
+
+        // The top bits are flags
+        let attr = (read_u16(attrs, 2) & 0x3fff, &attrs[NLA_HDRLEN..len]);
+        attrs = &attrs[align(len).min(attrs.len())..];
+
+        Some(attr)
+    })
+}
+
+pub(super) fn attr_string(value: &[u8]) -> Option<String> {
+    CStr::from_bytes_until_nul(value)
+        .ok()
+        .map(|s| s.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(len: usize, msg_type: u16, flags: libc::c_int, seq: u32) -> Vec<u8> {
+        let mut msg = vec![];
+        msg.extend((len as u32).to_ne_bytes());
+        msg.extend(msg_type.to_ne_bytes());
+        msg.extend((flags as u16).to_ne_bytes());
+        msg.extend(seq.to_ne_bytes());
+        msg.extend(0u32.to_ne_bytes());
+        msg
+    }
+
+    // NLMSG_ERROR reply to a message with a 6 byte payload. Capped replies
+    // only echo its header.
+    fn ack(seq: u32, errno: i32, capped: bool, message: Option<&str>) -> Vec<u8> {
+        let payload = [1u8, 2, 3, 4, 5, 6];
+        let mut original = header(NLMSG_HDRLEN + payload.len(), 0x0a06, 0, seq);
+        if !capped {
+            original.extend(payload);
+            original.resize(align(original.len()), 0);
+        }
+
+        let mut flags = 0;
+        if capped {
+            flags |= libc::NLM_F_CAPPED;
+        }
+        let mut attrs = vec![];
+        if let Some(message) = message {
+            flags |= libc::NLM_F_ACK_TLVS;
+            push_attr(
+                &mut attrs,
+                NLMSGERR_ATTR_MSG,
+                format!("{message}\0").as_bytes(),
+            );
+        }
+
+        let len = NLMSG_HDRLEN + 4 + original.len() + attrs.len();
+        let mut msg = header(len, libc::NLMSG_ERROR as u16, flags, seq);
+        msg.extend((-errno).to_ne_bytes());
+        msg.extend(original);
+        msg.extend(attrs);
+        msg
+    }
+
+    #[test]
+    fn acks_of_several_messages() {
+        let mut buf = ack(3, 0, true, None);
+        buf.extend(ack(4, libc::ENOENT, false, None));
+        // Anything but errors is skipped
+        buf.extend(header(NLMSG_HDRLEN, libc::NLMSG_DONE as u16, 0, 5));
+
+        let acks: Vec<_> = parse_acks(&buf)
+            .into_iter()
+            .map(|ack| (ack.seq, ack.errno, ack.message))
+            .collect();
+        assert_eq!(acks, [(3, 0, None), (4, libc::ENOENT, None)]);
+    }
+
+    #[test]
+    fn extended_ack_messages() {
+        for capped in [true, false] {
+            let buf = ack(7, libc::EEXIST, capped, Some("Chain already exists"));
+            let acks = parse_acks(&buf);
+            assert_eq!(acks.len(), 1);
+            assert_eq!((acks[0].seq, acks[0].errno), (7, libc::EEXIST));
+            assert_eq!(
+                acks[0].message.as_deref(),
+                Some("Chain already exists"),
+                "capped: {capped}"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_left_out() {
+        let mut buf = ack(1, 0, true, None);
+        let whole = buf.len();
+        buf.extend(&ack(2, 0, true, None)[..20]);
+
+        assert_eq!(messages(&buf).map(<[u8]>::len).collect::<Vec<_>>(), [whole]);
+        assert_eq!(parse_acks(&buf).len(), 1);
+    }
+
+    #[test]
+    fn attributes_are_aligned() {
+        let mut attrs = vec![];
+        push_attr(&mut attrs, 1, b"eth0\0");
+        push_attr(&mut attrs, 2 | 0x8000, &7u32.to_be_bytes());
+        // Too short to hold its header, ignored
+        attrs.extend([3, 0]);
+
+        let parsed: Vec<_> = attributes(&attrs).collect();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].0, 1);
+        assert_eq!(attr_string(parsed[0].1).as_deref(), Some("eth0"));
+        // The nested flag is dropped from the type
+        assert_eq!(parsed[1], (2, &7u32.to_be_bytes()[..]));
+    }
+
+    #[test]
+    fn netfilter_messages() {
+        let mut msg = nfnl_msg(0x0a00, libc::NLM_F_ACK, libc::NFPROTO_INET as u8, 0);
+        push_attr(&mut msg, 1, b"firewall-rs\0");
+        finish_msg(&mut msg);
+
+        let parsed: Vec<_> = messages(&msg).collect();
+        assert_eq!(parsed, [&msg[..]]);
+        let attrs: Vec<_> = attributes(&msg[NLMSG_HDRLEN + NFGENMSG_LEN..]).collect();
+        assert_eq!(attrs, [(1, &b"firewall-rs\0"[..])]);
+    }
+}
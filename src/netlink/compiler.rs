@@ -0,0 +1,1235 @@
+use super::{
+    Diff, Result, TrackedBatch, diff_ruleset,
+    expr::{Limit as LimitExpr, Log, Lookup, Meter as MeterExpr, Queue, Quota as QuotaExpr, Range},
+    nlmsg::{Placement, RuleMsg, SetKey, SetMsg},
+    send_and_process_batch,
+};
+use crate::rules::{
+    Direction, Limit, LogSettings, Meter, MeterKey, NatSettings, Port, Protocol, QueueSettings,
+    Quota, Rule, RulesFile, Verdict,
+};
+
+use cli_log::debug;
+use ipnet::IpNet;
+use nftnl::{
+    Chain as nftnlChain, ChainType as nftnlChainType, Hook as nftnlHook, Policy as nftnlPolicy,
+    ProtoFamily, Table as nftnlTable,
+    expr::{
+        IcmpCode, Immediate, InterfaceName, Masquerade, Nat, NatType, Register, RejectionType,
+        States, Verdict as nftnlVerdict,
+    },
+    nft_expr,
+    nftnl_sys::libc,
+};
+use std::{
+    ffi::CString,
+    net::{IpAddr, Ipv6Addr},
+    ops::RangeInclusive,
+};
+
+// Table holding every rule generated from the rules file
+pub const MANAGED_TABLE: &str = "firewall-rs";
+pub const INPUT_CHAIN: &str = "input";
+pub const OUTPUT_CHAIN: &str = "output";
+pub const FORWARD_CHAIN: &str = "forward";
+pub const PREROUTING_CHAIN: &str = "prerouting";
+pub const POSTROUTING_CHAIN: &str = "postrouting";
+
+// Base chains of the managed table, in the order they are created
+pub const MANAGED_CHAINS: [(&str, Hook); 5] = [
+    (INPUT_CHAIN, Hook::Input),
+    (OUTPUT_CHAIN, Hook::Output),
+    (FORWARD_CHAIN, Hook::Forward),
+    (PREROUTING_CHAIN, Hook::Prerouting),
+    (POSTROUTING_CHAIN, Hook::Postrouting),
+];
+
+// Where a base chain of the managed table is hooked. Prerouting and
+// postrouting are nat chains translating addresses, the others filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    Input,
+    Output,
+    Forward,
+    Prerouting,
+    Postrouting,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Input => "input",
+            Hook::Output => "output",
+            Hook::Forward => "forward",
+            Hook::Prerouting => "prerouting",
+            Hook::Postrouting => "postrouting",
+        }
+    }
+
+    pub fn is_nat(self) -> bool {
+        matches!(self, Hook::Prerouting | Hook::Postrouting)
+    }
+
+    // The usual dstnat and srcnat priorities for nat chains
+    pub fn priority(self) -> i32 {
+        match self {
+            Hook::Prerouting => -100,
+            Hook::Postrouting => 100,
+            _ => 0,
+        }
+    }
+}
+
+// Start of the prefix of packets logged by the managed table, followed by
+// the name of the rule
+pub const LOG_PREFIX: &str = "firewall-rs: ";
+// Longest log prefix the kernel takes
+const LOG_PREFIX_LEN: usize = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+// Addresses of one family matched by a rule, several of them are looked up
+// in one of the named sets of the table
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddrMatch {
+    Net(IpNet),
+    Set(String),
+}
+
+// Named interval set of the managed table holding the merged networks of an
+// address list. Rules listing the same networks share a set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddrSet {
+    pub name: String,
+    pub family: Family,
+    pub nets: Vec<IpNet>,
+}
+
+// A single nftables rule. Rules from the rules file can list addresses from
+// both families, which are split into one nftables rule per family. Ports
+// and addresses are kept together and matched with a range or a set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledRule {
+    // Name of the rule in the rules file
+    pub name: String,
+    pub chain: &'static str,
+    pub iifname: Option<String>,
+    pub oifname: Option<String>,
+    pub family: Option<Family>,
+    pub protocol: Option<Protocol>,
+    pub source: Option<AddrMatch>,
+    pub destination: Option<AddrMatch>,
+    // Sorted ranges that neither overlap nor touch, empty matching any port
+    pub sports: Vec<RangeInclusive<u16>>,
+    pub dports: Vec<RangeInclusive<u16>>,
+    // Every nftables rule of a rule has its own limit, quota and meter
+    pub limit: Option<Limit>,
+    pub quota: Option<Quota>,
+    pub meter: Option<CompiledMeter>,
+    pub log: Option<LogTarget>,
+    pub verdict: Verdict,
+    // Queue the packets go to with the queue verdict
+    pub queue: Option<QueueSettings>,
+    // Address translation done by rules of the nat chains
+    pub nat: Option<NatTarget>,
+    pub comment: String,
+}
+
+// Meter of a rule along with the name of the set keeping its addresses
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledMeter {
+    pub name: String,
+    pub meter: Meter,
+}
+
+impl CompiledMeter {
+    // Addresses are forgotten once they were quiet for a whole period of
+    // the rate
+    pub fn timeout_secs(&self) -> u64 {
+        self.meter.rate.per.seconds()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NatTarget {
+    // The address of the interface the packet leaves through
+    Masquerade,
+    Snat(IpAddr),
+    // Without a port the destination port is kept
+    Dnat { addr: IpAddr, port: Option<u16> },
+}
+
+// Where a rule logs the packets it matches, the prefix tells which rule
+// fired
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogTarget {
+    pub group: u16,
+    pub prefix: String,
+}
+
+impl LogTarget {
+    fn new(rule: &Rule, settings: LogSettings) -> Self {
+        let mut prefix = match &rule.log_prefix {
+            Some(prefix) => prefix.clone(),
+            None => format!("{LOG_PREFIX}{}", rule.name),
+        };
+        if prefix.len() > LOG_PREFIX_LEN {
+            let end = (0..=LOG_PREFIX_LEN)
+                .rev()
+                .find(|i| prefix.is_char_boundary(*i))
+                .unwrap_or_default();
+            prefix.truncate(end);
+        }
+
+        Self {
+            group: settings.group,
+            prefix,
+        }
+    }
+}
+
+// Contents of the managed table besides its base chains
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Compiled {
+    pub sets: Vec<AddrSet>,
+    pub rules: Vec<CompiledRule>,
+}
+
+impl Compiled {
+    // Base chains of the table, nat chains are only created when something
+    // is translated
+    pub fn chains(&self) -> impl Iterator<Item = (&'static str, Hook)> + '_ {
+        MANAGED_CHAINS.into_iter().filter(|(name, hook)| {
+            !hook.is_nat() || self.rules.iter().any(|rule| rule.chain == *name)
+        })
+    }
+}
+
+// Expands the rules file into the sets and nftables rules making up the
+// managed table, with the rules in the order they appear in the file
+pub fn compile(rules: &RulesFile) -> Compiled {
+    let mut compiled = Compiled::default();
+
+    for rule in &rules.rules {
+        let mut expanded = expand(
+            rule,
+            rules.queue_settings(),
+            rules.log_settings(),
+            &mut compiled.sets,
+        );
+        if let Some(meter) = rule.meter {
+            add_meters(&compiled, &rule.name, meter, &mut expanded);
+        }
+        debug!("Rule \"{}\" expands to {} rules", rule.name, expanded.len());
+        compiled.rules.extend(expanded);
+    }
+    if let Some(nat) = &rules.nat {
+        let expanded = expand_nat(nat, &mut compiled.sets);
+        debug!("The nat section expands to {} rules", expanded.len());
+        compiled.rules.extend(expanded);
+    }
+
+    compiled
+}
+
+// Builds a batch that atomically replaces the managed table with the rules
+// from the rules file
+pub fn build_batch(rules: &RulesFile) -> TrackedBatch {
+    table_batch(&compile(rules))
+}
+
+pub(super) fn table_batch(compiled: &Compiled) -> TrackedBatch {
+    let mut batch = TrackedBatch::new();
+
+    let table = managed_table();
+    let table_name = format!("table inet {MANAGED_TABLE}");
+
+    // Adding the table before deleting it makes sure the delete does not fail
+    // when the table doesn't exist yet, the new table is then created from scratch
+    batch.add(&table, nftnl::MsgType::Add, &table_name);
+    batch.add(&table, nftnl::MsgType::Del, &table_name);
+    batch.add(&table, nftnl::MsgType::Add, &table_name);
+
+    let chains: Vec<nftnlChain> = compiled
+        .chains()
+        .map(|(name, hook)| base_chain(&table, name, hook))
+        .collect();
+    for (chain, (name, _)) in chains.iter().zip(compiled.chains()) {
+        batch.add(chain, nftnl::MsgType::Add, format!("chain {name}"));
+    }
+
+    let mut set_id = 0;
+    let addr_sets = addr_sets(&table, &compiled.sets, &mut set_id);
+    for (set, compiled_set) in addr_sets.iter().zip(&compiled.sets) {
+        let set_name = format!("set {}", compiled_set.name);
+        batch.add(set, nftnl::MsgType::Add, &set_name);
+        for elems in set.elems() {
+            batch.add(
+                &elems,
+                nftnl::MsgType::Add,
+                format!("elements of {set_name}"),
+            );
+        }
+    }
+
+    for rule in &compiled.rules {
+        let chain = chains
+            .iter()
+            .find(|c| c.get_name().to_str() == Ok(rule.chain))
+            .expect("compiled rules only use the managed chains");
+        add_rule(
+            &mut batch,
+            chain,
+            rule,
+            AddrSets::Named(&addr_sets),
+            &mut set_id,
+            Placement::Append,
+        );
+    }
+
+    batch
+}
+
+pub(super) fn managed_table() -> nftnlTable {
+    nftnlTable::new(&CString::new(MANAGED_TABLE).unwrap(), ProtoFamily::Inet)
+}
+
+// Messages for the named sets, numbered after set_id
+pub(super) fn addr_sets(table: &nftnlTable, sets: &[AddrSet], set_id: &mut u32) -> Vec<SetMsg> {
+    sets.iter()
+        .map(|set| {
+            *set_id += 1;
+            let key = addr_key(set.family);
+            addr_set(SetMsg::named(table, &set.name, *set_id, key), set)
+        })
+        .collect()
+}
+
+// Where the address lists of a rule that don't fit a single network are
+// looked up
+pub(super) enum AddrSets<'a> {
+    // Named sets of the managed table, either part of the batch or already
+    // created
+    Named(&'a [SetMsg]),
+    // Sets compiled along with the rule, added as anonymous sets of the rule
+    // since other tables don't hold the named ones
+    Anonymous(&'a [AddrSet]),
+}
+
+// Adds the rule to the batch along with the anonymous sets it looks up
+pub(super) fn add_rule(
+    batch: &mut TrackedBatch,
+    chain: &nftnlChain,
+    rule: &CompiledRule,
+    addr_sets: AddrSets,
+    set_id: &mut u32,
+    placement: Placement,
+) {
+    let table = chain.get_table();
+    let mut next_id = || {
+        *set_id += 1;
+        *set_id
+    };
+
+    // Sets have to be complete before the rule looking them up is added
+    let sport_set = (rule.sports.len() > 1).then(|| port_set(table, next_id(), &rule.sports));
+    let dport_set = (rule.dports.len() > 1).then(|| port_set(table, next_id(), &rule.dports));
+    let mut addr_set = |addr: &Option<AddrMatch>| match (addr, &addr_sets) {
+        (Some(AddrMatch::Set(name)), AddrSets::Anonymous(sets)) => {
+            let set = sets
+                .iter()
+                .find(|s| s.name == *name)
+                .expect("address sets are compiled along with the rule");
+            let msg = SetMsg::anonymous(table, next_id(), addr_key(set.family));
+            Some(addr_set(msg, set))
+        }
+        _ => None,
+    };
+    let saddr_set = addr_set(&rule.source);
+    let daddr_set = addr_set(&rule.destination);
+    let meter_set = rule.meter.as_ref().map(|meter| {
+        let family = rule.family.expect("rules with a meter are split by family");
+        let timeout_ms = meter.timeout_secs() * 1000;
+        SetMsg::meter(table, &meter.name, next_id(), addr_key(family), timeout_ms)
+    });
+
+    let rule_name = format!(
+        "rule \"{}\" in chain {}",
+        rule.name,
+        chain.get_name().to_string_lossy()
+    );
+    for (set, kind) in [
+        (&saddr_set, "saddrs"),
+        (&daddr_set, "daddrs"),
+        (&sport_set, "sports"),
+        (&dport_set, "dports"),
+        (&meter_set, "meter"),
+    ] {
+        let Some(set) = set else {
+            continue;
+        };
+        let set_name = format!("{kind} set of {rule_name}");
+        batch.add(set, nftnl::MsgType::Add, &set_name);
+        for elems in set.elems() {
+            batch.add(
+                &elems,
+                nftnl::MsgType::Add,
+                format!("elements of {set_name}"),
+            );
+        }
+    }
+
+    let named_set = |addr: &Option<AddrMatch>| match (addr, &addr_sets) {
+        (Some(AddrMatch::Set(name)), AddrSets::Named(sets)) => {
+            sets.iter().find(|s| s.name().to_str() == Ok(name))
+        }
+        _ => None,
+    };
+    let sets = RuleSets {
+        saddrs: saddr_set.as_ref().or_else(|| named_set(&rule.source)),
+        daddrs: daddr_set.as_ref().or_else(|| named_set(&rule.destination)),
+        sports: sport_set.as_ref(),
+        dports: dport_set.as_ref(),
+        meter: meter_set.as_ref(),
+    };
+    let mut msg = build_rule(chain, rule, &sets);
+    msg.place(placement);
+    batch.add(&msg, nftnl::MsgType::Add, rule_name);
+}
+
+// Changes the managed table in place to match the rules file, returning
+// what was changed
+pub fn apply_rules(rules: &RulesFile) -> Result<Diff> {
+    debug!("Applying rules file to table {MANAGED_TABLE}");
+
+    // Without the active ruleset the table is created from scratch
+    let diff = diff_ruleset(rules).unwrap_or_else(|e| {
+        debug!("Unable to compare with the active ruleset: {e}");
+        Diff::recreate(compile(rules), None)
+    });
+    if diff.is_empty() {
+        debug!("Table {MANAGED_TABLE} is already up to date");
+        return Ok(diff);
+    }
+
+    send_and_process_batch(diff.batch())?;
+
+    Ok(diff)
+}
+
+pub fn chain_for(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Input => INPUT_CHAIN,
+        Direction::Output => OUTPUT_CHAIN,
+        Direction::Forward => FORWARD_CHAIN,
+    }
+}
+
+pub(super) fn base_chain<'a>(table: &'a nftnlTable, name: &str, hook: Hook) -> nftnlChain<'a> {
+    let (nftnl_hook, chain_type) = match hook {
+        Hook::Input => (nftnlHook::In, nftnlChainType::Filter),
+        Hook::Output => (nftnlHook::Out, nftnlChainType::Filter),
+        Hook::Forward => (nftnlHook::Forward, nftnlChainType::Filter),
+        Hook::Prerouting => (nftnlHook::PreRouting, nftnlChainType::Nat),
+        Hook::Postrouting => (nftnlHook::PostRouting, nftnlChainType::Nat),
+    };
+
+    let mut chain = nftnlChain::new(&CString::new(name).unwrap(), table);
+    chain.set_hook(nftnl_hook, hook.priority());
+    chain.set_policy(nftnlPolicy::Accept);
+    chain.set_type(chain_type);
+
+    chain
+}
+
+fn expand(
+    rule: &Rule,
+    queue: QueueSettings,
+    log: LogSettings,
+    sets: &mut Vec<AddrSet>,
+) -> Vec<CompiledRule> {
+    // The rule name is kept in the comment so rules can be traced back to
+    // the rules file
+    let comment = match &rule.comment {
+        Some(comment) => format!("{}: {comment}", rule.name),
+        None => rule.name.clone(),
+    };
+
+    let mut expansions = vec![CompiledRule {
+        name: rule.name.clone(),
+        chain: chain_for(rule.direction),
+        iifname: rule.iifname.clone(),
+        oifname: rule.oifname.clone(),
+        family: None,
+        protocol: None,
+        source: None,
+        destination: None,
+        sports: vec![],
+        dports: vec![],
+        limit: rule.limit,
+        quota: rule.quota,
+        meter: None,
+        log: rule.log.then(|| LogTarget::new(rule, log)),
+        verdict: rule.verdict,
+        queue: (rule.verdict == Verdict::Queue).then_some(queue),
+        nat: None,
+        comment,
+    }];
+
+    // Addresses from both families can be listed together, but a single
+    // nftables rule can only match one of them. Meters key on the address
+    // of one family too.
+    let has_addrs =
+        !rule.sources.is_empty() || !rule.destinations.is_empty() || rule.meter.is_some();
+    if has_addrs {
+        let families: Vec<Family> = [Family::Ipv4, Family::Ipv6]
+            .into_iter()
+            .filter(|family| {
+                let matches = |nets: &[IpNet]| {
+                    nets.is_empty() || nets.iter().any(|net| family_of(net) == *family)
+                };
+                matches(&rule.sources) && matches(&rule.destinations)
+            })
+            .collect();
+        expansions = product(expansions, &families, |e, family| CompiledRule {
+            family: Some(*family),
+            ..e.clone()
+        });
+    }
+
+    // Ports without a protocol match both tcp and udp, ports limited to one
+    // protocol are left out of the rules for the other
+    let protocols = match rule.protocols() {
+        Some(protocols) => protocols.into_iter().map(Some).collect(),
+        None => vec![None],
+    };
+    expansions = product(expansions, &protocols, |e, protocol| {
+        let ports = |ports: &[Port]| {
+            port_ranges(
+                ports
+                    .iter()
+                    .filter(|p| protocol.is_none_or(|protocol| p.applies_to(protocol))),
+            )
+        };
+        CompiledRule {
+            protocol: *protocol,
+            sports: ports(&rule.sports),
+            dports: ports(&rule.dports),
+            ..e.clone()
+        }
+    });
+
+    for e in &mut expansions {
+        let Some(family) = e.family else {
+            continue;
+        };
+        e.source = addr_match(sets, rule, "saddr", family, &rule.sources);
+        e.destination = addr_match(sets, rule, "daddr", family, &rule.destinations);
+    }
+
+    expansions
+}
+
+// Every nftables rule of the rule gets a meter set of its own, named like the
+// address sets
+fn add_meters(compiled: &Compiled, rule: &str, meter: Meter, expanded: &mut [CompiledRule]) {
+    for i in 0..expanded.len() {
+        let family = expanded[i]
+            .family
+            .expect("rules with a meter are split by family");
+        let name = set_name(rule, "meter", family, |name| {
+            let meter_named = |e: &CompiledRule| e.meter.as_ref().is_some_and(|m| m.name == name);
+            compiled.sets.iter().any(|s| s.name == name)
+                || compiled.rules.iter().any(meter_named)
+                || expanded[..i].iter().any(meter_named)
+        });
+        expanded[i].meter = Some(CompiledMeter { name, meter });
+    }
+}
+
+// Entries of the [nat] section are turned into rules matching the same way
+// as the ones of the rules file, then moved to the nat chains. Masquerade
+// and SNAT happen on the way out, port forwards on the way in.
+fn expand_nat(nat: &NatSettings, sets: &mut Vec<AddrSet>) -> Vec<CompiledRule> {
+    let rule = |name: String| {
+        Rule::new(
+            format!("nat: {name}"),
+            Direction::Forward,
+            Verdict::Continue,
+            false,
+        )
+    };
+    let mut compiled = vec![];
+
+    for oifname in &nat.masquerade {
+        let rule = Rule {
+            oifname: Some(oifname.clone()),
+            ..rule(format!("masquerade {oifname}"))
+        };
+        compiled.extend(translate(
+            &rule,
+            POSTROUTING_CHAIN,
+            None,
+            NatTarget::Masquerade,
+            sets,
+        ));
+    }
+
+    for snat in &nat.snat {
+        // Sources of the other family can't be translated to the address
+        let family = addr_family(snat.to);
+        let rule = Rule {
+            oifname: snat.oifname.clone(),
+            sources: snat
+                .sources
+                .iter()
+                .filter(|net| family_of(net) == family)
+                .copied()
+                .collect(),
+            ..rule(format!("snat to {}", snat.to))
+        };
+        let target = NatTarget::Snat(snat.to);
+        compiled.extend(translate(
+            &rule,
+            POSTROUTING_CHAIN,
+            Some(family),
+            target,
+            sets,
+        ));
+    }
+
+    for dnat in &nat.dnat {
+        let forward = &dnat.forward;
+        let rule = Rule {
+            iifname: dnat.iifname.clone(),
+            dports: vec![forward.port.clone()],
+            ..rule(format!("forward {forward}"))
+        };
+        let target = NatTarget::Dnat {
+            addr: forward.to,
+            port: forward.to_port,
+        };
+        let family = Some(addr_family(forward.to));
+        compiled.extend(translate(&rule, PREROUTING_CHAIN, family, target, sets));
+    }
+
+    compiled
+}
+
+fn translate(
+    rule: &Rule,
+    chain: &'static str,
+    family: Option<Family>,
+    nat: NatTarget,
+    sets: &mut Vec<AddrSet>,
+) -> Vec<CompiledRule> {
+    expand(rule, QueueSettings::default(), LogSettings::default(), sets)
+        .into_iter()
+        .map(|e| CompiledRule {
+            chain,
+            family: family.or(e.family),
+            nat: Some(nat),
+            ..e
+        })
+        .collect()
+}
+
+// Matches the addresses of the given family, using a named set when there is
+// more than one network left after merging them
+fn addr_match(
+    sets: &mut Vec<AddrSet>,
+    rule: &Rule,
+    field: &str,
+    family: Family,
+    nets: &[IpNet],
+) -> Option<AddrMatch> {
+    let nets: Vec<IpNet> = nets
+        .iter()
+        .filter(|net| family_of(net) == family)
+        .map(IpNet::trunc)
+        .collect();
+    let nets = IpNet::aggregate(&nets);
+
+    match nets.as_slice() {
+        [] => None,
+        [net] => Some(AddrMatch::Net(*net)),
+        _ => {
+            if let Some(set) = sets.iter().find(|s| s.family == family && s.nets == nets) {
+                return Some(AddrMatch::Set(set.name.clone()));
+            }
+
+            let name = set_name(&rule.name, field, family, |name| {
+                sets.iter().any(|s| s.name == name)
+            });
+            sets.push(AddrSet {
+                name: name.clone(),
+                family,
+                nets,
+            });
+            Some(AddrMatch::Set(name))
+        }
+    }
+}
+
+// Set names are derived from the rule, like "ssh-from-lan_saddr4", limited to
+// the characters nft accepts in names without quoting. Names also have to
+// start with a letter.
+fn set_name(rule: &str, field: &str, family: Family, taken: impl Fn(&str) -> bool) -> String {
+    let rule: String = rule
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let version = match family {
+        Family::Ipv4 => 4,
+        Family::Ipv6 => 6,
+    };
+
+    let name = if rule.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("{rule}_{field}{version}")
+    } else {
+        format!("set_{rule}_{field}{version}")
+    };
+    if !taken(&name) {
+        return name;
+    }
+    (2..)
+        .map(|i| format!("{name}_{i}"))
+        .find(|name| !taken(name))
+        .expect("set names run out")
+}
+
+fn product<T>(
+    expansions: Vec<CompiledRule>,
+    values: &[T],
+    f: impl Fn(&CompiledRule, &T) -> CompiledRule,
+) -> Vec<CompiledRule> {
+    expansions
+        .iter()
+        .flat_map(|e| values.iter().map(|value| f(e, value)).collect::<Vec<_>>())
+        .collect()
+}
+
+// Merges overlapping and adjacent ports, nft refuses overlapping intervals
+// in a set
+fn port_ranges<'a>(ports: impl Iterator<Item = &'a Port>) -> Vec<RangeInclusive<u16>> {
+    let mut ports: Vec<(u16, u16)> = ports.map(|p| (p.first, p.last)).collect();
+    ports.sort();
+
+    let mut ranges: Vec<RangeInclusive<u16>> = vec![];
+    for (first, last) in ports {
+        match ranges.last_mut() {
+            Some(range) if u32::from(first) <= u32::from(*range.end()) + 1 => {
+                *range = *range.start()..=last.max(*range.end());
+            }
+            _ => ranges.push(first..=last),
+        }
+    }
+
+    ranges
+}
+
+pub fn family_of(net: &IpNet) -> Family {
+    addr_family(net.addr())
+}
+
+fn addr_family(addr: IpAddr) -> Family {
+    match addr {
+        IpAddr::V4(_) => Family::Ipv4,
+        IpAddr::V6(_) => Family::Ipv6,
+    }
+}
+
+// Sets of the batch that a rule can look up
+struct RuleSets<'a> {
+    saddrs: Option<&'a SetMsg>,
+    daddrs: Option<&'a SetMsg>,
+    sports: Option<&'a SetMsg>,
+    dports: Option<&'a SetMsg>,
+    meter: Option<&'a SetMsg>,
+}
+
+fn build_rule<'a>(chain: &'a nftnlChain, compiled: &CompiledRule, sets: &RuleSets) -> RuleMsg<'a> {
+    let mut msg = RuleMsg::new(chain);
+
+    if let Some(iifname) = &compiled.iifname {
+        msg.add_expr(&nft_expr!(meta iifname));
+        msg.add_expr(&nft_expr!(cmp == interface_name(iifname)));
+    }
+    if let Some(oifname) = &compiled.oifname {
+        msg.add_expr(&nft_expr!(meta oifname));
+        msg.add_expr(&nft_expr!(cmp == interface_name(oifname)));
+    }
+
+    match compiled.family {
+        Some(Family::Ipv4) => {
+            msg.add_expr(&nft_expr!(meta nfproto));
+            msg.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV4 as u8));
+        }
+        Some(Family::Ipv6) => {
+            msg.add_expr(&nft_expr!(meta nfproto));
+            msg.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV6 as u8));
+        }
+        None => {}
+    }
+
+    if let (Some(addr), Some(family)) = (&compiled.source, compiled.family) {
+        add_addr_match(&mut msg, addr, family, true, sets.saddrs);
+    }
+    if let (Some(addr), Some(family)) = (&compiled.destination, compiled.family) {
+        add_addr_match(&mut msg, addr, family, false, sets.daddrs);
+    }
+
+    // Comes right before the ports, where nft puts the protocol match it
+    // adds for `tcp dport` in the script of `apply --dry-run`
+    if let Some(protocol) = compiled.protocol {
+        msg.add_expr(&nft_expr!(meta l4proto));
+        msg.add_expr(&nft_expr!(cmp == protocol.number()));
+    }
+
+    // tcp and udp keep both ports in the first four bytes of their header,
+    // so a raw transport header load works for either protocol. nftnl takes
+    // the offset and length in bytes, despite the names of the fields.
+    if !compiled.sports.is_empty() {
+        msg.add_expr(&nft_expr!(payload_raw th 0, 2));
+        add_port_match(&mut msg, &compiled.sports, sets.sports);
+    }
+    if !compiled.dports.is_empty() {
+        msg.add_expr(&nft_expr!(payload_raw th 2, 2));
+        add_port_match(&mut msg, &compiled.dports, sets.dports);
+    }
+
+    if let (Some(meter), Some(set), Some(family)) = (&compiled.meter, sets.meter, compiled.family) {
+        add_meter(&mut msg, meter, set, family);
+    }
+    if let Some(limit) = &compiled.limit {
+        msg.add_expr(&LimitExpr::new(limit));
+    }
+    if let Some(quota) = &compiled.quota {
+        msg.add_expr(&QuotaExpr::new(quota));
+    }
+
+    // Every rule counts what it matches, the interface shows the rates
+    msg.add_expr(&nft_expr!(counter));
+    if let Some(log) = &compiled.log {
+        msg.add_expr(&Log::new(Some(&log.prefix), Some(log.group)));
+    }
+
+    match compiled.nat {
+        Some(NatTarget::Masquerade) => msg.add_expr(&Masquerade),
+        Some(NatTarget::Snat(addr)) => add_nat(&mut msg, NatType::SNat, addr, None),
+        Some(NatTarget::Dnat { addr, port }) => add_nat(&mut msg, NatType::DNat, addr, port),
+        None => {}
+    }
+
+    match compiled.verdict {
+        Verdict::Accept => msg.add_expr(&nft_expr!(verdict accept)),
+        Verdict::Drop => msg.add_expr(&nft_expr!(verdict drop)),
+        Verdict::Reject if compiled.protocol == Some(Protocol::Tcp) => {
+            msg.add_expr(&nftnlVerdict::Reject(RejectionType::TcpRst))
+        }
+        Verdict::Reject => msg.add_expr(&nftnlVerdict::Reject(RejectionType::Icmp(
+            IcmpCode::PortUnreach,
+        ))),
+        Verdict::Queue => {
+            let queue = compiled.queue.unwrap_or_default();
+            msg.add_expr(&Queue::new(queue.number, queue.fail_open))
+        }
+        Verdict::Continue => {}
+    }
+
+    msg.set_comment(&compiled.comment);
+
+    msg
+}
+
+// Loads the address the meter keys on and updates its entry in the set,
+// matching when the limit of that address does. Connection meters only count
+// the first packet of each connection.
+fn add_meter(msg: &mut RuleMsg, meter: &CompiledMeter, set: &SetMsg, family: Family) {
+    if meter.meter.connections {
+        msg.add_expr(&nft_expr!(ct state));
+        let new = States::NEW.bits();
+        msg.add_expr(&nft_expr!(bitwise mask new, xor 0u32));
+        msg.add_expr(&nft_expr!(cmp != 0u32));
+    }
+
+    match (family, meter.meter.per) {
+        (Family::Ipv4, MeterKey::Source) => msg.add_expr(&nft_expr!(payload ipv4 saddr)),
+        (Family::Ipv4, MeterKey::Destination) => msg.add_expr(&nft_expr!(payload ipv4 daddr)),
+        (Family::Ipv6, MeterKey::Source) => msg.add_expr(&nft_expr!(payload ipv6 saddr)),
+        (Family::Ipv6, MeterKey::Destination) => msg.add_expr(&nft_expr!(payload ipv6 daddr)),
+    }
+    let timeout_ms = meter.timeout_secs() * 1000;
+    msg.add_expr(&MeterExpr::new(
+        set,
+        timeout_ms,
+        LimitExpr::new(&meter.meter.rate),
+    ));
+}
+
+// The nat expression reads the address and port from registers loaded
+// right before it
+fn add_nat(msg: &mut RuleMsg, nat_type: NatType, addr: IpAddr, port: Option<u16>) {
+    let family = match addr {
+        IpAddr::V4(addr) => {
+            msg.add_expr(&Immediate::new(addr.octets(), Register::Reg1));
+            ProtoFamily::Ipv4
+        }
+        IpAddr::V6(addr) => {
+            msg.add_expr(&Immediate::new(addr.octets(), Register::Reg1));
+            ProtoFamily::Ipv6
+        }
+    };
+    if let Some(port) = port {
+        msg.add_expr(&Immediate::new(port.to_be_bytes(), Register::Reg2));
+    }
+
+    msg.add_expr(&Nat {
+        nat_type,
+        family,
+        ip_register: Register::Reg1,
+        port_register: port.map(|_| Register::Reg2),
+    });
+}
+
+// Compares the loaded port against a single port or range, several of them
+// are looked up in the set built from them instead
+fn add_port_match(msg: &mut RuleMsg, ranges: &[RangeInclusive<u16>], set: Option<&SetMsg>) {
+    match (set, ranges) {
+        (Some(set), _) => msg.add_expr(&Lookup::new(set)),
+        (None, [range]) if range.start() == range.end() => {
+            msg.add_expr(&nft_expr!(cmp == range.start().to_be()))
+        }
+        (None, [range]) => msg.add_expr(&Range::new(
+            &range.start().to_be_bytes(),
+            &range.end().to_be_bytes(),
+        )),
+        (None, _) => unreachable!("several port ranges are matched with a set"),
+    }
+}
+
+// Anonymous interval set holding every port range. The end of a range is
+// stored as the first port after it, which doesn't exist for 65535.
+fn port_set(table: &nftnlTable, id: u32, ranges: &[RangeInclusive<u16>]) -> SetMsg {
+    let mut set = SetMsg::anonymous(table, id, SetKey::InetService);
+    for range in ranges {
+        let end = range.end().checked_add(1).map(u16::to_be_bytes);
+        set.add_interval(&range.start().to_be_bytes(), end.as_ref().map(|e| &e[..]));
+    }
+
+    set
+}
+
+fn addr_key(family: Family) -> SetKey {
+    match family {
+        Family::Ipv4 => SetKey::Ipv4Addr,
+        Family::Ipv6 => SetKey::Ipv6Addr,
+    }
+}
+
+// Fills the set with the merged networks as ranges of addresses, stored as
+// the first address and the one after the last
+fn addr_set(mut msg: SetMsg, set: &AddrSet) -> SetMsg {
+    let bytes = |addr: u128| match set.family {
+        Family::Ipv4 => (addr as u32).to_be_bytes().to_vec(),
+        Family::Ipv6 => addr.to_be_bytes().to_vec(),
+    };
+    for (first, last) in addr_ranges(&set.nets) {
+        let end = match set.family {
+            Family::Ipv4 if last == u128::from(u32::MAX) => None,
+            _ => last.checked_add(1),
+        };
+        msg.add_interval(&bytes(first), end.map(bytes).as_deref());
+    }
+
+    msg
+}
+
+// Networks that are adjacent without forming a larger network, like
+// 10.0.0.0/24 and 10.0.1.0/24 and 10.0.2.0/24, still end up in one range
+pub(super) fn addr_ranges(nets: &[IpNet]) -> Vec<(u128, u128)> {
+    let number = |addr: IpAddr| match addr {
+        IpAddr::V4(addr) => u128::from(u32::from(addr)),
+        IpAddr::V6(addr) => u128::from(addr),
+    };
+
+    merge_ranges(
+        nets.iter()
+            .map(|net| (number(net.network()), number(net.broadcast())))
+            .collect(),
+    )
+}
+
+pub(super) fn merge_ranges(mut nets: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    nets.sort();
+
+    let mut ranges: Vec<(u128, u128)> = vec![];
+    for (first, last) in nets {
+        match ranges.last_mut() {
+            Some(range) if range.1.checked_add(1).is_none_or(|next| first <= next) => {
+                range.1 = range.1.max(last);
+            }
+            _ => ranges.push((first, last)),
+        }
+    }
+
+    ranges
+}
+
+fn add_addr_match(
+    msg: &mut RuleMsg,
+    addr: &AddrMatch,
+    family: Family,
+    source: bool,
+    set: Option<&SetMsg>,
+) {
+    match (family, source) {
+        (Family::Ipv4, true) => msg.add_expr(&nft_expr!(payload ipv4 saddr)),
+        (Family::Ipv4, false) => msg.add_expr(&nft_expr!(payload ipv4 daddr)),
+        (Family::Ipv6, true) => msg.add_expr(&nft_expr!(payload ipv6 saddr)),
+        (Family::Ipv6, false) => msg.add_expr(&nft_expr!(payload ipv6 daddr)),
+    }
+
+    let net = match addr {
+        AddrMatch::Net(net) => net,
+        AddrMatch::Set(_) => {
+            let set = set.expect("address sets are added before the rules");
+            msg.add_expr(&Lookup::new(set));
+            return;
+        }
+    };
+
+    if net.prefix_len() < net.max_prefix_len() {
+        match net {
+            IpNet::V4(net) => msg.add_expr(&nft_expr!(bitwise mask net.netmask(), xor 0u32)),
+            IpNet::V6(net) => {
+                msg.add_expr(&nft_expr!(bitwise mask net.netmask(), xor Ipv6Addr::UNSPECIFIED))
+            }
+        }
+    }
+    msg.add_expr(&nft_expr!(cmp == net.network()));
+}
+
+// A trailing `*` matches every interface starting with the given name,
+// the same as in nft
+fn interface_name(name: &str) -> InterfaceName {
+    match name.strip_suffix('*') {
+        Some(prefix) => InterfaceName::StartingWith(CString::new(prefix).unwrap_or_default()),
+        None => InterfaceName::Exact(CString::new(name).unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules;
+    use std::path::Path;
+
+    fn compiled(contents: &str) -> Compiled {
+        compile(&rules::parse(Path::new("rules.toml"), contents).unwrap())
+    }
+
+    fn net(value: &str) -> IpNet {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn one_rule_per_family() {
+        let compiled = compiled(
+            r#"
+[[rules]]
+name = "loopback"
+direction = "input"
+sources = ["127.0.0.1", "::1"]
+verdict = "accept"
+"#,
+        );
+
+        let rules: Vec<_> = compiled
+            .rules
+            .iter()
+            .map(|r| (r.family, r.source.clone(), r.chain))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                (
+                    Some(Family::Ipv4),
+                    Some(AddrMatch::Net(net("127.0.0.1/32"))),
+                    INPUT_CHAIN
+                ),
+                (
+                    Some(Family::Ipv6),
+                    Some(AddrMatch::Net(net("::1/128"))),
+                    INPUT_CHAIN
+                ),
+            ]
+        );
+        assert!(compiled.sets.is_empty());
+    }
+
+    #[test]
+    fn families_without_destinations_are_left_out() {
+        let compiled = compiled(
+            r#"
+[[rules]]
+name = "to-dns"
+direction = "output"
+sources = ["10.0.0.1", "fd00::1"]
+destinations = ["10.0.0.53"]
+verdict = "accept"
+"#,
+        );
+
+        assert_eq!(compiled.rules.len(), 1);
+        assert_eq!(compiled.rules[0].family, Some(Family::Ipv4));
+        assert_eq!(compiled.rules[0].chain, OUTPUT_CHAIN);
+    }
+
+    #[test]
+    fn address_lists_become_shared_sets() {
+        let compiled = compiled(
+            r#"
+[[rules]]
+name = "lan"
+direction = "input"
+sources = ["10.0.0.0/24", "10.0.1.0/24", "192.168.0.0/16"]
+verdict = "accept"
+
+[[rules]]
+name = "lan-again"
+direction = "forward"
+sources = ["192.168.0.0/16", "10.0.0.0/23"]
+verdict = "drop"
+"#,
+        );
+
+        assert_eq!(
+            compiled.sets,
+            vec![AddrSet {
+                name: "lan_saddr4".to_string(),
+                family: Family::Ipv4,
+                nets: vec![net("10.0.0.0/23"), net("192.168.0.0/16")],
+            }]
+        );
+        for rule in &compiled.rules {
+            assert_eq!(rule.source, Some(AddrMatch::Set("lan_saddr4".to_string())));
+        }
+    }
+
+    #[test]
+    fn ports_without_protocol_match_tcp_and_udp() {
+        let compiled = compiled(
+            r#"
+[[rules]]
+name = "web"
+direction = "input"
+dports = [80, 443, "8000-8100", "8050-8200", "udp/53"]
+verdict = "accept"
+"#,
+        );
+
+        let rules: Vec<_> = compiled
+            .rules
+            .iter()
+            .map(|r| (r.protocol, r.dports.clone()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                (Some(Protocol::Tcp), vec![80..=80, 443..=443, 8000..=8200]),
+                (
+                    Some(Protocol::Udp),
+                    vec![53..=53, 80..=80, 443..=443, 8000..=8200]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_keep_their_name_and_order() {
+        let compiled = compiled(
+            r#"
+[nflog]
+group = 5
+
+[[rules]]
+name = "log-ssh"
+direction = "input"
+protocol = "tcp"
+dports = [22]
+verdict = "continue"
+log = true
+comment = "every attempt"
+
+[[rules]]
+name = "ssh"
+direction = "input"
+protocol = "tcp"
+dports = [22]
+verdict = "accept"
+"#,
+        );
+
+        let first = &compiled.rules[0];
+        assert_eq!(first.comment, "log-ssh: every attempt");
+        assert_eq!(
+            first.log,
+            Some(LogTarget {
+                group: 5,
+                prefix: format!("{LOG_PREFIX}log-ssh"),
+            })
+        );
+        assert_eq!(compiled.rules[1].name, "ssh");
+        assert_eq!(compiled.rules[1].log, None);
+    }
+
+    #[test]
+    fn nat_section_compiles_into_nat_chains() {
+        let compiled = compiled(
+            r#"
+[nat]
+masquerade = ["wan0"]
+dnat = [{ forward = "tcp/8080 -> 10.0.0.5:80" }]
+"#,
+        );
+
+        let chains: Vec<_> = compiled.chains().map(|(name, _)| name).collect();
+        assert!(chains.contains(&POSTROUTING_CHAIN));
+        assert!(chains.contains(&PREROUTING_CHAIN));
+
+        let forward = compiled
+            .rules
+            .iter()
+            .find(|r| r.chain == PREROUTING_CHAIN)
+            .unwrap();
+        assert_eq!(forward.dports, vec![8080..=8080]);
+        assert_eq!(
+            forward.nat,
+            Some(NatTarget::Dnat {
+                addr: "10.0.0.5".parse().unwrap(),
+                port: Some(80),
+            })
+        );
+    }
+
+    #[test]
+    fn adjacent_networks_merge_into_one_range() {
+        let nets = [net("10.0.0.0/24"), net("10.0.1.0/24"), net("10.0.2.0/24")];
+        assert_eq!(addr_ranges(&nets), vec![(0x0a00_0000, 0x0a00_02ff)]);
+        assert_eq!(
+            merge_ranges(vec![(u128::MAX - 1, u128::MAX), (0, 1)]),
+            vec![(0, 1), (u128::MAX - 1, u128::MAX)]
+        );
+    }
+}
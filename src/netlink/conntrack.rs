@@ -0,0 +1,721 @@
+use super::{
+    Error, Result,
+    batch::{
+        NFGENMSG_LEN, NLMSG_HDRLEN, attributes, finish_msg, messages, nfnl_msg, push_attr,
+        read_u16, read_u32, recv_ack,
+    },
+};
+
+use ipnet::IpNet;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+// Connection tracking table read and changed over ctnetlink, the same way
+// `conntrack -L` and `conntrack -D` do
+
+const IPCTNL_MSG_CT_GET: libc::c_int = 1;
+const IPCTNL_MSG_CT_DELETE: libc::c_int = 2;
+
+const CTA_TUPLE_ORIG: u16 = 1;
+const CTA_TUPLE_REPLY: u16 = 2;
+const CTA_STATUS: u16 = 3;
+const CTA_PROTOINFO: u16 = 4;
+const CTA_TIMEOUT: u16 = 7;
+const CTA_MARK: u16 = 8;
+const CTA_COUNTERS_ORIG: u16 = 9;
+const CTA_COUNTERS_REPLY: u16 = 10;
+const CTA_ID: u16 = 12;
+const CTA_ZONE: u16 = 18;
+
+const CTA_TUPLE_IP: u16 = 1;
+const CTA_TUPLE_PROTO: u16 = 2;
+const CTA_IP_V4_SRC: u16 = 1;
+const CTA_IP_V4_DST: u16 = 2;
+const CTA_IP_V6_SRC: u16 = 3;
+const CTA_IP_V6_DST: u16 = 4;
+const CTA_PROTO_NUM: u16 = 1;
+const CTA_PROTO_SRC_PORT: u16 = 2;
+const CTA_PROTO_DST_PORT: u16 = 3;
+const CTA_PROTOINFO_TCP: u16 = 1;
+const CTA_PROTOINFO_TCP_STATE: u16 = 1;
+const CTA_COUNTERS_PACKETS: u16 = 1;
+const CTA_COUNTERS_BYTES: u16 = 2;
+
+const NLA_F_NESTED: u16 = 1 << 15;
+
+const IPS_SEEN_REPLY: u32 = 1 << 1;
+const IPS_ASSURED: u32 = 1 << 2;
+
+// Indexed by the state number the kernel reports, as conntrack names them
+const TCP_STATES: [&str; 10] = [
+    "NONE",
+    "SYN_SENT",
+    "SYN_RECV",
+    "ESTABLISHED",
+    "FIN_WAIT",
+    "CLOSE_WAIT",
+    "LAST_ACK",
+    "TIME_WAIT",
+    "CLOSE",
+    "SYN_SENT2",
+];
+
+const PROTOCOLS: [(&str, u8); 8] = [
+    ("icmp", 1),
+    ("tcp", 6),
+    ("udp", 17),
+    ("dccp", 33),
+    ("gre", 47),
+    ("icmpv6", 58),
+    ("sctp", 132),
+    ("udplite", 136),
+];
+
+const RECV_BUFFER_LEN: usize = 64 * 1024;
+
+// Addresses and ports of one direction of a connection, ports are left out
+// for protocols without them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuple {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub protocol: u8,
+    pub original: Tuple,
+    pub reply: Tuple,
+    // Only tcp connections have a state
+    pub state: Option<u8>,
+    pub status: u32,
+    // Seconds until the entry expires
+    pub timeout: Option<u32>,
+    pub mark: Option<u32>,
+    // Only counted while nf_conntrack_acct is enabled
+    pub original_counters: Option<Counters>,
+    pub reply_counters: Option<Counters>,
+    id: Option<u32>,
+    zone: Option<u16>,
+    family: u8,
+    // Original tuple as the kernel sent it, identifies the entry when
+    // deleting it
+    original_attr: Vec<u8>,
+}
+
+impl Connection {
+    pub fn protocol_name(&self) -> String {
+        protocol_name(self.protocol)
+    }
+
+    pub fn state_name(&self) -> Option<&'static str> {
+        self.state
+            .map(|state| TCP_STATES.get(state as usize).copied().unwrap_or("UNKNOWN"))
+    }
+
+    pub fn is_assured(&self) -> bool {
+        self.status & IPS_ASSURED != 0
+    }
+
+    pub fn is_unreplied(&self) -> bool {
+        self.status & IPS_SEEN_REPLY == 0
+    }
+}
+
+// Lists `src=10.0.0.1 dst=10.0.0.2 sport=1234 dport=22`
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "src={} dst={}", self.src, self.dst)?;
+        if let (Some(sport), Some(dport)) = (self.sport, self.dport) {
+            write!(f, " sport={sport} dport={dport}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "packets={} bytes={}", self.packets, self.bytes)
+    }
+}
+
+// One line in the format of `conntrack -L`
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<8} {:<3}", self.protocol_name(), self.protocol)?;
+        if let Some(timeout) = self.timeout {
+            write!(f, " {timeout}")?;
+        }
+        if let Some(state) = self.state_name() {
+            write!(f, " {state}")?;
+        }
+
+        write!(f, " {}", self.original)?;
+        if let Some(counters) = self.original_counters {
+            write!(f, " {counters}")?;
+        }
+        if self.is_unreplied() {
+            write!(f, " [UNREPLIED]")?;
+        }
+        write!(f, " {}", self.reply)?;
+        if let Some(counters) = self.reply_counters {
+            write!(f, " {counters}")?;
+        }
+        if self.is_assured() {
+            write!(f, " [ASSURED]")?;
+        }
+        if let Some(mark) = self.mark {
+            write!(f, " mark={mark}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn protocol_name(protocol: u8) -> String {
+    PROTOCOLS
+        .iter()
+        .find(|(_, number)| *number == protocol)
+        .map_or_else(|| protocol.to_string(), |(name, _)| name.to_string())
+}
+
+// Connections matching every term of the filter. Terms are protocols, tcp
+// states, assured, unreplied, mark=N, ports and addresses or networks
+// matching either direction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConntrackFilter {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Protocol(u8),
+    State(u8),
+    Assured,
+    Unreplied,
+    Mark(u32),
+    Port(u16),
+    Net(IpNet),
+}
+
+impl ConntrackFilter {
+    pub fn parse<S: AsRef<str>>(terms: &[S]) -> Result<Self> {
+        let terms = terms
+            .iter()
+            .flat_map(|term| term.as_ref().split_whitespace())
+            .map(parse_term)
+            .collect::<Result<_>>()?;
+
+        Ok(Self { terms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, connection: &Connection) -> bool {
+        let tuples = [&connection.original, &connection.reply];
+
+        self.terms.iter().all(|term| match term {
+            Term::Protocol(protocol) => connection.protocol == *protocol,
+            Term::State(state) => connection.state == Some(*state),
+            Term::Assured => connection.is_assured(),
+            Term::Unreplied => connection.is_unreplied(),
+            Term::Mark(mark) => connection.mark == Some(*mark),
+            Term::Port(port) => tuples
+                .iter()
+                .any(|t| t.sport == Some(*port) || t.dport == Some(*port)),
+            Term::Net(net) => tuples
+                .iter()
+                .any(|t| net.contains(&t.src) || net.contains(&t.dst)),
+        })
+    }
+}
+
+fn parse_term(term: &str) -> Result<Term> {
+    let lower = term.to_lowercase();
+    let invalid = |reason: &str| Error::rejected(format!("conntrack filter {term}"), reason);
+
+    if let Some((_, number)) = PROTOCOLS.iter().find(|(name, _)| *name == lower) {
+        return Ok(Term::Protocol(*number));
+    }
+    if let Some(state) = TCP_STATES
+        .iter()
+        .position(|state| state.to_lowercase() == lower)
+    {
+        return Ok(Term::State(state as u8));
+    }
+    match lower.as_str() {
+        "assured" => return Ok(Term::Assured),
+        "unreplied" => return Ok(Term::Unreplied),
+        _ => {}
+    }
+    if let Some(mark) = lower.strip_prefix("mark=") {
+        return mark
+            .parse()
+            .map(Term::Mark)
+            .map_err(|_| invalid("invalid mark"));
+    }
+    if let Ok(port) = term.parse() {
+        return Ok(Term::Port(port));
+    }
+    if let Ok(net) = term.parse() {
+        return Ok(Term::Net(net));
+    }
+    if let Ok(addr) = term.parse::<IpAddr>() {
+        return Ok(Term::Net(addr.into()));
+    }
+
+    Err(invalid(
+        "expected a protocol, tcp state, assured, unreplied, mark=N, port, address or network",
+    ))
+}
+
+// Dumps the whole connection tracking table, of both families
+pub fn list_connections() -> Result<Vec<Connection>> {
+    let netlink = |e| Error::from_io("conntrack table", e);
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(netlink)?;
+
+    let mut msg = nfnl_msg(
+        libc::NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_GET,
+        libc::NLM_F_REQUEST | libc::NLM_F_DUMP,
+        libc::AF_UNSPEC as u8,
+        0,
+    );
+    finish_msg(&mut msg);
+    socket.send(&msg).map_err(netlink)?;
+
+    let mut connections = vec![];
+    let mut buffer = vec![0; RECV_BUFFER_LEN];
+    loop {
+        let len = socket.recv(&mut buffer).map_err(netlink)?;
+        for msg in messages(&buffer[..len]) {
+            match read_u16(msg, 4) as libc::c_int {
+                libc::NLMSG_DONE => return Ok(connections),
+                libc::NLMSG_ERROR => {
+                    let errno = -(read_u32(msg, NLMSG_HDRLEN) as i32);
+                    return Err(Error::from_io(
+                        "conntrack table",
+                        std::io::Error::from_raw_os_error(errno),
+                    ));
+                }
+                _ => connections.extend(parse_connection(msg)),
+            }
+        }
+    }
+}
+
+// Deletes the entries, one request each. Entries that expired in the
+// meantime are not an error.
+pub fn delete_connections(connections: &[Connection]) -> Result<()> {
+    let socket =
+        mnl::Socket::new(mnl::Bus::Netfilter).map_err(|e| Error::from_io("conntrack table", e))?;
+
+    for connection in connections {
+        let object = format!("conntrack entry {}", connection.original);
+
+        let mut msg = nfnl_msg(
+            libc::NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_DELETE,
+            libc::NLM_F_REQUEST | libc::NLM_F_ACK,
+            connection.family,
+            0,
+        );
+        push_attr(
+            &mut msg,
+            CTA_TUPLE_ORIG | NLA_F_NESTED,
+            &connection.original_attr,
+        );
+        // The id makes sure a new connection reusing the tuple is left alone
+        if let Some(id) = connection.id {
+            push_attr(&mut msg, CTA_ID, &id.to_be_bytes());
+        }
+        if let Some(zone) = connection.zone {
+            push_attr(&mut msg, CTA_ZONE, &zone.to_be_bytes());
+        }
+        finish_msg(&mut msg);
+
+        socket.send(&msg).map_err(|e| Error::from_io(&object, e))?;
+        match recv_ack(&socket, &object) {
+            Err(Error::Missing { .. }) => {}
+            res => res?,
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_connection(msg: &[u8]) -> Option<Connection> {
+    let family = *msg.get(NLMSG_HDRLEN)?;
+    let attrs = msg.get(NLMSG_HDRLEN + NFGENMSG_LEN..)?;
+
+    let mut original = None;
+    let mut reply = None;
+    let mut original_attr = vec![];
+    let mut state = None;
+    let mut status = 0;
+    let mut timeout = None;
+    let mut mark = None;
+    let mut original_counters = None;
+    let mut reply_counters = None;
+    let mut id = None;
+    let mut zone = None;
+
+    for (kind, value) in attributes(attrs) {
+        match kind {
+            CTA_TUPLE_ORIG => {
+                original = parse_tuple(value);
+                original_attr = value.to_vec();
+            }
+            CTA_TUPLE_REPLY => reply = parse_tuple(value),
+            CTA_STATUS => status = be_u32(value)?,
+            CTA_PROTOINFO => state = tcp_state(value),
+            CTA_TIMEOUT => timeout = be_u32(value),
+            CTA_MARK => mark = be_u32(value),
+            CTA_COUNTERS_ORIG => original_counters = parse_counters(value),
+            CTA_COUNTERS_REPLY => reply_counters = parse_counters(value),
+            CTA_ID => id = be_u32(value),
+            CTA_ZONE => zone = be_u16(value),
+            _ => {}
+        }
+    }
+
+    let (protocol, original) = original?;
+    let (_, reply) = reply?;
+
+    Some(Connection {
+        protocol,
+        original,
+        reply,
+        state,
+        status,
+        timeout,
+        mark,
+        original_counters,
+        reply_counters,
+        id,
+        zone,
+        family,
+        original_attr,
+    })
+}
+
+fn parse_tuple(attrs: &[u8]) -> Option<(u8, Tuple)> {
+    let mut src = None;
+    let mut dst = None;
+    let mut protocol = None;
+    let mut sport = None;
+    let mut dport = None;
+
+    for (kind, value) in attributes(attrs) {
+        match kind {
+            CTA_TUPLE_IP => {
+                for (kind, value) in attributes(value) {
+                    match kind {
+                        CTA_IP_V4_SRC => src = ipv4(value),
+                        CTA_IP_V4_DST => dst = ipv4(value),
+                        CTA_IP_V6_SRC => src = ipv6(value),
+                        CTA_IP_V6_DST => dst = ipv6(value),
+                        _ => {}
+                    }
+                }
+            }
+            CTA_TUPLE_PROTO => {
+                for (kind, value) in attributes(value) {
+                    match kind {
+                        CTA_PROTO_NUM => protocol = value.first().copied(),
+                        CTA_PROTO_SRC_PORT => sport = be_u16(value),
+                        CTA_PROTO_DST_PORT => dport = be_u16(value),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some((
+        protocol?,
+        Tuple {
+            src: src?,
+            dst: dst?,
+            sport,
+            dport,
+        },
+    ))
+}
+
+fn tcp_state(attrs: &[u8]) -> Option<u8> {
+    let (_, tcp) = attributes(attrs).find(|(kind, _)| *kind == CTA_PROTOINFO_TCP)?;
+    let (_, state) = attributes(tcp).find(|(kind, _)| *kind == CTA_PROTOINFO_TCP_STATE)?;
+
+    state.first().copied()
+}
+
+fn parse_counters(attrs: &[u8]) -> Option<Counters> {
+    let mut counters = Counters::default();
+    for (kind, value) in attributes(attrs) {
+        match kind {
+            CTA_COUNTERS_PACKETS => counters.packets = be_u64(value)?,
+            CTA_COUNTERS_BYTES => counters.bytes = be_u64(value)?,
+            _ => {}
+        }
+    }
+
+    Some(counters)
+}
+
+fn ipv4(value: &[u8]) -> Option<IpAddr> {
+    <[u8; 4]>::try_from(value)
+        .ok()
+        .map(|octets| Ipv4Addr::from(octets).into())
+}
+
+fn ipv6(value: &[u8]) -> Option<IpAddr> {
+    <[u8; 16]>::try_from(value)
+        .ok()
+        .map(|octets| Ipv6Addr::from(octets).into())
+}
+
+fn be_u16(value: &[u8]) -> Option<u16> {
+    value.try_into().ok().map(u16::from_be_bytes)
+}
+
+fn be_u32(value: &[u8]) -> Option<u32> {
+    value.try_into().ok().map(u32::from_be_bytes)
+}
+
+fn be_u64(value: &[u8]) -> Option<u64> {
+    value.try_into().ok().map(u64::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut value = vec![];
+        for (kind, attr) in attrs {
+            push_attr(&mut value, *kind, attr);
+        }
+
+        value
+    }
+
+    fn tuple(src: IpAddr, dst: IpAddr, protocol: u8, ports: Option<(u16, u16)>) -> Vec<u8> {
+        let octets = |addr: IpAddr| match addr {
+            IpAddr::V4(addr) => addr.octets().to_vec(),
+            IpAddr::V6(addr) => addr.octets().to_vec(),
+        };
+        let (src_kind, dst_kind) = match src {
+            IpAddr::V4(_) => (CTA_IP_V4_SRC, CTA_IP_V4_DST),
+            IpAddr::V6(_) => (CTA_IP_V6_SRC, CTA_IP_V6_DST),
+        };
+
+        let mut proto = vec![(CTA_PROTO_NUM, vec![protocol])];
+        if let Some((sport, dport)) = ports {
+            proto.push((CTA_PROTO_SRC_PORT, sport.to_be_bytes().to_vec()));
+            proto.push((CTA_PROTO_DST_PORT, dport.to_be_bytes().to_vec()));
+        }
+
+        nested(&[
+            (
+                CTA_TUPLE_IP | NLA_F_NESTED,
+                nested(&[(src_kind, octets(src)), (dst_kind, octets(dst))]),
+            ),
+            (CTA_TUPLE_PROTO | NLA_F_NESTED, nested(&proto)),
+        ])
+    }
+
+    fn counters(packets: u64, bytes: u64) -> Vec<u8> {
+        nested(&[
+            (CTA_COUNTERS_PACKETS, packets.to_be_bytes().to_vec()),
+            (CTA_COUNTERS_BYTES, bytes.to_be_bytes().to_vec()),
+        ])
+    }
+
+    fn message(family: u8, attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = nfnl_msg(
+            libc::NFNL_SUBSYS_CTNETLINK << 8 | IPCTNL_MSG_CT_GET,
+            0,
+            family,
+            0,
+        );
+        for (kind, value) in attrs {
+            push_attr(&mut msg, *kind, value);
+        }
+        finish_msg(&mut msg);
+
+        msg
+    }
+
+    fn ssh_connection() -> Vec<u8> {
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let server: IpAddr = "10.0.0.2".parse().unwrap();
+
+        message(
+            libc::AF_INET as u8,
+            &[
+                (
+                    CTA_TUPLE_ORIG | NLA_F_NESTED,
+                    tuple(client, server, 6, Some((40000, 22))),
+                ),
+                (
+                    CTA_TUPLE_REPLY | NLA_F_NESTED,
+                    tuple(server, client, 6, Some((22, 40000))),
+                ),
+                (
+                    CTA_STATUS,
+                    (IPS_SEEN_REPLY | IPS_ASSURED).to_be_bytes().to_vec(),
+                ),
+                (
+                    CTA_PROTOINFO | NLA_F_NESTED,
+                    nested(&[(
+                        CTA_PROTOINFO_TCP | NLA_F_NESTED,
+                        nested(&[(CTA_PROTOINFO_TCP_STATE, vec![3])]),
+                    )]),
+                ),
+                (CTA_TIMEOUT, 431999u32.to_be_bytes().to_vec()),
+                (CTA_MARK, 7u32.to_be_bytes().to_vec()),
+                (CTA_COUNTERS_ORIG | NLA_F_NESTED, counters(12, 2048)),
+                (CTA_COUNTERS_REPLY | NLA_F_NESTED, counters(10, 4096)),
+                (CTA_ID, 99u32.to_be_bytes().to_vec()),
+                (CTA_ZONE, 1u16.to_be_bytes().to_vec()),
+            ],
+        )
+    }
+
+    fn dns_connection() -> Vec<u8> {
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+        let server: IpAddr = "2001:db8::53".parse().unwrap();
+
+        message(
+            libc::AF_INET6 as u8,
+            &[
+                (
+                    CTA_TUPLE_ORIG | NLA_F_NESTED,
+                    tuple(client, server, 17, Some((5353, 53))),
+                ),
+                (
+                    CTA_TUPLE_REPLY | NLA_F_NESTED,
+                    tuple(server, client, 17, Some((53, 5353))),
+                ),
+                (CTA_STATUS, 0u32.to_be_bytes().to_vec()),
+                (CTA_TIMEOUT, 29u32.to_be_bytes().to_vec()),
+            ],
+        )
+    }
+
+    #[test]
+    fn tcp_connections() {
+        let connection = parse_connection(&ssh_connection()).unwrap();
+
+        assert_eq!(connection.protocol, 6);
+        assert_eq!(
+            connection.original,
+            Tuple {
+                src: "10.0.0.1".parse().unwrap(),
+                dst: "10.0.0.2".parse().unwrap(),
+                sport: Some(40000),
+                dport: Some(22),
+            }
+        );
+        assert_eq!(connection.reply.sport, Some(22));
+        assert_eq!(connection.state_name(), Some("ESTABLISHED"));
+        assert!(connection.is_assured());
+        assert!(!connection.is_unreplied());
+        assert_eq!(connection.timeout, Some(431999));
+        assert_eq!(connection.mark, Some(7));
+        assert_eq!(
+            connection.original_counters,
+            Some(Counters {
+                packets: 12,
+                bytes: 2048
+            })
+        );
+        assert_eq!(connection.id, Some(99));
+        assert_eq!(connection.zone, Some(1));
+        assert_eq!(connection.family, libc::AF_INET as u8);
+        // Sent back as it is when the entry is deleted
+        assert_eq!(
+            parse_tuple(&connection.original_attr).unwrap().1,
+            connection.original
+        );
+
+        assert_eq!(
+            connection.to_string(),
+            "tcp      6   431999 ESTABLISHED src=10.0.0.1 dst=10.0.0.2 sport=40000 dport=22 \
+             packets=12 bytes=2048 src=10.0.0.2 dst=10.0.0.1 sport=22 dport=40000 \
+             packets=10 bytes=4096 [ASSURED] mark=7"
+        );
+    }
+
+    #[test]
+    fn unreplied_udp_connections() {
+        let connection = parse_connection(&dns_connection()).unwrap();
+
+        assert_eq!(connection.protocol_name(), "udp");
+        assert_eq!(connection.state, None);
+        assert!(connection.is_unreplied());
+        assert!(!connection.is_assured());
+        assert_eq!(connection.original_counters, None);
+        assert_eq!(
+            connection.to_string(),
+            "udp      17  29 src=2001:db8::1 dst=2001:db8::53 sport=5353 dport=53 [UNREPLIED] \
+             src=2001:db8::53 dst=2001:db8::1 sport=53 dport=5353"
+        );
+    }
+
+    #[test]
+    fn connections_without_both_tuples_are_left_out() {
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let server: IpAddr = "10.0.0.2".parse().unwrap();
+        let msg = message(
+            libc::AF_INET as u8,
+            &[(
+                CTA_TUPLE_ORIG | NLA_F_NESTED,
+                tuple(client, server, 1, None),
+            )],
+        );
+
+        assert_eq!(parse_connection(&msg), None);
+        assert_eq!(parse_connection(&msg[..NLMSG_HDRLEN]), None);
+    }
+
+    #[test]
+    fn filters() {
+        let ssh = parse_connection(&ssh_connection()).unwrap();
+        let dns = parse_connection(&dns_connection()).unwrap();
+        let matching = |terms: &[&str]| {
+            let filter = ConntrackFilter::parse(terms).unwrap();
+            [&ssh, &dns]
+                .into_iter()
+                .filter(|connection| filter.matches(connection))
+                .count()
+        };
+
+        assert!(ConntrackFilter::parse::<&str>(&[]).unwrap().is_empty());
+        assert_eq!(matching(&[]), 2);
+        assert_eq!(matching(&["tcp"]), 1);
+        assert_eq!(matching(&["established assured"]), 1);
+        assert_eq!(matching(&["unreplied", "53"]), 1);
+        assert_eq!(matching(&["mark=7"]), 1);
+        assert_eq!(matching(&["10.0.0.0/24"]), 1);
+        assert_eq!(matching(&["2001:db8::53"]), 1);
+        assert_eq!(matching(&["udp", "22"]), 0);
+        assert_eq!(matching(&["UDP"]), 1);
+
+        assert!(ConntrackFilter::parse(&["mark=x"]).is_err());
+        assert!(ConntrackFilter::parse(&["nonsense"]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use super::{Error, MANAGED_TABLE, Result, Ruleset};
+
+use nftables::{
+    helper::DEFAULT_NFT,
+    stmt::{Counter, Statement},
+    types::NfFamily,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+// Polls made for the sparkline of a rule
+const HISTORY_LEN: usize = 20;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Traffic of a rule of the managed table, found by its chain and handle
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleCounters {
+    pub packets: u64,
+    pub bytes: u64,
+    // Per second, over the time since the previous poll
+    pub packet_rate: f64,
+    pub byte_rate: f64,
+    // Packet rates of the last polls, oldest first
+    pub history: VecDeque<f64>,
+}
+
+impl RuleCounters {
+    // Packet rates scaled to the highest one of the history
+    pub fn sparkline(&self) -> String {
+        let max = self.history.iter().copied().fold(0.0, f64::max);
+
+        self.history
+            .iter()
+            .map(|rate| match max {
+                0.0 => SPARKS[0],
+                _ => SPARKS[((rate / max) * (SPARKS.len() - 1) as f64).round() as usize],
+            })
+            .collect()
+    }
+
+    // Like `12.5 pkt/s 3.4 KB/s`
+    pub fn rates(&self) -> String {
+        format!(
+            "{:.1} pkt/s {}/s",
+            self.packet_rate,
+            human_bytes(self.byte_rate)
+        )
+    }
+
+    pub fn totals(&self) -> String {
+        format!("{} pkts {}", self.packets, human_bytes(self.bytes as f64))
+    }
+}
+
+// Counters of the rules of the managed table, polled on every tick of the
+// interface to work out their rates
+#[derive(Debug, Default)]
+pub struct CounterStats {
+    rules: HashMap<(String, u32), RuleCounters>,
+    last_poll: Option<Instant>,
+    // Bumped on every poll, components showing the counters redraw when it
+    // differs from what they last saw
+    pub generation: u64,
+}
+
+impl CounterStats {
+    pub fn rule(&self, chain: &str, handle: u32) -> Option<&RuleCounters> {
+        self.rules.get(&(chain.to_string(), handle))
+    }
+
+    pub fn poll(&mut self) -> Result<()> {
+        let samples = match Ruleset::read_table(NfFamily::INet, MANAGED_TABLE)? {
+            Some(table) => table
+                .chains
+                .iter()
+                .flat_map(|chain| {
+                    chain.rules.iter().filter_map(|rule| {
+                        Some((
+                            (chain.name.clone(), rule.handle?),
+                            counts(&rule.statements)?,
+                        ))
+                    })
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+        self.update(samples, Instant::now());
+
+        Ok(())
+    }
+
+    // Zeroes the counters of every rule of the managed table
+    pub fn reset(&mut self) -> Result<()> {
+        let args = ["reset", "rules", "table", "inet", MANAGED_TABLE];
+        nftables::helper::get_current_ruleset_with_args(DEFAULT_NFT, &args)
+            .map_err(|e| Error::from_nft("resetting the rule counters", e))?;
+
+        self.rules.clear();
+        self.last_poll = None;
+        self.generation += 1;
+
+        Ok(())
+    }
+
+    fn update(&mut self, samples: HashMap<(String, u32), (u64, u64)>, now: Instant) {
+        let elapsed = self
+            .last_poll
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .filter(|secs| *secs > 0.0);
+
+        let mut rules = HashMap::with_capacity(samples.len());
+        for (key, (packets, bytes)) in samples {
+            let mut counters = self.rules.remove(&key).unwrap_or_default();
+
+            // Counters only go back when they were reset or the rule was
+            // replaced, the rate starts over then
+            let rates = elapsed.filter(|_| packets >= counters.packets && bytes >= counters.bytes);
+            (counters.packet_rate, counters.byte_rate) = match rates {
+                Some(secs) => (
+                    (packets - counters.packets) as f64 / secs,
+                    (bytes - counters.bytes) as f64 / secs,
+                ),
+                None => (0.0, 0.0),
+            };
+            counters.packets = packets;
+            counters.bytes = bytes;
+
+            if counters.history.len() == HISTORY_LEN {
+                counters.history.pop_front();
+            }
+            counters.history.push_back(counters.packet_rate);
+
+            rules.insert(key, counters);
+        }
+
+        self.rules = rules;
+        self.last_poll = Some(now);
+        self.generation += 1;
+    }
+}
+
+fn counts(statements: &[Statement]) -> Option<(u64, u64)> {
+    statements.iter().find_map(|statement| match statement {
+        Statement::Counter(Counter::Anonymous(Some(counter))) => Some((
+            counter.packets.unwrap_or_default() as u64,
+            counter.bytes.unwrap_or_default() as u64,
+        )),
+        _ => None,
+    })
+}
+
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{value:.0} {}", UNITS[unit]),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn samples(counts: &[(&str, u32, u64, u64)]) -> HashMap<(String, u32), (u64, u64)> {
+        counts
+            .iter()
+            .map(|(chain, handle, packets, bytes)| {
+                ((chain.to_string(), *handle), (*packets, *bytes))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rates_between_polls() {
+        let start = Instant::now();
+        let mut stats = CounterStats::default();
+
+        stats.update(samples(&[("input", 4, 100, 10_000)]), start);
+        let ssh = stats.rule("input", 4).unwrap();
+        // Nothing to compare the first poll with
+        assert_eq!((ssh.packet_rate, ssh.byte_rate), (0.0, 0.0));
+
+        stats.update(
+            samples(&[("input", 4, 120, 12_048)]),
+            start + Duration::from_secs(2),
+        );
+        let ssh = stats.rule("input", 4).unwrap();
+        assert_eq!((ssh.packets, ssh.bytes), (120, 12_048));
+        assert_eq!((ssh.packet_rate, ssh.byte_rate), (10.0, 1024.0));
+        assert_eq!(ssh.rates(), "10.0 pkt/s 1.0 KB/s");
+        assert_eq!(ssh.totals(), "120 pkts 11.8 KB");
+        assert_eq!(stats.generation, 2);
+    }
+
+    #[test]
+    fn rates_start_over_when_counters_go_back() {
+        let start = Instant::now();
+        let mut stats = CounterStats::default();
+
+        stats.update(samples(&[("input", 4, 100, 10_000)]), start);
+        stats.update(
+            samples(&[("input", 4, 5, 500)]),
+            start + Duration::from_secs(1),
+        );
+        let ssh = stats.rule("input", 4).unwrap();
+        assert_eq!((ssh.packets, ssh.packet_rate), (5, 0.0));
+
+        // Polls at the same instant have no rate either
+        stats.update(
+            samples(&[("input", 4, 10, 1000)]),
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(stats.rule("input", 4).unwrap().packet_rate, 0.0);
+    }
+
+    #[test]
+    fn removed_rules_are_forgotten() {
+        let start = Instant::now();
+        let mut stats = CounterStats::default();
+
+        stats.update(
+            samples(&[("input", 4, 1, 60), ("output", 7, 2, 120)]),
+            start,
+        );
+        stats.update(
+            samples(&[("output", 7, 2, 120)]),
+            start + Duration::from_secs(1),
+        );
+
+        assert!(stats.rule("input", 4).is_none());
+        assert!(stats.rule("output", 7).is_some());
+    }
+
+    #[test]
+    fn sparklines_keep_the_last_polls() {
+        let start = Instant::now();
+        let mut stats = CounterStats::default();
+
+        for i in 0..=HISTORY_LEN as u64 + 5 {
+            stats.update(
+                samples(&[("input", 4, i * i, 0)]),
+                start + Duration::from_secs(i),
+            );
+        }
+        let ssh = stats.rule("input", 4).unwrap();
+        assert_eq!(ssh.history.len(), HISTORY_LEN);
+        let sparkline: Vec<char> = ssh.sparkline().chars().collect();
+        assert_eq!(sparkline.len(), HISTORY_LEN);
+        assert_eq!(sparkline.last(), Some(&'█'));
+        assert!(sparkline[0] < sparkline[HISTORY_LEN - 1]);
+
+        assert_eq!(RuleCounters::default().sparkline(), "");
+    }
+
+    #[test]
+    fn byte_units() {
+        assert_eq!(human_bytes(512.0), "512 B");
+        assert_eq!(human_bytes(1536.0), "1.5 KB");
+        assert_eq!(human_bytes(3.0 * 1024.0 * 1024.0 * 1024.0), "3.0 GB");
+    }
+}
@@ -0,0 +1,953 @@
+use super::{
+    Result, TrackedBatch,
+    compiler::{
+        AddrSet, AddrSets, Compiled, CompiledRule, Hook, MANAGED_TABLE, add_rule, addr_ranges,
+        addr_sets, base_chain, compile, managed_table, merge_ranges, table_batch,
+    },
+    nlmsg::{Placement, RuleMsg, SetKey, SetMsg},
+    ruleset::{self, Ruleset, Table},
+    script::{chain_spec, nft_rule, quote, set_type_name, to_ruleset},
+    sets::element_text,
+};
+use crate::rules::{DEFAULT_BURST, RulesFile, format_network};
+
+use ipnet::IpNet;
+use nftables::{
+    expr::{
+        Elem, Expression, MetaKey, NamedExpression, Payload, PayloadBase, PayloadField, SetItem,
+    },
+    stmt::{
+        AnonymousCounter, Counter, Limit, Match, Meter, Queue, Quota, QuotaOrQuotaRef, Statement,
+    },
+    types::{NfChainPolicy, NfFamily},
+};
+use nftnl::{Chain as nftnlChain, MsgType};
+use std::{ffi::CString, fmt, net::IpAddr};
+
+// Changes turning the live managed table into the one compiled from the
+// rules file. Rules and sets are changed in place so the ones left alone
+// keep their counters and established connections keep matching, the table
+// is only created again when it can't be changed in place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff {
+    compiled: Compiled,
+    pub changes: Vec<Change>,
+}
+
+// Changes in the order they are sent to the kernel
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    AddTable,
+    // The table is deleted before being added again
+    DeleteTable {
+        reason: String,
+    },
+    AddChain {
+        chain: &'static str,
+        hook: Hook,
+    },
+    // Only the policy of a base chain can change in place
+    UpdateChain {
+        chain: &'static str,
+        hook: Hook,
+        policy: NfChainPolicy,
+    },
+    DeleteChain {
+        chain: String,
+    },
+    AddSet {
+        set: AddrSet,
+    },
+    // Intervals can't be deleted one by one without knowing how the kernel
+    // merged them, the set is flushed and filled again instead
+    UpdateSet {
+        set: AddrSet,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    DeleteSet {
+        set: String,
+    },
+    DeleteRule {
+        chain: &'static str,
+        handle: u32,
+        comment: Option<String>,
+    },
+    // Before the rule with the given handle, at the end of the chain without one
+    AddRule {
+        rule: CompiledRule,
+        before: Option<u32>,
+    },
+    ReplaceRule {
+        rule: CompiledRule,
+        handle: u32,
+    },
+}
+
+// Compares the rules file with the managed table of the active ruleset,
+// which is only read
+pub fn diff_ruleset(rules: &RulesFile) -> Result<Diff> {
+    let live = Ruleset::read_table(NfFamily::INet, MANAGED_TABLE)?;
+
+    Ok(diff(compile(rules), live.as_ref()))
+}
+
+// Changes turning the live table into the compiled one, without touching
+// the kernel
+pub fn diff(compiled: Compiled, live: Option<&Table>) -> Diff {
+    let Some(live) = live else {
+        return Diff::recreate(compiled, None);
+    };
+    let desired = &to_ruleset(&compiled).tables[0];
+    if let Some(reason) = incompatible(desired, live) {
+        return Diff::recreate(compiled, Some(reason));
+    }
+
+    let mut changes = vec![];
+    let chains: Vec<(&'static str, Hook)> = compiled.chains().collect();
+
+    for &(name, hook) in &chains {
+        let wanted = desired.chain(name).and_then(|c| c.base.as_ref());
+        match live.chain(name) {
+            None => changes.push(Change::AddChain { chain: name, hook }),
+            Some(chain) if chain.base.as_ref() != wanted => changes.push(Change::UpdateChain {
+                chain: name,
+                hook,
+                policy: chain
+                    .base
+                    .as_ref()
+                    .map_or(NfChainPolicy::Accept, |b| b.policy),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for set in &compiled.sets {
+        let Some(live_set) = live.set(&set.name) else {
+            changes.push(Change::AddSet { set: set.clone() });
+            continue;
+        };
+
+        if element_ranges(&live_set.elements) != Some(addr_ranges(&set.nets)) {
+            let wanted: Vec<String> = set.nets.iter().map(format_network).collect();
+            let found: Vec<String> = live_set.elements.iter().map(element_text).collect();
+            changes.push(Change::UpdateSet {
+                set: set.clone(),
+                added: wanted
+                    .iter()
+                    .filter(|e| !found.contains(e))
+                    .cloned()
+                    .collect(),
+                removed: found
+                    .iter()
+                    .filter(|e| !wanted.contains(e))
+                    .cloned()
+                    .collect(),
+            });
+        }
+    }
+
+    for &(name, _) in &chains {
+        let wanted: Vec<(&CompiledRule, &ruleset::Rule)> = compiled
+            .rules
+            .iter()
+            .filter(|rule| rule.chain == name)
+            .zip(desired.chain(name).map_or(&[][..], |c| &c.rules))
+            .collect();
+        let found = live.chain(name).map_or(&[][..], |c| &c.rules);
+
+        changes.extend(diff_rules(name, &wanted, found));
+    }
+
+    for chain in &live.chains {
+        // Nat chains are dropped along with their rules once nothing is
+        // translated anymore
+        if !chains.iter().any(|(name, _)| *name == chain.name) {
+            changes.push(Change::DeleteChain {
+                chain: chain.name.clone(),
+            });
+        }
+    }
+    // Meter sets belong to their rule and go away with it
+    let meter_named = |name: &str| {
+        compiled
+            .rules
+            .iter()
+            .any(|rule| rule.meter.as_ref().is_some_and(|m| m.name == name))
+    };
+    for set in &live.sets {
+        if !compiled.sets.iter().any(|s| s.name == set.name) && !meter_named(&set.name) {
+            changes.push(Change::DeleteSet {
+                set: set.name.clone(),
+            });
+        }
+    }
+
+    Diff { compiled, changes }
+}
+
+// Live rules are paired with the wanted ones carrying the same comment, in
+// order. Rules left without a pair, or that would have to move, are deleted
+// and added again at the right position.
+fn diff_rules(
+    chain: &'static str,
+    wanted: &[(&CompiledRule, &ruleset::Rule)],
+    found: &[ruleset::Rule],
+) -> Vec<Change> {
+    // A rule with the same statements is preferred over the first one with
+    // the same comment, rules expanded from the same entry share it. Rules
+    // that are the same as a later wanted rule are left for that one.
+    let same = |a: &ruleset::Rule, b: &ruleset::Rule| {
+        normalized(&a.statements) == normalized(&b.statements)
+    };
+    let mut paired = vec![None; wanted.len()];
+    let mut next = 0;
+    for (i, (_, rule)) in wanted.iter().enumerate() {
+        let candidates = || {
+            found[next..]
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| f.handle.is_some() && f.comment == rule.comment)
+        };
+        let pair = candidates().find(|(_, f)| same(f, rule)).or_else(|| {
+            candidates().find(|(_, f)| !wanted[i + 1..].iter().any(|(_, w)| same(f, w)))
+        });
+        if let Some((j, _)) = pair {
+            paired[i] = Some(next + j);
+            next += j + 1;
+        }
+    }
+
+    let mut changes = vec![];
+
+    for (j, rule) in found.iter().enumerate() {
+        if let Some(handle) = rule.handle
+            && !paired.contains(&Some(j))
+        {
+            changes.push(Change::DeleteRule {
+                chain,
+                handle,
+                comment: rule.comment.clone(),
+            });
+        }
+    }
+
+    for (i, (compiled, rule)) in wanted.iter().enumerate() {
+        match paired[i].map(|j| &found[j]) {
+            Some(live) => {
+                if !same(live, rule) {
+                    changes.push(Change::ReplaceRule {
+                        rule: (*compiled).clone(),
+                        handle: live.handle.expect("only rules with a handle are paired"),
+                    });
+                }
+            }
+            None => changes.push(Change::AddRule {
+                rule: (*compiled).clone(),
+                before: paired[i..]
+                    .iter()
+                    .flatten()
+                    .next()
+                    .and_then(|j| found[*j].handle),
+            }),
+        }
+    }
+
+    changes
+}
+
+// Reasons the live table can't be changed in place. Base chains can't be
+// moved to another hook or priority and sets can't change their type.
+fn incompatible(desired: &Table, live: &Table) -> Option<String> {
+    for chain in &desired.chains {
+        let (Some(wanted), Some(found)) = (&chain.base, live.chain(&chain.name)) else {
+            continue;
+        };
+        let same_hook = found.base.as_ref().is_some_and(|base| {
+            base.chain_type == wanted.chain_type
+                && base.hook == wanted.hook
+                && base.priority == wanted.priority
+                && base.device == wanted.device
+        });
+        if !same_hook {
+            return Some(format!("chain {} is hooked differently", chain.name));
+        }
+    }
+
+    for set in &desired.sets {
+        if let Some(found) = live.set(&set.name)
+            && (found.key != set.key || found.flags != set.flags)
+        {
+            return Some(format!("set {} has a different type", set.name));
+        }
+    }
+
+    None
+}
+
+// Statements of a rule in the form they are compared in, so live rules
+// compare equal to the compiled ones they were created from. Counters and
+// quotas of live rules hold the traffic they matched so far, and nft lists
+// some statements differently from how they are written: the protocol match
+// `tcp dport` implies may come back as `meta l4proto tcp` followed by a load
+// of the transport header, and the family match an address implies may be
+// kept.
+fn normalized(statements: &[Statement<'static>]) -> Vec<Statement<'static>> {
+    let protocol = statements.iter().find_map(|statement| match statement {
+        Statement::Match(m) if is_meta(&m.left, MetaKey::L4proto) => match &m.right {
+            Expression::String(protocol) => Some(protocol.to_string()),
+            _ => None,
+        },
+        _ => None,
+    });
+    let statements: Vec<Statement<'static>> = statements
+        .iter()
+        .map(|statement| normalized_statement(statement, protocol.as_deref()))
+        .collect();
+
+    let field_matched = |fields: [&str; 2]| {
+        statements.iter().any(|statement| match statement {
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Payload(Payload::PayloadField(field))),
+                ..
+            }) => fields.contains(&field.field.as_ref()),
+            _ => false,
+        })
+    };
+    let ports = field_matched(["sport", "dport"]);
+    let addrs = field_matched(["saddr", "daddr"]);
+
+    statements
+        .iter()
+        .filter(|statement| match statement {
+            Statement::Match(m) if is_meta(&m.left, MetaKey::L4proto) => !ports,
+            Statement::Match(m) if is_meta(&m.left, MetaKey::Nfproto) => !addrs,
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+fn normalized_statement(
+    statement: &Statement<'static>,
+    protocol: Option<&str>,
+) -> Statement<'static> {
+    match statement {
+        Statement::Counter(Counter::Anonymous(_)) => {
+            Statement::Counter(Counter::Anonymous(Some(AnonymousCounter::default())))
+        }
+        Statement::Quota(QuotaOrQuotaRef::Quota(quota)) => {
+            Statement::Quota(QuotaOrQuotaRef::Quota(Quota {
+                used: None,
+                used_unit: None,
+                inv: quota.inv.filter(|inv| *inv),
+                ..quota.clone()
+            }))
+        }
+        Statement::Match(m) => Statement::Match(Match {
+            left: transport_field(&m.left, protocol),
+            right: single_item(&m.right),
+            op: m.op,
+        }),
+        Statement::Limit(limit) => Statement::Limit(normalized_limit(limit)),
+        // The timeout of a meter follows from its rate
+        Statement::Meter(meter) => Statement::Meter(Meter {
+            name: meter.name.clone(),
+            key: match &meter.key {
+                Expression::Named(NamedExpression::Elem(Elem { val, .. })) => (**val).clone(),
+                key => key.clone(),
+            },
+            stmt: Box::new(normalized_statement(&meter.stmt, protocol)),
+        }),
+        Statement::Masquerade(Some(nat))
+            if nat.addr.is_none()
+                && nat.port.is_none()
+                && nat.flags.as_ref().is_none_or(|flags| flags.is_empty()) =>
+        {
+            Statement::Masquerade(None)
+        }
+        Statement::Queue(queue) => Statement::Queue(Queue {
+            num: queue.num.clone(),
+            flags: queue.flags.clone().filter(|flags| !flags.is_empty()),
+        }),
+        statement => statement.clone(),
+    }
+}
+
+fn is_meta(expr: &Expression, key: MetaKey) -> bool {
+    matches!(expr, Expression::Named(NamedExpression::Meta(meta)) if meta.key == key)
+}
+
+// Ports loaded from the transport header, as `th dport` or the raw load of
+// the first four bytes, are written with the protocol matched before them
+fn transport_field(expr: &Expression<'static>, protocol: Option<&str>) -> Expression<'static> {
+    let field = match expr {
+        Expression::Named(NamedExpression::Payload(Payload::PayloadField(field)))
+            if field.protocol == "th" =>
+        {
+            field.field.to_string()
+        }
+        Expression::Named(NamedExpression::Payload(Payload::PayloadRaw(raw)))
+            if matches!(raw.base, PayloadBase::TH) && raw.len == 16 =>
+        {
+            match raw.offset {
+                0 => "sport".to_string(),
+                16 => "dport".to_string(),
+                _ => return expr.clone(),
+            }
+        }
+        _ => return expr.clone(),
+    };
+    let Some(protocol) = protocol else {
+        return expr.clone();
+    };
+
+    Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+        PayloadField {
+            protocol: protocol.to_string().into(),
+            field: field.into(),
+        },
+    )))
+}
+
+// A set or list of a single item is the same as the item
+fn single_item(expr: &Expression<'static>) -> Expression<'static> {
+    match expr {
+        Expression::Named(NamedExpression::Set(items)) => match items.as_slice() {
+            [SetItem::Element(item)] => item.clone(),
+            _ => expr.clone(),
+        },
+        Expression::List(items) if items.len() == 1 => items[0].clone(),
+        _ => expr.clone(),
+    }
+}
+
+// nft leaves out the unit, period and burst limits get by default
+fn normalized_limit(limit: &Limit<'static>) -> Limit<'static> {
+    let packets = matches!(limit.rate_unit.as_deref(), None | Some("packets"));
+    let burst = limit
+        .burst
+        .unwrap_or(if packets { DEFAULT_BURST as u32 } else { 0 });
+
+    Limit {
+        rate: limit.rate,
+        rate_unit: limit.rate_unit.clone().filter(|_| !packets),
+        per: Some(limit.per.clone().unwrap_or("second".into())),
+        burst: Some(burst),
+        burst_unit: (!packets && burst > 0)
+            .then(|| limit.burst_unit.clone().unwrap_or("bytes".into())),
+        inv: limit.inv.filter(|inv| *inv),
+    }
+}
+
+// The kernel merges adjacent intervals, so elements are compared as the
+// addresses they cover. None when an element isn't an address.
+fn element_ranges(elements: &[Expression]) -> Option<Vec<(u128, u128)>> {
+    let addr = |expr: &Expression| match expr {
+        Expression::String(addr) => addr.parse::<IpAddr>().ok(),
+        _ => None,
+    };
+
+    let ranges = elements
+        .iter()
+        .map(|element| match element {
+            Expression::Named(NamedExpression::Prefix(prefix)) => {
+                let len = prefix.len.try_into().ok()?;
+                let net = IpNet::new(addr(&prefix.addr)?, len).ok()?;
+                Some((number(net.network()), number(net.broadcast())))
+            }
+            Expression::Range(range) => Some((
+                number(addr(&range.range[0])?),
+                number(addr(&range.range[1])?),
+            )),
+            element => addr(element).map(|a| (number(a), number(a))),
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(merge_ranges(ranges))
+}
+
+fn number(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u128::from(u32::from(addr)),
+        IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+impl Diff {
+    pub(super) fn recreate(compiled: Compiled, reason: Option<String>) -> Self {
+        let mut changes = vec![];
+        if let Some(reason) = reason {
+            changes.push(Change::DeleteTable { reason });
+        }
+        changes.push(Change::AddTable);
+        changes.extend(
+            compiled
+                .chains()
+                .map(|(chain, hook)| Change::AddChain { chain, hook }),
+        );
+        changes.extend(
+            compiled
+                .sets
+                .iter()
+                .map(|set| Change::AddSet { set: set.clone() }),
+        );
+        changes.extend(compiled.rules.iter().map(|rule| Change::AddRule {
+            rule: rule.clone(),
+            before: None,
+        }));
+
+        Self { compiled, changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // Whether the whole table is created again
+    pub fn recreates_table(&self) -> bool {
+        self.changes.contains(&Change::AddTable)
+    }
+
+    // Batch making the changes, a table created again uses the same batch
+    // as applying the rules file from scratch
+    pub fn batch(&self) -> TrackedBatch {
+        if self.recreates_table() {
+            return table_batch(&self.compiled);
+        }
+
+        let mut batch = TrackedBatch::new();
+        let table = managed_table();
+
+        let chains: Vec<nftnlChain> = self
+            .compiled
+            .chains()
+            .map(|(name, hook)| base_chain(&table, name, hook))
+            .collect();
+        let chain = |name: &str| {
+            chains
+                .iter()
+                .find(|c| c.get_name().to_str() == Ok(name))
+                .expect("only managed chains are changed")
+        };
+
+        // Rules looking up a set that isn't part of the batch find the
+        // existing one by its name
+        let mut set_id = 0;
+        let addr_sets = addr_sets(&table, &self.compiled.sets, &mut set_id);
+        let addr_set = |name: &str| {
+            addr_sets
+                .iter()
+                .find(|s| s.name().to_str() == Ok(name))
+                .expect("changed sets are compiled from the rules file")
+        };
+
+        for change in &self.changes {
+            match change {
+                Change::AddTable | Change::DeleteTable { .. } => {}
+                Change::AddChain { chain: name, .. } | Change::UpdateChain { chain: name, .. } => {
+                    batch.add(chain(name), MsgType::Add, format!("chain {name}"));
+                }
+                Change::DeleteChain { chain: name } => {
+                    let msg =
+                        nftnlChain::new(&CString::new(name.as_str()).unwrap_or_default(), &table);
+                    batch.add(&msg, MsgType::Del, format!("chain {name}"));
+                }
+                Change::AddSet { set } => {
+                    let msg = addr_set(&set.name);
+                    let set_name = format!("set {}", set.name);
+                    batch.add(msg, MsgType::Add, &set_name);
+                    for elems in msg.elems() {
+                        batch.add(&elems, MsgType::Add, format!("elements of {set_name}"));
+                    }
+                }
+                Change::UpdateSet { set, .. } => {
+                    let msg = addr_set(&set.name);
+                    let set_name = format!("set {}", set.name);
+                    batch.add(
+                        &msg.flush(),
+                        MsgType::Del,
+                        format!("elements of {set_name}"),
+                    );
+                    for elems in msg.elems() {
+                        batch.add(&elems, MsgType::Add, format!("elements of {set_name}"));
+                    }
+                }
+                Change::DeleteSet { set } => {
+                    // Only the name identifies the set to delete
+                    let msg = SetMsg::named(&table, set, 0, SetKey::Ipv4Addr);
+                    batch.add(&msg, MsgType::Del, format!("set {set}"));
+                }
+                Change::DeleteRule {
+                    chain: name,
+                    handle,
+                    ..
+                } => {
+                    let mut msg = RuleMsg::new(chain(name));
+                    msg.set_handle((*handle).into());
+                    batch.add(
+                        &msg,
+                        MsgType::Del,
+                        format!("rule handle {handle} in chain {name}"),
+                    );
+                }
+                Change::AddRule { rule, before } => {
+                    let placement =
+                        before.map_or(Placement::Append, |h| Placement::Before(h.into()));
+                    add_rule(
+                        &mut batch,
+                        chain(rule.chain),
+                        rule,
+                        AddrSets::Named(&addr_sets),
+                        &mut set_id,
+                        placement,
+                    );
+                }
+                Change::ReplaceRule { rule, handle } => {
+                    let placement = Placement::Replace((*handle).into());
+                    add_rule(
+                        &mut batch,
+                        chain(rule.chain),
+                        rule,
+                        AddrSets::Named(&addr_sets),
+                        &mut set_id,
+                        placement,
+                    );
+                }
+            }
+        }
+
+        batch
+    }
+}
+
+// One line per change, `-` for what is removed from the live table and `+`
+// for what is added to it, in nft syntax
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            match change {
+                Change::AddTable => writeln!(f, "+ table inet {MANAGED_TABLE}")?,
+                Change::DeleteTable { reason } => {
+                    writeln!(f, "- table inet {MANAGED_TABLE} ({reason})")?
+                }
+                Change::AddChain { chain, hook } => writeln!(
+                    f,
+                    "+ chain {chain} {{ {} policy accept; }}",
+                    chain_spec(*hook)
+                )?,
+                Change::UpdateChain {
+                    chain,
+                    hook,
+                    policy,
+                } => {
+                    let spec = chain_spec(*hook);
+                    let policy = match policy {
+                        NfChainPolicy::Accept => "accept",
+                        NfChainPolicy::Drop => "drop",
+                    };
+                    writeln!(f, "- chain {chain} {{ {spec} policy {policy}; }}")?;
+                    writeln!(f, "+ chain {chain} {{ {spec} policy accept; }}")?;
+                }
+                Change::DeleteChain { chain } => writeln!(f, "- chain {chain}")?,
+                Change::AddSet { set } => {
+                    let elements: Vec<String> = set.nets.iter().map(format_network).collect();
+                    writeln!(
+                        f,
+                        "+ set {} {{ type {}; flags interval; elements = {{ {} }} }}",
+                        set.name,
+                        set_type_name(set.family),
+                        elements.join(", ")
+                    )?;
+                }
+                Change::UpdateSet {
+                    set,
+                    added,
+                    removed,
+                } => {
+                    if !removed.is_empty() {
+                        writeln!(f, "- element {} {{ {} }}", set.name, removed.join(", "))?;
+                    }
+                    if !added.is_empty() {
+                        writeln!(f, "+ element {} {{ {} }}", set.name, added.join(", "))?;
+                    }
+                }
+                Change::DeleteSet { set } => writeln!(f, "- set {set}")?,
+                Change::DeleteRule {
+                    chain,
+                    handle,
+                    comment,
+                } => {
+                    write!(f, "- rule {chain} handle {handle}")?;
+                    match comment {
+                        Some(comment) => writeln!(f, " comment {}", quote(comment))?,
+                        None => writeln!(f)?,
+                    }
+                }
+                Change::AddRule { rule, .. } => {
+                    writeln!(f, "+ rule {} {}", rule.chain, nft_rule(rule))?
+                }
+                Change::ReplaceRule { rule, handle } => {
+                    writeln!(
+                        f,
+                        "- rule {} handle {handle} comment {}",
+                        rule.chain,
+                        quote(&rule.comment)
+                    )?;
+                    writeln!(
+                        f,
+                        "+ rule {} handle {handle} {}",
+                        rule.chain,
+                        nft_rule(rule)
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules;
+    use nftables::{
+        expr::{Meta, PayloadRaw},
+        stmt::Operator,
+    };
+    use std::path::Path;
+
+    const RULES: &str = r#"
+version = 2
+
+[[rules]]
+name = "ssh-from-lan"
+direction = "input"
+protocol = "tcp"
+sources = ["10.0.0.0/8", "192.168.0.0/16", "fd00::/8"]
+dports = [22]
+verdict = "accept"
+
+[[rules]]
+name = "web"
+direction = "input"
+dports = ["80", "443", "8000-8100"]
+verdict = "accept"
+
+[[rules]]
+name = "no-ssh-elsewhere"
+direction = "input"
+protocol = "tcp"
+dports = [22]
+verdict = "drop"
+"#;
+
+    fn compiled(contents: &str) -> Compiled {
+        compile(&rules::parse(Path::new("rules.toml"), contents).unwrap())
+    }
+
+    // The compiled table as it is listed once applied, with handles and the
+    // given counts in its counters
+    fn listed(compiled: &Compiled, packets: usize) -> Table {
+        let mut table = to_ruleset(compiled).tables.remove(0);
+        let mut handle = 0;
+        for chain in &mut table.chains {
+            handle += 1;
+            chain.handle = Some(handle);
+            for rule in &mut chain.rules {
+                handle += 1;
+                rule.handle = Some(handle);
+                for statement in &mut rule.statements {
+                    if let Statement::Counter(Counter::Anonymous(counter)) = statement {
+                        *counter = Some(AnonymousCounter {
+                            packets: Some(packets),
+                            bytes: Some(packets * 100),
+                        });
+                    }
+                }
+            }
+        }
+
+        table
+    }
+
+    // The forms nft may list the statements of the table in: ports loaded
+    // from the transport header after the protocol match, addresses after
+    // the family match, single ports as a set and limits without the default
+    // burst
+    fn as_nft_lists(table: &mut Table) {
+        let meta_match = |key: MetaKey, value: &str| {
+            Statement::Match(Match {
+                left: Expression::Named(NamedExpression::Meta(Meta { key })),
+                right: Expression::String(value.to_string().into()),
+                op: Operator::EQ,
+            })
+        };
+
+        for chain in &mut table.chains {
+            for rule in &mut chain.rules {
+                let mut statements = vec![];
+                for statement in rule.statements.drain(..) {
+                    let mut statement = statement;
+                    if let Statement::Match(m) = &mut statement
+                        && let Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+                            field,
+                        ))) = &m.left
+                    {
+                        match (field.protocol.as_ref(), field.field.as_ref()) {
+                            ("ip", _) => statements.push(meta_match(MetaKey::Nfproto, "ipv4")),
+                            ("ip6", _) => statements.push(meta_match(MetaKey::Nfproto, "ipv6")),
+                            (protocol, port) => {
+                                statements.push(meta_match(MetaKey::L4proto, protocol));
+                                m.left = Expression::Named(NamedExpression::Payload(
+                                    Payload::PayloadRaw(PayloadRaw {
+                                        base: PayloadBase::TH,
+                                        offset: if port == "sport" { 0 } else { 16 },
+                                        len: 16,
+                                    }),
+                                ));
+                                if let Expression::Number(_) = m.right {
+                                    m.right = Expression::Named(NamedExpression::Set(vec![
+                                        SetItem::Element(m.right.clone()),
+                                    ]));
+                                }
+                            }
+                        }
+                    }
+                    if let Statement::Limit(limit) = &mut statement
+                        && limit.burst == Some(DEFAULT_BURST as u32)
+                    {
+                        limit.burst = None;
+                    }
+                    statements.push(statement);
+                }
+                rule.statements = statements;
+            }
+        }
+    }
+
+    fn handle_of(table: &Table, comment: &str) -> u32 {
+        table.chains[0]
+            .rules
+            .iter()
+            .find(|rule| rule.comment.as_deref() == Some(comment))
+            .and_then(|rule| rule.handle)
+            .unwrap()
+    }
+
+    #[test]
+    fn unchanged_rules_file() {
+        let compiled = compiled(RULES);
+        let live = listed(&compiled, 12);
+
+        let diff = diff(compiled, Some(&live));
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    #[test]
+    fn rules_listed_differently_are_unchanged() {
+        let contents = format!(
+            "{RULES}{}",
+            r#"
+[[rules]]
+name = "ping"
+direction = "input"
+protocol = "icmp"
+limit = "10/second"
+verdict = "accept"
+"#
+        );
+        let mut live = listed(&compiled(&contents), 12);
+        as_nft_lists(&mut live);
+
+        let diff = diff(compiled(&contents), Some(&live));
+        assert!(diff.is_empty(), "{diff}");
+
+        // Differences are still found in the listed form
+        let changed = compiled(&contents.replacen("dports = [22]", "dports = [2222]", 1));
+        let changed = super::diff(changed, Some(&live));
+        assert_eq!(changed.changes.len(), 2, "{changed}");
+        assert!(
+            changed
+                .changes
+                .iter()
+                .all(|c| matches!(c, Change::ReplaceRule { .. }))
+        );
+    }
+
+    #[test]
+    fn missing_table_is_created() {
+        let compiled = compiled(RULES);
+
+        let diff = diff(compiled, None);
+        assert!(diff.recreates_table());
+        assert!(
+            !diff
+                .changes
+                .iter()
+                .any(|c| matches!(c, Change::DeleteTable { .. }))
+        );
+    }
+
+    #[test]
+    fn changed_rule_is_replaced() {
+        let live = listed(&compiled(RULES), 12);
+        let compiled = compiled(&RULES.replacen("dports = [22]", "dports = [2222]", 1));
+
+        let diff = diff(compiled, Some(&live));
+        let handle = handle_of(&live, "ssh-from-lan");
+        let replaced: Vec<u32> = diff
+            .changes
+            .iter()
+            .map(|change| match change {
+                Change::ReplaceRule { handle, .. } => *handle,
+                change => panic!("unexpected change {change:?}"),
+            })
+            .collect();
+        // One rule for each family
+        assert_eq!(replaced, [handle, handle + 1]);
+    }
+
+    #[test]
+    fn removed_and_added_rules() {
+        let live = listed(&compiled(RULES), 12);
+        let contents = RULES.replace("name = \"web\"", "name = \"www\"");
+        let compiled = compiled(&contents);
+
+        let diff = diff(compiled, Some(&live));
+        let before = handle_of(&live, "no-ssh-elsewhere");
+        let deleted = diff
+            .changes
+            .iter()
+            .filter(|c| matches!(c, Change::DeleteRule { comment, .. } if comment.as_deref() == Some("web")))
+            .count();
+        let added: Vec<Option<u32>> = diff
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::AddRule { rule, before } if rule.name == "www" => Some(*before),
+                _ => None,
+            })
+            .collect();
+        // The ports of tcp and udp
+        assert_eq!(deleted, 2);
+        assert_eq!(added, [Some(before), Some(before)]);
+    }
+
+    #[test]
+    fn changed_set_is_updated() {
+        let live = listed(&compiled(RULES), 12);
+        let contents = RULES.replace("192.168.0.0/16", "172.16.0.0/12");
+        let compiled = compiled(&contents);
+
+        let diff = diff(compiled, Some(&live));
+        match diff.changes.as_slice() {
+            [Change::UpdateSet { added, removed, .. }] => {
+                assert_eq!(added, &["172.16.0.0/12"]);
+                assert_eq!(removed, &["192.168.0.0/16"]);
+            }
+            changes => panic!("unexpected changes {changes:?}"),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use super::{
+    BaseChain, Error, Result, TrackedBatch,
+    compiler::{AddrSets, add_rule, compile},
+    nlmsg::{Placement, RenameChainMsg, RuleMsg},
+    send_and_process_batch,
+};
+use crate::rules::{Rule, RulesFile};
+
+use nftables::types::{NfChainPolicy, NfChainType, NfFamily, NfHook};
+use nftnl::{
+    Chain as nftnlChain, ChainType as nftnlChainType, Hook as nftnlHook, MsgType,
+    Policy as nftnlPolicy, ProtoFamily, Table as nftnlTable,
+};
+use std::ffi::CString;
+
+// Single changes to rules and chains of any table, made in place. Rules are
+// found by the handle the kernel gave them, which `nft -a list ruleset` and
+// the Ruleset model show.
+
+// Where a rule goes in its chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Position {
+    First,
+    Last,
+    // Before or after the rule with this handle
+    Before(u32),
+    After(u32),
+}
+
+// Adds the rule to the chain. A rule from the rules file can expand into
+// several nftables rules, which are kept together and in order.
+pub fn insert_rule(
+    family: NfFamily,
+    table: &str,
+    chain: &str,
+    rule: &Rule,
+    position: Position,
+) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let chain = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    // Rules put in front of the same position end up in reverse order
+    let placement = match position {
+        Position::First => Placement::Prepend,
+        Position::Last => Placement::Append,
+        Position::Before(handle) => Placement::Before(handle.into()),
+        Position::After(handle) => Placement::After(handle.into()),
+    };
+    let reverse = matches!(position, Position::First | Position::After(_));
+
+    let mut batch = TrackedBatch::new();
+    add_rules(&mut batch, &chain, rule, reverse, |_| placement)?;
+
+    send_and_process_batch(batch)
+}
+
+// Replaces the rule with the given handle, keeping its position. Further
+// rules the new one expands into follow right after it.
+pub fn replace_rule(
+    family: NfFamily,
+    table: &str,
+    chain: &str,
+    handle: u32,
+    rule: &Rule,
+) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let chain = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    let mut batch = TrackedBatch::new();
+    add_rules(&mut batch, &chain, rule, true, |i| match i {
+        0 => Placement::Replace(handle.into()),
+        _ => Placement::After(handle.into()),
+    })?;
+
+    send_and_process_batch(batch)
+}
+
+pub fn delete_rule(family: NfFamily, table: &str, chain: &str, handle: u32) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let chain = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    let mut msg = RuleMsg::new(&chain);
+    msg.set_handle(handle.into());
+
+    let mut batch = TrackedBatch::new();
+    batch.add(&msg, MsgType::Del, format!("rule handle {handle}"));
+
+    send_and_process_batch(batch)
+}
+
+// Creates a regular chain, or a base chain when given a hook
+pub fn create_chain(
+    family: NfFamily,
+    table: &str,
+    chain: &str,
+    base: Option<&BaseChain>,
+) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let mut msg = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    if let Some(base) = base {
+        let hook = match base.hook {
+            NfHook::Prerouting => nftnlHook::PreRouting,
+            NfHook::Input => nftnlHook::In,
+            NfHook::Forward => nftnlHook::Forward,
+            NfHook::Output => nftnlHook::Out,
+            NfHook::Postrouting => nftnlHook::PostRouting,
+            NfHook::Ingress | NfHook::Egress => {
+                return Err(Error::rejected(
+                    format!("chain {chain}"),
+                    "ingress and egress hooks are not supported",
+                ));
+            }
+        };
+        msg.set_hook(hook, base.priority);
+        msg.set_type(match base.chain_type {
+            NfChainType::Filter => nftnlChainType::Filter,
+            NfChainType::Route => nftnlChainType::Route,
+            NfChainType::NAT => nftnlChainType::Nat,
+        });
+        msg.set_policy(match base.policy {
+            NfChainPolicy::Accept => nftnlPolicy::Accept,
+            NfChainPolicy::Drop => nftnlPolicy::Drop,
+        });
+    }
+
+    let mut batch = TrackedBatch::new();
+    batch.add(&msg, MsgType::Add, format!("chain {chain}"));
+
+    send_and_process_batch(batch)
+}
+
+// The kernel only renames chains found by their handle
+pub fn rename_chain(family: NfFamily, table: &str, handle: u32, name: &str) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let msg = RenameChainMsg::new(&table, handle.into(), &c_name("chain", name)?);
+
+    let mut batch = TrackedBatch::new();
+    batch.add(
+        &msg,
+        MsgType::Add,
+        format!("new name {name} of chain handle {handle}"),
+    );
+
+    send_and_process_batch(batch)
+}
+
+// Deletes every rule of the chain
+pub fn flush_chain(family: NfFamily, table: &str, chain: &str) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let chain_msg = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    // A rule deletion without a handle applies to the whole chain
+    let msg = RuleMsg::new(&chain_msg);
+    let mut batch = TrackedBatch::new();
+    batch.add(&msg, MsgType::Del, format!("rules of chain {chain}"));
+
+    send_and_process_batch(batch)
+}
+
+// Deletes the chain along with its rules, the kernel refuses while other
+// rules jump to it
+pub fn delete_chain(family: NfFamily, table: &str, chain: &str) -> Result<()> {
+    let table = nftnl_table(family, table)?;
+    let msg = nftnlChain::new(&c_name("chain", chain)?, &table);
+
+    let mut batch = TrackedBatch::new();
+    batch.add(&msg, MsgType::Del, format!("chain {chain}"));
+
+    send_and_process_batch(batch)
+}
+
+// Adds the nftables rules the rule expands into, placing the i-th of them
+// with placement(i)
+fn add_rules(
+    batch: &mut TrackedBatch,
+    chain: &nftnlChain,
+    rule: &Rule,
+    reverse: bool,
+    placement: impl Fn(usize) -> Placement,
+) -> Result<()> {
+    let rule_name = format!("rule \"{}\"", rule.name);
+    rule.validate()
+        .map_err(|e| Error::rejected(&rule_name, format!("{e:#}")))?;
+
+    let compiled = compile(&RulesFile {
+        rules: vec![rule.clone()],
+        ..RulesFile::default()
+    });
+    if compiled.rules.is_empty() {
+        return Err(Error::rejected(
+            rule_name,
+            "its addresses leave no family to match",
+        ));
+    }
+
+    let mut set_id = 0;
+    let mut expanded: Vec<_> = compiled.rules.iter().enumerate().collect();
+    if reverse {
+        expanded.reverse();
+    }
+    for (i, compiled_rule) in expanded {
+        add_rule(
+            batch,
+            chain,
+            compiled_rule,
+            AddrSets::Anonymous(&compiled.sets),
+            &mut set_id,
+            placement(i),
+        );
+    }
+
+    Ok(())
+}
+
+fn nftnl_table(family: NfFamily, name: &str) -> Result<nftnlTable> {
+    let family = match family {
+        NfFamily::IP => ProtoFamily::Ipv4,
+        NfFamily::IP6 => ProtoFamily::Ipv6,
+        NfFamily::INet => ProtoFamily::Inet,
+        NfFamily::ARP => ProtoFamily::Arp,
+        NfFamily::Bridge => ProtoFamily::Bridge,
+        NfFamily::NetDev => ProtoFamily::NetDev,
+    };
+
+    Ok(nftnlTable::new(&c_name("table", name)?, family))
+}
+
+fn c_name(kind: &str, name: &str) -> Result<CString> {
+    CString::new(name).map_err(|e| Error::rejected(format!("{kind} {name}"), e))
+}
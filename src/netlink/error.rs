@@ -0,0 +1,107 @@
+use nftables::helper::NftablesError;
+use rustables::error::QueryError;
+use std::{fmt, io};
+
+// Errors from talking to netfilter, either directly over netlink or through
+// the nft executable
+#[derive(Debug)]
+pub enum Error {
+    // Changing or listing the ruleset needs CAP_NET_ADMIN
+    PermissionDenied { action: String },
+    // The kernel or nft refused the object
+    Rejected { object: String, reason: String },
+    // A table, chain, rule or interface that doesn't exist
+    Missing { object: String },
+    // Output of nft or a netlink message that couldn't be read
+    Parse { what: String, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn rejected(object: impl Into<String>, reason: impl fmt::Display) -> Self {
+        Error::Rejected {
+            object: object.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn parse(what: impl Into<String>, reason: impl fmt::Display) -> Self {
+        Error::Parse {
+            what: what.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    // Sorts a failed system call on object by its errno
+    pub fn from_io(object: impl Into<String>, e: io::Error) -> Self {
+        let object = object.into();
+
+        match e.raw_os_error() {
+            Some(libc::EPERM | libc::EACCES) => Error::PermissionDenied {
+                action: format!("accessing {object}"),
+            },
+            Some(libc::ENOENT | libc::ENODEV) => Error::Missing { object },
+            _ => Error::rejected(object, e),
+        }
+    }
+
+    pub fn from_nft(action: impl Into<String>, e: NftablesError) -> Self {
+        let action = action.into();
+
+        match e {
+            NftablesError::NftExecution { inner, .. } => match inner.kind() {
+                io::ErrorKind::NotFound => Error::Missing {
+                    object: "nft executable".to_string(),
+                },
+                io::ErrorKind::PermissionDenied => Error::PermissionDenied { action },
+                _ => Error::rejected(action, inner),
+            },
+            NftablesError::NftOutputEncoding { inner, .. } => Error::parse("nft output", inner),
+            NftablesError::NftInvalidJson(inner) => Error::parse("nft output", inner),
+            NftablesError::NftFailed { stderr, .. } => {
+                let stderr = stderr.trim();
+                if stderr.contains("Operation not permitted") {
+                    Error::PermissionDenied { action }
+                } else {
+                    Error::rejected(action, stderr)
+                }
+            }
+        }
+    }
+
+    pub fn from_query(object: impl Into<String>, e: QueryError) -> Self {
+        let object = object.into();
+
+        match e {
+            QueryError::NetlinkOpenError(errno)
+            | QueryError::NetlinkSendError(errno)
+            | QueryError::NetlinkRecvError(errno) => {
+                Error::from_io(object, io::Error::from_raw_os_error(errno as i32))
+            }
+            QueryError::NetlinkError(err) => {
+                Error::from_io(object, io::Error::from_raw_os_error(-err.error))
+            }
+            e @ (QueryError::ProcessNetlinkError(_) | QueryError::BuilderError(_)) => {
+                Error::parse(object, e)
+            }
+            e => Error::rejected(object, e),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PermissionDenied { action } => write!(
+                f,
+                "permission denied while {action}, firewall-rs has to run as root"
+            ),
+            Error::Rejected { object, reason } => write!(f, "{object} was rejected: {reason}"),
+            Error::Missing { object } => write!(f, "{object} does not exist"),
+            Error::Parse { what, reason } => write!(f, "unable to parse {what}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
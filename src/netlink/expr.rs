@@ -0,0 +1,305 @@
+use super::nlmsg::SetMsg;
+use crate::rules;
+
+use nftnl::{
+    Rule as nftnlRule,
+    expr::Expression,
+    nftnl_sys::{self as sys, libc},
+};
+use std::ffi::{CString, c_void};
+
+// Expressions that nftnl does not provide a wrapper for, built directly
+// on top of the libnftnl bindings
+
+// Logs the matching packet with an optional prefix, through the kernel log
+// or to the NFLOG group when given one
+pub struct Log {
+    prefix: Option<CString>,
+    group: Option<u16>,
+}
+
+impl Log {
+    pub fn new(prefix: Option<&str>, group: Option<u16>) -> Self {
+        Self {
+            prefix: prefix.and_then(|p| CString::new(p).ok()),
+            group,
+        }
+    }
+}
+
+impl Expression for Log {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"log".as_ptr());
+
+            if let Some(prefix) = &self.prefix {
+                sys::nftnl_expr_set_str(expr, sys::NFTNL_EXPR_LOG_PREFIX as u16, prefix.as_ptr());
+            }
+            if let Some(group) = self.group {
+                sys::nftnl_expr_set_u16(expr, sys::NFTNL_EXPR_LOG_GROUP as u16, group);
+            }
+
+            expr
+        }
+    }
+}
+
+// Hands the packet to the program reading the queue. With bypass the
+// packet is accepted instead of dropped while no program reads it.
+pub struct Queue {
+    number: u16,
+    bypass: bool,
+}
+
+impl Queue {
+    pub fn new(number: u16, bypass: bool) -> Self {
+        Self { number, bypass }
+    }
+}
+
+impl Expression for Queue {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"queue".as_ptr());
+
+            sys::nftnl_expr_set_u16(expr, sys::NFTNL_EXPR_QUEUE_NUM as u16, self.number);
+            sys::nftnl_expr_set_u16(expr, sys::NFTNL_EXPR_QUEUE_TOTAL as u16, 1);
+            if self.bypass {
+                sys::nftnl_expr_set_u16(
+                    expr,
+                    sys::NFTNL_EXPR_QUEUE_FLAGS as u16,
+                    libc::NFT_QUEUE_FLAG_BYPASS as u16,
+                );
+            }
+
+            expr
+        }
+    }
+}
+
+// Matches when the value loaded into the first register is in the set.
+// nftnl::expr::Lookup only works with nftnl's own sets.
+pub struct Lookup {
+    set: CString,
+    set_id: u32,
+}
+
+impl Lookup {
+    pub fn new(set: &SetMsg) -> Self {
+        Self {
+            set: set.name().clone(),
+            set_id: set.id(),
+        }
+    }
+}
+
+impl Expression for Lookup {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"lookup".as_ptr());
+
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_LOOKUP_SREG as u16,
+                libc::NFT_REG_1 as u32,
+            );
+            sys::nftnl_expr_set_str(expr, sys::NFTNL_EXPR_LOOKUP_SET as u16, self.set.as_ptr());
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_LOOKUP_SET_ID as u16, self.set_id);
+
+            expr
+        }
+    }
+}
+
+// Attributes of the range expression, which the bindings for libnftnl 1.0.6
+// don't have yet
+const NFTNL_EXPR_RANGE_SREG: u16 = 1;
+const NFTNL_EXPR_RANGE_OP: u16 = 2;
+const NFTNL_EXPR_RANGE_FROM_DATA: u16 = 3;
+const NFTNL_EXPR_RANGE_TO_DATA: u16 = 4;
+
+// Matches when the value loaded into the first register is between first
+// and last, both included. nft compiles ranges like `tcp dport 8000-8100`
+// to it.
+pub struct Range {
+    first: Vec<u8>,
+    last: Vec<u8>,
+}
+
+impl Range {
+    pub fn new(first: &[u8], last: &[u8]) -> Self {
+        Self {
+            first: first.to_vec(),
+            last: last.to_vec(),
+        }
+    }
+}
+
+impl Expression for Range {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"range".as_ptr());
+
+            sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_RANGE_SREG, libc::NFT_REG_1 as u32);
+            sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_RANGE_OP, libc::NFT_RANGE_EQ as u32);
+            sys::nftnl_expr_set(
+                expr,
+                NFTNL_EXPR_RANGE_FROM_DATA,
+                self.first.as_ptr() as *const c_void,
+                self.first.len() as u32,
+            );
+            sys::nftnl_expr_set(
+                expr,
+                NFTNL_EXPR_RANGE_TO_DATA,
+                self.last.as_ptr() as *const c_void,
+                self.last.len() as u32,
+            );
+
+            expr
+        }
+    }
+}
+
+// Matches while the packets or bytes stay below the rate, or once they go
+// over it when inverted. The burst is taken on top of the rate.
+pub struct Limit {
+    rate: u64,
+    // Seconds the rate is counted over
+    unit: u64,
+    burst: u32,
+    bytes: bool,
+    over: bool,
+}
+
+impl Limit {
+    pub fn new(limit: &rules::Limit) -> Self {
+        Self {
+            rate: limit.rate,
+            unit: limit.per.seconds(),
+            burst: limit.burst_or_default().try_into().unwrap_or(u32::MAX),
+            bytes: limit.bytes,
+            over: limit.over,
+        }
+    }
+}
+
+impl Expression for Limit {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        let limit_type = if self.bytes {
+            libc::NFT_LIMIT_PKT_BYTES
+        } else {
+            libc::NFT_LIMIT_PKTS
+        };
+
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"limit".as_ptr());
+
+            sys::nftnl_expr_set_u64(expr, sys::NFTNL_EXPR_LIMIT_RATE as u16, self.rate);
+            sys::nftnl_expr_set_u64(expr, sys::NFTNL_EXPR_LIMIT_UNIT as u16, self.unit);
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_LIMIT_BURST as u16, self.burst);
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_LIMIT_TYPE as u16, limit_type as u32);
+            if self.over {
+                sys::nftnl_expr_set_u32(
+                    expr,
+                    sys::NFTNL_EXPR_LIMIT_FLAGS as u16,
+                    libc::NFT_LIMIT_F_INV as u32,
+                );
+            }
+
+            expr
+        }
+    }
+}
+
+// Attributes of the quota expression, which the bindings for libnftnl 1.0.6
+// don't have yet
+const NFTNL_EXPR_QUOTA_BYTES: u16 = 1;
+const NFTNL_EXPR_QUOTA_FLAGS: u16 = 2;
+
+// Matches until the rule went through that many bytes, or only after that
+// when inverted
+pub struct Quota {
+    bytes: u64,
+    over: bool,
+}
+
+impl Quota {
+    pub fn new(quota: &rules::Quota) -> Self {
+        Self {
+            bytes: quota.bytes,
+            over: quota.over,
+        }
+    }
+}
+
+impl Expression for Quota {
+    fn to_expr(&self, _rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"quota".as_ptr());
+
+            sys::nftnl_expr_set_u64(expr, NFTNL_EXPR_QUOTA_BYTES, self.bytes);
+            if self.over {
+                sys::nftnl_expr_set_u32(expr, NFTNL_EXPR_QUOTA_FLAGS, libc::NFT_QUOTA_F_INV as u32);
+            }
+
+            expr
+        }
+    }
+}
+
+// Adds the address loaded into the first register to the meter set, or
+// refreshes it, and matches when the limit kept for that address does.
+// Addresses are dropped from the set once they have been quiet for the
+// timeout.
+pub struct Meter {
+    set: CString,
+    set_id: u32,
+    timeout_ms: u64,
+    limit: Limit,
+}
+
+impl Meter {
+    pub fn new(set: &SetMsg, timeout_ms: u64, limit: Limit) -> Self {
+        Self {
+            set: set.name().clone(),
+            set_id: set.id(),
+            timeout_ms,
+            limit,
+        }
+    }
+}
+
+impl Expression for Meter {
+    fn to_expr(&self, rule: &nftnlRule) -> *mut sys::nftnl_expr {
+        unsafe {
+            let expr = sys::nftnl_expr_alloc(c"dynset".as_ptr());
+
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_DYNSET_SREG_KEY as u16,
+                libc::NFT_REG_1 as u32,
+            );
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_DYNSET_OP as u16,
+                libc::NFT_DYNSET_OP_UPDATE as u32,
+            );
+            sys::nftnl_expr_set_str(
+                expr,
+                sys::NFTNL_EXPR_DYNSET_SET_NAME as u16,
+                self.set.as_ptr(),
+            );
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_DYNSET_SET_ID as u16, self.set_id);
+            sys::nftnl_expr_set_u64(expr, sys::NFTNL_EXPR_DYNSET_TIMEOUT as u16, self.timeout_ms);
+            // The dynset expression takes ownership of the limit
+            sys::nftnl_expr_set(
+                expr,
+                sys::NFTNL_EXPR_DYNSET_EXPR as u16,
+                self.limit.to_expr(rule) as *const c_void,
+                0,
+            );
+
+            expr
+        }
+    }
+}
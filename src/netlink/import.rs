@@ -0,0 +1,515 @@
+use super::{Chain, Ruleset, ruleset::family_name};
+use crate::rules::{
+    DEFAULT_BURST, Direction, Limit, LogSettings, Port, Protocol, Quota, Rule, RulesFile, Verdict,
+    parse_network,
+};
+
+use anyhow::{Result, anyhow, bail};
+use ipnet::IpNet;
+use nftables::{
+    expr::{Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField, SetItem},
+    stmt::{self, Match, Operator, QuotaOrQuotaRef, Statement},
+    types::{NfChainPolicy, NfChainType, NfHook},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+// Translates the live ruleset into a rules file. Only rules made of
+// address, port, protocol and interface matches followed by limit, quota,
+// counter, log and a verdict can be expressed in the rules file, anything
+// else is skipped.
+
+// An nftables rule or chain that could not be translated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skipped {
+    pub location: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.reason)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Import {
+    pub rules: RulesFile,
+    // Number of nftables rules in the ruleset
+    pub total: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+pub fn import_ruleset() -> super::Result<Import> {
+    Ok(translate(&Ruleset::read()?))
+}
+
+// Rules are imported in the order the kernel evaluates them, by the
+// priority of their base chain and then as listed. Only the rules of filter
+// base chains hooked to input, output or forward are imported.
+pub fn translate(ruleset: &Ruleset) -> Import {
+    let mut import = Import::default();
+    let mut names = HashSet::new();
+    // First base chain imported for each hook
+    let mut hooked: HashMap<Direction, String> = HashMap::new();
+
+    let mut chains: Vec<_> = ruleset
+        .tables
+        .iter()
+        .flat_map(|table| table.chains.iter().map(move |chain| (table, chain)))
+        .collect();
+    chains.sort_by_key(|(_, chain)| chain.base.as_ref().map(|b| b.priority));
+
+    for (table, chain) in chains {
+        let direction = direction(chain);
+        let chain_location = format!(
+            "{} {} {}",
+            family_name(table.family),
+            table.name,
+            chain.name
+        );
+
+        // The kernel runs every base chain of a hook, a packet accepted
+        // by one still goes through the next. Once imported into one
+        // chain the first accept ends it.
+        if let Some(direction) = direction {
+            match hooked.get(&direction) {
+                Some(first) => import.skipped.push(Skipped {
+                    location: chain_location.clone(),
+                    reason: format!(
+                        "shares the {direction} hook with {first}, its rules no longer see the packets accepted there"
+                    ),
+                }),
+                None => {
+                    hooked.insert(direction, chain_location.clone());
+                }
+            }
+        }
+
+        // Every imported rule ends up in a chain with an accept policy
+        if direction.is_some() && chain.base.as_ref().map(|b| b.policy) == Some(NfChainPolicy::Drop)
+        {
+            import.skipped.push(Skipped {
+                location: chain_location.clone(),
+                reason: "the drop policy of the chain is not imported, add a last rule dropping everything instead".to_string(),
+            });
+        }
+
+        for rule in &chain.rules {
+            import.total += 1;
+
+            let location = format!(
+                "{chain_location} handle {}",
+                rule.handle.map_or("?".to_string(), |h| h.to_string())
+            );
+            let Some(direction) = direction else {
+                import.skipped.push(Skipped {
+                    location,
+                    reason: "not in a filter chain hooked to input, output or forward".to_string(),
+                });
+                continue;
+            };
+
+            let name = unique_name(
+                &mut names,
+                &format!("{}-{}", table.name, chain.name),
+                rule.handle,
+            );
+            let mut imported = Rule::new(name, direction, Verdict::Continue, false);
+            imported.comment = rule.comment.clone();
+
+            match translate_rule(&mut imported, &rule.statements).and_then(|group| {
+                imported.validate()?;
+                set_log_group(&mut import.rules, group)
+            }) {
+                Ok(()) => import.rules.rules.push(imported),
+                Err(e) => import.skipped.push(Skipped {
+                    location,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    import
+}
+
+fn direction(chain: &Chain) -> Option<Direction> {
+    let base = chain.base.as_ref()?;
+    if base.chain_type != NfChainType::Filter {
+        return None;
+    }
+
+    match base.hook {
+        NfHook::Input => Some(Direction::Input),
+        NfHook::Output => Some(Direction::Output),
+        NfHook::Forward => Some(Direction::Forward),
+        _ => None,
+    }
+}
+
+// Rules are named after their table, chain and handle like "filter-input-5"
+fn unique_name(names: &mut HashSet<String>, prefix: &str, handle: Option<u32>) -> String {
+    let base = match handle {
+        Some(handle) => format!("{prefix}-{handle}"),
+        None => prefix.to_string(),
+    };
+
+    let name = std::iter::once(base.clone())
+        .chain((2..).map(|i| format!("{base}-{i}")))
+        .find(|name| !names.contains(name))
+        .expect("rule names run out");
+    names.insert(name.clone());
+
+    name
+}
+
+// The NFLOG group is set for the whole rules file, by the first imported
+// rule that logs
+fn set_log_group(rules: &mut RulesFile, group: Option<u16>) -> Result<()> {
+    let Some(group) = group else {
+        return Ok(());
+    };
+
+    match rules.nflog {
+        Some(settings) if settings.group != group => bail!(
+            "logs to group {group}, the rules imported before log to group {}",
+            settings.group
+        ),
+        _ => rules.nflog = Some(LogSettings { group }),
+    }
+
+    Ok(())
+}
+
+// Returns the NFLOG group the rule logs to
+fn translate_rule(rule: &mut Rule, statements: &[Statement]) -> Result<Option<u16>> {
+    let mut verdict = None;
+    let mut group = None;
+
+    for statement in statements {
+        if verdict.is_some() {
+            bail!("statements after the verdict are not supported");
+        }
+
+        match statement {
+            Statement::Match(m) => translate_match(rule, m)?,
+            Statement::Limit(_) | Statement::Quota(_)
+                if rule.limit.is_some() || rule.quota.is_some() =>
+            {
+                bail!("more than one limit or quota is not supported")
+            }
+            Statement::Limit(limit) => rule.limit = Some(limit_of(limit)?),
+            Statement::Quota(QuotaOrQuotaRef::Quota(quota)) => rule.quota = Some(quota_of(quota)?),
+            Statement::Counter(_) => rule.counter = true,
+            Statement::Log(_) if rule.log => bail!("more than one log is not supported"),
+            Statement::Log(log) => group = Some(log_of(rule, log.as_ref())?),
+            Statement::Accept(_) => verdict = Some(Verdict::Accept),
+            Statement::Drop(_) => verdict = Some(Verdict::Drop),
+            Statement::Reject(_) => verdict = Some(Verdict::Reject),
+            statement => bail!("unsupported statement {}", describe(statement)),
+        }
+    }
+
+    rule.verdict = verdict.unwrap_or(Verdict::Continue);
+
+    Ok(group)
+}
+
+// Rules log to an NFLOG group with a prefix, the log options of the kernel
+// log can't be expressed
+fn log_of(rule: &mut Rule, log: Option<&stmt::Log>) -> Result<u16> {
+    let Some((log, group)) = log.and_then(|log| Some((log, log.group?))) else {
+        bail!("logging to the kernel log is not supported, only to an NFLOG group");
+    };
+    let group = u16::try_from(group).map_err(|_| anyhow!("invalid log group {group}"))?;
+
+    if log.snaplen.is_some()
+        || log.queue_threshold.is_some()
+        || log.level.is_some()
+        || log.flags.is_some()
+    {
+        bail!("log snaplen, queue-threshold, level and flags are not supported");
+    }
+
+    rule.log = true;
+    rule.log_prefix = log.prefix.as_ref().map(|prefix| prefix.to_string());
+
+    Ok(group)
+}
+
+// Limits and quotas are written out the way nft lists them and read back
+// like the rules file would
+fn limit_of(limit: &stmt::Limit) -> Result<Limit> {
+    let rate_unit = limit.rate_unit.as_deref().unwrap_or("packets");
+    let per = limit.per.as_deref().unwrap_or("second");
+    let mut text = format!("{} {rate_unit}/{per}", limit.rate);
+    if limit.inv == Some(true) {
+        text = format!("over {text}");
+    }
+
+    let bytes = rate_unit != "packets";
+    match limit.burst.map(u64::from) {
+        Some(burst) if bytes && burst > 0 => {
+            let burst_unit = limit.burst_unit.as_deref().unwrap_or("bytes");
+            text += &format!(" burst {burst} {burst_unit}");
+        }
+        Some(burst) if !bytes && burst != DEFAULT_BURST => {
+            text += &format!(" burst {burst} packets");
+        }
+        _ => {}
+    }
+
+    text.parse()
+}
+
+fn quota_of(quota: &stmt::Quota) -> Result<Quota> {
+    let text = format!("{} {}", quota.val, quota.val_unit);
+    if quota.inv == Some(true) {
+        return format!("over {text}").parse();
+    }
+
+    text.parse()
+}
+
+fn translate_match(rule: &mut Rule, m: &Match) -> Result<()> {
+    if !matches!(m.op, Operator::EQ | Operator::IN) {
+        bail!("only == matches are supported");
+    }
+
+    match &m.left {
+        Expression::Named(NamedExpression::Payload(Payload::PayloadField(PayloadField {
+            protocol,
+            field,
+        }))) => match (protocol.as_ref(), field.as_ref()) {
+            ("ip" | "ip6", "saddr") => rule.sources = networks(&m.right)?,
+            ("ip" | "ip6", "daddr") => rule.destinations = networks(&m.right)?,
+            ("ip", "protocol") | ("ip6", "nexthdr") => set_protocol(rule, protocol_of(&m.right)?)?,
+            ("tcp" | "udp", "sport" | "dport") => {
+                let protocol = match protocol.as_ref() {
+                    "tcp" => Protocol::Tcp,
+                    _ => Protocol::Udp,
+                };
+                set_protocol(rule, protocol)?;
+
+                let ports = ports(&m.right)?;
+                if field == "sport" {
+                    rule.sports = ports;
+                } else {
+                    rule.dports = ports;
+                }
+            }
+            (protocol, field) => bail!("unsupported match on {protocol} {field}"),
+        },
+        Expression::Named(NamedExpression::Meta(Meta { key })) => match key {
+            MetaKey::L4proto => set_protocol(rule, protocol_of(&m.right)?)?,
+            MetaKey::Iifname => rule.iifname = Some(string(&m.right)?),
+            MetaKey::Oifname => rule.oifname = Some(string(&m.right)?),
+            key => bail!("unsupported match on meta {key:?}"),
+        },
+        left => bail!("unsupported match on {}", describe_expr(left)),
+    }
+
+    Ok(())
+}
+
+fn set_protocol(rule: &mut Rule, protocol: Protocol) -> Result<()> {
+    match rule.protocol {
+        Some(other) if other != protocol => {
+            bail!("matches both {other} and {protocol}")
+        }
+        _ => rule.protocol = Some(protocol),
+    }
+
+    Ok(())
+}
+
+// Single values and anonymous sets are expanded into a list, named sets
+// can't be expressed in the rules file
+fn elements<'a>(expr: &'a Expression<'a>) -> Result<Vec<&'a Expression<'a>>> {
+    match expr {
+        Expression::Named(NamedExpression::Set(items)) => items
+            .iter()
+            .map(|item| match item {
+                SetItem::Element(expr) => Ok(expr),
+                _ => Err(anyhow!("maps are not supported")),
+            })
+            .collect(),
+        Expression::String(s) if s.starts_with('@') => {
+            bail!("lookups in named set {s} are not supported")
+        }
+        expr => Ok(vec![expr]),
+    }
+}
+
+fn networks(expr: &Expression) -> Result<Vec<IpNet>> {
+    elements(expr)?
+        .into_iter()
+        .map(|expr| match expr {
+            Expression::String(addr) => parse_network(addr),
+            Expression::Named(NamedExpression::Prefix(prefix)) => match prefix.addr.as_ref() {
+                Expression::String(addr) => parse_network(&format!("{addr}/{}", prefix.len)),
+                addr => bail!("unsupported address {}", describe_expr(addr)),
+            },
+            Expression::Range(range) => bail!(
+                "address ranges like {} are not supported",
+                describe_expr(&Expression::Range(range.clone()))
+            ),
+            expr => bail!("unsupported address {}", describe_expr(expr)),
+        })
+        .collect()
+}
+
+fn ports(expr: &Expression) -> Result<Vec<Port>> {
+    let port = |expr: &Expression| match expr {
+        Expression::Number(port) => {
+            u16::try_from(*port).map_err(|_| anyhow!("invalid port {port}"))
+        }
+        expr => bail!("unsupported port {}", describe_expr(expr)),
+    };
+
+    elements(expr)?
+        .into_iter()
+        .map(|expr| match expr {
+            Expression::Range(range) => Ok(Port {
+                first: port(&range.range[0])?,
+                last: port(&range.range[1])?,
+                ..Port::from(0)
+            }),
+            expr => Ok(Port::from(port(expr)?)),
+        })
+        .collect()
+}
+
+fn protocol_of(expr: &Expression) -> Result<Protocol> {
+    match expr {
+        Expression::String(s) if s == "tcp" => Ok(Protocol::Tcp),
+        Expression::String(s) if s == "udp" => Ok(Protocol::Udp),
+        Expression::String(s) if s == "icmp" => Ok(Protocol::Icmp),
+        Expression::String(s) if s == "ipv6-icmp" || s == "icmpv6" => Ok(Protocol::Icmpv6),
+        Expression::Number(6) => Ok(Protocol::Tcp),
+        Expression::Number(17) => Ok(Protocol::Udp),
+        Expression::Number(1) => Ok(Protocol::Icmp),
+        Expression::Number(58) => Ok(Protocol::Icmpv6),
+        expr => bail!("unsupported protocol {}", describe_expr(expr)),
+    }
+}
+
+fn string(expr: &Expression) -> Result<String> {
+    match expr {
+        Expression::String(s) => Ok(s.to_string()),
+        expr => bail!("unsupported value {}", describe_expr(expr)),
+    }
+}
+
+// Unsupported parts are shown the way nft -j lists them
+fn describe(statement: &Statement) -> String {
+    serde_json::to_string(statement).unwrap_or_else(|_| format!("{statement:?}"))
+}
+
+fn describe_expr(expr: &Expression) -> String {
+    serde_json::to_string(expr).unwrap_or_else(|_| format!("{expr:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nftables::schema::Nftables;
+
+    // Trimmed down output of nft -j list ruleset
+    const LISTED: &str = r#"{"nftables": [
+{"table": {"family": "inet", "name": "filter", "handle": 1}},
+{"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+  "type": "filter", "hook": "input", "prio": 0, "policy": "accept"}},
+{"chain": {"family": "inet", "table": "filter", "name": "early", "handle": 2,
+  "type": "filter", "hook": "input", "prio": -10, "policy": "accept"}},
+{"chain": {"family": "inet", "table": "filter", "name": "helper", "handle": 3}},
+{"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4,
+  "expr": [
+    {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+      "right": {"set": [22, {"range": [8000, 8100]}]}}},
+    {"log": {"prefix": "ssh: ", "group": 2}},
+    {"accept": null}
+  ]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 5,
+  "expr": [
+    {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+      "right": {"prefix": {"addr": "10.0.0.0", "len": 8}}}},
+    {"log": {"group": 3}},
+    {"drop": null}
+  ]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 6,
+  "expr": [{"log": null}, {"drop": null}]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "early", "handle": 7,
+  "expr": [
+    {"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "lo"}},
+    {"counter": {"packets": 0, "bytes": 0}},
+    {"accept": null}
+  ]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "helper", "handle": 8,
+  "expr": [{"accept": null}]}}
+]}"#;
+
+    fn import() -> Import {
+        let listed: Nftables<'static> = serde_json::from_str(LISTED).unwrap();
+        translate(&Ruleset::from_nftables(&listed))
+    }
+
+    fn skipped_reason<'a>(import: &'a Import, location: &str) -> &'a str {
+        import
+            .skipped
+            .iter()
+            .find(|s| s.location == location)
+            .map(|s| s.reason.as_str())
+            .unwrap_or_else(|| panic!("{location} not skipped: {:?}", import.skipped))
+    }
+
+    #[test]
+    fn rules_in_priority_order() {
+        let import = import();
+        let names: Vec<&str> = import.rules.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["filter-early-7", "filter-input-4"]);
+        assert_eq!(import.total, 5);
+
+        let early = &import.rules.rules[0];
+        assert_eq!(early.iifname.as_deref(), Some("lo"));
+        assert!(early.counter);
+        assert_eq!(early.verdict, Verdict::Accept);
+
+        let ssh = &import.rules.rules[1];
+        assert_eq!(ssh.protocol, Some(Protocol::Tcp));
+        assert_eq!(
+            ssh.dports,
+            vec![Port::from(22), "8000-8100".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn shared_hook_is_reported() {
+        let import = import();
+        let reason = skipped_reason(&import, "inet filter input");
+        assert!(reason.contains("inet filter early"), "{reason}");
+    }
+
+    #[test]
+    fn log_prefix_and_group() {
+        let import = import();
+        let ssh = &import.rules.rules[1];
+        assert!(ssh.log);
+        assert_eq!(ssh.log_prefix.as_deref(), Some("ssh: "));
+        assert_eq!(import.rules.nflog, Some(LogSettings { group: 2 }));
+
+        let reason = skipped_reason(&import, "inet filter input handle 5");
+        assert!(reason.contains("group 3"), "{reason}");
+        let reason = skipped_reason(&import, "inet filter input handle 6");
+        assert!(reason.contains("kernel log"), "{reason}");
+    }
+
+    #[test]
+    fn regular_chains_are_skipped() {
+        let import = import();
+        skipped_reason(&import, "inet filter helper handle 8");
+    }
+}
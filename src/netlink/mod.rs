@@ -1,22 +1,61 @@
+mod batch;
+mod compiler;
+mod conntrack;
+mod counters;
+mod diff;
+mod edit;
+mod error;
+mod expr;
+mod import;
+mod monitor;
+mod nflog;
 mod nlmsg;
+mod queue;
+mod rollback;
+mod ruleset;
+mod script;
+mod sets;
 mod statement;
+mod syntax;
 mod types;
 
-use ratatui::text::Text;
+pub use batch::{TrackedBatch, send_and_process_batch};
+pub use compiler::{CompiledRule, MANAGED_TABLE, apply_rules, build_batch, compile};
+pub use conntrack::{
+    Connection, ConntrackFilter, Counters, Tuple, delete_connections, list_connections,
+};
+pub use counters::{CounterStats, RuleCounters};
+pub use diff::{Change, Diff, diff, diff_ruleset};
+pub use edit::{
+    Position, create_chain, delete_chain, delete_rule, flush_chain, insert_rule, rename_chain,
+    replace_rule,
+};
+pub use error::{Error, Result};
+pub use import::{Import, Skipped, import_ruleset, translate};
+pub use monitor::{RulesetChange, monitor};
+pub use nflog::{LogEntry, nflog};
+pub use queue::{AcceptAll, Always, Decide, Decision, QueueStats, spawn_queue_worker};
+pub use rollback::{DEFAULT_CONFIRM_TIMEOUT, PendingApply, Snapshot, apply_with_rollback};
+pub use ruleset::{BaseChain, Chain, Rule, Ruleset, Set, Table, family_name, parse_family};
+pub use script::{ScriptFormat, render_script};
+pub use sets::{add_elements, delete_elements, element_key, element_text, parse_element};
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span, Text},
+};
 use statement::StatementDisplay;
+use syntax::{nft_chain, nft_rule, nft_table};
 
-use anyhow::Result;
 use cli_log::debug;
 use nfq::Queue;
 use nftnl::{
-    Batch, Chain as nftnlChain, ChainType as nftnlChainType, FinalizedBatch, Hook as nftnlHook,
+    Batch, Chain as nftnlChain, ChainType as nftnlChainType, Hook as nftnlHook,
     Policy as nftnlPolicy, ProtoFamily, Rule as nftnlRule, Table as nftnlTable, nft_expr,
 };
-use rustables::{ChainPolicy, ChainType, Hook};
 use tui_tree_widget::TreeItem;
 
 use std::{
-    borrow::Cow,
     ffi::CString,
     io::{self},
 };
@@ -30,14 +69,7 @@ const LOG_NAME: &str = "log-name";
 const TEST_TABLE: &str = "test-table";
 const TEST_CHAIN: &str = "test-chain";
 
-pub struct FirewallChain {
-    name: String,
-    chain_type: ChainType,
-    hook_class: Hook,
-    policy: ChainPolicy,
-}
-
-fn iface_index(name: &str) -> Result<libc::c_uint, io::Error> {
+fn iface_index(name: &str) -> io::Result<libc::c_uint> {
     let c_name = CString::new(name)?;
     let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
     match index {
@@ -47,11 +79,11 @@ fn iface_index(name: &str) -> Result<libc::c_uint, io::Error> {
 }
 
 pub fn create_nfqueue(id: u16) -> Result<Queue> {
-    debug!("New netfilter queue created with");
+    debug!("Binding netfilter queue {id}");
 
-    let mut nf_queue = Queue::open()?;
-    nf_queue.set_nonblocking(true);
-    nf_queue.bind(id)?;
+    let object = format!("netfilter queue {id}");
+    let mut nf_queue = Queue::open().map_err(|e| Error::from_io(&object, e))?;
+    nf_queue.bind(id).map_err(|e| Error::from_io(&object, e))?;
 
     Ok(nf_queue)
 }
@@ -63,8 +95,8 @@ pub fn get_interfaces() -> Vec<String> {
         .collect()
 }
 
-pub fn get_table_names() -> Vec<String> {
-    let tables = rustables::list_tables().unwrap();
+pub fn get_table_names() -> Result<Vec<String>> {
+    let tables = rustables::list_tables().map_err(|e| Error::from_query("table list", e))?;
     let mut table_names = vec![];
     if !tables.is_empty() {
         table_names = tables
@@ -79,93 +111,119 @@ pub fn get_table_names() -> Vec<String> {
             .collect();
     }
 
-    table_names
+    Ok(table_names)
+}
+
+// How rules are shown in the tree, in English or the way `nft list ruleset`
+// writes them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSyntax {
+    #[default]
+    English,
+    Nft,
 }
 
-pub fn format_expr(expr: &Cow<'_, [nftables::stmt::Statement]>) -> String {
+// Every statement of a rule, in order
+pub fn format_expr(expr: &[nftables::stmt::Statement]) -> String {
     debug!("current statement:\n{expr:?}");
 
-    expr[0].display_statement()
+    if expr.is_empty() {
+        return "empty rule".to_string();
+    }
+    let statements: Vec<String> = expr.iter().map(|s| s.display_statement()).collect();
+    statements.join("; ")
 }
 
-pub fn format_chain_info<'a>(chain: &nftables::schema::Chain<'a>) -> Text<'a> {
-    let family = chain.family.display_family();
+pub fn format_chain_info(table: &Table, chain: &Chain, syntax: RuleSyntax) -> Text<'static> {
+    if syntax == RuleSyntax::Nft {
+        return Text::from(nft_chain(table, chain));
+    }
+
+    let family = table.family.display_family();
     let name = chain.name.clone();
-    let _type = chain
-        ._type
-        .map_or("-".to_string(), |t| t.display_chain_type());
-    let policy = chain
-        .policy
-        .map_or("-".to_string(), |p| p.display_chain_policy());
-    let prio = chain.prio.map_or("-".to_string(), |p| p.to_string());
-    let hook = chain
-        .hook
-        .map_or("-".to_string(), |h| h.display_chain_hook());
+    let base = chain.base.as_ref();
+    let _type = base.map_or("-".to_string(), |b| b.chain_type.display_chain_type());
+    let policy = base.map_or("-".to_string(), |b| b.policy.display_chain_policy());
+    let prio = base.map_or("-".to_string(), |b| b.priority.to_string());
+    let hook = base.map_or("-".to_string(), |b| b.hook.display_chain_hook());
 
     let info = Text::from(format!("{name}\n {family} {_type} {policy} {prio} {hook}"));
 
     info
 }
 
-pub fn build_tree() -> Vec<TreeItem<'static, usize>> {
-    let ruleset_raw =
-        nftables::helper::get_current_ruleset().expect("Unable to get active ruleset: {e}");
+// Rules of the managed table show their rates next to them
+pub fn format_rule(
+    table: &Table,
+    chain: &Chain,
+    rule: &Rule,
+    counters: &CounterStats,
+    syntax: RuleSyntax,
+) -> Line<'static> {
+    let mut line = match syntax {
+        RuleSyntax::English => Line::from(format_expr(&rule.statements)),
+        RuleSyntax::Nft => Line::from(nft_rule(table, chain, rule)),
+    };
+
+    let managed = table.family == nftables::types::NfFamily::INet && table.name == MANAGED_TABLE;
+    if managed
+        && let Some(handle) = rule.handle
+        && let Some(rule) = counters.rule(&chain.name, handle)
+    {
+        line.push_span(Span::styled(
+            format!(
+                "  {} {} ({})",
+                rule.sparkline(),
+                rule.rates(),
+                rule.totals()
+            ),
+            Style::new().fg(Color::Cyan),
+        ));
+    }
 
-    debug!("objects: {:?}", ruleset_raw.objects);
+    line
+}
 
-    let mut table_objs = vec![];
-    let mut chain_objs = vec![];
-    let mut rule_objs = vec![];
+pub fn build_tree(
+    ruleset: &Ruleset,
+    counters: &CounterStats,
+    syntax: RuleSyntax,
+) -> Vec<TreeItem<'static, usize>> {
+    debug!("tables: {:?}", ruleset.tables);
 
-    let mut table_idx = 0;
     let mut chain_idx = 0;
     let mut rule_idx = 0;
 
-    ruleset_raw
-        .objects
-        .into_iter()
-        .for_each(|nft_object| match nft_object {
-            nftables::schema::NfObject::ListObject(obj) => match obj {
-                nftables::schema::NfListObject::Table(obj) => {
-                    table_objs.push((table_idx, obj));
-                    table_idx += 1;
-                }
-                nftables::schema::NfListObject::Chain(obj) => {
-                    chain_objs.push((chain_idx, obj));
-                    chain_idx += 1;
-                }
-                nftables::schema::NfListObject::Rule(obj) => {
-                    rule_objs.push((rule_idx, obj));
-                    rule_idx += 1;
-                }
-                _ => {}
-            },
-            _ => {}
-        });
-
     let mut table_nodes = vec![];
-    table_objs.iter().for_each(|table_tuple| {
-        let table = table_tuple.1;
-        let chains = chain_objs.iter().filter(|c| c.1.table == table.name);
-
-        let mut chain_nodes = vec![];
-        chains.into_iter().for_each(|chain_tuple| {
-            let chain = chain_tuple.1;
-            let rules = rule_objs.iter().filter(|r| r.1.chain == chain.name);
-
-            let rules_leaves = rules
-                .into_iter()
-                .map(|r| TreeItem::new_leaf(r.0, format_expr(&r.1.expr)))
-                .collect();
-            let chain_info = format_chain_info(chain);
-            let node = TreeItem::new(chain_tuple.0, chain_info, rules_leaves).unwrap();
-            chain_nodes.push(node);
+    ruleset
+        .tables
+        .iter()
+        .enumerate()
+        .for_each(|(table_idx, table)| {
+            let mut chain_nodes = vec![];
+            table.chains.iter().for_each(|chain| {
+                let rules_leaves = chain
+                    .rules
+                    .iter()
+                    .map(|r| {
+                        rule_idx += 1;
+                        TreeItem::new_leaf(rule_idx, format_rule(table, chain, r, counters, syntax))
+                    })
+                    .collect();
+                let chain_info = format_chain_info(table, chain, syntax);
+                chain_idx += 1;
+                let node = TreeItem::new(chain_idx, chain_info, rules_leaves).unwrap();
+                chain_nodes.push(node);
+            });
+
+            let table_info = match syntax {
+                RuleSyntax::English => table.name.clone(),
+                RuleSyntax::Nft => nft_table(table),
+            };
+            let node = TreeItem::new(table_idx, table_info, chain_nodes).unwrap();
+            table_nodes.push(node);
         });
 
-        let node = TreeItem::new(table_tuple.0, table.name.clone(), chain_nodes).unwrap();
-        table_nodes.push(node);
-    });
-
     table_nodes
 
     //tables.iter().for_each(|t| {
@@ -194,10 +252,14 @@ pub fn build_tree() -> Vec<TreeItem<'static, usize>> {
 
 pub fn create_test_table() -> Result<()> {
     debug!("Creating test table");
-    let mut batch = Batch::new();
+    let mut batch = TrackedBatch::new();
 
     let table = nftnlTable::new(&CString::new(TEST_TABLE).unwrap(), ProtoFamily::Inet);
-    batch.add(&table, nftnl::MsgType::Add);
+    batch.add(
+        &table,
+        nftnl::MsgType::Add,
+        format!("table inet {TEST_TABLE}"),
+    );
 
     let mut chain = nftnlChain::new(&CString::new(TEST_CHAIN).unwrap(), &table);
 
@@ -205,19 +267,21 @@ pub fn create_test_table() -> Result<()> {
     chain.set_policy(nftnlPolicy::Accept);
     chain.set_type(nftnlChainType::Filter);
 
-    batch.add(&chain, nftnl::MsgType::Add);
+    batch.add(&chain, nftnl::MsgType::Add, format!("chain {TEST_CHAIN}"));
 
     let mut rule = nftnlRule::new(&chain);
-    let lo_index = iface_index("lo").unwrap();
+    let lo_index = iface_index("lo").map_err(|e| Error::from_io("interface lo", e))?;
     rule.add_expr(&nft_expr!(meta iif));
     rule.add_expr(&nft_expr!(cmp == lo_index));
     rule.add_expr(&nft_expr!(verdict accept));
 
-    batch.add(&rule, nftnl::MsgType::Add);
-
-    let finalized_batch = batch.finalize();
+    batch.add(
+        &rule,
+        nftnl::MsgType::Add,
+        format!("rule in chain {TEST_CHAIN}"),
+    );
 
-    send_and_process_batch(&finalized_batch)?;
+    send_and_process_batch(batch)?;
 
     Ok(())
 }
@@ -226,52 +290,24 @@ pub fn get_table() {
     let mut batch = Batch::new();
 }
 
-pub fn cleanup_test_tables() {
-    delete_table(TEST_TABLE, ProtoFamily::Inet).unwrap();
+pub fn cleanup_test_tables() -> Result<()> {
+    delete_table(TEST_TABLE, ProtoFamily::Inet)
 }
 
 pub fn delete_table(table_name: &str, protocol: ProtoFamily) -> Result<()> {
-    let mut batch = Batch::new();
-
-    let table = nftnlTable::new(&CString::new(table_name).unwrap(), protocol);
-
-    batch.add(&table, nftnl::MsgType::Del);
+    let mut batch = TrackedBatch::new();
 
-    let finalized_batch = batch.finalize();
+    let name =
+        CString::new(table_name).map_err(|e| Error::rejected(format!("table {table_name}"), e))?;
+    let table = nftnlTable::new(&name, protocol);
 
-    send_and_process_batch(&finalized_batch)?;
+    batch.add(&table, nftnl::MsgType::Del, format!("table {table_name}"));
 
-    Ok(())
-}
-
-pub fn send_and_process_batch(batch: &FinalizedBatch) -> io::Result<()> {
-    let socket = mnl::Socket::new(mnl::Bus::Netfilter)?;
-    let port_id = socket.portid();
-
-    socket.send_all(batch)?;
-    let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
-    let bleh = 2;
-    while let Some(message) = socket_recv(&socket, &mut buffer[..])? {
-        match mnl::cb_run(message, bleh, port_id)? {
-            mnl::CbResult::Stop => {
-                break;
-            }
-            mnl::CbResult::Ok => (),
-        }
-    }
+    send_and_process_batch(batch)?;
 
     Ok(())
 }
 
-fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> io::Result<Option<&'a [u8]>> {
-    let ret = socket.recv(buf)?;
-    if ret > 0 {
-        Ok(Some(&buf[..ret]))
-    } else {
-        Ok(None)
-    }
-}
-
 //
 //pub fn get_all_tables() -> io::Result<()> {
 //    let socket = socket::socket(
@@ -0,0 +1,229 @@
+use super::{
+    Error, Result,
+    batch::{NFGENMSG_LEN, NLMSG_HDRLEN, attr_string, attributes, messages, read_u16, set_option},
+};
+
+use cli_log::debug;
+use std::fmt;
+use tokio::sync::mpsc::{self};
+
+const NFTA_GEN_ID: u16 = 1;
+const NFTA_GEN_PROC_PID: u16 = 2;
+const NFTA_GEN_PROC_NAME: u16 = 3;
+
+// A committed transaction that changed tables, chains, rules or sets, the
+// same events `nft monitor` shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulesetChange {
+    // Generation of the ruleset after the change, None when events were lost
+    pub generation: Option<u32>,
+    // Process that committed the change
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+    // Number of objects added or deleted by the transaction
+    pub objects: usize,
+}
+
+impl RulesetChange {
+    pub fn is_external(&self) -> bool {
+        self.pid != Some(std::process::id())
+    }
+}
+
+impl fmt::Display for RulesetChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.process, self.pid) {
+            (Some(process), Some(pid)) => write!(f, "{process}[{pid}]"),
+            (None, Some(pid)) => write!(f, "pid {pid}"),
+            _ => write!(f, "an unknown process"),
+        }
+    }
+}
+
+// Subscribes to the nftables multicast group, a change is sent on the returned
+// channel every time a transaction modifying the ruleset is committed
+pub fn monitor() -> Result<mpsc::UnboundedReceiver<RulesetChange>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    // The socket blocks until the next event, a plain thread doesn't hold
+    // up the runtime on shutdown. Sockets can't be moved between threads so
+    // it is opened by the thread, which reports whether that worked.
+    std::thread::spawn(move || {
+        let socket = match subscribe() {
+            Ok(socket) => {
+                let _ = ready_tx.send(Ok(()));
+                socket
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        debug!("Monitoring the ruleset for changes");
+
+        let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+        let mut objects = 0;
+
+        loop {
+            let changes = match socket.recv(&mut buffer) {
+                Ok(len) => messages(&buffer[..len])
+                    .filter_map(|msg| parse_event(msg, &mut objects))
+                    .collect(),
+                // Events were dropped, the ruleset has to be read again
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    debug!("Ruleset monitor lost events");
+                    objects = 0;
+                    vec![RulesetChange {
+                        generation: None,
+                        pid: None,
+                        process: None,
+                        objects: 0,
+                    }]
+                }
+                Err(e) => {
+                    debug!("Ruleset monitor stopped: {e}");
+                    break;
+                }
+            };
+
+            if changes.into_iter().any(|change| tx.send(change).is_err()) {
+                break;
+            }
+        }
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|e| Error::rejected("nftables monitor", e))??;
+
+    Ok(rx)
+}
+
+fn subscribe() -> Result<mnl::Socket> {
+    let netlink = |e| Error::from_io("nftables monitor", e);
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(netlink)?;
+    set_option(
+        &socket,
+        libc::NETLINK_ADD_MEMBERSHIP,
+        libc::NFNLGRP_NFTABLES,
+    )
+    .map_err(netlink)?;
+
+    Ok(socket)
+}
+
+// Objects changed by a transaction are counted until the NEWGEN message
+// ending it, which names the process behind the transaction
+fn parse_event(msg: &[u8], objects: &mut usize) -> Option<RulesetChange> {
+    let msg_type = read_u16(msg, 4) as libc::c_int;
+    if msg_type >> 8 != libc::NFNL_SUBSYS_NFTABLES {
+        return None;
+    }
+
+    if msg_type & 0xff != libc::NFT_MSG_NEWGEN {
+        *objects += 1;
+        return None;
+    }
+    if *objects == 0 {
+        return None;
+    }
+
+    let mut change = RulesetChange {
+        generation: None,
+        pid: None,
+        process: None,
+        objects: std::mem::take(objects),
+    };
+    let attrs = msg.get(NLMSG_HDRLEN + NFGENMSG_LEN..).unwrap_or_default();
+    for (kind, value) in attributes(attrs) {
+        match kind {
+            NFTA_GEN_ID => change.generation = be_u32(value),
+            NFTA_GEN_PROC_PID => change.pid = be_u32(value),
+            NFTA_GEN_PROC_NAME => change.process = attr_string(value),
+            _ => {}
+        }
+    }
+
+    Some(change)
+}
+
+fn be_u32(value: &[u8]) -> Option<u32> {
+    value.try_into().ok().map(u32::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::batch::{finish_msg, nfnl_msg, push_attr};
+
+    fn event(msg_type: libc::c_int, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+        let msg_type = (libc::NFNL_SUBSYS_NFTABLES << 8) | msg_type;
+        let mut msg = nfnl_msg(msg_type, 0, libc::NFPROTO_UNSPEC as u8, 0);
+        for (kind, value) in attrs {
+            push_attr(&mut msg, *kind, value);
+        }
+        finish_msg(&mut msg);
+        msg
+    }
+
+    #[test]
+    fn objects_are_counted_until_newgen() {
+        let mut objects = 0;
+        for msg_type in [libc::NFT_MSG_NEWRULE, libc::NFT_MSG_DELRULE] {
+            assert_eq!(parse_event(&event(msg_type, &[]), &mut objects), None);
+        }
+
+        let newgen = event(
+            libc::NFT_MSG_NEWGEN,
+            &[
+                (NFTA_GEN_ID, &42u32.to_be_bytes()),
+                (NFTA_GEN_PROC_PID, &1234u32.to_be_bytes()),
+                (NFTA_GEN_PROC_NAME, b"nft\0"),
+            ],
+        );
+        let change = parse_event(&newgen, &mut objects).unwrap();
+        assert_eq!(
+            change,
+            RulesetChange {
+                generation: Some(42),
+                pid: Some(1234),
+                process: Some("nft".to_string()),
+                objects: 2,
+            }
+        );
+        assert_eq!(change.to_string(), "nft[1234]");
+        assert!(change.is_external());
+        assert_eq!(objects, 0);
+    }
+
+    #[test]
+    fn empty_transactions_are_ignored() {
+        let mut objects = 0;
+        let newgen = event(libc::NFT_MSG_NEWGEN, &[(NFTA_GEN_ID, &1u32.to_be_bytes())]);
+        assert_eq!(parse_event(&newgen, &mut objects), None);
+    }
+
+    #[test]
+    fn other_subsystems_are_ignored() {
+        let mut objects = 0;
+        let mut msg = event(libc::NFT_MSG_NEWRULE, &[]);
+        let conntrack = ((libc::NFNL_SUBSYS_CTNETLINK << 8) as u16).to_ne_bytes();
+        msg[4..6].copy_from_slice(&conntrack);
+
+        assert_eq!(parse_event(&msg, &mut objects), None);
+        assert_eq!(objects, 0);
+    }
+
+    #[test]
+    fn unknown_process() {
+        let change = RulesetChange {
+            generation: None,
+            pid: Some(std::process::id()),
+            process: None,
+            objects: 1,
+        };
+        assert!(!change.is_external());
+        assert_eq!(change.to_string(), format!("pid {}", std::process::id()));
+    }
+}
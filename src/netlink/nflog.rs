@@ -0,0 +1,258 @@
+use super::{
+    Error, Result,
+    batch::{
+        NFGENMSG_LEN, NLMSG_HDRLEN, attr_string, attributes, finish_msg, messages, nfnl_msg,
+        push_attr, read_u16, recv_ack,
+    },
+    compiler::LOG_PREFIX,
+};
+
+use cli_log::debug;
+use std::{
+    ffi::CStr,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc::{self};
+
+// Only the headers are shown, the rest of the packet isn't copied
+const COPY_RANGE: u32 = 256;
+// Large enough for the messages the kernel puts in a single read
+const RECV_BUFFER_LEN: usize = 64 * 1024;
+
+// A packet logged to the NFLOG group by a log statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub time: SystemTime,
+    // Prefix of the log statement that logged the packet
+    pub prefix: Option<String>,
+    pub iifname: Option<String>,
+    pub oifname: Option<String>,
+    pub mark: Option<u32>,
+    // Start of the packet from the network header on
+    pub payload: Vec<u8>,
+}
+
+impl LogEntry {
+    // Name of the rule from the rules file that logged the packet, None when
+    // it was logged by a rule from another table
+    pub fn rule(&self) -> Option<&str> {
+        self.prefix.as_deref()?.strip_prefix(LOG_PREFIX)
+    }
+}
+
+// Binds the NFLOG group, every packet logged to it is sent on the returned
+// channel. Only a single program can read a group at a time.
+pub fn nflog(group: u16) -> Result<mpsc::UnboundedReceiver<LogEntry>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    // Like the ruleset monitor the socket is opened and read by a plain
+    // thread, which reports whether binding the group worked
+    std::thread::spawn(move || {
+        let socket = match bind(group) {
+            Ok(socket) => {
+                let _ = ready_tx.send(Ok(()));
+                socket
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        debug!("Reading packets logged to NFLOG group {group}");
+
+        let mut buffer = vec![0; RECV_BUFFER_LEN];
+        loop {
+            let entries: Vec<LogEntry> = match socket.recv(&mut buffer) {
+                Ok(len) => messages(&buffer[..len]).filter_map(parse_packet).collect(),
+                // The packets that didn't fit are gone, the log just misses
+                // them
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    debug!("NFLOG group {group} overflowed");
+                    continue;
+                }
+                Err(e) => {
+                    debug!("NFLOG reader stopped: {e}");
+                    break;
+                }
+            };
+
+            if entries.into_iter().any(|entry| tx.send(entry).is_err()) {
+                break;
+            }
+        }
+    });
+
+    ready_rx
+        .recv()
+        .map_err(|e| Error::rejected(format!("NFLOG group {group}"), e))??;
+
+    Ok(rx)
+}
+
+// The group is released along with the socket
+fn bind(group: u16) -> Result<mnl::Socket> {
+    let object = format!("NFLOG group {group}");
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(|e| Error::from_io(&object, e))?;
+
+    // The command is handled before the copy mode, both fit in one message
+    let mut msg = nfnl_msg(
+        libc::NFNL_SUBSYS_ULOG << 8 | libc::NFULNL_MSG_CONFIG,
+        libc::NLM_F_REQUEST | libc::NLM_F_ACK,
+        libc::AF_UNSPEC as u8,
+        group,
+    );
+    push_attr(
+        &mut msg,
+        libc::NFULA_CFG_CMD as u16,
+        &[libc::NFULNL_CFG_CMD_BIND as u8],
+    );
+    let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+    mode.extend([libc::NFULNL_COPY_PACKET as u8, 0]);
+    push_attr(&mut msg, libc::NFULA_CFG_MODE as u16, &mode);
+
+    finish_msg(&mut msg);
+
+    socket.send(&msg).map_err(|e| Error::from_io(&object, e))?;
+    recv_ack(&socket, &object)?;
+
+    Ok(socket)
+}
+
+fn parse_packet(msg: &[u8]) -> Option<LogEntry> {
+    let msg_type = read_u16(msg, 4) as libc::c_int;
+    if msg_type != (libc::NFNL_SUBSYS_ULOG << 8 | libc::NFULNL_MSG_PACKET) {
+        return None;
+    }
+
+    let mut entry = LogEntry {
+        time: SystemTime::now(),
+        prefix: None,
+        iifname: None,
+        oifname: None,
+        mark: None,
+        payload: vec![],
+    };
+    let attrs = msg.get(NLMSG_HDRLEN + NFGENMSG_LEN..).unwrap_or_default();
+    for (kind, value) in attributes(attrs) {
+        match kind as libc::c_int {
+            libc::NFULA_PREFIX => entry.prefix = attr_string(value),
+            libc::NFULA_MARK => entry.mark = be_u32(value),
+            libc::NFULA_IFINDEX_INDEV => entry.iifname = be_u32(value).map(iface_name),
+            libc::NFULA_IFINDEX_OUTDEV => entry.oifname = be_u32(value).map(iface_name),
+            libc::NFULA_PAYLOAD => entry.payload = value.to_vec(),
+            // Only packets that got a timestamp on the way in have one
+            libc::NFULA_TIMESTAMP => {
+                if let Some(time) = timestamp(value) {
+                    entry.time = time;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(entry)
+}
+
+// Seconds and microseconds, both 64 bits
+fn timestamp(value: &[u8]) -> Option<SystemTime> {
+    let secs = u64::from_be_bytes(value.get(..8)?.try_into().ok()?);
+    let usecs = u64::from_be_bytes(value.get(8..16)?.try_into().ok()?);
+
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs) + Duration::from_micros(usecs))
+}
+
+fn iface_name(index: u32) -> String {
+    let mut name = [0 as libc::c_char; libc::IFNAMSIZ];
+    let ret = unsafe { libc::if_indextoname(index, name.as_mut_ptr()) };
+    if ret.is_null() {
+        // Interfaces can be gone by the time the packet is read
+        return index.to_string();
+    }
+
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    name.to_string_lossy().into_owned()
+}
+
+fn be_u32(value: &[u8]) -> Option<u32> {
+    value.try_into().ok().map(u32::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(attrs: &[(libc::c_int, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = nfnl_msg(
+            libc::NFNL_SUBSYS_ULOG << 8 | libc::NFULNL_MSG_PACKET,
+            0,
+            libc::AF_INET as u8,
+            2,
+        );
+        for (kind, value) in attrs {
+            push_attr(&mut msg, *kind as u16, value);
+        }
+        finish_msg(&mut msg);
+
+        msg
+    }
+
+    #[test]
+    fn logged_packets() {
+        let mut timestamp = 1_700_000_000u64.to_be_bytes().to_vec();
+        timestamp.extend(250_000u64.to_be_bytes());
+        let msg = packet(&[
+            (libc::NFULA_PREFIX, b"firewall-rs: ssh\0".to_vec()),
+            (libc::NFULA_MARK, 3u32.to_be_bytes().to_vec()),
+            // No interface has this index, its number is shown instead
+            (
+                libc::NFULA_IFINDEX_INDEV,
+                4_000_000u32.to_be_bytes().to_vec(),
+            ),
+            (libc::NFULA_TIMESTAMP, timestamp),
+            (libc::NFULA_PAYLOAD, vec![0x45, 0, 0, 20]),
+        ]);
+
+        let entry = parse_packet(&msg).unwrap();
+        assert_eq!(entry.prefix.as_deref(), Some("firewall-rs: ssh"));
+        assert_eq!(entry.rule(), Some("ssh"));
+        assert_eq!(entry.mark, Some(3));
+        assert_eq!(entry.iifname.as_deref(), Some("4000000"));
+        assert_eq!(entry.oifname, None);
+        assert_eq!(entry.payload, [0x45, 0, 0, 20]);
+        assert_eq!(
+            entry.time,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250)
+        );
+    }
+
+    #[test]
+    fn packets_of_other_tables() {
+        let entry =
+            parse_packet(&packet(&[(libc::NFULA_PREFIX, b"dropped: \0".to_vec())])).unwrap();
+        assert_eq!(entry.rule(), None);
+
+        let entry = parse_packet(&packet(&[])).unwrap();
+        assert_eq!(entry.prefix, None);
+        assert_eq!(entry.rule(), None);
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        let mut msg = nfnl_msg(
+            libc::NFNL_SUBSYS_ULOG << 8 | libc::NFULNL_MSG_CONFIG,
+            0,
+            libc::AF_UNSPEC as u8,
+            2,
+        );
+        finish_msg(&mut msg);
+
+        assert_eq!(parse_packet(&msg), None);
+    }
+
+    #[test]
+    fn short_timestamps() {
+        assert_eq!(timestamp(&[0; 8]), None);
+        assert_eq!(timestamp(&[0; 16]), Some(SystemTime::UNIX_EPOCH));
+    }
+}
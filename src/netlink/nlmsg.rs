@@ -1,9 +1,424 @@
-//struct NlMessage<'a> {
-//    buf: &'a Vec<u8>,
-//}
-//
-//impl<'a> NlMessage<'a> {
-//    pub fn new(buf: &'a mut Vec<u8>) -> Self {
-//        Self { buf }
-//    }
-//}
+use nftnl::{
+    Chain as nftnlChain, MsgType, NlMsg, Rule as nftnlRule, Table as nftnlTable,
+    expr::Expression,
+    nftnl_sys::{self as sys, libc},
+};
+use std::{
+    ffi::{CStr, CString, c_void},
+    os::raw::c_char,
+};
+
+// Userdata type used by nft to store rule comments
+const UDATA_RULE_COMMENT: u8 = 0;
+const UDATA_MAX_LEN: u32 = 256;
+
+// Set elements are sent in several messages so none of them outgrows a
+// batch page
+const SET_ELEMS_PER_MSG: usize = 128;
+
+// Where an added rule ends up in its chain
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    #[default]
+    Append,
+    // At the start of the chain
+    Prepend,
+    // Before or after the rule with this handle
+    Before(u64),
+    After(u64),
+    // In place of the rule with this handle, keeping its position
+    Replace(u64),
+}
+
+// Rule message with support for the parts nftnl::Rule does not expose,
+// like comments stored in the rule userdata
+pub struct RuleMsg<'a> {
+    rule: *mut sys::nftnl_rule,
+    placement: Placement,
+    // Expressions are built against an nftnl rule in the same chain since
+    // some of them (reject for example) depend on the table family
+    context: nftnlRule<'a>,
+}
+
+impl<'a> RuleMsg<'a> {
+    pub fn new(chain: &'a nftnlChain<'_>) -> Self {
+        let table = chain.get_table();
+
+        unsafe {
+            let rule = sys::nftnl_rule_alloc();
+            sys::nftnl_rule_set_u32(
+                rule,
+                sys::NFTNL_RULE_FAMILY as u16,
+                table.get_family() as u32,
+            );
+            sys::nftnl_rule_set_str(
+                rule,
+                sys::NFTNL_RULE_TABLE as u16,
+                table.get_name().as_ptr(),
+            );
+            sys::nftnl_rule_set_str(
+                rule,
+                sys::NFTNL_RULE_CHAIN as u16,
+                chain.get_name().as_ptr(),
+            );
+
+            Self {
+                rule,
+                placement: Placement::Append,
+                context: nftnlRule::new(chain),
+            }
+        }
+    }
+
+    pub fn add_expr(&mut self, expr: &impl Expression) {
+        unsafe { sys::nftnl_rule_add_expr(self.rule, expr.to_expr(&self.context)) }
+    }
+
+    pub fn place(&mut self, placement: Placement) {
+        match placement {
+            Placement::Append | Placement::Prepend => {}
+            Placement::Before(handle) | Placement::After(handle) => unsafe {
+                sys::nftnl_rule_set_u64(self.rule, sys::NFTNL_RULE_POSITION as u16, handle)
+            },
+            Placement::Replace(handle) => self.set_handle(handle),
+        }
+        self.placement = placement;
+    }
+
+    // Identifies the rule to delete
+    pub fn set_handle(&mut self, handle: u64) {
+        unsafe { sys::nftnl_rule_set_u64(self.rule, sys::NFTNL_RULE_HANDLE as u16, handle) }
+    }
+
+    pub fn set_comment(&mut self, comment: &str) {
+        let Ok(comment) = CString::new(comment) else {
+            return;
+        };
+
+        unsafe {
+            let udata = sys::nftnl_udata_buf_alloc(UDATA_MAX_LEN);
+            if sys::nftnl_udata_put_strz(udata, UDATA_RULE_COMMENT, comment.as_ptr()) {
+                sys::nftnl_rule_set_data(
+                    self.rule,
+                    sys::NFTNL_RULE_USERDATA as u16,
+                    sys::nftnl_udata_buf_data(udata),
+                    sys::nftnl_udata_buf_len(udata),
+                );
+            }
+            sys::nftnl_udata_buf_free(udata);
+        }
+    }
+}
+
+unsafe impl NlMsg for RuleMsg<'_> {
+    unsafe fn write(&self, buf: *mut c_void, seq: u32, msg_type: MsgType) {
+        // Without NLM_F_APPEND a rule is inserted before its position, or at
+        // the start of the chain without one
+        let (raw_msg_type, flags) = match (msg_type, self.placement) {
+            (MsgType::Add, Placement::Append | Placement::After(_)) => (
+                libc::NFT_MSG_NEWRULE,
+                libc::NLM_F_CREATE | libc::NLM_F_APPEND | libc::NLM_F_EXCL | libc::NLM_F_ACK,
+            ),
+            (MsgType::Add, Placement::Prepend | Placement::Before(_)) => (
+                libc::NFT_MSG_NEWRULE,
+                libc::NLM_F_CREATE | libc::NLM_F_EXCL | libc::NLM_F_ACK,
+            ),
+            (MsgType::Add, Placement::Replace(_)) => {
+                (libc::NFT_MSG_NEWRULE, libc::NLM_F_REPLACE | libc::NLM_F_ACK)
+            }
+            (MsgType::Del, _) => (libc::NFT_MSG_DELRULE, libc::NLM_F_ACK),
+        };
+
+        unsafe {
+            let family = sys::nftnl_rule_get_u32(self.rule, sys::NFTNL_RULE_FAMILY as u16);
+            let header = sys::nftnl_nlmsg_build_hdr(
+                buf as *mut c_char,
+                raw_msg_type as u16,
+                family as u16,
+                flags as u16,
+                seq,
+            );
+            sys::nftnl_rule_nlmsg_build_payload(header, self.rule);
+        }
+    }
+}
+
+impl Drop for RuleMsg<'_> {
+    fn drop(&mut self) {
+        unsafe { sys::nftnl_rule_free(self.rule) };
+    }
+}
+
+// Renames the chain with the given handle, which nftnl::Chain can't hold
+pub struct RenameChainMsg {
+    chain: *mut sys::nftnl_chain,
+}
+
+impl RenameChainMsg {
+    pub fn new(table: &nftnlTable, handle: u64, name: &CStr) -> Self {
+        unsafe {
+            let chain = sys::nftnl_chain_alloc();
+            sys::nftnl_chain_set_u32(
+                chain,
+                sys::NFTNL_CHAIN_FAMILY as u16,
+                table.get_family() as u32,
+            );
+            sys::nftnl_chain_set_str(
+                chain,
+                sys::NFTNL_CHAIN_TABLE as u16,
+                table.get_name().as_ptr(),
+            );
+            sys::nftnl_chain_set_u64(chain, sys::NFTNL_CHAIN_HANDLE as u16, handle);
+            sys::nftnl_chain_set_str(chain, sys::NFTNL_CHAIN_NAME as u16, name.as_ptr());
+
+            Self { chain }
+        }
+    }
+}
+
+unsafe impl NlMsg for RenameChainMsg {
+    unsafe fn write(&self, buf: *mut c_void, seq: u32, _msg_type: MsgType) {
+        unsafe {
+            let family = sys::nftnl_chain_get_u32(self.chain, sys::NFTNL_CHAIN_FAMILY as u16);
+            let header = sys::nftnl_nlmsg_build_hdr(
+                buf as *mut c_char,
+                libc::NFT_MSG_NEWCHAIN as u16,
+                family as u16,
+                libc::NLM_F_ACK as u16,
+                seq,
+            );
+            sys::nftnl_chain_nlmsg_build_payload(header, self.chain);
+        }
+    }
+}
+
+impl Drop for RenameChainMsg {
+    fn drop(&mut self) {
+        unsafe { sys::nftnl_chain_free(self.chain) };
+    }
+}
+
+// Key types of set elements, the numbers are the datatypes nft uses so the
+// sets are listed the same way as ones it created itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetKey {
+    Ipv4Addr,
+    Ipv6Addr,
+    InetService,
+}
+
+impl SetKey {
+    fn datatype(&self) -> u32 {
+        match self {
+            SetKey::Ipv4Addr => 7,
+            SetKey::Ipv6Addr => 8,
+            SetKey::InetService => 13,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            SetKey::Ipv4Addr => 4,
+            SetKey::Ipv6Addr => 16,
+            SetKey::InetService => 2,
+        }
+    }
+}
+
+// Keys a meter keeps at most, the default of nft
+pub const METER_SIZE: u32 = 65535;
+
+// Interval set, either anonymous and belonging to a single rule like the
+// set nft creates for `tcp dport { 22, 8000-8100 }`, or named and shared by
+// any rule of the table. nftnl::Set can't hold intervals, so the set is
+// built on top of the libnftnl bindings.
+pub struct SetMsg {
+    set: *mut sys::nftnl_set,
+    name: CString,
+    id: u32,
+    key: SetKey,
+    // Big endian keys, each flagged when it is the exclusive end of an
+    // interval
+    elems: Vec<(Vec<u8>, bool)>,
+}
+
+impl SetMsg {
+    // The id has to be unique within the batch, rules find the set by it
+    // since the kernel only names anonymous sets once they are created
+    pub fn anonymous(table: &nftnlTable, id: u32, key: SetKey) -> Self {
+        let flags = libc::NFT_SET_ANONYMOUS | libc::NFT_SET_CONSTANT | libc::NFT_SET_INTERVAL;
+
+        Self::new(table, c"__set%d".to_owned(), id, key, flags as u32)
+    }
+
+    pub fn named(table: &nftnlTable, name: &str, id: u32, key: SetKey) -> Self {
+        let name = CString::new(name).unwrap_or_default();
+
+        Self::new(table, name, id, key, libc::NFT_SET_INTERVAL as u32)
+    }
+
+    // Set of a meter, filled by the rule as packets come in and emptied of
+    // the keys that were quiet for the timeout. Like the sets of nft meters
+    // it is bound to the rule and goes away with it.
+    pub fn meter(table: &nftnlTable, name: &str, id: u32, key: SetKey, timeout_ms: u64) -> Self {
+        let name = CString::new(name).unwrap_or_default();
+        let flags = libc::NFT_SET_ANONYMOUS | libc::NFT_SET_EVAL | libc::NFT_SET_TIMEOUT;
+
+        let msg = Self::new(table, name, id, key, flags as u32);
+        unsafe {
+            sys::nftnl_set_set_u64(msg.set, sys::NFTNL_SET_TIMEOUT as u16, timeout_ms);
+            sys::nftnl_set_set_u32(msg.set, sys::NFTNL_SET_DESC_SIZE as u16, METER_SIZE);
+        }
+
+        msg
+    }
+
+    fn new(table: &nftnlTable, name: CString, id: u32, key: SetKey, flags: u32) -> Self {
+        unsafe {
+            let set = sys::nftnl_set_alloc();
+            sys::nftnl_set_set_u32(set, sys::NFTNL_SET_FAMILY as u16, table.get_family() as u32);
+            sys::nftnl_set_set_str(set, sys::NFTNL_SET_TABLE as u16, table.get_name().as_ptr());
+            sys::nftnl_set_set_str(set, sys::NFTNL_SET_NAME as u16, name.as_ptr());
+            sys::nftnl_set_set_u32(set, sys::NFTNL_SET_ID as u16, id);
+            sys::nftnl_set_set_u32(set, sys::NFTNL_SET_FLAGS as u16, flags);
+            sys::nftnl_set_set_u32(set, sys::NFTNL_SET_KEY_TYPE as u16, key.datatype());
+            sys::nftnl_set_set_u32(set, sys::NFTNL_SET_KEY_LEN as u16, key.len() as u32);
+
+            Self {
+                set,
+                name,
+                id,
+                key,
+                elems: vec![],
+            }
+        }
+    }
+
+    pub fn name(&self) -> &CString {
+        &self.name
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Adds the interval from start up to, but not including, end. Without
+    // an end the interval runs up to the largest key. Intervals have to be
+    // added in order and must not overlap.
+    pub fn add_interval(&mut self, start: &[u8], end: Option<&[u8]>) {
+        debug_assert!(start.len() == self.key.len());
+
+        // Like nft, everything below the first interval is explicitly
+        // marked as not part of the set
+        if self.elems.is_empty() && start.iter().any(|b| *b != 0) {
+            self.elems.push((vec![0; self.key.len()], true));
+        }
+
+        self.elems.push((start.to_vec(), false));
+        if let Some(end) = end {
+            self.elems.push((end.to_vec(), true));
+        }
+    }
+
+    // Messages adding the elements to the set, to be sent right after it
+    pub fn elems(&self) -> impl Iterator<Item = SetElemsMsg<'_>> {
+        self.elems
+            .chunks(SET_ELEMS_PER_MSG)
+            .map(|elems| SetElemsMsg { set: self, elems })
+    }
+
+    // Deleting no elements in particular removes every element of the set
+    pub fn flush(&self) -> SetElemsMsg<'_> {
+        SetElemsMsg {
+            set: self,
+            elems: &[],
+        }
+    }
+}
+
+unsafe impl NlMsg for SetMsg {
+    unsafe fn write(&self, buf: *mut c_void, seq: u32, msg_type: MsgType) {
+        let (raw_msg_type, flags) = match msg_type {
+            MsgType::Add => (libc::NFT_MSG_NEWSET, libc::NLM_F_CREATE | libc::NLM_F_ACK),
+            MsgType::Del => (libc::NFT_MSG_DELSET, libc::NLM_F_ACK),
+        };
+
+        unsafe {
+            let family = sys::nftnl_set_get_u32(self.set, sys::NFTNL_SET_FAMILY as u16);
+            let header = sys::nftnl_nlmsg_build_hdr(
+                buf as *mut c_char,
+                raw_msg_type as u16,
+                family as u16,
+                flags as u16,
+                seq,
+            );
+            sys::nftnl_set_nlmsg_build_payload(header, self.set);
+        }
+    }
+}
+
+impl Drop for SetMsg {
+    fn drop(&mut self) {
+        unsafe { sys::nftnl_set_free(self.set) };
+    }
+}
+
+pub struct SetElemsMsg<'a> {
+    set: &'a SetMsg,
+    elems: &'a [(Vec<u8>, bool)],
+}
+
+unsafe impl NlMsg for SetElemsMsg<'_> {
+    unsafe fn write(&self, buf: *mut c_void, seq: u32, msg_type: MsgType) {
+        let (raw_msg_type, flags) = match msg_type {
+            MsgType::Add => (
+                libc::NFT_MSG_NEWSETELEM,
+                libc::NLM_F_CREATE | libc::NLM_F_EXCL | libc::NLM_F_ACK,
+            ),
+            MsgType::Del => (libc::NFT_MSG_DELSETELEM, libc::NLM_F_ACK),
+        };
+
+        unsafe {
+            // A set with only the attributes identifying it, holding this
+            // chunk of the elements
+            let set = sys::nftnl_set_alloc();
+            for attr in [sys::NFTNL_SET_TABLE, sys::NFTNL_SET_NAME] {
+                let value = sys::nftnl_set_get_str(self.set.set, attr as u16);
+                sys::nftnl_set_set_str(set, attr as u16, value);
+            }
+            for attr in [sys::NFTNL_SET_FAMILY, sys::NFTNL_SET_ID] {
+                let value = sys::nftnl_set_get_u32(self.set.set, attr as u16);
+                sys::nftnl_set_set_u32(set, attr as u16, value);
+            }
+
+            for (key, interval_end) in self.elems {
+                let elem = sys::nftnl_set_elem_alloc();
+                sys::nftnl_set_elem_set(
+                    elem,
+                    sys::NFTNL_SET_ELEM_KEY as u16,
+                    key.as_ptr() as *const c_void,
+                    key.len() as u32,
+                );
+                if *interval_end {
+                    sys::nftnl_set_elem_set_u32(
+                        elem,
+                        sys::NFTNL_SET_ELEM_FLAGS as u16,
+                        libc::NFT_SET_ELEM_INTERVAL_END as u32,
+                    );
+                }
+                sys::nftnl_set_elem_add(set, elem);
+            }
+
+            let family = sys::nftnl_set_get_u32(set, sys::NFTNL_SET_FAMILY as u16);
+            let header = sys::nftnl_nlmsg_build_hdr(
+                buf as *mut c_char,
+                raw_msg_type as u16,
+                family as u16,
+                flags as u16,
+                seq,
+            );
+            sys::nftnl_set_elems_nlmsg_build_payload(header, set);
+            sys::nftnl_set_free(set);
+        }
+    }
+}
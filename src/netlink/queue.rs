@@ -0,0 +1,155 @@
+use super::{Error, Result, create_nfqueue};
+use crate::rules::{QueueDecision, QueueSettings};
+
+use cli_log::debug;
+use nfq::{Message, Verdict};
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+// What becomes of a packet sent to the queue by the queue verdict
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    Drop,
+    // Sends the packet through the same hook again with the mark set. Rules
+    // in front of the queue rule have to let marked packets through or they
+    // end up in the queue again.
+    Repeat { mark: u32 },
+}
+
+// Decides the fate of queued packets. Packets it fails on, by returning an
+// error or panicking, get the fail open or fail closed verdict of the queue.
+pub trait Decide: Send + 'static {
+    fn decide(&mut self, packet: &Message) -> anyhow::Result<Decision>;
+}
+
+// Lets every queued packet through
+pub struct AcceptAll;
+
+impl Decide for AcceptAll {
+    fn decide(&mut self, _packet: &Message) -> anyhow::Result<Decision> {
+        Ok(Decision::Accept)
+    }
+}
+
+impl From<QueueDecision> for Decision {
+    fn from(decision: QueueDecision) -> Self {
+        match decision {
+            QueueDecision::Accept => Decision::Accept,
+            QueueDecision::Drop => Decision::Drop,
+            QueueDecision::Repeat(mark) => Decision::Repeat { mark },
+        }
+    }
+}
+
+// Gives every queued packet the same decision, the one configured in the
+// rules file
+pub struct Always(pub Decision);
+
+impl Decide for Always {
+    fn decide(&mut self, _packet: &Message) -> anyhow::Result<Decision> {
+        Ok(self.0)
+    }
+}
+
+// Packets the worker has handled so far
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    pub accepted: AtomicU64,
+    pub dropped: AtomicU64,
+    pub repeated: AtomicU64,
+    // Packets without a decision
+    pub failed: AtomicU64,
+}
+
+impl fmt::Display for QueueStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accepted, {} dropped, {} repeated, {} failed",
+            self.accepted.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.repeated.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        )
+    }
+}
+
+// Reads packets from the queue on its own thread and sets the verdict the
+// decider picks for them. The queue is bound before returning so errors,
+// like another program already reading it, are reported to the caller.
+pub fn spawn_queue_worker(
+    settings: QueueSettings,
+    mut decider: impl Decide,
+) -> Result<Arc<QueueStats>> {
+    let number = settings.number;
+    let mut queue = create_nfqueue(number)?;
+    // The kernel accepts packets instead of dropping them once the queue
+    // is full
+    queue
+        .set_fail_open(number, settings.fail_open)
+        .map_err(|e| Error::from_io(format!("netfilter queue {number}"), e))?;
+
+    let stats = Arc::new(QueueStats::default());
+    let worker_stats = Arc::clone(&stats);
+
+    std::thread::spawn(move || {
+        debug!("Reading packets from netfilter queue {number}");
+
+        loop {
+            let mut msg = match queue.recv() {
+                Ok(msg) => msg,
+                // The queue overflowed, the kernel already handled the
+                // packets that didn't fit
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    debug!("Netfilter queue {number} overflowed");
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Netfilter queue {number} worker stopped: {e}");
+                    break;
+                }
+            };
+
+            let decision = match panic::catch_unwind(AssertUnwindSafe(|| decider.decide(&msg))) {
+                Ok(Ok(decision)) => Some(decision),
+                Ok(Err(e)) => {
+                    debug!(
+                        "No decision for queued packet {}: {e:#}",
+                        msg.get_packet_id()
+                    );
+                    None
+                }
+                Err(_) => {
+                    debug!("Decider panicked on queued packet {}", msg.get_packet_id());
+                    None
+                }
+            };
+
+            let (verdict, counter) = match decision {
+                Some(Decision::Accept) => (Verdict::Accept, &worker_stats.accepted),
+                Some(Decision::Drop) => (Verdict::Drop, &worker_stats.dropped),
+                Some(Decision::Repeat { mark }) => {
+                    msg.set_nfmark(mark);
+                    (Verdict::Repeat, &worker_stats.repeated)
+                }
+                None if settings.fail_open => (Verdict::Accept, &worker_stats.failed),
+                None => (Verdict::Drop, &worker_stats.failed),
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+
+            msg.set_verdict(verdict);
+            if let Err(e) = queue.verdict(msg) {
+                debug!("Unable to set the verdict of a queued packet: {e}");
+            }
+        }
+    });
+
+    Ok(stats)
+}
@@ -0,0 +1,126 @@
+use super::{Error, Result, apply_rules};
+use crate::rules::RulesFile;
+
+use cli_log::debug;
+use nftables::schema::{FlushObject, NfCmd, NfListObject, NfObject, Nftables};
+use std::time::{Duration, Instant};
+
+// How long the operator has to confirm new rules when no timeout is given
+pub const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Copy of the whole live ruleset taken before applying new rules
+pub struct Snapshot {
+    ruleset: Nftables<'static>,
+}
+
+impl Snapshot {
+    pub fn take() -> Result<Self> {
+        let ruleset = nftables::helper::get_current_ruleset()
+            .map_err(|e| Error::from_nft("taking a snapshot of the ruleset", e))?;
+
+        Ok(Self { ruleset })
+    }
+
+    // Flushes the live ruleset and recreates the snapshot in a single nft
+    // transaction, so a failed restore leaves the current rules in place
+    pub fn restore(&self) -> Result<()> {
+        debug!("Restoring ruleset snapshot");
+
+        let mut objects = vec![NfObject::CmdObject(NfCmd::Flush(FlushObject::Ruleset(
+            None,
+        )))];
+
+        for object in self.ruleset.objects.iter() {
+            let NfObject::ListObject(obj) = object else {
+                continue;
+            };
+
+            let obj = match obj {
+                NfListObject::MetainfoObject(_) => continue,
+                // Rule handles of the old ruleset are meaningless once it has
+                // been flushed and would be read as positions to add after
+                NfListObject::Rule(rule) => {
+                    let mut rule = rule.clone();
+                    rule.handle = None;
+                    rule.index = None;
+                    NfListObject::Rule(rule)
+                }
+                obj => obj.clone(),
+            };
+            objects.push(NfObject::CmdObject(NfCmd::Add(obj)));
+        }
+
+        let ruleset = Nftables {
+            objects: objects.into(),
+        };
+        nftables::helper::apply_ruleset(&ruleset)
+            .map_err(|e| Error::from_nft("restoring the ruleset snapshot", e))?;
+
+        Ok(())
+    }
+}
+
+// Rules that have been applied but not confirmed yet. Unless confirm is
+// called before the deadline they have to be rolled back, which also happens
+// when they are dropped without being confirmed.
+pub struct PendingApply {
+    // Taken by confirm and rollback
+    snapshot: Option<Snapshot>,
+    deadline: Instant,
+}
+
+impl PendingApply {
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    pub fn confirm(mut self) {
+        self.snapshot = None;
+        debug!("New rules confirmed");
+    }
+
+    pub fn rollback(mut self) -> Result<()> {
+        match self.snapshot.take() {
+            Some(snapshot) => snapshot.restore(),
+            None => Ok(()),
+        }
+    }
+}
+
+// An early return or a panic between applying and confirming must not leave
+// the unconfirmed rules in place. Signals end the process without dropping
+// anything, the CLI and the interface catch SIGINT and SIGTERM to roll back
+// first.
+impl Drop for PendingApply {
+    fn drop(&mut self) {
+        let Some(snapshot) = self.snapshot.take() else {
+            return;
+        };
+
+        debug!("Rolling back rules dropped without confirmation");
+        if let Err(e) = snapshot.restore() {
+            debug!("Unable to roll back: {e:?}");
+        }
+    }
+}
+
+// Snapshots the live ruleset and applies the rules file in a single batch,
+// like iptables-apply the caller has to confirm or roll back before timeout
+pub fn apply_with_rollback(rules: &RulesFile, timeout: Duration) -> Result<PendingApply> {
+    let snapshot = Snapshot::take()?;
+
+    apply_rules(rules)?;
+    debug!(
+        "Rules applied, waiting {}s for confirmation",
+        timeout.as_secs()
+    );
+
+    Ok(PendingApply {
+        snapshot: Some(snapshot),
+        deadline: Instant::now() + timeout,
+    })
+}
@@ -0,0 +1,437 @@
+use super::{Error, Result, compile, script::to_ruleset};
+use crate::rules::RulesFile;
+
+use nftables::{
+    expr::Expression,
+    schema::{
+        Chain as NfChain, Map as NfMap, NfCmd, NfListObject, NfObject, Nftables, Rule as NfRule,
+        Set as NfSet, SetFlag, SetTypeValue, Table as NfTable,
+    },
+    stmt::Statement,
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// The ruleset as nested tables, chains, rules and sets, whether it was read
+// from the kernel or compiled from the rules file. Rule bodies are kept as
+// the statements of the libnftables JSON schema.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ruleset {
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Table {
+    pub family: NfFamily,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u32>,
+    #[serde(default)]
+    pub chains: Vec<Chain>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sets: Vec<Set>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chain {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u32>,
+    // Only base chains are attached to a hook, regular chains are jumped to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<BaseChain>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaseChain {
+    #[serde(rename = "type")]
+    pub chain_type: NfChainType,
+    pub hook: NfHook,
+    pub priority: i32,
+    pub policy: NfChainPolicy,
+    // Device of netdev ingress and egress chains
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub statements: Vec<Statement<'static>>,
+}
+
+// Maps are sets whose elements also carry a value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Set {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handle: Option<u32>,
+    #[serde(rename = "type")]
+    pub key: SetTypeValue<'static>,
+    // Type of the values of a map, None for sets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map: Option<SetTypeValue<'static>>,
+    // Seconds elements stay when they aren't given their own timeout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub flags: HashSet<SetFlag>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub elements: Vec<Expression<'static>>,
+}
+
+impl Ruleset {
+    // Reads the whole active ruleset
+    pub fn read() -> Result<Self> {
+        let ruleset = nftables::helper::get_current_ruleset()
+            .map_err(|e| Error::from_nft("listing the ruleset", e))?;
+
+        Ok(Self::from_nftables(&ruleset))
+    }
+
+    // Reads a single table, None when it doesn't exist
+    pub fn read_table(family: NfFamily, name: &str) -> Result<Option<Table>> {
+        let args = ["list", "table", family_name(family), name];
+        let ruleset = match nftables::helper::get_current_ruleset_with_args(
+            nftables::helper::DEFAULT_NFT,
+            &args,
+        ) {
+            Ok(ruleset) => ruleset,
+            Err(e) => match Error::from_nft(format!("listing table {name}"), e) {
+                Error::Rejected { reason, .. } if reason.contains("No such file or directory") => {
+                    return Ok(None);
+                }
+                e => return Err(e),
+            },
+        };
+
+        Ok(Self::from_nftables(&ruleset)
+            .tables
+            .into_iter()
+            .find(|t| t.family == family && t.name == name))
+    }
+
+    // The managed table the rules file compiles to
+    pub fn from_rules(rules: &RulesFile) -> Self {
+        to_ruleset(&compile(rules))
+    }
+
+    // Objects nft lists are flat, they are attached to the table and chain
+    // they name. Objects the model doesn't cover, like flowtables, are left
+    // out.
+    pub fn from_nftables(ruleset: &Nftables<'static>) -> Self {
+        let mut tables: Vec<Table> = vec![];
+
+        for object in ruleset.objects.iter() {
+            let NfObject::ListObject(object) = object else {
+                continue;
+            };
+
+            match object {
+                NfListObject::Table(table) => tables.push(Table {
+                    family: table.family,
+                    name: table.name.to_string(),
+                    handle: table.handle,
+                    chains: vec![],
+                    sets: vec![],
+                }),
+                NfListObject::Chain(chain) => {
+                    if let Some(table) = find_table(&mut tables, chain.family, &chain.table) {
+                        table.chains.push(Chain::from(chain));
+                    }
+                }
+                NfListObject::Set(set) => {
+                    if let Some(table) = find_table(&mut tables, set.family, &set.table) {
+                        table.sets.push(Set::from(set.as_ref()));
+                    }
+                }
+                NfListObject::Map(map) => {
+                    if let Some(table) = find_table(&mut tables, map.family, &map.table) {
+                        table.sets.push(Set::from(map.as_ref()));
+                    }
+                }
+                NfListObject::Rule(rule) => {
+                    if let Some(chain) = find_table(&mut tables, rule.family, &rule.table)
+                        .and_then(|t| t.chains.iter_mut().find(|c| c.name == rule.chain))
+                    {
+                        chain.rules.push(Rule::from(rule));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { tables }
+    }
+
+    pub fn table(&self, family: NfFamily, name: &str) -> Option<&Table> {
+        self.tables
+            .iter()
+            .find(|t| t.family == family && t.name == name)
+    }
+
+    // Commands creating every object of the ruleset, sets come before the
+    // rules looking them up. Handles are left out since nft reads handles
+    // of added rules as the position to add them at.
+    pub fn commands(&self) -> Vec<NfObject<'static>> {
+        let add = |object| NfObject::CmdObject(NfCmd::Add(object));
+        let mut commands = vec![];
+
+        for table in &self.tables {
+            commands.push(add(NfListObject::Table(table.to_nftables())));
+            for chain in &table.chains {
+                commands.push(add(NfListObject::Chain(chain.to_nftables(table))));
+            }
+            for set in &table.sets {
+                commands.push(add(set.to_nftables(table)));
+            }
+            for chain in &table.chains {
+                for rule in &chain.rules {
+                    commands.push(add(NfListObject::Rule(rule.to_nftables(table, chain))));
+                }
+            }
+        }
+
+        commands
+    }
+
+    // Replaces the tables of the ruleset in a single nft transaction, other
+    // tables are left alone
+    pub fn apply(&self) -> Result<()> {
+        let mut objects = vec![];
+        for table in &self.tables {
+            // Adding the table first makes sure deleting it can't fail
+            let nf_table = NfListObject::Table(table.to_nftables());
+            objects.push(NfObject::CmdObject(NfCmd::Add(nf_table.clone())));
+            objects.push(NfObject::CmdObject(NfCmd::Delete(nf_table)));
+        }
+        objects.extend(self.commands());
+
+        let ruleset = Nftables {
+            objects: objects.into(),
+        };
+        nftables::helper::apply_ruleset(&ruleset)
+            .map_err(|e| Error::from_nft("applying the ruleset", e))
+    }
+}
+
+pub fn parse_family(name: &str) -> Option<NfFamily> {
+    match name {
+        "ip" => Some(NfFamily::IP),
+        "ip6" => Some(NfFamily::IP6),
+        "inet" => Some(NfFamily::INet),
+        "arp" => Some(NfFamily::ARP),
+        "bridge" => Some(NfFamily::Bridge),
+        "netdev" => Some(NfFamily::NetDev),
+        _ => None,
+    }
+}
+
+pub fn family_name(family: NfFamily) -> &'static str {
+    match family {
+        NfFamily::IP => "ip",
+        NfFamily::IP6 => "ip6",
+        NfFamily::INet => "inet",
+        NfFamily::ARP => "arp",
+        NfFamily::Bridge => "bridge",
+        NfFamily::NetDev => "netdev",
+    }
+}
+
+fn type_text(value: &SetTypeValue) -> String {
+    match value {
+        SetTypeValue::Single(set_type) => serde_name(set_type),
+        SetTypeValue::Concatenated(types) => {
+            types.iter().map(serde_name).collect::<Vec<_>>().join(" . ")
+        }
+    }
+}
+
+// The nft keyword of a type or flag is its JSON name
+pub(super) fn serde_name(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "?".to_string(),
+    }
+}
+
+fn find_table<'a>(tables: &'a mut [Table], family: NfFamily, name: &str) -> Option<&'a mut Table> {
+    tables
+        .iter_mut()
+        .find(|t| t.family == family && t.name == name)
+}
+
+impl Table {
+    pub fn chain(&self, name: &str) -> Option<&Chain> {
+        self.chains.iter().find(|c| c.name == name)
+    }
+
+    pub fn set(&self, name: &str) -> Option<&Set> {
+        self.sets.iter().find(|s| s.name == name)
+    }
+
+    fn to_nftables(&self) -> NfTable<'static> {
+        NfTable {
+            family: self.family,
+            name: self.name.clone().into(),
+            handle: None,
+        }
+    }
+}
+
+impl Chain {
+    fn to_nftables(&self, table: &Table) -> NfChain<'static> {
+        let mut chain = NfChain {
+            family: table.family,
+            table: table.name.clone().into(),
+            name: self.name.clone().into(),
+            ..NfChain::default()
+        };
+        if let Some(base) = &self.base {
+            chain._type = Some(base.chain_type);
+            chain.hook = Some(base.hook);
+            chain.prio = Some(base.priority);
+            chain.policy = Some(base.policy);
+            chain.dev = base.device.clone().map(Into::into);
+        }
+
+        chain
+    }
+}
+
+impl From<&NfChain<'static>> for Chain {
+    fn from(chain: &NfChain<'static>) -> Self {
+        let base = match (chain._type, chain.hook) {
+            (Some(chain_type), Some(hook)) => Some(BaseChain {
+                chain_type,
+                hook,
+                priority: chain.prio.unwrap_or_default(),
+                policy: chain.policy.unwrap_or(NfChainPolicy::Accept),
+                device: chain.dev.as_ref().map(|d| d.to_string()),
+            }),
+            _ => None,
+        };
+
+        Self {
+            name: chain.name.to_string(),
+            handle: chain.handle,
+            base,
+            rules: vec![],
+        }
+    }
+}
+
+impl Rule {
+    fn to_nftables(&self, table: &Table, chain: &Chain) -> NfRule<'static> {
+        NfRule {
+            family: table.family,
+            table: table.name.clone().into(),
+            chain: chain.name.clone().into(),
+            expr: self.statements.clone().into(),
+            handle: None,
+            index: None,
+            comment: self.comment.clone().map(Into::into),
+        }
+    }
+}
+
+impl From<&NfRule<'static>> for Rule {
+    fn from(rule: &NfRule<'static>) -> Self {
+        Self {
+            handle: rule.handle,
+            comment: rule.comment.as_ref().map(|c| c.to_string()),
+            statements: rule.expr.to_vec(),
+        }
+    }
+}
+
+impl Set {
+    pub fn is_map(&self) -> bool {
+        self.map.is_some()
+    }
+
+    // Like `ipv4_addr . inet_service : mark`
+    pub fn type_text(&self) -> String {
+        match &self.map {
+            Some(map) => format!("{} : {}", type_text(&self.key), type_text(map)),
+            None => type_text(&self.key),
+        }
+    }
+
+    pub fn flags_text(&self) -> Vec<String> {
+        let mut flags: Vec<String> = self.flags.iter().map(serde_name).collect();
+        flags.sort();
+        flags
+    }
+
+    fn to_nftables(&self, table: &Table) -> NfListObject<'static> {
+        let flags = (!self.flags.is_empty()).then(|| self.flags.clone());
+        let elem = (!self.elements.is_empty()).then(|| self.elements.clone().into());
+
+        match &self.map {
+            Some(map) => NfListObject::Map(Box::new(NfMap {
+                family: table.family,
+                table: table.name.clone().into(),
+                name: self.name.clone().into(),
+                handle: None,
+                set_type: self.key.clone(),
+                map: map.clone(),
+                policy: None,
+                flags,
+                elem,
+                timeout: self.timeout,
+                gc_interval: None,
+                size: None,
+                comment: None,
+            })),
+            None => NfListObject::Set(Box::new(NfSet {
+                family: table.family,
+                table: table.name.clone().into(),
+                name: self.name.clone().into(),
+                handle: None,
+                set_type: self.key.clone(),
+                policy: None,
+                flags,
+                elem,
+                timeout: self.timeout,
+                gc_interval: None,
+                size: None,
+                comment: None,
+            })),
+        }
+    }
+}
+
+impl From<&NfSet<'static>> for Set {
+    fn from(set: &NfSet<'static>) -> Self {
+        Self {
+            name: set.name.to_string(),
+            handle: set.handle,
+            key: set.set_type.clone(),
+            map: None,
+            timeout: set.timeout,
+            flags: set.flags.clone().unwrap_or_default(),
+            elements: set.elem.as_ref().map(|e| e.to_vec()).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&NfMap<'static>> for Set {
+    fn from(map: &NfMap<'static>) -> Self {
+        Self {
+            name: map.name.to_string(),
+            handle: map.handle,
+            key: map.set_type.clone(),
+            map: Some(map.map.clone()),
+            timeout: map.timeout,
+            flags: map.flags.clone().unwrap_or_default(),
+            elements: map.elem.as_ref().map(|e| e.to_vec()).unwrap_or_default(),
+        }
+    }
+}
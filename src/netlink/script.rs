@@ -0,0 +1,751 @@
+use super::compiler::{
+    AddrMatch, AddrSet, Compiled, CompiledMeter, CompiledRule, Family, Hook, MANAGED_TABLE,
+    NatTarget,
+};
+use super::{
+    Error, Result,
+    nlmsg::METER_SIZE,
+    ruleset::{self, BaseChain, Ruleset},
+};
+use crate::rules::{Limit, MeterKey, Protocol, Quota, Verdict, format_network, split_bytes};
+
+use clap::ValueEnum;
+use ipnet::IpNet;
+use nftables::{
+    expr::{
+        CT, Elem, Expression, Meta, MetaKey, NamedExpression, Payload, PayloadField, Prefix, Range,
+        SetItem,
+    },
+    schema::{NfCmd, NfListObject, NfObject, Nftables, SetFlag, SetType, SetTypeValue, Table},
+    stmt::{
+        AnonymousCounter, Counter, Limit as LimitStmt, Log, Match, Meter, NAT, NATFamily, Operator,
+        Queue, QueueFlag, Quota as QuotaStmt, QuotaOrQuotaRef, Reject, RejectType, Statement,
+    },
+    types::{NfChainPolicy, NfChainType, NfFamily, NfHook, RejectCode},
+};
+use std::{fmt::Write, net::IpAddr, ops::RangeInclusive};
+
+// Renders the managed table the way it would be created by `apply`, without
+// touching the kernel. Loading the script with `nft -f` gives the same rules
+// as the batch `apply` builds from the same compiled rules.
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScriptFormat {
+    // Script accepted by `nft -f`
+    #[default]
+    Nft,
+    // libnftables JSON accepted by `nft -j -f`
+    Json,
+}
+
+pub fn render_script(compiled: &Compiled, format: ScriptFormat) -> Result<String> {
+    match format {
+        ScriptFormat::Nft => Ok(to_nft(compiled)),
+        ScriptFormat::Json => to_json(compiled),
+    }
+}
+
+pub fn to_nft(compiled: &Compiled) -> String {
+    let mut script = String::new();
+
+    // Same add/delete/add sequence as the batch sent to the kernel
+    let _ = writeln!(script, "table inet {MANAGED_TABLE}");
+    let _ = writeln!(script, "delete table inet {MANAGED_TABLE}");
+    let _ = writeln!(script, "table inet {MANAGED_TABLE} {{");
+
+    for set in &compiled.sets {
+        let elements: Vec<String> = set.nets.iter().map(format_network).collect();
+        let _ = writeln!(script, "\tset {} {{", set.name);
+        let _ = writeln!(script, "\t\ttype {}", set_type_name(set.family));
+        let _ = writeln!(script, "\t\tflags interval");
+        let _ = writeln!(script, "\t\telements = {{ {} }}", elements.join(", "));
+        let _ = writeln!(script, "\t}}");
+        let _ = writeln!(script);
+    }
+
+    for (i, (name, hook)) in compiled.chains().enumerate() {
+        if i > 0 {
+            let _ = writeln!(script);
+        }
+        let _ = writeln!(script, "\tchain {name} {{");
+        let _ = writeln!(script, "\t\t{} policy accept;", chain_spec(hook));
+        for rule in compiled.rules.iter().filter(|r| r.chain == name) {
+            let _ = writeln!(script, "\t\t{}", nft_rule(rule));
+        }
+        let _ = writeln!(script, "\t}}");
+    }
+
+    let _ = writeln!(script, "}}");
+
+    script
+}
+
+pub fn to_json(compiled: &Compiled) -> Result<String> {
+    let ruleset = to_ruleset(compiled);
+
+    // Same add/delete/add sequence as the batch sent to the kernel
+    let table = NfListObject::Table(Table {
+        family: NfFamily::INet,
+        name: MANAGED_TABLE.into(),
+        handle: None,
+    });
+    let mut objects = vec![
+        NfObject::CmdObject(NfCmd::Add(table.clone())),
+        NfObject::CmdObject(NfCmd::Delete(table)),
+    ];
+    objects.extend(ruleset.commands());
+
+    let nftables = Nftables {
+        objects: objects.into(),
+    };
+
+    serde_json::to_string_pretty(&nftables).map_err(|e| Error::parse("generated ruleset", e))
+}
+
+// The managed table as it should be found in the kernel once applied
+pub fn to_ruleset(compiled: &Compiled) -> Ruleset {
+    let chains = compiled
+        .chains()
+        .map(|(name, hook)| {
+            let (nf_hook, chain_type) = match hook {
+                Hook::Input => (NfHook::Input, NfChainType::Filter),
+                Hook::Output => (NfHook::Output, NfChainType::Filter),
+                Hook::Forward => (NfHook::Forward, NfChainType::Filter),
+                Hook::Prerouting => (NfHook::Prerouting, NfChainType::NAT),
+                Hook::Postrouting => (NfHook::Postrouting, NfChainType::NAT),
+            };
+            let rules = compiled
+                .rules
+                .iter()
+                .filter(|rule| rule.chain == name)
+                .map(|rule| ruleset::Rule {
+                    handle: None,
+                    comment: Some(rule.comment.clone()),
+                    statements: json_statements(rule),
+                })
+                .collect();
+
+            ruleset::Chain {
+                name: name.to_string(),
+                handle: None,
+                base: Some(BaseChain {
+                    chain_type,
+                    hook: nf_hook,
+                    priority: hook.priority(),
+                    policy: NfChainPolicy::Accept,
+                    device: None,
+                }),
+                rules,
+            }
+        })
+        .collect();
+
+    Ruleset {
+        tables: vec![ruleset::Table {
+            family: NfFamily::INet,
+            name: MANAGED_TABLE.to_string(),
+            handle: None,
+            chains,
+            sets: compiled.sets.iter().map(json_set).collect(),
+        }],
+    }
+}
+
+// Type, hook and priority of a base chain, as written inside the chain
+pub(super) fn chain_spec(hook: Hook) -> String {
+    let chain_type = if hook.is_nat() { "nat" } else { "filter" };
+    format!(
+        "type {chain_type} hook {} priority {};",
+        hook.name(),
+        hook.priority()
+    )
+}
+
+pub(super) fn nft_rule(rule: &CompiledRule) -> String {
+    let mut parts = vec![];
+
+    if let Some(iifname) = &rule.iifname {
+        parts.push(format!("iifname {}", quote(iifname)));
+    }
+    if let Some(oifname) = &rule.oifname {
+        parts.push(format!("oifname {}", quote(oifname)));
+    }
+
+    // Address matches already imply the family
+    if let Some(family) = rule.family
+        && rule.source.is_none()
+        && rule.destination.is_none()
+    {
+        parts.push(format!("meta nfproto {}", nfproto_name(family)));
+    }
+
+    if let (Some(addr), Some(family)) = (&rule.source, rule.family) {
+        parts.push(format!(
+            "{} saddr {}",
+            addr_protocol(family),
+            nft_addr(addr)
+        ));
+    }
+    if let (Some(addr), Some(family)) = (&rule.destination, rule.family) {
+        parts.push(format!(
+            "{} daddr {}",
+            addr_protocol(family),
+            nft_addr(addr)
+        ));
+    }
+
+    // nft adds the protocol match for `tcp dport` itself, followed by the
+    // same transport header load the batch of `apply` uses
+    let has_ports = !rule.sports.is_empty() || !rule.dports.is_empty();
+    match rule.protocol {
+        Some(protocol) if has_ports => {
+            if !rule.sports.is_empty() {
+                parts.push(format!("{protocol} sport {}", nft_ports(&rule.sports)));
+            }
+            if !rule.dports.is_empty() {
+                parts.push(format!("{protocol} dport {}", nft_ports(&rule.dports)));
+            }
+        }
+        Some(protocol) => parts.push(format!("meta l4proto {protocol}")),
+        None => {}
+    }
+
+    if let (Some(meter), Some(family)) = (&rule.meter, rule.family) {
+        if meter.meter.connections {
+            parts.push("ct state new".to_string());
+        }
+        parts.push(format!(
+            "meter {} size {METER_SIZE} {{ {} {} timeout {}s limit rate {} }}",
+            meter.name,
+            addr_protocol(family),
+            meter_field(meter.meter.per),
+            meter.timeout_secs(),
+            meter.meter.rate
+        ));
+    }
+    if let Some(limit) = &rule.limit {
+        parts.push(format!("limit rate {limit}"));
+    }
+    if let Some(quota) = &rule.quota {
+        parts.push(format!("quota {quota}"));
+    }
+
+    parts.push("counter".to_string());
+    if let Some(log) = &rule.log {
+        parts.push(format!(
+            "log prefix {} group {}",
+            quote(&log.prefix),
+            log.group
+        ));
+    }
+
+    match rule.nat {
+        Some(NatTarget::Masquerade) => parts.push("masquerade".to_string()),
+        Some(NatTarget::Snat(addr)) => parts.push(format!("snat {}", nft_nat(addr, None))),
+        Some(NatTarget::Dnat { addr, port }) => parts.push(format!("dnat {}", nft_nat(addr, port))),
+        None => {}
+    }
+
+    match rule.verdict {
+        Verdict::Accept => parts.push("accept".to_string()),
+        Verdict::Drop => parts.push("drop".to_string()),
+        Verdict::Reject if rule.protocol == Some(Protocol::Tcp) => {
+            parts.push("reject with tcp reset".to_string())
+        }
+        Verdict::Reject => parts.push("reject with icmpx port-unreachable".to_string()),
+        Verdict::Queue => {
+            let queue = rule.queue.unwrap_or_default();
+            if queue.fail_open {
+                parts.push(format!("queue num {} bypass", queue.number));
+            } else {
+                parts.push(format!("queue num {}", queue.number));
+            }
+        }
+        Verdict::Continue => {}
+    }
+
+    parts.push(format!("comment {}", quote(&rule.comment)));
+
+    parts.join(" ")
+}
+
+fn json_statements(rule: &CompiledRule) -> Vec<Statement<'static>> {
+    let mut statements = vec![];
+
+    if let Some(iifname) = &rule.iifname {
+        statements.push(json_match(meta(MetaKey::Iifname), json_interface(iifname)));
+    }
+    if let Some(oifname) = &rule.oifname {
+        statements.push(json_match(meta(MetaKey::Oifname), json_interface(oifname)));
+    }
+
+    if let Some(family) = rule.family
+        && rule.source.is_none()
+        && rule.destination.is_none()
+    {
+        statements.push(json_match(
+            meta(MetaKey::Nfproto),
+            Expression::String(nfproto_name(family).into()),
+        ));
+    }
+
+    if let (Some(addr), Some(family)) = (&rule.source, rule.family) {
+        statements.push(json_match(
+            payload(addr_protocol(family), "saddr"),
+            json_addr(addr),
+        ));
+    }
+    if let (Some(addr), Some(family)) = (&rule.destination, rule.family) {
+        statements.push(json_match(
+            payload(addr_protocol(family), "daddr"),
+            json_addr(addr),
+        ));
+    }
+
+    let has_ports = !rule.sports.is_empty() || !rule.dports.is_empty();
+    match rule.protocol {
+        Some(protocol) if has_ports => {
+            let protocol = protocol.to_string();
+            if !rule.sports.is_empty() {
+                statements.push(json_match(
+                    payload(&protocol, "sport"),
+                    json_ports(&rule.sports),
+                ));
+            }
+            if !rule.dports.is_empty() {
+                statements.push(json_match(
+                    payload(&protocol, "dport"),
+                    json_ports(&rule.dports),
+                ));
+            }
+        }
+        Some(protocol) => statements.push(json_match(
+            meta(MetaKey::L4proto),
+            Expression::String(protocol.to_string().into()),
+        )),
+        None => {}
+    }
+
+    if let (Some(meter), Some(family)) = (&rule.meter, rule.family) {
+        statements.extend(json_meter(meter, family));
+    }
+    if let Some(limit) = &rule.limit {
+        statements.push(Statement::Limit(json_limit(limit)));
+    }
+    if let Some(quota) = &rule.quota {
+        statements.push(Statement::Quota(QuotaOrQuotaRef::Quota(json_quota(quota))));
+    }
+
+    statements.push(Statement::Counter(Counter::Anonymous(Some(
+        AnonymousCounter::default(),
+    ))));
+    if let Some(log) = &rule.log {
+        statements.push(Statement::Log(Some(Log {
+            prefix: Some(log.prefix.clone().into()),
+            group: Some(log.group.into()),
+            ..Log::new(None)
+        })));
+    }
+
+    match rule.nat {
+        Some(NatTarget::Masquerade) => statements.push(Statement::Masquerade(None)),
+        Some(NatTarget::Snat(addr)) => statements.push(Statement::SNAT(Some(json_nat(addr, None)))),
+        Some(NatTarget::Dnat { addr, port }) => {
+            statements.push(Statement::DNAT(Some(json_nat(addr, port))))
+        }
+        None => {}
+    }
+
+    match rule.verdict {
+        Verdict::Accept => statements.push(Statement::Accept(None)),
+        Verdict::Drop => statements.push(Statement::Drop(None)),
+        Verdict::Reject if rule.protocol == Some(Protocol::Tcp) => statements.push(
+            Statement::Reject(Some(Reject::new(Some(RejectType::TCPReset), None))),
+        ),
+        Verdict::Reject => statements.push(Statement::Reject(Some(Reject::new(
+            Some(RejectType::ICMPX),
+            Some(RejectCode::PortUnreach),
+        )))),
+        Verdict::Queue => {
+            let queue = rule.queue.unwrap_or_default();
+            statements.push(Statement::Queue(Queue {
+                num: Expression::Number(queue.number.into()),
+                flags: queue.fail_open.then(|| [QueueFlag::Bypass].into()),
+            }))
+        }
+        Verdict::Continue => {}
+    }
+
+    statements
+}
+
+// The meter is preceded by the match on new connections when it only counts
+// those, which nft writes as a lookup of the state flag
+fn json_meter(meter: &CompiledMeter, family: Family) -> Vec<Statement<'static>> {
+    let mut statements = vec![];
+
+    if meter.meter.connections {
+        statements.push(Statement::Match(Match {
+            left: Expression::Named(NamedExpression::CT(CT {
+                key: "state".into(),
+                family: None,
+                dir: None,
+            })),
+            right: Expression::String("new".into()),
+            op: Operator::IN,
+        }));
+    }
+
+    let key = payload(addr_protocol(family), meter_field(meter.meter.per));
+    statements.push(Statement::Meter(Meter {
+        name: meter.name.clone().into(),
+        key: Expression::Named(NamedExpression::Elem(Elem {
+            val: Box::new(key),
+            timeout: Some(meter.timeout_secs() as u32),
+            expires: None,
+            comment: None,
+            counter: None,
+        })),
+        stmt: Box::new(Statement::Limit(json_limit(&meter.meter.rate))),
+    }));
+
+    statements
+}
+
+// Byte rates and bursts are given in the largest unit they are a whole
+// number of, like nft lists them. Limits are validated to fit.
+fn json_limit(limit: &Limit) -> LimitStmt<'static> {
+    let burst = limit.burst_or_default();
+    if !limit.bytes {
+        return LimitStmt {
+            rate: limit.rate as u32,
+            rate_unit: None,
+            per: Some(limit.per.to_string().into()),
+            burst: Some(burst as u32),
+            burst_unit: None,
+            inv: limit.over.then_some(true),
+        };
+    }
+
+    let (rate, rate_unit) = split_bytes(limit.rate);
+    let (burst, burst_unit) = split_bytes(burst);
+    LimitStmt {
+        rate: rate as u32,
+        rate_unit: Some(rate_unit.into()),
+        per: Some(limit.per.to_string().into()),
+        burst: (burst > 0).then_some(burst as u32),
+        burst_unit: (burst > 0).then_some(burst_unit.into()),
+        inv: limit.over.then_some(true),
+    }
+}
+
+fn json_quota(quota: &Quota) -> QuotaStmt<'static> {
+    let (val, val_unit) = split_bytes(quota.bytes);
+
+    QuotaStmt {
+        val: val as u32,
+        val_unit: val_unit.into(),
+        used: None,
+        used_unit: None,
+        inv: quota.over.then_some(true),
+    }
+}
+
+fn meter_field(key: MeterKey) -> &'static str {
+    match key {
+        MeterKey::Source => "saddr",
+        MeterKey::Destination => "daddr",
+    }
+}
+
+// Like `ip to 10.0.0.5:80`, IPv6 addresses go in brackets before a port
+fn nft_nat(addr: IpAddr, port: Option<u16>) -> String {
+    match (addr, port) {
+        (IpAddr::V4(addr), Some(port)) => format!("ip to {addr}:{port}"),
+        (IpAddr::V4(addr), None) => format!("ip to {addr}"),
+        (IpAddr::V6(addr), Some(port)) => format!("ip6 to [{addr}]:{port}"),
+        (IpAddr::V6(addr), None) => format!("ip6 to {addr}"),
+    }
+}
+
+fn json_nat(addr: IpAddr, port: Option<u16>) -> NAT<'static> {
+    let family = match addr {
+        IpAddr::V4(_) => NATFamily::IP,
+        IpAddr::V6(_) => NATFamily::IP6,
+    };
+
+    NAT {
+        addr: Some(Expression::String(addr.to_string().into())),
+        family: Some(family),
+        port: port.map(|port| Expression::Number(port.into())),
+        flags: None,
+    }
+}
+
+// A single port or range as is, several of them as an anonymous set
+fn nft_ports(ranges: &[RangeInclusive<u16>]) -> String {
+    let ports: Vec<String> = ranges
+        .iter()
+        .map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+        .collect();
+
+    match ports.as_slice() {
+        [port] => port.clone(),
+        ports => format!("{{ {} }}", ports.join(", ")),
+    }
+}
+
+fn json_ports(ranges: &[RangeInclusive<u16>]) -> Expression<'static> {
+    let port = |range: &RangeInclusive<u16>| {
+        let start = Expression::Number((*range.start()).into());
+        if range.start() == range.end() {
+            return start;
+        }
+        Expression::Range(Box::new(Range {
+            range: [start, Expression::Number((*range.end()).into())],
+        }))
+    };
+
+    match ranges {
+        [range] => port(range),
+        ranges => Expression::Named(NamedExpression::Set(
+            ranges.iter().map(|r| SetItem::Element(port(r))).collect(),
+        )),
+    }
+}
+
+fn json_match(left: Expression<'static>, right: Expression<'static>) -> Statement<'static> {
+    Statement::Match(Match {
+        left,
+        right,
+        op: Operator::EQ,
+    })
+}
+
+fn meta(key: MetaKey) -> Expression<'static> {
+    Expression::Named(NamedExpression::Meta(Meta { key }))
+}
+
+fn payload(protocol: &str, field: &str) -> Expression<'static> {
+    Expression::Named(NamedExpression::Payload(Payload::PayloadField(
+        PayloadField {
+            protocol: protocol.to_string().into(),
+            field: field.to_string().into(),
+        },
+    )))
+}
+
+// nft uses a trailing `*` for interface name prefixes in both formats
+fn json_interface(name: &str) -> Expression<'static> {
+    Expression::String(name.to_string().into())
+}
+
+// Named sets are referred to as @name in both formats
+fn nft_addr(addr: &AddrMatch) -> String {
+    match addr {
+        AddrMatch::Net(net) => format_network(net),
+        AddrMatch::Set(name) => format!("@{name}"),
+    }
+}
+
+fn json_addr(addr: &AddrMatch) -> Expression<'static> {
+    match addr {
+        AddrMatch::Net(net) => json_network(net),
+        AddrMatch::Set(name) => Expression::String(format!("@{name}").into()),
+    }
+}
+
+fn json_set(set: &AddrSet) -> ruleset::Set {
+    let set_type = match set.family {
+        Family::Ipv4 => SetType::Ipv4Addr,
+        Family::Ipv6 => SetType::Ipv6Addr,
+    };
+
+    ruleset::Set {
+        name: set.name.clone(),
+        handle: None,
+        key: SetTypeValue::Single(set_type),
+        map: None,
+        timeout: None,
+        flags: [SetFlag::Interval].into(),
+        elements: set.nets.iter().map(json_network).collect(),
+    }
+}
+
+fn json_network(net: &IpNet) -> Expression<'static> {
+    let addr = Expression::String(net.addr().to_string().into());
+    if net.prefix_len() == net.max_prefix_len() {
+        return addr;
+    }
+
+    Expression::Named(NamedExpression::Prefix(Prefix {
+        addr: Box::new(Expression::String(net.network().to_string().into())),
+        len: net.prefix_len().into(),
+    }))
+}
+
+fn addr_protocol(family: Family) -> &'static str {
+    match family {
+        Family::Ipv4 => "ip",
+        Family::Ipv6 => "ip6",
+    }
+}
+
+fn nfproto_name(family: Family) -> &'static str {
+    match family {
+        Family::Ipv4 => "ipv4",
+        Family::Ipv6 => "ipv6",
+    }
+}
+
+pub(super) fn set_type_name(family: Family) -> &'static str {
+    match family {
+        Family::Ipv4 => "ipv4_addr",
+        Family::Ipv6 => "ipv6_addr",
+    }
+}
+
+// Quotes a string for nft, which has no way to escape quotes inside strings.
+// The rules file can't hold them, only strings of other tables are changed.
+pub(super) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{netlink::compiler::compile, rules};
+    use std::path::Path;
+
+    fn script(contents: &str) -> String {
+        to_nft(&compile(
+            &rules::parse(Path::new("rules.toml"), contents).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn whole_tables() {
+        let script = script(
+            r#"
+[nat]
+masquerade = ["wan0"]
+dnat = [{ forward = "tcp/8080 -> 10.0.0.5:80" }]
+
+[[rules]]
+name = "deny-list"
+sources = ["10.0.0.0/24", "10.0.1.0/24", "192.168.1.1"]
+verdict = "drop"
+
+[[rules]]
+name = "ssh"
+protocol = "tcp"
+dports = [22]
+verdict = "accept"
+"#,
+        );
+
+        assert_eq!(
+            script,
+            r#"table inet firewall-rs
+delete table inet firewall-rs
+table inet firewall-rs {
+	set deny-list_saddr4 {
+		type ipv4_addr
+		flags interval
+		elements = { 10.0.0.0/23, 192.168.1.1 }
+	}
+
+	chain input {
+		type filter hook input priority 0; policy accept;
+		ip saddr @deny-list_saddr4 counter drop comment "deny-list"
+		tcp dport 22 counter accept comment "ssh"
+	}
+
+	chain output {
+		type filter hook output priority 0; policy accept;
+	}
+
+	chain forward {
+		type filter hook forward priority 0; policy accept;
+	}
+
+	chain prerouting {
+		type nat hook prerouting priority -100; policy accept;
+		meta nfproto ipv4 tcp dport 8080 counter dnat ip to 10.0.0.5:80 comment "nat: forward tcp/8080 -> 10.0.0.5:80"
+	}
+
+	chain postrouting {
+		type nat hook postrouting priority 100; policy accept;
+		oifname "wan0" counter masquerade comment "nat: masquerade wan0"
+	}
+}
+"#
+        );
+    }
+
+    #[test]
+    fn meters_limits_quotas_and_logs() {
+        let script = script(
+            r#"
+[nflog]
+group = 5
+
+[[rules]]
+name = "ssh-flood"
+protocol = "tcp"
+dports = [22]
+meter = { per = "source", rate = "over 20/minute", connections = true }
+verdict = "drop"
+log = true
+
+[[rules]]
+name = "ping"
+protocol = "icmp"
+limit = "10/second burst 20 packets"
+verdict = "accept"
+
+[[rules]]
+name = "downloads"
+direction = "output"
+dports = [80, 443]
+limit = "over 1 mbytes/second burst 256 kbytes"
+quota = "over 10 gbytes"
+verdict = "drop"
+"#,
+        );
+
+        let rules: Vec<&str> = script
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.ends_with('"'))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                r#"meta nfproto ipv4 tcp dport 22 ct state new meter ssh-flood_meter4 size 65535 { ip saddr timeout 60s limit rate over 20/minute } counter log prefix "firewall-rs: ssh-flood" group 5 drop comment "ssh-flood""#,
+                r#"meta nfproto ipv6 tcp dport 22 ct state new meter ssh-flood_meter6 size 65535 { ip6 saddr timeout 60s limit rate over 20/minute } counter log prefix "firewall-rs: ssh-flood" group 5 drop comment "ssh-flood""#,
+                r#"meta l4proto icmp limit rate 10/second burst 20 packets counter accept comment "ping""#,
+                r#"tcp dport { 80, 443 } limit rate over 1 mbytes/second burst 256 kbytes quota over 10 gbytes counter drop comment "downloads""#,
+                r#"udp dport { 80, 443 } limit rate over 1 mbytes/second burst 256 kbytes quota over 10 gbytes counter drop comment "downloads""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn ports_and_quotes() {
+        assert_eq!(nft_ports(&[22..=22]), "22");
+        assert_eq!(nft_ports(&[8000..=8080]), "8000-8080");
+        assert_eq!(nft_ports(&[22..=22, 8000..=8080]), "{ 22, 8000-8080 }");
+        assert_eq!(quote(r#"say "hi""#), r#""say 'hi'""#);
+        assert_eq!(
+            nft_nat("2001:db8::5".parse().unwrap(), Some(80)),
+            "ip6 to [2001:db8::5]:80"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use super::{Error, Result};
+
+use nftables::{
+    expr::{Elem, Expression, NamedExpression, Prefix, Range},
+    schema::{Element, NfCmd, NfListObject, NfObject, Nftables},
+    types::NfFamily,
+};
+use std::net::IpAddr;
+
+// Elements of named sets and maps of any table, added and deleted in place.
+// The rules looking the set up see the change right away.
+
+// Reads an element the way nft writes it: an address, number or name, a
+// prefix (`10.0.0.0/8`), a range (`8000-8100`) or a concatenation
+// (`10.0.0.1 . 22`), followed by ` : <value>` for maps. With a timeout the
+// element is dropped that many seconds after being added.
+pub fn parse_element(text: &str, timeout: Option<u32>) -> Result<Expression<'static>> {
+    let (key, value) = match text.split_once(" : ") {
+        Some((key, value)) => (key, Some(value)),
+        None => (text, None),
+    };
+
+    let mut key = parse_value(text, key)?;
+    if let Some(timeout) = timeout {
+        key = Expression::Named(NamedExpression::Elem(Elem {
+            val: Box::new(key),
+            timeout: Some(timeout),
+            expires: None,
+            comment: None,
+            counter: None,
+        }));
+    }
+
+    match value {
+        Some(value) => Ok(Expression::List(vec![key, parse_value(text, value)?])),
+        None => Ok(key),
+    }
+}
+
+pub fn add_elements(
+    family: NfFamily,
+    table: &str,
+    set: &str,
+    elements: &[Expression<'static>],
+) -> Result<()> {
+    let element = element_object(family, table, set, elements.to_vec());
+    send(
+        NfCmd::Add(element),
+        format!("adding elements to set {table} {set}"),
+    )
+}
+
+// Elements are found by their key, map values and timeouts are left out
+pub fn delete_elements(
+    family: NfFamily,
+    table: &str,
+    set: &str,
+    elements: &[Expression<'static>],
+) -> Result<()> {
+    let keys = elements.iter().map(|e| element_key(e).clone()).collect();
+    let element = element_object(family, table, set, keys);
+    send(
+        NfCmd::Delete(element),
+        format!("deleting elements from set {table} {set}"),
+    )
+}
+
+// The key of an element, without the value of a map element and the
+// timeout nft lists along with it
+pub fn element_key<'a>(element: &'a Expression<'static>) -> &'a Expression<'static> {
+    match element {
+        Expression::List(pair) if pair.len() == 2 => element_key(&pair[0]),
+        Expression::Named(NamedExpression::Elem(elem)) => element_key(&elem.val),
+        element => element,
+    }
+}
+
+// Writes an element back the way parse_element reads it, with the time
+// left for elements that expire
+pub fn element_text(element: &Expression) -> String {
+    match element {
+        Expression::String(value) => value.to_string(),
+        Expression::Number(value) => value.to_string(),
+        Expression::Boolean(value) => value.to_string(),
+        Expression::List(pair) if pair.len() == 2 => {
+            format!("{} : {}", element_text(&pair[0]), element_text(&pair[1]))
+        }
+        Expression::Range(range) => format!(
+            "{}-{}",
+            element_text(&range.range[0]),
+            element_text(&range.range[1])
+        ),
+        Expression::Named(NamedExpression::Prefix(prefix)) => {
+            format!("{}/{}", element_text(&prefix.addr), prefix.len)
+        }
+        Expression::Named(NamedExpression::Concat(values)) => values
+            .iter()
+            .map(element_text)
+            .collect::<Vec<_>>()
+            .join(" . "),
+        Expression::Named(NamedExpression::Elem(elem)) => {
+            let mut text = element_text(&elem.val);
+            if let Some(timeout) = elem.timeout {
+                text.push_str(&format!(" timeout {timeout}s"));
+            }
+            if let Some(expires) = elem.expires {
+                text.push_str(&format!(" expires {expires}s"));
+            }
+            if let Some(comment) = &elem.comment {
+                text.push_str(&format!(" comment \"{comment}\""));
+            }
+            text
+        }
+        element => serde_json::to_string(element).unwrap_or_else(|_| format!("{element:?}")),
+    }
+}
+
+fn parse_value(element: &str, text: &str) -> Result<Expression<'static>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(Error::rejected(
+            format!("element \"{element}\""),
+            "it has an empty value",
+        ));
+    }
+
+    if text.contains(" . ") {
+        let values = text
+            .split(" . ")
+            .map(|value| parse_value(element, value))
+            .collect::<Result<_>>()?;
+        return Ok(Expression::Named(NamedExpression::Concat(values)));
+    }
+
+    if let Some((addr, len)) = text.split_once('/')
+        && let Ok(len) = len.parse::<u32>()
+    {
+        return Ok(Expression::Named(NamedExpression::Prefix(Prefix {
+            addr: Box::new(Expression::String(addr.to_string().into())),
+            len,
+        })));
+    }
+
+    // Names like interfaces can have dashes too, only addresses and numbers
+    // make a range
+    if let Some((low, high)) = text.split_once('-') {
+        let bound = |value: &str| {
+            value
+                .parse::<IpAddr>()
+                .is_ok()
+                .then(|| Expression::String(value.to_string().into()))
+                .or_else(|| value.parse().ok().map(Expression::Number))
+        };
+        if let (Some(low), Some(high)) = (bound(low), bound(high)) {
+            return Ok(Expression::Range(Box::new(Range { range: [low, high] })));
+        }
+    }
+
+    Ok(match text.parse() {
+        Ok(number) => Expression::Number(number),
+        Err(_) => Expression::String(text.to_string().into()),
+    })
+}
+
+fn element_object(
+    family: NfFamily,
+    table: &str,
+    set: &str,
+    elements: Vec<Expression<'static>>,
+) -> NfListObject<'static> {
+    NfListObject::Element(Element {
+        family,
+        table: table.to_string().into(),
+        name: set.to_string().into(),
+        elem: elements.into(),
+    })
+}
+
+fn send(command: NfCmd<'static>, action: String) -> Result<()> {
+    let ruleset = Nftables {
+        objects: vec![NfObject::CmdObject(command)].into(),
+    };
+
+    nftables::helper::apply_ruleset(&ruleset).map_err(|e| Error::from_nft(action, e))
+}
@@ -1,11 +1,16 @@
 use nftables::expr::Expression;
 use nftables::expr::NamedExpression;
 use nftables::expr::{
-    CTDir, CTFamily, MetaKey, Payload, PayloadBase, RTFamily, RTKey, SetItem, Verdict,
+    BinaryOperation, CTDir, CTFamily, Elem, FibFlag, FibResult, MetaKey, NgMode, OsfTtl, Payload,
+    PayloadBase, RTFamily, RTKey, SetItem, SocketAttr, Verdict,
 };
 use nftables::stmt::Counter;
 use nftables::stmt::Operator;
 use nftables::stmt::Statement;
+use nftables::stmt::{FWDFamily, QueueFlag, Reject, RejectType, SetOp};
+use nftables::stmt::{Limit, Log, LogFlag, LogLevel, QuotaOrQuotaRef};
+use nftables::stmt::{NAT, NATFamily, NATFlag};
+use nftables::types::{RejectCode, SynProxyFlag};
 
 pub trait OpDisplay {
     fn display_op(&self) -> &str;
@@ -23,7 +28,7 @@ impl OpDisplay for Operator {
             Operator::XOR => "xor",
             Operator::NEQ => "not equal to",
             Operator::LEQ => "less than or equal to",
-            Operator::GEQ => "greater than or qual to",
+            Operator::GEQ => "greater than or equal to",
             Operator::LSHIFT => "<<",
             Operator::RSHIFT => ">>",
         }
@@ -37,6 +42,86 @@ pub trait NamedExprDisplay {
 impl<'a> NamedExprDisplay for NamedExpression<'a> {
     fn display_named_expr(&self) -> String {
         match self {
+            NamedExpression::Concat(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|expr| expr.display_expr()).collect();
+                exprs.join(" . ")
+            }
+            NamedExpression::Set(set) => {
+                let items: Vec<String> = set.iter().map(|item| item.display_set_item()).collect();
+                format!("{{{}}}", items.join(", "))
+            }
+            NamedExpression::Map(map) => format!(
+                "{} looked up in {}",
+                map.key.display_expr(),
+                map.data.display_expr()
+            ),
+            NamedExpression::Prefix(prefix) => {
+                format!("{}/{}", prefix.addr.display_expr(), prefix.len)
+            }
+            NamedExpression::Payload(payload_data) => match payload_data {
+                Payload::PayloadField(field) => format!("{} {}", field.field, field.protocol),
+                Payload::PayloadRaw(raw) => {
+                    let base = match raw.base {
+                        PayloadBase::LL => "LL".to_string(),
+                        PayloadBase::NH => "NH".to_string(),
+                        PayloadBase::TH => "TH".to_string(),
+                        PayloadBase::IH => "IH".to_string(),
+                    };
+                    format!("at {base} + {} for {} bits", raw.offset, raw.len)
+                }
+            },
+            NamedExpression::Exthdr(exthdr) => {
+                let mut header = format!("{} extension header", exthdr.name);
+                if let Some(field) = &exthdr.field {
+                    header += &format!(" {field}");
+                }
+                if let Some(offset) = exthdr.offset {
+                    header += &format!(" at {offset}");
+                }
+
+                header
+            }
+            NamedExpression::TcpOption(tcp_options) => {
+                if let Some(field) = tcp_options.field.clone() {
+                    format!("{} {field}", tcp_options.name)
+                } else {
+                    format!("{}", tcp_options.name)
+                }
+            }
+            NamedExpression::SctpChunk(chunk) => {
+                format!("sctp chunk {} {}", chunk.name, chunk.field)
+            }
+            NamedExpression::Meta(meta_data) => match meta_data.key {
+                MetaKey::Pkttype => "packet type".to_string(),
+                MetaKey::Length => "packet length".to_string(),
+                MetaKey::Protocol => "proto".to_string(),
+                MetaKey::Nfproto => "netfilter proto".to_string(),
+                MetaKey::L4proto => "L4 proto".to_string(),
+                MetaKey::Iif => "input interface index".to_string(),
+                MetaKey::Iifname => "input interface name".to_string(),
+                MetaKey::Iiftype => "input interface type".to_string(),
+                MetaKey::Iifkind => "input interface kind".to_string(),
+                MetaKey::Iifgroup => "input interface group".to_string(),
+                MetaKey::Oif => "output interface index".to_string(),
+                MetaKey::Oifname => "output interface name".to_string(),
+                MetaKey::Oiftype => "output interface type".to_string(),
+                MetaKey::Oifkind => "output interface kind".to_string(),
+                MetaKey::Oifgroup => "output interface group".to_string(),
+                MetaKey::Ibridgename => "input bridge name".to_string(),
+                MetaKey::Obridgename => "output bridge name".to_string(),
+                MetaKey::Ibriport => "input bridge port".to_string(),
+                MetaKey::Obriport => "output bridge port".to_string(),
+                MetaKey::Mark => "packet mark".to_string(),
+                MetaKey::Priority => "priority".to_string(),
+                MetaKey::Rtclassid => "routing realm".to_string(),
+                MetaKey::Skuid => "UID".to_string(),
+                MetaKey::Skgid => "GID".to_string(),
+                MetaKey::Cpu => "CPU".to_string(),
+                MetaKey::Cgroup => "cgroup".to_string(),
+                MetaKey::Secpath => "IPsec path".to_string(),
+                MetaKey::Random => "random number".to_string(),
+                MetaKey::Nftrace => "trace flag".to_string(),
+            },
             NamedExpression::RT(routing_data) => {
                 let key = match routing_data.key {
                     RTKey::MTU => "MTU".to_string(),
@@ -53,7 +138,7 @@ impl<'a> NamedExprDisplay for NamedExpression<'a> {
                 format!("{key} {family}")
             }
             NamedExpression::CT(conntrack_data) => {
-                let mut key = conntrack_data.key.to_string();
+                let mut key = format!("conntrack {}", conntrack_data.key);
                 key = match conntrack_data.family {
                     Some(CTFamily::IP) => key + " ipv4",
                     Some(CTFamily::IP6) => key + " ipv6",
@@ -67,55 +152,89 @@ impl<'a> NamedExprDisplay for NamedExpression<'a> {
 
                 key
             }
-            NamedExpression::Meta(meta_data) => match meta_data.key {
-                MetaKey::Iif => "input interface index".to_string(),
-                MetaKey::Oif => "output interface index".to_string(),
-                MetaKey::Iifname => "input interface name".to_string(),
-                MetaKey::Oifname => "output interface name".to_string(),
-                MetaKey::Iiftype => "input interface type".to_string(),
-                MetaKey::Oiftype => "output interface type".to_string(),
-                MetaKey::Skgid => "GID".to_string(),
-                MetaKey::Length => "packet length".to_string(),
-                MetaKey::Pkttype => "packet type".to_string(),
-                MetaKey::Nfproto => "netfilter proto".to_string(),
-                MetaKey::L4proto => "L4 proto".to_string(),
-                MetaKey::Protocol => "proto".to_string(),
-                _ => "todo".to_string(),
-            },
-            //NamedExpression::Socket(socket_data) => match socket_data.key {
-            //    SocketAttr::Mark => "mark",
-            //    SocketAttr::Wildcard => "wildcard",
-            //    SocketAttr::Cgroupv2 => "cgroupv2",
-            //    SocketAttr::Transparent => "IP_TRANSPARENT",
-            //},
-            NamedExpression::Payload(payload_data) => match payload_data {
-                Payload::PayloadField(field) => format!("{} {}", field.field, field.protocol),
-                Payload::PayloadRaw(raw) => {
-                    let base = match raw.base {
-                        PayloadBase::LL => "LL".to_string(),
-                        PayloadBase::NH => "NH".to_string(),
-                        PayloadBase::TH => "TH".to_string(),
-                        PayloadBase::IH => "IH".to_string(),
-                    };
-                    format!("at {base} + {} for {} bits", raw.offset, raw.len)
-                }
-            },
-            NamedExpression::TcpOption(tcp_options) => {
-                if let Some(field) = tcp_options.field.clone() {
-                    format!("{} {field}", tcp_options.name)
-                } else {
-                    format!("{}", tcp_options.name)
-                }
+            NamedExpression::Numgen(numgen) => {
+                let mode = match numgen.mode {
+                    NgMode::Inc => "incrementing",
+                    NgMode::Random => "random",
+                };
+                let offset = numgen.offset.map_or("".to_string(), |o| format!(" + {o}"));
+                format!("{mode} number mod {}{offset}", numgen.ng_mod)
             }
-            NamedExpression::Set(set) => {
-                let items: Vec<String> = set.iter().map(|item| item.display_set_item()).collect();
-                format!("{{{}}}", items.join(" "))
+            NamedExpression::JHash(jhash) => {
+                let seed = jhash.seed.map_or("".to_string(), |s| format!(" seed {s}"));
+                let offset = jhash.offset.map_or("".to_string(), |o| format!(" + {o}"));
+                format!(
+                    "hash of {} mod {}{seed}{offset}",
+                    jhash.expr.display_expr(),
+                    jhash.hash_mod
+                )
+            }
+            NamedExpression::SymHash(symhash) => {
+                let offset = symhash.offset.map_or("".to_string(), |o| format!(" + {o}"));
+                format!("symmetric hash mod {}{offset}", symhash.hash_mod)
+            }
+            NamedExpression::Fib(fib) => {
+                let result = match fib.result {
+                    FibResult::Oif => "output interface index",
+                    FibResult::Oifname => "output interface name",
+                    FibResult::Type => "address type",
+                };
+                let mut flags: Vec<&str> = fib
+                    .flags
+                    .iter()
+                    .map(|flag| match flag {
+                        FibFlag::Saddr => "saddr",
+                        FibFlag::Daddr => "daddr",
+                        FibFlag::Mark => "mark",
+                        FibFlag::Iif => "iif",
+                        FibFlag::Oif => "oif",
+                    })
+                    .collect();
+                flags.sort();
+                format!("routing {result} of {}", flags.join(" . "))
+            }
+            NamedExpression::Elem(elem) => elem.display_elem(),
+            NamedExpression::Socket(socket_data) => match socket_data.key.as_ref() {
+                SocketAttr::Transparent => "socket IP_TRANSPARENT".to_string(),
+                SocketAttr::Mark => "socket mark".to_string(),
+                SocketAttr::Wildcard => "socket wildcard".to_string(),
+                SocketAttr::Cgroupv2 => "socket cgroupv2".to_string(),
+            },
+            NamedExpression::Osf(osf) => {
+                let ttl = match osf.ttl {
+                    OsfTtl::Loose => "loose",
+                    OsfTtl::Skip => "skip",
+                };
+                format!("OS fingerprint {} ttl {ttl}", osf.key)
             }
-            _ => "todo".to_string(),
         }
     }
 }
 
+pub trait ElemDisplay {
+    fn display_elem(&self) -> String;
+}
+
+impl<'a> ElemDisplay for Elem<'a> {
+    fn display_elem(&self) -> String {
+        let mut elem = self.val.display_expr();
+        if let Some(timeout) = self.timeout {
+            elem += &format!(" timeout {timeout}s");
+        }
+        if let Some(expires) = self.expires {
+            elem += &format!(" expires {expires}s");
+        }
+        if let Some(counter) = &self.counter {
+            elem += &format!(" {}", counter.display_counter());
+        }
+        if let Some(comment) = &self.comment {
+            elem += &format!(" \"{comment}\"");
+        }
+
+        elem
+    }
+}
+
 pub trait SetItemDisplay {
     fn display_set_item(&self) -> String;
 }
@@ -125,11 +244,7 @@ impl<'a> SetItemDisplay for SetItem<'a> {
         match self {
             SetItem::Element(expr) => expr.display_expr(),
             SetItem::Mapping(expr_one, expr_two) => {
-                format!(
-                    "{{{}}}, {{{}}}",
-                    expr_one.display_expr(),
-                    expr_two.display_expr()
-                )
+                format!("{} -> {}", expr_one.display_expr(), expr_two.display_expr())
             }
             SetItem::MappingStatement(expr, stmt) => {
                 format!("{} -> {}", expr.display_expr(), stmt.display_statement())
@@ -147,8 +262,10 @@ impl<'a> VerdictDisplay for Verdict<'a> {
         match self {
             Verdict::Drop => "drop".to_string(),
             Verdict::Accept => "accept".to_string(),
+            Verdict::Continue => "continue".to_string(),
             Verdict::Return => "return".to_string(),
-            _ => "todo".to_string(),
+            Verdict::Jump(jump) => format!("jump to {}", jump.target),
+            Verdict::Goto(goto) => format!("go to {}", goto.target),
         }
     }
 }
@@ -160,7 +277,7 @@ pub trait CounterDisplay {
 impl<'a> CounterDisplay for Counter<'a> {
     fn display_counter(&self) -> String {
         match self {
-            Counter::Named(str) => String::from("counter") + str,
+            Counter::Named(name) => format!("counter {name}"),
             Counter::Anonymous(ctr) => {
                 if let Some(ctr) = ctr {
                     let packets = ctr.packets.map_or("0".to_string(), |p| p.to_string());
@@ -168,13 +285,199 @@ impl<'a> CounterDisplay for Counter<'a> {
 
                     format!("counter {packets} packets {bytes} bytes")
                 } else {
-                    "".to_string()
+                    "counter".to_string()
                 }
             }
         }
     }
 }
 
+pub trait NatDisplay {
+    fn display_nat(&self) -> String;
+}
+
+impl<'a> NatDisplay for NAT<'a> {
+    fn display_nat(&self) -> String {
+        let mut parts = vec![];
+
+        match (&self.addr, &self.family) {
+            (Some(addr), Some(NATFamily::IP)) => {
+                parts.push(format!("ipv4 {}", addr.display_expr()))
+            }
+            (Some(addr), Some(NATFamily::IP6)) => {
+                parts.push(format!("ipv6 {}", addr.display_expr()))
+            }
+            (Some(addr), None) => parts.push(addr.display_expr()),
+            (None, _) => {}
+        }
+        if let Some(port) = &self.port {
+            parts.push(format!("port {}", port.display_expr()));
+        }
+
+        let mut flags: Vec<&str> = self
+            .flags
+            .iter()
+            .flatten()
+            .map(|flag| match flag {
+                NATFlag::Random => "random",
+                NATFlag::FullyRandom => "fully random",
+                NATFlag::Persistent => "persistent",
+            })
+            .collect();
+        flags.sort();
+        parts.extend(flags.into_iter().map(String::from));
+
+        if parts.is_empty() {
+            return "".to_string();
+        }
+        format!(" to {}", parts.join(" "))
+    }
+}
+
+pub trait LimitDisplay {
+    fn display_limit(&self) -> String;
+}
+
+impl<'a> LimitDisplay for Limit<'a> {
+    fn display_limit(&self) -> String {
+        let bound = if self.inv == Some(true) {
+            "over"
+        } else {
+            "at most"
+        };
+        let rate_unit = self.rate_unit.as_deref().unwrap_or("packets");
+        let per = self.per.as_deref().unwrap_or("second");
+        let mut limit = format!("{bound} {} {rate_unit} per {per}", self.rate);
+
+        match self.burst {
+            Some(burst) if burst > 0 && rate_unit == "packets" => {
+                limit += &format!(", burst {burst} packets")
+            }
+            Some(burst) if burst > 0 => {
+                let burst_unit = self.burst_unit.as_deref().unwrap_or("bytes");
+                limit += &format!(", burst {burst} {burst_unit}")
+            }
+            _ => {}
+        }
+
+        limit
+    }
+}
+
+pub trait QuotaDisplay {
+    fn display_quota(&self) -> String;
+}
+
+impl<'a> QuotaDisplay for QuotaOrQuotaRef<'a> {
+    fn display_quota(&self) -> String {
+        let quota = match self {
+            QuotaOrQuotaRef::Quota(quota) => quota,
+            QuotaOrQuotaRef::QuotaRef(name) => return format!("quota {name}"),
+        };
+
+        let mut text = if quota.inv == Some(true) {
+            format!("after {} {} in total", quota.val, quota.val_unit)
+        } else {
+            format!("until {} {} in total", quota.val, quota.val_unit)
+        };
+        if let Some(used) = quota.used {
+            let used_unit = quota.used_unit.as_deref().unwrap_or("bytes");
+            text += &format!(" ({used} {used_unit} used)");
+        }
+
+        text
+    }
+}
+
+pub trait LogDisplay {
+    fn display_log(&self) -> String;
+}
+
+impl<'a> LogDisplay for Log<'a> {
+    fn display_log(&self) -> String {
+        let mut log = "log".to_string();
+
+        if let Some(prefix) = &self.prefix {
+            log += &format!(" \"{prefix}\"");
+        }
+        if let Some(group) = self.group {
+            log += &format!(" to group {group}");
+        }
+        if let Some(level) = &self.level {
+            let level = match level {
+                LogLevel::Emerg => "emergency",
+                LogLevel::Alert => "alert",
+                LogLevel::Crit => "critical",
+                LogLevel::Err => "error",
+                LogLevel::Warn => "warning",
+                LogLevel::Notice => "notice",
+                LogLevel::Info => "info",
+                LogLevel::Debug => "debug",
+                LogLevel::Audit => "audit",
+            };
+            log += &format!(" at level {level}");
+        }
+        if let Some(snaplen) = self.snaplen {
+            log += &format!(", first {snaplen} bytes");
+        }
+        if let Some(threshold) = self.queue_threshold {
+            log += &format!(", {threshold} packets at once");
+        }
+
+        let mut flags: Vec<&str> = self
+            .flags
+            .iter()
+            .flatten()
+            .map(|flag| match flag {
+                LogFlag::TCPSequence => "tcp sequence",
+                LogFlag::TCPOptions => "tcp options",
+                LogFlag::IPOptions => "ip options",
+                LogFlag::Skuid => "UID",
+                LogFlag::Ether => "ethernet header",
+                LogFlag::All => "everything",
+            })
+            .collect();
+        flags.sort();
+        if !flags.is_empty() {
+            log += &format!(" with {}", flags.join(", "));
+        }
+
+        log
+    }
+}
+
+pub trait RejectDisplay {
+    fn display_reject(&self) -> String;
+}
+
+impl RejectDisplay for Reject {
+    fn display_reject(&self) -> String {
+        let code = self.expr.as_ref().map(|code| match code {
+            RejectCode::AdminProhibited => "admin prohibited",
+            RejectCode::PortUnreach => "port unreachable",
+            RejectCode::NoRoute => "no route",
+            RejectCode::HostUnreach => "host unreachable",
+            RejectCode::NetUnreach => "net unreachable",
+            RejectCode::ProtUnreach => "protocol unreachable",
+            RejectCode::NetProhibited => "net prohibited",
+            RejectCode::HostProhibited => "host prohibited",
+            RejectCode::AddrUnreach => "address unreachable",
+        });
+        let with = match &self._type {
+            Some(RejectType::TCPReset) => "tcp reset",
+            Some(RejectType::ICMPX) => "icmpx",
+            Some(RejectType::ICMP) => "icmp",
+            Some(RejectType::ICMPv6) => "icmpv6",
+            None => return code.map_or("reject".to_string(), |c| format!("reject with {c}")),
+        };
+
+        match code {
+            Some(code) => format!("reject with {with} {code}"),
+            None => format!("reject with {with}"),
+        }
+    }
+}
+
 pub trait ExprDisplay {
     fn display_expr(&self) -> String;
 }
@@ -185,13 +488,28 @@ impl<'a> ExprDisplay for Expression<'a> {
             Expression::Named(expr) => expr.display_named_expr(),
             Expression::String(str) => str.to_string(),
             Expression::Number(num) => num.to_string(),
+            Expression::Boolean(bool) => bool.to_string(),
             Expression::Verdict(verdict) => verdict.display_verdict(),
             Expression::List(list) => {
                 let exprs: Vec<String> = list.iter().map(|expr| expr.display_expr()).collect();
 
-                exprs.join(" ")
+                exprs.join(", ")
+            }
+            Expression::Range(range) => format!(
+                "{}-{}",
+                range.range[0].display_expr(),
+                range.range[1].display_expr()
+            ),
+            Expression::BinaryOperation(operation) => {
+                let (left, op, right) = match operation.as_ref() {
+                    BinaryOperation::AND(left, right) => (left, "&", right),
+                    BinaryOperation::OR(left, right) => (left, "|", right),
+                    BinaryOperation::XOR(left, right) => (left, "^", right),
+                    BinaryOperation::LSHIFT(left, right) => (left, "<<", right),
+                    BinaryOperation::RSHIFT(left, right) => (left, ">>", right),
+                };
+                format!("({} {op} {})", left.display_expr(), right.display_expr())
             }
-            _ => "todo".to_string(),
         }
     }
 }
@@ -203,6 +521,12 @@ pub trait StatementDisplay {
 impl<'a> StatementDisplay for Statement<'a> {
     fn display_statement(&self) -> String {
         match self {
+            Statement::Accept(_) => "accept".to_string(),
+            Statement::Drop(_) => "drop".to_string(),
+            Statement::Continue(_) => "continue".to_string(),
+            Statement::Return(_) => "return".to_string(),
+            Statement::Jump(jump) => format!("jump to {}", jump.target),
+            Statement::Goto(goto) => format!("go to {}", goto.target),
             Statement::Match(expr) => {
                 let left = &expr.left.display_expr();
                 let right = &expr.right.display_expr();
@@ -211,7 +535,274 @@ impl<'a> StatementDisplay for Statement<'a> {
                 format!("{left} {op} {right}")
             }
             Statement::Counter(ctr) => ctr.display_counter(),
-            _ => "todo".to_string(),
+            Statement::Mangle(mangle) => format!(
+                "set {} to {}",
+                mangle.key.display_expr(),
+                mangle.value.display_expr()
+            ),
+            Statement::Quota(quota) => quota.display_quota(),
+            Statement::Limit(limit) => limit.display_limit(),
+            Statement::Flow(flow) => {
+                let op = match flow.op {
+                    SetOp::Add => "add",
+                    SetOp::Update => "update",
+                };
+                format!("{op} flowtable {}", flow.flowtable)
+            }
+            Statement::FWD(fwd) => {
+                let Some(fwd) = fwd else {
+                    return "forward".to_string();
+                };
+                let mut text = "forward".to_string();
+                if let Some(dev) = &fwd.dev {
+                    text += &format!(" to {}", dev.display_expr());
+                }
+                match (&fwd.addr, &fwd.family) {
+                    (Some(addr), Some(FWDFamily::IP)) => {
+                        text += &format!(" via ipv4 {}", addr.display_expr())
+                    }
+                    (Some(addr), Some(FWDFamily::IP6)) => {
+                        text += &format!(" via ipv6 {}", addr.display_expr())
+                    }
+                    (Some(addr), None) => text += &format!(" via {}", addr.display_expr()),
+                    (None, _) => {}
+                }
+
+                text
+            }
+            Statement::Notrack => "don't track".to_string(),
+            Statement::Dup(dup) => {
+                let dev = dup
+                    .dev
+                    .as_ref()
+                    .map_or("".to_string(), |dev| format!(" via {}", dev.display_expr()));
+                format!("duplicate to {}{dev}", dup.addr.display_expr())
+            }
+            Statement::SNAT(nat) => {
+                let to = nat.as_ref().map_or("".to_string(), |n| n.display_nat());
+                format!("source nat{to}")
+            }
+            Statement::DNAT(nat) => {
+                let to = nat.as_ref().map_or("".to_string(), |n| n.display_nat());
+                format!("destination nat{to}")
+            }
+            Statement::Masquerade(nat) => {
+                let to = nat.as_ref().map_or("".to_string(), |n| n.display_nat());
+                format!("masquerade{to}")
+            }
+            Statement::Redirect(nat) => {
+                let to = nat.as_ref().map_or("".to_string(), |n| n.display_nat());
+                format!("redirect{to}")
+            }
+            Statement::Reject(reject) => reject
+                .as_ref()
+                .map_or("reject".to_string(), |r| r.display_reject()),
+            Statement::Set(set) => match set.op {
+                SetOp::Add => format!("add {} to {}", set.elem.display_expr(), set.set),
+                SetOp::Update => format!("update {} in {}", set.elem.display_expr(), set.set),
+            },
+            Statement::Log(log) => log.as_ref().map_or("log".to_string(), |l| l.display_log()),
+            Statement::CTHelper(helper) => format!("conntrack helper {helper}"),
+            // Elements of a meter are kept per key, with a timeout
+            Statement::Meter(meter) => {
+                let key = match &meter.key {
+                    Expression::Named(NamedExpression::Elem(elem)) => elem.val.display_expr(),
+                    key => key.display_expr(),
+                };
+                format!("per {key}: {}", meter.stmt.display_statement())
+            }
+            Statement::Queue(queue) => {
+                let mut flags: Vec<&str> = queue
+                    .flags
+                    .iter()
+                    .flatten()
+                    .map(|flag| match flag {
+                        QueueFlag::Bypass => "bypass",
+                        QueueFlag::Fanout => "fanout",
+                    })
+                    .collect();
+                flags.sort();
+                flags.insert(0, "");
+                format!("queue to {}{}", queue.num.display_expr(), flags.join(" "))
+            }
+            Statement::VerdictMap(vmap) => format!(
+                "verdict for {} looked up in {}",
+                vmap.key.display_expr(),
+                vmap.data.display_expr()
+            ),
+            Statement::CTCount(count) => {
+                let bound = if count.inv == Some(true) {
+                    "over"
+                } else {
+                    "at most"
+                };
+                format!("{bound} {} connections", count.val.display_expr())
+            }
+            Statement::CTTimeout(timeout) => {
+                format!("conntrack timeout {}", timeout.display_expr())
+            }
+            Statement::CTExpectation(expectation) => {
+                format!("conntrack expectation {}", expectation.display_expr())
+            }
+            Statement::XT(_) => "xtables extension".to_string(),
+            Statement::SynProxy(synproxy) => {
+                let mut text = "synproxy".to_string();
+                if let Some(mss) = synproxy.mss {
+                    text += &format!(" mss {mss}");
+                }
+                if let Some(wscale) = synproxy.wscale {
+                    text += &format!(" wscale {wscale}");
+                }
+                let mut flags: Vec<&str> = synproxy
+                    .flags
+                    .iter()
+                    .flatten()
+                    .map(|flag| match flag {
+                        SynProxyFlag::Timestamp => "timestamp",
+                        SynProxyFlag::SackPerm => "sack-perm",
+                    })
+                    .collect();
+                flags.sort();
+                flags.insert(0, "");
+                text + &flags.join(" ")
+            }
+            Statement::TProxy(tproxy) => {
+                let family = tproxy
+                    .family
+                    .as_ref()
+                    .map_or("".to_string(), |family| format!("{family} "));
+                let addr = tproxy
+                    .addr
+                    .as_ref()
+                    .map_or("".to_string(), |addr| format!("{addr} "));
+                format!("transparent proxy to {family}{addr}port {}", tproxy.port)
+            }
+            // Statements added to nftables after this was written are shown
+            // as the JSON nft lists them
+            statement => serde_json::to_string(statement).unwrap_or_default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Statements as `nft -j list ruleset` lists them
+    fn display(json: &str) -> String {
+        let statement: Statement = serde_json::from_str(json).unwrap();
+        statement.display_statement()
+    }
+
+    #[test]
+    fn matches() {
+        assert_eq!(
+            display(
+                r#"{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": 22}}"#
+            ),
+            "dport tcp equal to 22"
+        );
+        assert_eq!(
+            display(
+                r#"{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}}"#
+            ),
+            "conntrack state is in established, related"
+        );
+        assert_eq!(
+            display(
+                r#"{"match": {"op": "!=", "left": {"meta": {"key": "iifname"}}, "right": "lo"}}"#
+            ),
+            "input interface name not equal to lo"
+        );
+        assert_eq!(
+            display(
+                r#"{"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "10.0.0.0", "len": 8}}}}"#
+            ),
+            "saddr ip equal to 10.0.0.0/8"
+        );
+        assert_eq!(
+            display(
+                r#"{"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"set": [22, {"range": [8000, 8080]}]}}}"#
+            ),
+            "dport tcp equal to {22, 8000-8080}"
+        );
+    }
+
+    #[test]
+    fn verdicts_and_counters() {
+        assert_eq!(display(r#"{"accept": null}"#), "accept");
+        assert_eq!(display(r#"{"jump": {"target": "ssh"}}"#), "jump to ssh");
+        assert_eq!(
+            display(r#"{"counter": {"packets": 12, "bytes": 2048}}"#),
+            "counter 12 packets 2048 bytes"
+        );
+        assert_eq!(display(r#"{"counter": "web"}"#), "counter web");
+        assert_eq!(
+            display(r#"{"reject": {"type": "tcp reset"}}"#),
+            "reject with tcp reset"
+        );
+        assert_eq!(
+            display(r#"{"reject": {"type": "icmpx", "expr": "admin-prohibited"}}"#),
+            "reject with icmpx admin prohibited"
+        );
+    }
+
+    #[test]
+    fn limits_and_quotas() {
+        assert_eq!(
+            display(r#"{"limit": {"rate": 10, "per": "minute", "burst": 5}}"#),
+            "at most 10 packets per minute, burst 5 packets"
+        );
+        assert_eq!(
+            display(
+                r#"{"limit": {"rate": 1, "rate_unit": "mbytes", "per": "second", "inv": true}}"#
+            ),
+            "over 1 mbytes per second"
+        );
+        assert_eq!(
+            display(
+                r#"{"quota": {"val": 100, "val_unit": "mbytes", "used": 512, "used_unit": "kbytes"}}"#
+            ),
+            "until 100 mbytes in total (512 kbytes used)"
+        );
+        assert_eq!(
+            display(r#"{"quota": {"val": 1, "val_unit": "gbytes", "inv": true}}"#),
+            "after 1 gbytes in total"
+        );
+    }
+
+    #[test]
+    fn nat() {
+        assert_eq!(
+            display(r#"{"dnat": {"addr": "10.0.0.2", "family": "ip", "port": 8080}}"#),
+            "destination nat to ipv4 10.0.0.2 port 8080"
+        );
+        assert_eq!(display(r#"{"masquerade": null}"#), "masquerade");
+        assert_eq!(
+            display(r#"{"snat": {"addr": "192.0.2.1", "flags": ["persistent", "random"]}}"#),
+            "source nat to 192.0.2.1 persistent random"
+        );
+    }
+
+    #[test]
+    fn logs_and_meters() {
+        assert_eq!(
+            display(r#"{"log": {"prefix": "firewall-rs: ssh", "group": 2}}"#),
+            "log \"firewall-rs: ssh\" to group 2"
+        );
+        assert_eq!(
+            display(r#"{"log": {"level": "warn", "flags": "all"}}"#),
+            "log at level warning with everything"
+        );
+        assert_eq!(
+            display(
+                r#"{"meter": {"name": "ssh", "key": {"elem": {"val": {"payload": {"protocol": "ip", "field": "saddr"}}, "timeout": 60}}, "stmt": {"limit": {"rate": 3, "per": "minute"}}}}"#
+            ),
+            "per saddr ip: at most 3 packets per minute"
+        );
+        assert_eq!(
+            display(r#"{"queue": {"num": 1, "flags": ["fanout", "bypass"]}}"#),
+            "queue to 1 bypass fanout"
+        );
+    }
+}
@@ -0,0 +1,907 @@
+use super::{
+    ruleset::{Chain, Rule, Table, family_name, serde_name},
+    script::quote,
+};
+use crate::rules::DEFAULT_BURST;
+
+use nftables::{
+    expr::{
+        BinaryOperation, CTDir, CTFamily, Elem, Expression, FibFlag, MetaKey, NamedExpression,
+        NgMode, Payload, PayloadBase, RTFamily, SetItem, Verdict,
+    },
+    stmt::{
+        Counter, FWDFamily, Limit, Log, LogFlag, NAT, NATFamily, NATFlag, Operator, QueueFlag,
+        QuotaOrQuotaRef, Reject, RejectType, Statement, SynProxy,
+    },
+};
+
+// Renders the ruleset in the syntax of `nft -a list ruleset`, every table,
+// chain and rule as the whole command adding it, like
+// `add rule inet filter input tcp dport 22 accept # handle 4`. Any line can be
+// pasted into `nft -f`, and so can the tree from the top down. Rules nft can't
+// load again are commented out.
+
+pub trait NftSyntax {
+    fn nft_syntax(&self) -> String;
+}
+
+pub fn nft_table(table: &Table) -> String {
+    format!(
+        "add table {} {}{}",
+        family_name(table.family),
+        table.name,
+        handle_comment(table.handle)
+    )
+}
+
+// Base chains with their hook, like `nft list` writes them inside the chain
+pub fn nft_chain(table: &Table, chain: &Chain) -> String {
+    let mut line = format!(
+        "add chain {} {} {}",
+        family_name(table.family),
+        table.name,
+        chain.name
+    );
+    if let Some(base) = &chain.base {
+        let device = base.device.as_ref().map_or("".to_string(), |device| {
+            format!(" device {}", quote(device))
+        });
+        line += &format!(
+            " {{ type {} hook {}{device} priority {}; policy {}; }}",
+            serde_name(&base.chain_type),
+            serde_name(&base.hook),
+            base.priority,
+            serde_name(&base.policy)
+        );
+    }
+
+    line + &handle_comment(chain.handle)
+}
+
+pub fn nft_rule(table: &Table, chain: &Chain, rule: &Rule) -> String {
+    let mut parts = vec![format!(
+        "add rule {} {} {}",
+        family_name(table.family),
+        table.name,
+        chain.name
+    )];
+    parts.extend(rule.statements.iter().map(|s| s.nft_syntax()));
+    if let Some(comment) = &rule.comment {
+        parts.push(format!("comment {}", quote(comment)));
+    }
+
+    let line = parts.join(" ") + &handle_comment(rule.handle);
+    if rule.statements.iter().any(is_unsupported) {
+        return format!("# unsupported: {line}");
+    }
+
+    line
+}
+
+fn handle_comment(handle: Option<u32>) -> String {
+    handle.map_or("".to_string(), |handle| format!(" # handle {handle}"))
+}
+
+impl<'a> NftSyntax for Statement<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            Statement::Accept(_) => "accept".to_string(),
+            Statement::Drop(_) => "drop".to_string(),
+            Statement::Continue(_) => "continue".to_string(),
+            Statement::Return(_) => "return".to_string(),
+            Statement::Jump(jump) => format!("jump {}", jump.target),
+            Statement::Goto(goto) => format!("goto {}", goto.target),
+            Statement::Match(m) => {
+                // Flags like `ct state established,related` and equality are
+                // written without an operator, except after a bitmask like
+                // `tcp flags & syn == syn`
+                let op = match (&m.op, &m.left) {
+                    (Operator::EQ, Expression::BinaryOperation(_)) => "== ".to_string(),
+                    (Operator::EQ | Operator::IN, _) => "".to_string(),
+                    (op, _) => format!("{} ", serde_name(op)),
+                };
+                let right = match &m.right {
+                    Expression::Boolean(true) => return format!("{} exists", m.left.nft_syntax()),
+                    Expression::Boolean(false) => {
+                        return format!("{} missing", m.left.nft_syntax());
+                    }
+                    right if is_string(&m.left) => quoted(right),
+                    right => right.nft_syntax(),
+                };
+                format!("{} {op}{right}", m.left.nft_syntax())
+            }
+            Statement::Counter(counter) => counter.nft_syntax(),
+            Statement::Mangle(mangle) => {
+                let value = if is_string(&mangle.key) {
+                    quoted(&mangle.value)
+                } else {
+                    mangle.value.nft_syntax()
+                };
+                format!("{} set {value}", mangle.key.nft_syntax())
+            }
+            Statement::Quota(quota) => quota.nft_syntax(),
+            Statement::Limit(limit) => limit.nft_syntax(),
+            Statement::Flow(flow) => {
+                format!("flow {} @{}", serde_name(&flow.op), flow.flowtable)
+            }
+            Statement::FWD(fwd) => {
+                let Some(fwd) = fwd else {
+                    return "fwd".to_string();
+                };
+                let device = fwd.dev.as_ref().map(quoted);
+                match (&fwd.addr, &fwd.family, device) {
+                    (Some(addr), family, device) => {
+                        let family = match family {
+                            Some(FWDFamily::IP) => " ip",
+                            Some(FWDFamily::IP6) => " ip6",
+                            None => "",
+                        };
+                        let device = device.map_or("".to_string(), |d| format!(" device {d}"));
+                        format!("fwd{family} to {}{device}", addr.nft_syntax())
+                    }
+                    (None, _, Some(device)) => format!("fwd to {device}"),
+                    (None, _, None) => "fwd".to_string(),
+                }
+            }
+            Statement::Notrack => "notrack".to_string(),
+            Statement::Dup(dup) => {
+                let device = dup
+                    .dev
+                    .as_ref()
+                    .map_or("".to_string(), |dev| format!(" device {}", quoted(dev)));
+                format!("dup to {}{device}", dup.addr.nft_syntax())
+            }
+            Statement::SNAT(nat) => nat_syntax("snat", nat),
+            Statement::DNAT(nat) => nat_syntax("dnat", nat),
+            Statement::Masquerade(nat) => nat_syntax("masquerade", nat),
+            Statement::Redirect(nat) => nat_syntax("redirect", nat),
+            Statement::Reject(reject) => reject
+                .as_ref()
+                .map_or("reject".to_string(), |r| r.nft_syntax()),
+            Statement::Set(set) => format!(
+                "{} @{} {{ {} }}",
+                serde_name(&set.op),
+                set.set,
+                set.elem.nft_syntax()
+            ),
+            Statement::Log(log) => log.as_ref().map_or("log".to_string(), |l| l.nft_syntax()),
+            Statement::CTHelper(helper) => format!("ct helper set {}", quote(helper)),
+            Statement::Meter(meter) => format!(
+                "meter {} {{ {} {} }}",
+                meter.name,
+                meter.key.nft_syntax(),
+                meter.stmt.nft_syntax()
+            ),
+            Statement::Queue(queue) => {
+                let mut flags: Vec<&str> = queue
+                    .flags
+                    .iter()
+                    .flatten()
+                    .map(|flag| match flag {
+                        QueueFlag::Bypass => "bypass",
+                        QueueFlag::Fanout => "fanout",
+                    })
+                    .collect();
+                flags.sort();
+                let flags = if flags.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(" {}", flags.join(","))
+                };
+                format!("queue num {}{flags}", queue.num.nft_syntax())
+            }
+            Statement::VerdictMap(vmap) => {
+                format!("{} vmap {}", vmap.key.nft_syntax(), vmap.data.nft_syntax())
+            }
+            Statement::CTCount(count) => {
+                let over = if count.inv == Some(true) { "over " } else { "" };
+                format!("ct count {over}{}", count.val.nft_syntax())
+            }
+            Statement::CTTimeout(timeout) => format!("ct timeout set {}", quoted(timeout)),
+            Statement::CTExpectation(expectation) => {
+                format!("ct expectation set {}", quoted(expectation))
+            }
+            // Rules of iptables-nft, which nft lists but can't load
+            Statement::XT(xt) => {
+                let field = |name: &str| {
+                    xt.as_ref()
+                        .and_then(|xt| xt.get(name))
+                        .and_then(|value| value.as_str())
+                        .map_or("".to_string(), |value| format!(" {value}"))
+                };
+                format!("xt{}{}", field("type"), field("name"))
+            }
+            Statement::SynProxy(synproxy) => synproxy.nft_syntax(),
+            Statement::TProxy(tproxy) => {
+                let family = tproxy
+                    .family
+                    .as_ref()
+                    .map_or("".to_string(), |family| format!(" {family}"));
+                let to = match &tproxy.addr {
+                    Some(addr) if addr.contains(':') => format!("[{addr}]:{}", tproxy.port),
+                    Some(addr) => format!("{addr}:{}", tproxy.port),
+                    None => format!(":{}", tproxy.port),
+                };
+                format!("tproxy{family} to {to}")
+            }
+            // Statements added to nftables after this was written, only by
+            // the name nft gives them in JSON
+            statement => match serde_json::to_value(statement) {
+                Ok(serde_json::Value::Object(object)) => object.keys().cloned().collect(),
+                _ => "?".to_string(),
+            },
+        }
+    }
+}
+
+impl<'a> NftSyntax for Expression<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            Expression::String(str) => str.to_string(),
+            Expression::Number(num) => num.to_string(),
+            Expression::Boolean(bool) => bool.to_string(),
+            Expression::List(list) => {
+                let exprs: Vec<String> = list.iter().map(|expr| expr.nft_syntax()).collect();
+                exprs.join(",")
+            }
+            Expression::BinaryOperation(operation) => {
+                let (left, op, right) = match operation.as_ref() {
+                    BinaryOperation::AND(left, right) => (left, "&", right),
+                    BinaryOperation::OR(left, right) => (left, "|", right),
+                    BinaryOperation::XOR(left, right) => (left, "^", right),
+                    BinaryOperation::LSHIFT(left, right) => (left, "<<", right),
+                    BinaryOperation::RSHIFT(left, right) => (left, ">>", right),
+                };
+                format!("{} {op} {}", left.nft_syntax(), right.nft_syntax())
+            }
+            Expression::Range(range) => format!(
+                "{}-{}",
+                range.range[0].nft_syntax(),
+                range.range[1].nft_syntax()
+            ),
+            Expression::Named(expr) => expr.nft_syntax(),
+            Expression::Verdict(verdict) => verdict.nft_syntax(),
+        }
+    }
+}
+
+impl<'a> NftSyntax for NamedExpression<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            NamedExpression::Concat(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|expr| expr.nft_syntax()).collect();
+                exprs.join(" . ")
+            }
+            NamedExpression::Set(set) => {
+                let items: Vec<String> = set.iter().map(|item| item.nft_syntax()).collect();
+                format!("{{ {} }}", items.join(", "))
+            }
+            NamedExpression::Map(map) => {
+                format!("{} map {}", map.key.nft_syntax(), map.data.nft_syntax())
+            }
+            NamedExpression::Prefix(prefix) => {
+                format!("{}/{}", prefix.addr.nft_syntax(), prefix.len)
+            }
+            NamedExpression::Payload(Payload::PayloadField(field)) => {
+                format!("{} {}", field.protocol, field.field)
+            }
+            NamedExpression::Payload(Payload::PayloadRaw(raw)) => {
+                let base = match raw.base {
+                    PayloadBase::LL => "ll",
+                    PayloadBase::NH => "nh",
+                    PayloadBase::TH => "th",
+                    PayloadBase::IH => "ih",
+                };
+                format!("@{base},{},{}", raw.offset, raw.len)
+            }
+            NamedExpression::Exthdr(exthdr) => match &exthdr.field {
+                Some(field) => format!("{} {field}", exthdr.name),
+                None => format!("exthdr {}", exthdr.name),
+            },
+            NamedExpression::TcpOption(option) => match &option.field {
+                Some(field) => format!("tcp option {} {field}", option.name),
+                None => format!("tcp option {}", option.name),
+            },
+            NamedExpression::SctpChunk(chunk) => {
+                format!("sctp chunk {} {}", chunk.name, chunk.field)
+            }
+            // nft lists interfaces without the meta keyword
+            NamedExpression::Meta(meta) => match meta.key {
+                MetaKey::Iif
+                | MetaKey::Oif
+                | MetaKey::Iifname
+                | MetaKey::Oifname
+                | MetaKey::Iifgroup
+                | MetaKey::Oifgroup => serde_name(&meta.key),
+                _ => format!("meta {}", serde_name(&meta.key)),
+            },
+            NamedExpression::RT(rt) => {
+                let family = match rt.family {
+                    Some(RTFamily::IP) => "ip ",
+                    Some(RTFamily::IP6) => "ip6 ",
+                    None => "",
+                };
+                format!("rt {family}{}", serde_name(&rt.key))
+            }
+            NamedExpression::CT(ct) => {
+                let dir = match ct.dir {
+                    Some(CTDir::Original) => "original ",
+                    Some(CTDir::Reply) => "reply ",
+                    None => "",
+                };
+                let family = match ct.family {
+                    Some(CTFamily::IP) => "ip ",
+                    Some(CTFamily::IP6) => "ip6 ",
+                    None => "",
+                };
+                format!("ct {dir}{family}{}", ct.key)
+            }
+            NamedExpression::Numgen(numgen) => {
+                let mode = match numgen.mode {
+                    NgMode::Inc => "inc",
+                    NgMode::Random => "random",
+                };
+                format!(
+                    "numgen {mode} mod {}{}",
+                    numgen.ng_mod,
+                    offset(numgen.offset)
+                )
+            }
+            NamedExpression::JHash(jhash) => {
+                let seed = jhash.seed.map_or("".to_string(), |s| format!(" seed {s}"));
+                format!(
+                    "jhash {} mod {}{seed}{}",
+                    jhash.expr.nft_syntax(),
+                    jhash.hash_mod,
+                    offset(jhash.offset)
+                )
+            }
+            NamedExpression::SymHash(symhash) => {
+                format!("symhash mod {}{}", symhash.hash_mod, offset(symhash.offset))
+            }
+            NamedExpression::Fib(fib) => {
+                // In the order of the kernel flags, like `fib saddr . iif`
+                let flags: Vec<&str> = [
+                    FibFlag::Saddr,
+                    FibFlag::Daddr,
+                    FibFlag::Mark,
+                    FibFlag::Iif,
+                    FibFlag::Oif,
+                ]
+                .iter()
+                .filter(|flag| fib.flags.contains(flag))
+                .map(|flag| match flag {
+                    FibFlag::Saddr => "saddr",
+                    FibFlag::Daddr => "daddr",
+                    FibFlag::Mark => "mark",
+                    FibFlag::Iif => "iif",
+                    FibFlag::Oif => "oif",
+                })
+                .collect();
+                format!("fib {} {}", flags.join(" . "), serde_name(&fib.result))
+            }
+            NamedExpression::Elem(elem) => elem.nft_syntax(),
+            NamedExpression::Socket(socket) => format!("socket {}", serde_name(&socket.key)),
+            NamedExpression::Osf(osf) => {
+                format!("osf ttl {} {}", serde_name(&osf.ttl), osf.key)
+            }
+        }
+    }
+}
+
+impl<'a> NftSyntax for Elem<'a> {
+    fn nft_syntax(&self) -> String {
+        let mut elem = self.val.nft_syntax();
+        if let Some(timeout) = self.timeout {
+            elem += &format!(" timeout {}", time(timeout));
+        }
+        if let Some(expires) = self.expires {
+            elem += &format!(" expires {}", time(expires));
+        }
+        if let Some(counter) = &self.counter {
+            elem += &format!(" {}", counter.nft_syntax());
+        }
+        if let Some(comment) = &self.comment {
+            elem += &format!(" comment {}", quote(comment));
+        }
+
+        elem
+    }
+}
+
+impl<'a> NftSyntax for SetItem<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            // The JSON of map elements, `[key, value]`, is read back as a list
+            SetItem::Element(Expression::List(pair)) if pair.len() == 2 => {
+                format!("{} : {}", pair[0].nft_syntax(), pair[1].nft_syntax())
+            }
+            SetItem::Element(expr) => expr.nft_syntax(),
+            SetItem::Mapping(key, value) => {
+                format!("{} : {}", key.nft_syntax(), value.nft_syntax())
+            }
+            SetItem::MappingStatement(key, stmt) => {
+                format!("{} : {}", key.nft_syntax(), stmt.nft_syntax())
+            }
+        }
+    }
+}
+
+impl<'a> NftSyntax for Verdict<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            Verdict::Accept => "accept".to_string(),
+            Verdict::Drop => "drop".to_string(),
+            Verdict::Continue => "continue".to_string(),
+            Verdict::Return => "return".to_string(),
+            Verdict::Jump(jump) => format!("jump {}", jump.target),
+            Verdict::Goto(goto) => format!("goto {}", goto.target),
+        }
+    }
+}
+
+impl<'a> NftSyntax for Counter<'a> {
+    fn nft_syntax(&self) -> String {
+        match self {
+            Counter::Named(name) => format!("counter name {}", quote(name)),
+            Counter::Anonymous(Some(counter)) => format!(
+                "counter packets {} bytes {}",
+                counter.packets.unwrap_or_default(),
+                counter.bytes.unwrap_or_default()
+            ),
+            Counter::Anonymous(None) => "counter".to_string(),
+        }
+    }
+}
+
+impl<'a> NftSyntax for Limit<'a> {
+    fn nft_syntax(&self) -> String {
+        let over = if self.inv == Some(true) { "over " } else { "" };
+        let per = self.per.as_deref().unwrap_or("second");
+        let mut limit = match self.rate_unit.as_deref() {
+            None | Some("packets") => format!("limit rate {over}{}/{per}", self.rate),
+            Some(unit) => format!("limit rate {over}{} {unit}/{per}", self.rate),
+        };
+
+        // nft leaves out the burst packet limits get by default
+        let packets = matches!(self.rate_unit.as_deref(), None | Some("packets"));
+        match self.burst {
+            Some(burst) if packets && burst == DEFAULT_BURST as u32 => {}
+            Some(burst) if burst > 0 && packets => limit += &format!(" burst {burst} packets"),
+            Some(burst) if burst > 0 => {
+                let unit = self.burst_unit.as_deref().unwrap_or("bytes");
+                limit += &format!(" burst {burst} {unit}");
+            }
+            _ => {}
+        }
+
+        limit
+    }
+}
+
+impl<'a> NftSyntax for QuotaOrQuotaRef<'a> {
+    fn nft_syntax(&self) -> String {
+        let quota = match self {
+            QuotaOrQuotaRef::Quota(quota) => quota,
+            QuotaOrQuotaRef::QuotaRef(name) => return format!("quota name {}", quote(name)),
+        };
+
+        let over = if quota.inv == Some(true) { "over " } else { "" };
+        let mut text = format!("quota {over}{} {}", quota.val, quota.val_unit);
+        if let Some(used) = quota.used {
+            let unit = quota.used_unit.as_deref().unwrap_or("bytes");
+            text += &format!(" used {used} {unit}");
+        }
+
+        text
+    }
+}
+
+impl<'a> NftSyntax for Log<'a> {
+    fn nft_syntax(&self) -> String {
+        let mut log = "log".to_string();
+
+        if let Some(prefix) = &self.prefix {
+            log += &format!(" prefix {}", quote(prefix));
+        }
+        if let Some(group) = self.group {
+            log += &format!(" group {group}");
+        }
+        if let Some(snaplen) = self.snaplen {
+            log += &format!(" snaplen {snaplen}");
+        }
+        if let Some(threshold) = self.queue_threshold {
+            log += &format!(" queue-threshold {threshold}");
+        }
+        if let Some(level) = &self.level {
+            log += &format!(" level {}", serde_name(level));
+        }
+
+        let flags = self.flags.clone().unwrap_or_default();
+        let tcp: Vec<&str> = [
+            (LogFlag::TCPSequence, "sequence"),
+            (LogFlag::TCPOptions, "options"),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| flags.contains(flag))
+        .map(|(_, name)| name)
+        .collect();
+        if !tcp.is_empty() {
+            log += &format!(" flags tcp {}", tcp.join(","));
+        }
+        for (flag, name) in [
+            (LogFlag::IPOptions, "ip options"),
+            (LogFlag::Skuid, "skuid"),
+            (LogFlag::Ether, "ether"),
+            (LogFlag::All, "all"),
+        ] {
+            if flags.contains(&flag) {
+                log += &format!(" flags {name}");
+            }
+        }
+
+        log
+    }
+}
+
+impl NftSyntax for Reject {
+    fn nft_syntax(&self) -> String {
+        let code = self
+            .expr
+            .as_ref()
+            .map_or("port-unreachable".to_string(), serde_name);
+        match (&self._type, &self.expr) {
+            (Some(RejectType::TCPReset), _) => "reject with tcp reset".to_string(),
+            (Some(RejectType::ICMPX), _) | (None, Some(_)) => format!("reject with icmpx {code}"),
+            (Some(RejectType::ICMP), _) => format!("reject with icmp {code}"),
+            (Some(RejectType::ICMPv6), _) => format!("reject with icmpv6 {code}"),
+            (None, None) => "reject".to_string(),
+        }
+    }
+}
+
+impl NftSyntax for SynProxy {
+    fn nft_syntax(&self) -> String {
+        let mut text = "synproxy".to_string();
+        if let Some(mss) = self.mss {
+            text += &format!(" mss {mss}");
+        }
+        if let Some(wscale) = self.wscale {
+            text += &format!(" wscale {wscale}");
+        }
+        let mut flags: Vec<String> = self.flags.iter().flatten().map(serde_name).collect();
+        flags.sort();
+        for flag in flags {
+            text += &format!(" {flag}");
+        }
+
+        text
+    }
+}
+
+// Like `dnat ip to 10.0.0.5:80` or `masquerade to :1024-2048 random`
+fn nat_syntax(kind: &str, nat: &Option<NAT>) -> String {
+    let Some(nat) = nat else {
+        return kind.to_string();
+    };
+
+    let mut text = kind.to_string();
+    match nat.family {
+        Some(NATFamily::IP) => text += " ip",
+        Some(NATFamily::IP6) => text += " ip6",
+        None => {}
+    }
+
+    let addr = nat.addr.as_ref().map(|addr| addr.nft_syntax());
+    let port = nat.port.as_ref().map(|port| port.nft_syntax());
+    match (addr, port) {
+        (Some(addr), Some(port)) if addr.contains(':') => text += &format!(" to [{addr}]:{port}"),
+        (Some(addr), Some(port)) => text += &format!(" to {addr}:{port}"),
+        (Some(addr), None) => text += &format!(" to {addr}"),
+        (None, Some(port)) => text += &format!(" to :{port}"),
+        (None, None) => {}
+    }
+
+    let mut flags: Vec<&str> = nat
+        .flags
+        .iter()
+        .flatten()
+        .map(|flag| match flag {
+            NATFlag::Random => "random",
+            NATFlag::FullyRandom => "fully-random",
+            NATFlag::Persistent => "persistent",
+        })
+        .collect();
+    flags.sort();
+    if !flags.is_empty() {
+        text += &format!(" {}", flags.join(","));
+    }
+
+    text
+}
+
+// iptables-nft rules are listed by nft but can't be loaded again, and
+// statements added to nftables after this was written have no syntax here
+fn is_unsupported(statement: &Statement) -> bool {
+    !matches!(
+        statement,
+        Statement::Accept(_)
+            | Statement::Drop(_)
+            | Statement::Continue(_)
+            | Statement::Return(_)
+            | Statement::Jump(_)
+            | Statement::Goto(_)
+            | Statement::Match(_)
+            | Statement::Counter(_)
+            | Statement::Mangle(_)
+            | Statement::Quota(_)
+            | Statement::Limit(_)
+            | Statement::Flow(_)
+            | Statement::FWD(_)
+            | Statement::Notrack
+            | Statement::Dup(_)
+            | Statement::SNAT(_)
+            | Statement::DNAT(_)
+            | Statement::Masquerade(_)
+            | Statement::Redirect(_)
+            | Statement::Reject(_)
+            | Statement::Set(_)
+            | Statement::Log(_)
+            | Statement::CTHelper(_)
+            | Statement::Meter(_)
+            | Statement::Queue(_)
+            | Statement::VerdictMap(_)
+            | Statement::CTCount(_)
+            | Statement::CTTimeout(_)
+            | Statement::CTExpectation(_)
+            | Statement::SynProxy(_)
+            | Statement::TProxy(_)
+    )
+}
+
+// Like `1h30m`, the way nft lists timeouts
+fn time(secs: u32) -> String {
+    if secs == 0 {
+        return "0s".to_string();
+    }
+
+    let mut text = String::new();
+    let mut rest = secs;
+    for (unit, len) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if rest >= len {
+            text += &format!("{}{unit}", rest / len);
+            rest %= len;
+        }
+    }
+
+    text
+}
+
+fn offset(offset: Option<u32>) -> String {
+    offset.map_or("".to_string(), |offset| format!(" offset {offset}"))
+}
+
+// Interface names and other strings have to be quoted, symbols like
+// `established` or `tcp` and addresses must not be
+fn is_string(expr: &Expression) -> bool {
+    match expr {
+        Expression::Named(NamedExpression::Meta(meta)) => matches!(
+            meta.key,
+            MetaKey::Iifname
+                | MetaKey::Oifname
+                | MetaKey::Iifkind
+                | MetaKey::Oifkind
+                | MetaKey::Ibridgename
+                | MetaKey::Obridgename
+        ),
+        Expression::Named(NamedExpression::CT(ct)) => ct.key == "helper",
+        Expression::Named(NamedExpression::Osf(_)) => true,
+        _ => false,
+    }
+}
+
+fn quoted(expr: &Expression) -> String {
+    match expr {
+        Expression::String(value) => quote(value),
+        Expression::Named(NamedExpression::Set(set)) => {
+            let items: Vec<String> = set
+                .iter()
+                .map(|item| match item {
+                    SetItem::Element(expr) => quoted(expr),
+                    item => item.nft_syntax(),
+                })
+                .collect();
+            format!("{{ {} }}", items.join(", "))
+        }
+        expr => expr.nft_syntax(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        netlink::{compiler::compile, ruleset::Ruleset, script::to_ruleset},
+        rules,
+    };
+    use nftables::schema::Nftables;
+    use std::path::Path;
+
+    fn lines(ruleset: &Ruleset) -> Vec<String> {
+        let mut lines = vec![];
+        for table in &ruleset.tables {
+            lines.push(nft_table(table));
+            for chain in &table.chains {
+                lines.push(nft_chain(table, chain));
+                for rule in &chain.rules {
+                    lines.push(nft_rule(table, chain, rule));
+                }
+            }
+        }
+
+        lines
+    }
+
+    #[test]
+    fn compiled_rules_as_nft_lists_them() {
+        let compiled = compile(
+            &rules::parse(
+                Path::new("rules.toml"),
+                r#"
+[nflog]
+group = 5
+
+[nat]
+masquerade = ["wan0"]
+dnat = [{ forward = "tcp/8080 -> 10.0.0.5:80" }]
+
+[[rules]]
+name = "ssh-flood"
+protocol = "tcp"
+dports = [22]
+meter = { per = "source", rate = "over 20/minute", connections = true }
+verdict = "drop"
+log = true
+
+[[rules]]
+name = "ping"
+protocol = "icmp"
+limit = "10/second burst 20 packets"
+verdict = "accept"
+
+[[rules]]
+name = "downloads"
+direction = "output"
+dports = [80, 443]
+limit = "over 1 mbytes/second burst 256 kbytes"
+quota = "over 10 gbytes"
+verdict = "drop"
+"#,
+            )
+            .unwrap(),
+        );
+
+        let ruleset = to_ruleset(&compiled);
+        let table = &ruleset.tables[0];
+        let rules: Vec<String> = table
+            .chains
+            .iter()
+            .flat_map(|chain| {
+                chain
+                    .rules
+                    .iter()
+                    .map(move |rule| nft_rule(table, chain, rule))
+            })
+            .collect();
+
+        // The rules as `nft list table inet firewall-rs` lists them once
+        // applied, after the chain they're added to. nft lists meters with
+        // their size, which their JSON leaves out, the default is the size
+        // of the meters of the managed table.
+        let listed = [
+            (
+                "input",
+                r#"meta nfproto ipv4 tcp dport 22 ct state new meter ssh-flood_meter4 { ip saddr timeout 1m limit rate over 20/minute } counter packets 0 bytes 0 log prefix "firewall-rs: ssh-flood" group 5 drop comment "ssh-flood""#,
+            ),
+            (
+                "input",
+                r#"meta nfproto ipv6 tcp dport 22 ct state new meter ssh-flood_meter6 { ip6 saddr timeout 1m limit rate over 20/minute } counter packets 0 bytes 0 log prefix "firewall-rs: ssh-flood" group 5 drop comment "ssh-flood""#,
+            ),
+            (
+                "input",
+                r#"meta l4proto icmp limit rate 10/second burst 20 packets counter packets 0 bytes 0 accept comment "ping""#,
+            ),
+            (
+                "output",
+                r#"tcp dport { 80, 443 } limit rate over 1 mbytes/second burst 256 kbytes quota over 10 gbytes counter packets 0 bytes 0 drop comment "downloads""#,
+            ),
+            (
+                "output",
+                r#"udp dport { 80, 443 } limit rate over 1 mbytes/second burst 256 kbytes quota over 10 gbytes counter packets 0 bytes 0 drop comment "downloads""#,
+            ),
+            (
+                "prerouting",
+                r#"meta nfproto ipv4 tcp dport 8080 counter packets 0 bytes 0 dnat ip to 10.0.0.5:80 comment "nat: forward tcp/8080 -> 10.0.0.5:80""#,
+            ),
+            (
+                "postrouting",
+                r#"oifname "wan0" counter packets 0 bytes 0 masquerade comment "nat: masquerade wan0""#,
+            ),
+        ];
+        let listed: Vec<String> = listed
+            .iter()
+            .map(|(chain, rule)| format!("add rule inet firewall-rs {chain} {rule}"))
+            .collect();
+        assert_eq!(rules, listed);
+
+        let chains: Vec<String> = table
+            .chains
+            .iter()
+            .map(|chain| nft_chain(table, chain))
+            .collect();
+        assert_eq!(
+            chains[3],
+            "add chain inet firewall-rs prerouting { type nat hook prerouting priority -100; policy accept; }"
+        );
+    }
+
+    // Trimmed down output of nft -j -a list ruleset
+    const LISTED: &str = r#"{"nftables": [
+{"table": {"family": "inet", "name": "filter", "handle": 1}},
+{"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+  "type": "filter", "hook": "input", "prio": 0, "policy": "drop"}},
+{"chain": {"family": "inet", "table": "filter", "name": "ssh", "handle": 2}},
+{"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 3,
+  "expr": [
+    {"match": {"op": "in", "left": {"ct": {"key": "state"}},
+      "right": ["established", "related"]}},
+    {"accept": null}
+  ]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4,
+  "expr": [
+    {"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "eth0"}},
+    {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}},
+      "right": 22}},
+    {"jump": {"target": "ssh"}}
+  ]}},
+{"rule": {"family": "inet", "table": "filter", "chain": "ssh", "handle": 5,
+  "expr": [
+    {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+      "right": {"prefix": {"addr": "10.0.0.0", "len": 8}}}},
+    {"counter": {"packets": 12, "bytes": 720}},
+    {"accept": null}
+  ], "comment": "office"}},
+{"rule": {"family": "inet", "table": "filter", "chain": "ssh", "handle": 6,
+  "expr": [
+    {"xt": {"type": "match", "name": "conntrack"}},
+    {"drop": null}
+  ]}}
+]}"#;
+
+    #[test]
+    fn listed_rules_with_handles() {
+        let listed: Nftables<'static> = serde_json::from_str(LISTED).unwrap();
+
+        assert_eq!(
+            lines(&Ruleset::from_nftables(&listed)),
+            [
+                "add table inet filter # handle 1",
+                "add chain inet filter input { type filter hook input priority 0; policy drop; } # handle 1",
+                "add rule inet filter input ct state established,related accept # handle 3",
+                r#"add rule inet filter input iifname "eth0" tcp dport 22 jump ssh # handle 4"#,
+                "add chain inet filter ssh # handle 2",
+                r#"add rule inet filter ssh ip saddr 10.0.0.0/8 counter packets 12 bytes 720 accept comment "office" # handle 5"#,
+                "# unsupported: add rule inet filter ssh xt match conntrack drop # handle 6",
+            ]
+        );
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(time(0), "0s");
+        assert_eq!(time(45), "45s");
+        assert_eq!(time(60), "1m");
+        assert_eq!(time(5400), "1h30m");
+        assert_eq!(time(86400 + 61), "1d1m1s");
+    }
+}
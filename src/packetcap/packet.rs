@@ -1,44 +1,162 @@
-use chrono::{DateTime, Utc};
+use crate::netlink::LogEntry;
+
+use chrono::{DateTime, Local};
 use core::net::IpAddr;
 use pcap::Packet;
+use pnet::packet::{
+    Packet as _,
+    ethernet::{EtherTypes, EthernetPacket},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    tcp::TcpPacket,
+    udp::UdpPacket,
+};
 use tokio::sync::mpsc::{self};
 
+// Oldest packets are dropped from the log past this many
+const MAX_PACKETS: usize = 1000;
+
 pub struct PacketInfo {
+    pub time: DateTime<Local>,
+    // Rule that logged the packet, the whole log prefix when it wasn't one
+    // from the rules file and None for captured packets
+    pub rule: Option<String>,
+    pub iifname: Option<String>,
+    pub oifname: Option<String>,
     pub proto: String,
-    pub src: IpAddr,
-    pub dst: IpAddr,
-    //pub time: DateTime<Utc>,
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
 }
 
 impl PacketInfo {
+    // Captured packets start with the ethernet header
     pub fn build(packet: &Packet) -> Self {
         let header = packet.header;
-        let proto = "test".to_string();
-        let src = IpAddr::from([127, 0, 0, 1]);
-        let dst = IpAddr::from([127, 0, 0, 1]);
+        let time = DateTime::from_timestamp(header.ts.tv_sec, header.ts.tv_usec as u32 * 1000)
+            .map_or_else(Local::now, |time| time.with_timezone(&Local));
+
+        let mut info = Self::empty(time);
+        if let Some(frame) = EthernetPacket::new(packet.data) {
+            match frame.get_ethertype() {
+                EtherTypes::Ipv4 | EtherTypes::Ipv6 => info.read_ip(frame.payload()),
+                ethertype => info.proto = ethertype.to_string(),
+            }
+        }
+
+        info
+    }
+
+    // Logged packets start with the network header
+    pub fn from_log(entry: &LogEntry) -> Self {
+        let mut info = Self::empty(DateTime::<Local>::from(entry.time));
+        info.rule = entry
+            .rule()
+            .or(entry.prefix.as_deref())
+            .map(|rule| rule.trim().to_string());
+        info.iifname = entry.iifname.clone();
+        info.oifname = entry.oifname.clone();
+        info.read_ip(&entry.payload);
+
+        info
+    }
+
+    fn empty(time: DateTime<Local>) -> Self {
+        Self {
+            time,
+            rule: None,
+            iifname: None,
+            oifname: None,
+            proto: "?".to_string(),
+            src: None,
+            dst: None,
+            sport: None,
+            dport: None,
+        }
+    }
 
-        Self { proto, src, dst }
+    fn read_ip(&mut self, data: &[u8]) {
+        match data.first().map(|b| b >> 4) {
+            Some(4) => {
+                if let Some(ip) = Ipv4Packet::new(data) {
+                    self.src = Some(ip.get_source().into());
+                    self.dst = Some(ip.get_destination().into());
+                    self.read_transport(ip.get_next_level_protocol(), ip.payload());
+                }
+            }
+            Some(6) => {
+                if let Some(ip) = Ipv6Packet::new(data) {
+                    self.src = Some(ip.get_source().into());
+                    self.dst = Some(ip.get_destination().into());
+                    self.read_transport(ip.get_next_header(), ip.payload());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_transport(&mut self, protocol: IpNextHeaderProtocol, data: &[u8]) {
+        self.proto = protocol.to_string().to_lowercase();
+
+        let ports = match protocol {
+            IpNextHeaderProtocols::Tcp => {
+                TcpPacket::new(data).map(|tcp| (tcp.get_source(), tcp.get_destination()))
+            }
+            IpNextHeaderProtocols::Udp => {
+                UdpPacket::new(data).map(|udp| (udp.get_source(), udp.get_destination()))
+            }
+            _ => None,
+        };
+        if let Some((sport, dport)) = ports {
+            self.sport = Some(sport);
+            self.dport = Some(dport);
+        }
     }
 }
 
+// Packets logged to the NFLOG group and captured from the selected interface
 pub struct PacketCollector {
     pub packets: Vec<PacketInfo>,
-    pub packets_rx: mpsc::Receiver<PacketInfo>,
+    pub packets_rx: mpsc::UnboundedReceiver<PacketInfo>,
+    log_rx: Option<mpsc::UnboundedReceiver<LogEntry>>,
 }
 
 impl PacketCollector {
-    pub fn new() -> (Self, mpsc::Sender<PacketInfo>) {
-        let (packets_tx, packets_rx) = mpsc::channel::<PacketInfo>(1);
+    pub fn new() -> (Self, mpsc::UnboundedSender<PacketInfo>) {
+        let (packets_tx, packets_rx) = mpsc::unbounded_channel::<PacketInfo>();
 
         (
             Self {
                 packets: vec![],
                 packets_rx,
+                log_rx: None,
             },
             packets_tx,
         )
     }
 
+    // Packets logged to the group are collected along with captured ones
+    pub fn read_log(&mut self, log_rx: mpsc::UnboundedReceiver<LogEntry>) {
+        self.log_rx = Some(log_rx);
+    }
+
+    // Moves the packets received since the last call into the log
+    pub fn collect(&mut self) {
+        while let Ok(packet) = self.packets_rx.try_recv() {
+            self.packets.push(packet);
+        }
+        if let Some(log_rx) = &mut self.log_rx {
+            while let Ok(entry) = log_rx.try_recv() {
+                self.packets.push(PacketInfo::from_log(&entry));
+            }
+        }
+
+        let excess = self.packets.len().saturating_sub(MAX_PACKETS);
+        self.packets.drain(..excess);
+    }
+
     pub fn clear(&mut self) {
         self.packets.clear();
     }
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+// Rules files can pull in shared fragments with `include = ["common.toml"]`
+// and name groups of addresses or ports in a [defines] table, which rules
+// refer to as "$NAME" in their address and port lists. Both are resolved
+// into a plain table before the rules file itself is deserialized.
+
+// Lists of a rule that can refer to defines
+const LISTS: [&str; 4] = ["sources", "destinations", "sports", "dports"];
+
+// Everything else is only allowed in the file passed with `-r`
+const INCLUDABLE: [&str; 3] = ["include", "defines", "rules"];
+
+// Named groups of addresses and ports, along with the file defining them
+#[derive(Debug, Default)]
+pub struct Defines {
+    groups: BTreeMap<String, (Vec<Value>, PathBuf)>,
+}
+
+impl Defines {
+    fn insert(&mut self, name: String, value: Value, path: &Path) -> Result<()> {
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        if values
+            .iter()
+            .any(|v| !matches!(v, Value::String(_) | Value::Integer(_)))
+        {
+            bail!("${name} can only hold addresses, networks and ports");
+        }
+
+        if let Some((_, other)) = self.groups.get(&name) {
+            bail!(
+                "${name} is defined in both {} and {}",
+                other.display(),
+                path.display()
+            );
+        }
+        self.groups.insert(name, (values, path.to_path_buf()));
+
+        Ok(())
+    }
+
+    // Replaces every reference in values by the contents of the define,
+    // which can refer to other defines in turn
+    pub fn expand(&self, values: &[Value]) -> Result<Vec<Value>> {
+        self.expand_with(values, &mut vec![])
+    }
+
+    fn expand_with(&self, values: &[Value], stack: &mut Vec<String>) -> Result<Vec<Value>> {
+        let mut expanded = vec![];
+
+        for value in values {
+            let Some(name) = reference(value) else {
+                expanded.push(value.clone());
+                continue;
+            };
+
+            if let Some(start) = stack.iter().position(|s| s == name) {
+                let cycle: Vec<String> = stack[start..]
+                    .iter()
+                    .chain([&name.to_string()])
+                    .map(|s| format!("${s}"))
+                    .collect();
+                bail!("${name} refers to itself: {}", cycle.join(" -> "));
+            }
+            let (values, _) = self
+                .groups
+                .get(name)
+                .ok_or_else(|| anyhow!("undefined reference ${name}"))?;
+
+            stack.push(name.to_string());
+            expanded.extend(self.expand_with(values, stack)?);
+            stack.pop();
+        }
+
+        Ok(expanded)
+    }
+
+    // Expands the references in the address and port lists of a rule
+    pub fn expand_rule(&self, rule: &mut Value) -> Result<()> {
+        let Value::Table(rule) = rule else {
+            return Ok(());
+        };
+
+        for list in LISTS {
+            if let Some(Value::Array(values)) = rule.get_mut(list) {
+                *values = self.expand(values)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// "$NAME" strings, anything else is used as is
+pub fn reference(value: &Value) -> Option<&str> {
+    value.as_str().and_then(|s| s.strip_prefix('$'))
+}
+
+// Files included so far, rules and defines are collected in the order the
+// files are included with the including file last
+#[derive(Default)]
+struct Loader {
+    // Files currently being included, to report include cycles
+    stack: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+    defines: Defines,
+    rules: Vec<Value>,
+}
+
+impl Loader {
+    fn add(&mut self, path: &Path, table: &mut Table) -> Result<()> {
+        let includes = match table.remove("include") {
+            None => vec![],
+            Some(Value::Array(includes)) => includes,
+            Some(include) => vec![include],
+        };
+
+        for include in includes {
+            let Value::String(include) = include else {
+                bail!("include must be a list of file names");
+            };
+            // Relative paths are relative to the file including them
+            let included = path.parent().unwrap_or(Path::new("")).join(&include);
+            self.include(path, &included)?;
+        }
+
+        if let Some(defines) = table.remove("defines") {
+            let Value::Table(defines) = defines else {
+                bail!("defines must be a table");
+            };
+            for (name, value) in defines {
+                self.defines.insert(name, value, path)?;
+            }
+        }
+
+        if let Some(rules) = table.remove("rules") {
+            let Value::Array(rules) = rules else {
+                bail!("rules must be an array of tables");
+            };
+            self.rules.extend(rules);
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, from: &Path, path: &Path) -> Result<()> {
+        let key = fs::canonicalize(path).with_context(|| {
+            format!(
+                "Unable to read {} included from {}",
+                path.display(),
+                from.display()
+            )
+        })?;
+
+        if let Some(start) = self.stack.iter().position(|p| *p == key) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&key])
+                .map(|p| p.display().to_string())
+                .collect();
+            bail!("include cycle: {}", cycle.join(" -> "));
+        }
+        // A file included from several places is only used once
+        if !self.seen.insert(key.clone()) {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read included file {}", path.display()))?;
+        let mut table: Table = toml::from_str(&contents)
+            .with_context(|| format!("Invalid included file {}", path.display()))?;
+
+        if let Some(key) = table.keys().find(|k| !INCLUDABLE.contains(&k.as_str())) {
+            bail!(
+                "{key} can't be used in included file {}, only include, [defines] and [[rules]] can",
+                path.display()
+            );
+        }
+
+        self.stack.push(key);
+        self.add(path, &mut table)?;
+        self.stack.pop();
+
+        Ok(())
+    }
+
+    fn start(path: &Path, contents: &str) -> Result<(Self, Table)> {
+        let mut loader = Self::default();
+        let mut table: Table = toml::from_str(contents)?;
+
+        if let Ok(key) = fs::canonicalize(path) {
+            loader.seen.insert(key.clone());
+            loader.stack.push(key);
+        }
+        loader.add(path, &mut table)?;
+
+        Ok((loader, table))
+    }
+}
+
+// Merges the file read from path with every file it includes and expands
+// the references to defines in its rules
+pub fn resolve(path: &Path, contents: &str) -> Result<Table> {
+    let (mut loader, mut table) = Loader::start(path, contents)?;
+
+    for rule in &mut loader.rules {
+        loader.defines.expand_rule(rule).map_err(|e| {
+            let name = rule.get("name").and_then(Value::as_str).unwrap_or_default();
+            anyhow!("invalid rule \"{name}\": {e}")
+        })?;
+    }
+
+    if !loader.rules.is_empty() {
+        table.insert("rules".to_string(), Value::Array(loader.rules));
+    }
+
+    Ok(table)
+}
+
+// Every define available to the file read from path, including the ones from
+// included files
+pub fn defines(path: &Path, contents: &str) -> Result<Defines> {
+    let (loader, _) = Loader::start(path, contents)?;
+
+    Ok(loader.defines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    // Directory of its own for the files of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("firewall-rs-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn list(table: &Table, rule: usize, field: &str) -> Vec<Value> {
+        table["rules"][rule][field].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn nested_defines_are_expanded() {
+        let table = resolve(
+            Path::new("rules.toml"),
+            r#"
+[defines]
+LAN = ["10.0.0.0/8", "$TRUSTED"]
+TRUSTED = "192.0.2.1"
+WEB = [80, 443]
+
+[[rules]]
+name = "web-from-lan"
+sources = ["$LAN", "198.51.100.1"]
+dports = ["$WEB", 8080]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            list(&table, 0, "sources"),
+            vec![
+                Value::from("10.0.0.0/8"),
+                Value::from("192.0.2.1"),
+                Value::from("198.51.100.1")
+            ]
+        );
+        assert_eq!(
+            list(&table, 0, "dports"),
+            vec![Value::from(80), Value::from(443), Value::from(8080)]
+        );
+        assert!(!table.contains_key("defines"));
+    }
+
+    #[test]
+    fn define_cycles_are_reported() {
+        let e = resolve(
+            Path::new("rules.toml"),
+            r#"
+[defines]
+A = ["$B"]
+B = ["10.0.0.1", "$A"]
+
+[[rules]]
+name = "cycle"
+sources = ["$A"]
+"#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            e.to_string(),
+            "invalid rule \"cycle\": $A refers to itself: $A -> $B -> $A"
+        );
+    }
+
+    #[test]
+    fn undefined_references_are_reported() {
+        let e = resolve(
+            Path::new("rules.toml"),
+            r#"
+[[rules]]
+name = "typo"
+sources = ["$LNA"]
+"#,
+        )
+        .unwrap_err();
+
+        assert!(e.to_string().contains("undefined reference $LNA"), "{e}");
+    }
+
+    #[test]
+    fn includes_add_defines_and_rules() {
+        let dir = test_dir("includes");
+        fs::create_dir(dir.join("shared")).unwrap();
+        fs::write(
+            dir.join("shared/common.toml"),
+            r#"
+[defines]
+DNS = ["192.0.2.53"]
+
+[[rules]]
+name = "dns"
+destinations = ["$DNS"]
+"#,
+        )
+        .unwrap();
+        let path = dir.join("rules.toml");
+
+        let table = resolve(
+            &path,
+            r#"
+include = ["shared/common.toml"]
+
+[[rules]]
+name = "more-dns"
+destinations = ["$DNS"]
+"#,
+        )
+        .unwrap();
+
+        let names: Vec<_> = table["rules"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rule| rule["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["dns", "more-dns"]);
+        assert_eq!(
+            list(&table, 1, "destinations"),
+            vec![Value::from("192.0.2.53")]
+        );
+        assert!(!table.contains_key("include"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let dir = test_dir("include-cycle");
+        let path = dir.join("rules.toml");
+        fs::write(&path, "include = [\"a.toml\"]\n").unwrap();
+        fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        fs::write(dir.join("b.toml"), "include = [\"rules.toml\"]\n").unwrap();
+
+        let e = resolve(&path, &fs::read_to_string(&path).unwrap()).unwrap_err();
+        let message = e.to_string();
+        let cycle: Vec<_> = message
+            .strip_prefix("include cycle: ")
+            .unwrap_or_else(|| panic!("{message}"))
+            .split(" -> ")
+            .map(|file| Path::new(file).file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(cycle, ["rules.toml", "a.toml", "b.toml", "rules.toml"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn included_files_only_hold_rules_and_defines() {
+        let dir = test_dir("include-settings");
+        fs::write(dir.join("nat.toml"), "[nat]\nmasquerade = [\"wan0\"]\n").unwrap();
+
+        let e = resolve(&dir.join("rules.toml"), "include = [\"nat.toml\"]\n").unwrap_err();
+        assert!(e.to_string().starts_with("nat can't be used"), "{e}");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use super::{Direction, Port, Rule, Verdict};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+// One of the [allow], [deny] or [log] sections of the original rules file
+// layout. These are upgraded into ordered rules when the file is loaded.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleSection {
+    pub sources: Vec<IpAddr>,
+    pub destinations: Vec<IpAddr>,
+    pub source_networks: Vec<IpNet>,
+    pub destination_networks: Vec<IpNet>,
+    pub dports: Vec<u16>,
+    pub sports: Vec<u16>,
+}
+
+impl RuleSection {
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+            && self.destinations.is_empty()
+            && self.source_networks.is_empty()
+            && self.destination_networks.is_empty()
+            && self.dports.is_empty()
+            && self.sports.is_empty()
+    }
+
+    // Sources, source networks and destination ports apply to incoming
+    // traffic, destinations, destination networks and source ports to
+    // outgoing traffic. Each kind of entry becomes its own rule named
+    // after the section it came from.
+    fn upgrade(&self, section: &str, verdict: Verdict, log: bool) -> Vec<Rule> {
+        let mut rules = vec![];
+
+        let sources: Vec<IpNet> = self
+            .sources
+            .iter()
+            .map(|addr| IpNet::from(*addr))
+            .chain(self.source_networks.iter().copied())
+            .collect();
+        if !sources.is_empty() {
+            rules.push(Rule {
+                sources,
+                ..Rule::new(format!("{section}-sources"), Direction::Input, verdict, log)
+            });
+        }
+
+        let destinations: Vec<IpNet> = self
+            .destinations
+            .iter()
+            .map(|addr| IpNet::from(*addr))
+            .chain(self.destination_networks.iter().copied())
+            .collect();
+        if !destinations.is_empty() {
+            rules.push(Rule {
+                destinations,
+                ..Rule::new(
+                    format!("{section}-destinations"),
+                    Direction::Output,
+                    verdict,
+                    log,
+                )
+            });
+        }
+
+        if !self.dports.is_empty() {
+            rules.push(Rule {
+                dports: self.dports.iter().copied().map(Port::from).collect(),
+                ..Rule::new(format!("{section}-dports"), Direction::Input, verdict, log)
+            });
+        }
+
+        if !self.sports.is_empty() {
+            rules.push(Rule {
+                sports: self.sports.iter().copied().map(Port::from).collect(),
+                ..Rule::new(format!("{section}-sports"), Direction::Output, verdict, log)
+            });
+        }
+
+        rules
+    }
+}
+
+// Upgrades the legacy sections in the order log, deny, allow so that logging
+// always happens and a deny entry wins over an allow entry for the same packet
+pub fn upgrade(allow: &RuleSection, deny: &RuleSection, log: &RuleSection) -> Vec<Rule> {
+    let mut rules = log.upgrade("log", Verdict::Continue, true);
+    rules.extend(deny.upgrade("deny", Verdict::Drop, false));
+    rules.extend(allow.upgrade("allow", Verdict::Accept, false));
+
+    rules
+}
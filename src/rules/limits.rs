@@ -0,0 +1,388 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+// Units nft accepts for byte amounts, in the order amounts are written with
+const BYTE_UNITS: [(&str, u64); 4] = [
+    ("gbytes", 1 << 30),
+    ("mbytes", 1 << 20),
+    ("kbytes", 1 << 10),
+    ("bytes", 1),
+];
+
+// Packets let through at once on top of the rate when a packet limit has no
+// burst, the same as nft
+pub const DEFAULT_BURST: u64 = 5;
+
+// Rate of packets or bytes, written the way nft does: "10/second",
+// "over 20/minute burst 10 packets" or "1 mbytes/second burst 256 kbytes".
+// Rules match the traffic below the rate, or above it with "over".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub over: bool,
+    // Packets, or bytes for byte rates
+    pub rate: u64,
+    pub bytes: bool,
+    pub per: TimeUnit,
+    pub burst: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+}
+
+// Bytes a rule matches in total, "500 mbytes" matching until they went
+// through and "over 10 gbytes" matching once they did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub over: bool,
+    pub bytes: u64,
+}
+
+// Limits every source, or destination, address on its own, like at most 20
+// new ssh connections per minute from each address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Meter {
+    #[serde(default)]
+    pub per: MeterKey,
+    pub rate: Limit,
+    // Only the first packet of each connection counts
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub connections: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MeterKey {
+    #[default]
+    Source,
+    Destination,
+}
+
+impl Limit {
+    // Burst the kernel gets when none is given
+    pub fn burst_or_default(&self) -> u64 {
+        match (self.burst, self.bytes) {
+            (Some(burst), _) => burst,
+            (None, true) => 0,
+            (None, false) => DEFAULT_BURST,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.rate == 0 {
+            bail!("the rate of limit \"{self}\" can't be 0");
+        }
+        // nft takes rates and bursts as 32 bit numbers of the unit they are
+        // written in
+        let amounts = [Some(self.rate), self.burst].into_iter().flatten();
+        for amount in amounts {
+            let (value, _) = if self.bytes {
+                split_bytes(amount)
+            } else {
+                (amount, "packets")
+            };
+            if value > u64::from(u32::MAX) {
+                bail!("limit \"{self}\" is too large");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TimeUnit {
+    pub fn seconds(&self) -> u64 {
+        match self {
+            TimeUnit::Second => 1,
+            TimeUnit::Minute => 60,
+            TimeUnit::Hour => 60 * 60,
+            TimeUnit::Day => 60 * 60 * 24,
+            TimeUnit::Week => 60 * 60 * 24 * 7,
+        }
+    }
+}
+
+impl Quota {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.bytes == 0 {
+            bail!("quota \"{self}\" can't be 0 bytes");
+        }
+        if split_bytes(self.bytes).0 > u64::from(u32::MAX) {
+            bail!("quota \"{self}\" is too large");
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for MeterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeterKey::Source => write!(f, "source"),
+            MeterKey::Destination => write!(f, "destination"),
+        }
+    }
+}
+
+// The largest unit the amount is a whole number of, like (2, "mbytes") for
+// 2097152 bytes
+pub fn split_bytes(bytes: u64) -> (u64, &'static str) {
+    BYTE_UNITS
+        .iter()
+        .find(|(_, size)| bytes.is_multiple_of(*size) && bytes >= *size)
+        .map_or((bytes, "bytes"), |(unit, size)| (bytes / size, unit))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let (value, unit) = split_bytes(bytes);
+    format!("{value} {unit}")
+}
+
+// An amount like "20", "20 packets" or "1 mbytes", with whether it is in
+// bytes. Plain numbers are left for the caller to decide.
+fn parse_amount(text: &str) -> Option<(u64, Option<bool>)> {
+    let mut words = text.split_whitespace();
+    let value: u64 = words.next()?.parse().ok()?;
+    let amount = match words.next() {
+        None => (value, None),
+        Some("packets") => (value, Some(false)),
+        Some(unit) => {
+            let (_, size) = BYTE_UNITS.iter().find(|(name, _)| *name == unit)?;
+            (value.checked_mul(*size)?, Some(true))
+        }
+    };
+
+    words.next().is_none().then_some(amount)
+}
+
+impl FromStr for TimeUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "second" => Ok(TimeUnit::Second),
+            "minute" => Ok(TimeUnit::Minute),
+            "hour" => Ok(TimeUnit::Hour),
+            "day" => Ok(TimeUnit::Day),
+            "week" => Ok(TimeUnit::Week),
+            _ => bail!("invalid time unit \"{value}\", expected second, minute, hour, day or week"),
+        }
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeUnit::Second => write!(f, "second"),
+            TimeUnit::Minute => write!(f, "minute"),
+            TimeUnit::Hour => write!(f, "hour"),
+            TimeUnit::Day => write!(f, "day"),
+            TimeUnit::Week => write!(f, "week"),
+        }
+    }
+}
+
+impl FromStr for Limit {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "invalid limit \"{value}\", expected like \"over 20/minute\" or \"1 mbytes/second burst 256 kbytes\""
+            )
+        };
+
+        let text = value.trim();
+        let (over, text) = match text.strip_prefix("over ") {
+            Some(text) => (true, text.trim_start()),
+            None => (false, text),
+        };
+        let (rate, text) = text.split_once('/').ok_or_else(invalid)?;
+        let (per, burst) = match text.split_once(" burst ") {
+            Some((per, burst)) => (per, Some(burst)),
+            None => (text, None),
+        };
+
+        let (rate, bytes) = parse_amount(rate).ok_or_else(invalid)?;
+        let bytes = bytes.unwrap_or_default();
+        let burst = match burst.map(parse_amount) {
+            None => None,
+            Some(None) => return Err(invalid()),
+            Some(Some((burst, unit))) => {
+                if unit.is_some_and(|b| b != bytes) {
+                    bail!("invalid limit \"{value}\", the burst has to be in the unit of the rate");
+                }
+                Some(burst)
+            }
+        };
+
+        Ok(Self {
+            over,
+            rate,
+            bytes,
+            per: per.trim().parse()?,
+            burst,
+        })
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.over {
+            write!(f, "over ")?;
+        }
+        if self.bytes {
+            write!(f, "{}/{}", format_bytes(self.rate), self.per)?;
+        } else {
+            write!(f, "{}/{}", self.rate, self.per)?;
+        }
+        match self.burst {
+            Some(burst) if self.bytes => write!(f, " burst {}", format_bytes(burst)),
+            Some(burst) => write!(f, " burst {burst} packets"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Quota {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!("invalid quota \"{value}\", expected like \"500 mbytes\" or \"over 10 gbytes\"")
+        };
+
+        let text = value.trim();
+        let (over, text) = match text.strip_prefix("over ") {
+            Some(text) => (true, text.trim_start()),
+            None => (false, text),
+        };
+        match parse_amount(text) {
+            Some((bytes, Some(true))) => Ok(Self { over, bytes }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.over {
+            write!(f, "over ")?;
+        }
+        write!(f, "{}", format_bytes(self.bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Limit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Quota {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Quota {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_limits() {
+        let limit: Limit = "over 20/minute burst 10 packets".parse().unwrap();
+        assert_eq!(
+            limit,
+            Limit {
+                over: true,
+                rate: 20,
+                bytes: false,
+                per: TimeUnit::Minute,
+                burst: Some(10),
+            }
+        );
+        assert_eq!(limit.to_string(), "over 20/minute burst 10 packets");
+
+        let limit: Limit = "10 packets/second".parse().unwrap();
+        assert_eq!(limit.burst_or_default(), DEFAULT_BURST);
+        assert_eq!(limit.to_string(), "10/second");
+    }
+
+    #[test]
+    fn byte_limits() {
+        let limit: Limit = "1 mbytes/second burst 256 kbytes".parse().unwrap();
+        assert_eq!(
+            (limit.rate, limit.bytes, limit.burst),
+            (1 << 20, true, Some(256 << 10))
+        );
+        assert_eq!(limit.to_string(), "1 mbytes/second burst 256 kbytes");
+
+        assert!("1 mbytes/second burst 10 packets".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn invalid_limits() {
+        for value in [
+            "20",
+            "20/fortnight",
+            "many/second",
+            "20/second burst",
+            "1 tbytes/second",
+        ] {
+            assert!(value.parse::<Limit>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn quotas() {
+        let quota: Quota = "over 10 gbytes".parse().unwrap();
+        assert_eq!(
+            quota,
+            Quota {
+                over: true,
+                bytes: 10 << 30,
+            }
+        );
+        assert_eq!(quota.to_string(), "over 10 gbytes");
+
+        let quota: Quota = "1536 kbytes".parse().unwrap();
+        assert_eq!(quota.to_string(), "1536 kbytes");
+        assert!("10 packets".parse::<Quota>().is_err());
+    }
+}
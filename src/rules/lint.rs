@@ -0,0 +1,771 @@
+use super::{
+    DEFAULT_SERVICES_FILE, Port, Rule, Services, Verdict,
+    defines::{self, Defines, reference},
+    format_network, parse, parse_network,
+};
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+
+// Checks a rules file for entries that are invalid, redundant or that
+// contradict each other. Unlike loading the file, every problem is reported
+// with the line it was found on instead of stopping at the first one.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+// Same layout as the rules file but keeping the position of every entry and
+// accepting invalid values, so they can be reported instead of failing
+#[derive(Deserialize)]
+struct RawFile {
+    services: Option<PathBuf>,
+    include: Option<Spanned<toml::Value>>,
+    #[serde(default)]
+    defines: BTreeMap<String, Spanned<toml::Value>>,
+    #[serde(default)]
+    rules: Vec<Spanned<RawRule>>,
+    allow: Option<Spanned<RawSection>>,
+    deny: Option<Spanned<RawSection>>,
+    log: Option<Spanned<RawSection>>,
+}
+
+// Every rule on its own, so each invalid rule can be reported
+#[derive(Deserialize)]
+struct RuleTables {
+    #[serde(default)]
+    rules: Vec<Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+struct RawRule {
+    name: Spanned<String>,
+    #[serde(default)]
+    sources: Vec<Spanned<String>>,
+    #[serde(default)]
+    destinations: Vec<Spanned<String>>,
+    #[serde(default)]
+    sports: Vec<Spanned<toml::Value>>,
+    #[serde(default)]
+    dports: Vec<Spanned<toml::Value>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct RawSection {
+    sources: Vec<Spanned<String>>,
+    destinations: Vec<Spanned<String>>,
+    source_networks: Vec<Spanned<String>>,
+    destination_networks: Vec<Spanned<String>>,
+    dports: Vec<Spanned<toml::Value>>,
+    sports: Vec<Spanned<toml::Value>>,
+}
+
+struct Linter<'a> {
+    path: &'a Path,
+    contents: &'a str,
+    findings: Vec<Finding>,
+}
+
+pub fn lint_file(path: impl AsRef<Path>) -> Result<Vec<Finding>> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read rules file {}", path.display()))?;
+
+    Ok(lint(path, &contents))
+}
+
+pub fn lint(path: &Path, contents: &str) -> Vec<Finding> {
+    let mut linter = Linter {
+        path,
+        contents,
+        findings: vec![],
+    };
+    linter.run();
+
+    let mut findings = linter.findings;
+    findings.sort_by_key(|f| (f.line, f.column));
+
+    findings
+}
+
+impl Linter<'_> {
+    fn run(&mut self) {
+        let raw: RawFile = match toml::from_str(self.contents) {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.report(e.span().unwrap_or(0..0), Severity::Error, e.message());
+                return;
+            }
+        };
+
+        // Errors in included files can only be reported where they are
+        // included
+        let defines = match defines::defines(self.path, self.contents) {
+            Ok(defines) => defines,
+            Err(e) => {
+                let span = raw.include.as_ref().map_or(0..0, |i| i.span());
+                self.report(span, Severity::Error, &format!("{e:#}"));
+                return;
+            }
+        };
+        self.check_defines(&raw.defines, &defines);
+
+        for rule in &raw.rules {
+            let rule = rule.get_ref();
+            let name = rule.name.get_ref();
+            self.check_networks(&[&rule.sources], &format!("sources of \"{name}\""));
+            self.check_networks(
+                &[&rule.destinations],
+                &format!("destinations of \"{name}\""),
+            );
+            self.check_ports(&rule.sports, &format!("sports of \"{name}\""));
+            self.check_ports(&rule.dports, &format!("dports of \"{name}\""));
+        }
+
+        let sections = [
+            ("allow", &raw.allow),
+            ("deny", &raw.deny),
+            ("log", &raw.log),
+        ];
+        for (name, section) in sections {
+            let Some(section) = section else {
+                continue;
+            };
+            let section = section.get_ref();
+            self.check_networks(
+                &[&section.sources, &section.source_networks],
+                &format!("[{name}] sources"),
+            );
+            self.check_networks(
+                &[&section.destinations, &section.destination_networks],
+                &format!("[{name}] destinations"),
+            );
+            self.check_ports(&section.sports, &format!("[{name}] sports"));
+            self.check_ports(&section.dports, &format!("[{name}] dports"));
+        }
+
+        if let (Some(allow), Some(deny)) = (&raw.allow, &raw.deny) {
+            self.check_allow_deny(allow.get_ref(), deny.get_ref());
+        }
+
+        // The remaining checks need the rules the way they are applied, which
+        // is only possible once the values themselves are valid
+        if self.has_errors() {
+            return;
+        }
+
+        self.check_rules(&raw.rules, &defines, raw.services.as_deref());
+        if self.has_errors() {
+            return;
+        }
+
+        let rules = match parse(self.path, self.contents) {
+            Ok(rules) => rules,
+            Err(e) => {
+                let span = e
+                    .downcast_ref::<toml::de::Error>()
+                    .and_then(|e| e.span())
+                    .unwrap_or(0..0);
+                let message = match e.downcast_ref::<toml::de::Error>() {
+                    Some(e) => e.message().to_string(),
+                    None => e.to_string(),
+                };
+                self.report(span, Severity::Error, &message);
+                return;
+            }
+        };
+
+        // Legacy sections are upgraded into rules that only overlap where an
+        // entry is both allowed and denied, which is reported above already
+        if raw.rules.is_empty() {
+            return;
+        }
+
+        let raw_rule = |name: &str| {
+            raw.rules
+                .iter()
+                .map(|r| r.get_ref())
+                .find(|r| r.name.get_ref() == name)
+        };
+
+        for (i, rule) in rules.rules.iter().enumerate() {
+            let Some(raw) = raw_rule(&rule.name) else {
+                continue;
+            };
+
+            // Only firewall-rs and its queue command read the queue
+            if rule.verdict == Verdict::Queue && !rules.queue_settings().fail_open {
+                self.report(
+                    raw.name.span(),
+                    Severity::Warning,
+                    &format!(
+                        "rule \"{}\" drops its packets while nothing reads queue {}, set fail_open in [queue] to accept them instead",
+                        rule.name,
+                        rules.queue_settings().number
+                    ),
+                );
+            }
+
+            let earlier = &rules.rules[..i];
+            if let Some(shadow) = earlier.iter().find(|e| shadows(e, rule)) {
+                self.report(
+                    raw.name.span(),
+                    Severity::Warning,
+                    &format!(
+                        "rule \"{}\" can never match, rule \"{}\" above already matches all of its traffic",
+                        rule.name, shadow.name
+                    ),
+                );
+                continue;
+            }
+
+            // A reference to a define stands for every entry of the define
+            let spans = |raw_nets: &[Spanned<String>]| -> Vec<Range<usize>> {
+                raw_nets
+                    .iter()
+                    .flat_map(|value| {
+                        let entries = defines
+                            .expand(&[toml::Value::String(value.get_ref().clone())])
+                            .map_or(1, |entries| entries.len());
+                        std::iter::repeat_n(value.span(), entries)
+                    })
+                    .collect()
+            };
+
+            for other in earlier.iter().filter(|e| conflicts(e, rule)) {
+                let addrs = [
+                    (&rule.sources, spans(&raw.sources), &other.sources),
+                    (
+                        &rule.destinations,
+                        spans(&raw.destinations),
+                        &other.destinations,
+                    ),
+                ];
+                for (nets, spans, other_nets) in addrs {
+                    for (net, span) in nets.iter().zip(spans) {
+                        if other_nets.contains(net) {
+                            self.report(
+                                span,
+                                Severity::Warning,
+                                &format!(
+                                    "{} appears in both {} rule \"{}\" and {} rule \"{}\"",
+                                    format_network(net),
+                                    other.verdict,
+                                    other.name,
+                                    rule.verdict,
+                                    rule.name
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn has_errors(&self) -> bool {
+        self.findings.iter().any(|f| f.severity == Severity::Error)
+    }
+
+    // Deserializes and validates every rule separately, loading the whole
+    // file would only report the first invalid rule without its position
+    fn check_rules(
+        &mut self,
+        raw_rules: &[Spanned<RawRule>],
+        defines: &Defines,
+        services: Option<&Path>,
+    ) {
+        let Ok(tables) = toml::from_str::<RuleTables>(self.contents) else {
+            return;
+        };
+
+        let mut names = HashSet::new();
+        let mut rules = vec![];
+        for (table, raw) in tables.rules.iter().zip(raw_rules) {
+            let name = &raw.get_ref().name;
+            if !names.insert(name.get_ref().as_str()) {
+                self.report(
+                    name.span(),
+                    Severity::Error,
+                    &format!("duplicate rule name \"{}\"", name.get_ref()),
+                );
+            }
+
+            let mut value = table.get_ref().clone();
+            if let Err(e) = defines.expand_rule(&mut value) {
+                self.report(name.span(), Severity::Error, &e.to_string());
+                continue;
+            }
+            match Rule::deserialize(value) {
+                Ok(rule) => rules.push((rule, name)),
+                Err(e) => self.report(table.span(), Severity::Error, &e.to_string()),
+            }
+        }
+
+        let services = if rules.iter().any(|(rule, _)| rule.needs_services()) {
+            let path = services.unwrap_or(Path::new(DEFAULT_SERVICES_FILE));
+            match Services::load(path) {
+                Ok(services) => services,
+                Err(e) => {
+                    self.report(0..0, Severity::Error, &format!("{e:#}"));
+                    return;
+                }
+            }
+        } else {
+            Services::default()
+        };
+
+        for (mut rule, name) in rules {
+            let res = rule
+                .resolve_services(&services)
+                .and_then(|()| rule.validate());
+            if let Err(e) = res {
+                self.report(name.span(), Severity::Error, &e.to_string());
+            }
+        }
+    }
+
+    // Entries of a define can be used as both addresses and ports, so each
+    // one has to be valid as either of them
+    fn check_defines(
+        &mut self,
+        raw_defines: &BTreeMap<String, Spanned<toml::Value>>,
+        defines: &Defines,
+    ) {
+        for (name, value) in raw_defines {
+            if let Err(e) = defines.expand(&[toml::Value::String(format!("${name}"))]) {
+                self.report(value.span(), Severity::Error, &e.to_string());
+                continue;
+            }
+
+            let entries = match value.get_ref() {
+                toml::Value::Array(entries) => entries.clone(),
+                entry => vec![entry.clone()],
+            };
+            for entry in entries.iter().filter(|e| reference(e).is_none()) {
+                let valid = match entry {
+                    toml::Value::String(s) => parse_network(s).is_ok() || s.parse::<Port>().is_ok(),
+                    entry => Port::deserialize(entry.clone()).is_ok(),
+                };
+                if !valid {
+                    self.report(
+                        value.span(),
+                        Severity::Error,
+                        &format!("${name} holds {entry}, which is neither an address nor a port"),
+                    );
+                }
+            }
+        }
+    }
+
+    // Lists of addresses are checked together, since the legacy layout
+    // splits them into addresses and networks that are matched the same way
+    fn check_networks(&mut self, lists: &[&Vec<Spanned<String>>], what: &str) {
+        let mut networks: Vec<(IpNet, Range<usize>)> = vec![];
+
+        for value in lists.iter().flat_map(|list| list.iter()) {
+            if value.get_ref().starts_with('$') {
+                continue;
+            }
+            match parse_network(value.get_ref()) {
+                Ok(net) => networks.push((net.trunc(), value.span())),
+                Err(e) => self.report(value.span(), Severity::Error, &e.to_string()),
+            }
+        }
+
+        for (i, (net, span)) in networks.iter().enumerate() {
+            let others = networks
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, (other, _))| (j, other));
+
+            for (j, other) in others {
+                if other == net && j < i {
+                    self.report(
+                        span.clone(),
+                        Severity::Warning,
+                        &format!("duplicate entry {} in {what}", format_network(net)),
+                    );
+                    break;
+                }
+                if other != net && other.contains(net) {
+                    self.report(
+                        span.clone(),
+                        Severity::Warning,
+                        &format!(
+                            "{} in {what} is already covered by {}",
+                            format_network(net),
+                            format_network(other)
+                        ),
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    // Service names are only resolved once the whole rule is checked, here
+    // they are compared by name
+    fn check_ports(&mut self, values: &[Spanned<toml::Value>], what: &str) {
+        let mut ports: Vec<Port> = vec![];
+
+        for value in values {
+            if reference(value.get_ref()).is_some() {
+                continue;
+            }
+            let port = match Port::deserialize(value.get_ref().clone()) {
+                Ok(port) => port,
+                Err(e) => {
+                    self.report(value.span(), Severity::Error, e.message());
+                    continue;
+                }
+            };
+
+            if port.service.is_none() && port.first == 0 {
+                self.report(
+                    value.span(),
+                    Severity::Error,
+                    &format!("invalid port {port}, ports must be between 1 and 65535"),
+                );
+            } else if port.first > port.last {
+                self.report(
+                    value.span(),
+                    Severity::Error,
+                    &format!("invalid port range {port}, the first port is above the last"),
+                );
+            } else if ports.contains(&port) {
+                self.report(
+                    value.span(),
+                    Severity::Warning,
+                    &format!("duplicate port {port} in {what}"),
+                );
+            } else if let Some(other) = ports
+                .iter()
+                .find(|p| p.service.is_none() && port.service.is_none() && p.contains(&port))
+            {
+                self.report(
+                    value.span(),
+                    Severity::Warning,
+                    &format!("port {port} in {what} is already covered by {other}"),
+                );
+            }
+
+            ports.push(port);
+        }
+    }
+
+    // Deny entries are applied before allow entries, so an allow entry that
+    // is also denied has no effect
+    fn check_allow_deny(&mut self, allow: &RawSection, deny: &RawSection) {
+        let networks = |lists: [&Vec<Spanned<String>>; 2]| -> Vec<(IpNet, Range<usize>)> {
+            lists
+                .iter()
+                .flat_map(|list| list.iter())
+                .filter_map(|value| Some((parse_network(value.get_ref()).ok()?, value.span())))
+                .collect()
+        };
+
+        let addrs = [
+            (
+                "source",
+                [&allow.sources, &allow.source_networks],
+                [&deny.sources, &deny.source_networks],
+            ),
+            (
+                "destination",
+                [&allow.destinations, &allow.destination_networks],
+                [&deny.destinations, &deny.destination_networks],
+            ),
+        ];
+        for (what, allowed, denied) in addrs {
+            let denied = networks(denied);
+            for (net, span) in networks(allowed) {
+                if let Some((deny_net, _)) = denied.iter().find(|(d, _)| d.contains(&net)) {
+                    self.report(
+                        span,
+                        Severity::Warning,
+                        &format!(
+                            "{what} {} is allowed but also denied by {} in [deny]",
+                            format_network(&net),
+                            format_network(deny_net)
+                        ),
+                    );
+                }
+            }
+        }
+
+        let ports = [
+            ("source", &allow.sports, &deny.sports),
+            ("destination", &allow.dports, &deny.dports),
+        ];
+        for (what, allowed, denied) in ports {
+            for port in allowed {
+                if denied.iter().any(|p| p.get_ref() == port.get_ref()) {
+                    self.report(
+                        port.span(),
+                        Severity::Warning,
+                        &format!(
+                            "{what} port {} is in both [allow] and [deny]",
+                            port.get_ref()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, span: Range<usize>, severity: Severity, message: &str) {
+        let start = span.start.min(self.contents.len());
+        let before = &self.contents[..start];
+        let line = before.matches('\n').count() + 1;
+        let column = start - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+        self.findings.push(Finding {
+            path: self.path.to_path_buf(),
+            line,
+            column,
+            severity,
+            message: message.to_string(),
+        });
+    }
+}
+
+// An empty list matches everything, so it covers any other list
+fn covers<T>(outer: &[T], inner: &[T], contains: impl Fn(&T, &T) -> bool) -> bool {
+    outer.is_empty()
+        || (!inner.is_empty() && inner.iter().all(|i| outer.iter().any(|o| contains(o, i))))
+}
+
+fn covers_interface(outer: &Option<String>, inner: &Option<String>) -> bool {
+    match (outer, inner) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(outer), Some(inner)) => match outer.strip_suffix('*') {
+            Some(prefix) => inner.starts_with(prefix),
+            None => outer == inner,
+        },
+    }
+}
+
+// Whether every packet matched by rule is already matched by earlier, which
+// then decides its fate before rule is reached
+fn shadows(earlier: &Rule, rule: &Rule) -> bool {
+    if earlier.verdict == Verdict::Continue
+        || earlier.direction != rule.direction
+        || earlier.is_limited()
+    {
+        return false;
+    }
+
+    let protocols_covered = match (earlier.protocols(), rule.protocols()) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(outer), Some(inner)) => inner.iter().all(|p| outer.contains(p)),
+    };
+
+    protocols_covered
+        && covers_interface(&earlier.iifname, &rule.iifname)
+        && covers_interface(&earlier.oifname, &rule.oifname)
+        && covers(&earlier.sources, &rule.sources, |o, i| o.contains(i))
+        && covers(&earlier.destinations, &rule.destinations, |o, i| {
+            o.contains(i)
+        })
+        && covers(&earlier.sports, &rule.sports, |o, i| o.contains(i))
+        && covers(&earlier.dports, &rule.dports, |o, i| o.contains(i))
+}
+
+// Whether an accept rule and a drop or reject rule can match the same
+// packets, ignoring addresses which are compared entry by entry
+fn conflicts(earlier: &Rule, rule: &Rule) -> bool {
+    let accepts = |r: &Rule| r.verdict == Verdict::Accept;
+    let denies = |r: &Rule| matches!(r.verdict, Verdict::Drop | Verdict::Reject);
+    if !(accepts(earlier) && denies(rule) || denies(earlier) && accepts(rule)) {
+        return false;
+    }
+    // Accepting up to a rate and dropping the rest is what limits are for
+    if earlier.direction != rule.direction || earlier.is_limited() || rule.is_limited() {
+        return false;
+    }
+
+    let protocols_overlap = match (earlier.protocols(), rule.protocols()) {
+        (Some(a), Some(b)) => a.iter().any(|p| b.contains(p)),
+        _ => true,
+    };
+    let ports_overlap = |a: &[Port], b: &[Port]| {
+        a.is_empty() || b.is_empty() || a.iter().any(|p| b.iter().any(|q| p.overlaps(q)))
+    };
+
+    protocols_overlap
+        && ports_overlap(&earlier.sports, &rule.sports)
+        && ports_overlap(&earlier.dports, &rule.dports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings(contents: &str) -> Vec<String> {
+        lint(Path::new("rules.toml"), contents)
+            .iter()
+            .map(Finding::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn clean_file() {
+        let findings = findings(
+            r#"
+version = 2
+
+[[rules]]
+name = "ssh"
+direction = "input"
+protocol = "tcp"
+sources = ["10.0.0.0/8", "fd00::/8"]
+dports = [22]
+verdict = "accept"
+"#,
+        );
+        assert!(findings.is_empty(), "{findings:?}");
+    }
+
+    #[test]
+    fn entries_are_reported_where_they_are() {
+        let findings = findings(
+            r#"[[rules]]
+name = "ssh"
+direction = "input"
+sources = ["10.0.0.0/8", "10.1.0.0/16", "10.0.0.0/8", "not-an-address"]
+dports = [22, 70000]
+verdict = "accept"
+"#,
+        );
+        assert_eq!(
+            findings,
+            [
+                "rules.toml:4:26: warning: 10.1.0.0/16 in sources of \"ssh\" is already covered by 10.0.0.0/8",
+                "rules.toml:4:41: warning: duplicate entry 10.0.0.0/8 in sources of \"ssh\"",
+                "rules.toml:4:55: error: invalid address or network \"not-an-address\"",
+                "rules.toml:5:15: error: invalid port 70000, ports must be between 1 and 65535",
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowed_rules() {
+        let findings = findings(
+            r#"[[rules]]
+name = "ssh"
+direction = "input"
+protocol = "tcp"
+dports = [22]
+verdict = "accept"
+
+[[rules]]
+name = "ssh-again"
+direction = "input"
+protocol = "tcp"
+sources = ["10.0.0.0/8"]
+dports = [22]
+verdict = "drop"
+"#,
+        );
+        assert_eq!(
+            findings,
+            [
+                "rules.toml:9:8: warning: rule \"ssh-again\" can never match, rule \"ssh\" above already matches all of its traffic"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_rule_names() {
+        let findings = findings(
+            r#"[[rules]]
+name = "web"
+direction = "input"
+dports = [80]
+verdict = "accept"
+
+[[rules]]
+name = "web"
+direction = "output"
+dports = [80]
+verdict = "accept"
+"#,
+        );
+        assert!(
+            findings.contains(&"rules.toml:8:8: error: duplicate rule name \"web\"".to_string()),
+            "{findings:?}"
+        );
+    }
+
+    #[test]
+    fn queue_without_fail_open() {
+        let rule = r#"
+[[rules]]
+name = "inspect"
+direction = "forward"
+verdict = "queue"
+"#;
+        assert_eq!(
+            findings(&format!("[queue]\nnumber = 3\n{rule}")),
+            [
+                "rules.toml:5:8: warning: rule \"inspect\" drops its packets while nothing reads queue 3, set fail_open in [queue] to accept them instead"
+            ]
+        );
+        assert!(findings(&format!("[queue]\nfail_open = true\n{rule}")).is_empty());
+    }
+
+    #[test]
+    fn invalid_toml() {
+        let findings = findings("[[rules]\nname = \"web\"\n");
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("rules.toml:1:"), "{findings:?}");
+        assert!(findings[0].contains(": error: "), "{findings:?}");
+    }
+}
@@ -0,0 +1,868 @@
+mod defines;
+mod legacy;
+mod limits;
+mod lint;
+mod nat;
+mod ports;
+mod watch;
+
+pub use legacy::RuleSection;
+pub use limits::{DEFAULT_BURST, Limit, Meter, MeterKey, Quota, TimeUnit, split_bytes};
+pub use lint::{Finding, Severity, lint, lint_file};
+pub use nat::{Dnat, NatSettings, PortForward, Snat};
+pub use ports::{DEFAULT_SERVICES_FILE, Port, Services};
+pub use watch::watch;
+
+use anyhow::{Context, Result, anyhow, bail};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+pub const SCHEMA_VERSION: u32 = 2;
+
+// Contents of the TOML rules file passed with `-r`. Files using the
+// original [allow], [deny] and [log] layout are upgraded on load.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "RawRulesFile")]
+pub struct RulesFile {
+    pub version: u32,
+    // Where named ports are looked up, /etc/services unless given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub services: Option<PathBuf>,
+    // Where rules with the queue verdict send their packets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<QueueSettings>,
+    // Where rules with log = true send the packets they log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nflog: Option<LogSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nat: Option<NatSettings>,
+    pub rules: Vec<Rule>,
+}
+
+impl Default for RulesFile {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            services: None,
+            queue: None,
+            nflog: None,
+            nat: None,
+            rules: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueSettings {
+    #[serde(default)]
+    pub number: u16,
+    // Accept packets while nothing reads the queue, when it is full or when
+    // no decision can be made for them, instead of dropping them
+    #[serde(default)]
+    pub fail_open: bool,
+    // What the worker of firewall-rs does with every queued packet
+    #[serde(default)]
+    pub decision: QueueDecision,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueDecision {
+    #[default]
+    Accept,
+    Drop,
+    // Sends the packet through the hook again with this mark
+    Repeat(u32),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogSettings {
+    // NFLOG group the packet log reads
+    #[serde(default)]
+    pub group: u16,
+}
+
+// What is actually read from disk, before upgrading and validation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRulesFile {
+    version: Option<u32>,
+    services: Option<PathBuf>,
+    queue: Option<QueueSettings>,
+    nflog: Option<LogSettings>,
+    nat: Option<NatSettings>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+    allow: Option<RuleSection>,
+    deny: Option<RuleSection>,
+    log: Option<RuleSection>,
+}
+
+impl TryFrom<RawRulesFile> for RulesFile {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawRulesFile) -> Result<Self> {
+        let legacy = [&raw.allow, &raw.deny, &raw.log]
+            .iter()
+            .any(|section| section.is_some());
+
+        if let Some(version) = raw.version.filter(|v| *v > SCHEMA_VERSION) {
+            bail!("unsupported rules file version {version}");
+        }
+
+        let rules = if legacy {
+            if !raw.rules.is_empty() {
+                bail!("[[rules]] can't be mixed with the [allow], [deny] and [log] sections");
+            }
+            legacy::upgrade(
+                &raw.allow.unwrap_or_default(),
+                &raw.deny.unwrap_or_default(),
+                &raw.log.unwrap_or_default(),
+            )
+        } else {
+            raw.rules
+        };
+
+        let rules = resolve_services(rules, raw.services.as_deref())?;
+
+        let rules_file = Self {
+            version: SCHEMA_VERSION,
+            services: raw.services,
+            queue: raw.queue,
+            nflog: raw.nflog,
+            nat: raw.nat,
+            rules,
+        };
+        rules_file.validate()?;
+
+        Ok(rules_file)
+    }
+}
+
+impl RulesFile {
+    // Settings of the queue, the defaults when the file doesn't configure it
+    pub fn queue_settings(&self) -> QueueSettings {
+        self.queue.unwrap_or_default()
+    }
+
+    // Settings of the NFLOG group, the defaults when the file doesn't
+    // configure it
+    pub fn log_settings(&self) -> LogSettings {
+        self.nflog.unwrap_or_default()
+    }
+
+    pub fn uses_queue(&self) -> bool {
+        self.rules.iter().any(|r| r.verdict == Verdict::Queue)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            if !names.insert(rule.name.as_str()) {
+                bail!("duplicate rule name \"{}\"", rule.name);
+            }
+            // Error contexts don't survive the conversion into a toml error,
+            // so the rule name is added to the message itself
+            rule.validate()
+                .map_err(|e| anyhow!("invalid rule \"{}\": {e}", rule.name))?;
+        }
+        if let Some(nat) = &self.nat {
+            nat.validate().map_err(|e| anyhow!("invalid [nat]: {e}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Replaces service names in the ports of every rule by their numbers. The
+// services file is only read when a rule actually uses a name.
+fn resolve_services(mut rules: Vec<Rule>, path: Option<&Path>) -> Result<Vec<Rule>> {
+    if !rules.iter().any(Rule::needs_services) {
+        return Ok(rules);
+    }
+
+    let path = path.unwrap_or(Path::new(DEFAULT_SERVICES_FILE));
+    let services = Services::load(path).map_err(|e| anyhow!("{e:#}"))?;
+
+    for rule in &mut rules {
+        rule.resolve_services(&services)
+            .map_err(|e| anyhow!("invalid rule \"{}\": {e}", rule.name))?;
+    }
+
+    Ok(rules)
+}
+
+// Rules added, removed or modified between two versions of a rules file,
+// matched by name
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RulesChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    // Set when the rules are the same but their order isn't
+    pub reordered: bool,
+    // Set when anything besides the rules changed, like the [nat] section
+    pub settings_changed: bool,
+}
+
+impl RulesChanges {
+    pub fn between(old: &RulesFile, new: &RulesFile) -> Self {
+        let find =
+            |rules: &RulesFile, name: &str| rules.rules.iter().find(|r| r.name == name).cloned();
+
+        let mut changes = Self::default();
+        for rule in &new.rules {
+            match find(old, &rule.name) {
+                None => changes.added.push(rule.name.clone()),
+                Some(old_rule) if old_rule != *rule => changes.changed.push(rule.name.clone()),
+                Some(_) => {}
+            }
+        }
+        for rule in &old.rules {
+            if find(new, &rule.name).is_none() {
+                changes.removed.push(rule.name.clone());
+            }
+        }
+
+        changes.reordered = changes.added.is_empty()
+            && changes.removed.is_empty()
+            && changes.changed.is_empty()
+            && old.rules != new.rules;
+        changes.settings_changed = RulesFile {
+            rules: vec![],
+            ..old.clone()
+        } != RulesFile {
+            rules: vec![],
+            ..new.clone()
+        };
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && !self.reordered
+            && !self.settings_changed
+    }
+}
+
+impl fmt::Display for RulesChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules_changed =
+            !self.added.is_empty() || !self.removed.is_empty() || !self.changed.is_empty();
+
+        if self.reordered {
+            write!(f, "rules reordered")?;
+        } else if rules_changed || !self.settings_changed {
+            write!(
+                f,
+                "{} added, {} removed, {} changed",
+                self.added.len(),
+                self.removed.len(),
+                self.changed.len()
+            )?;
+        }
+        if self.settings_changed {
+            if self.reordered || rules_changed {
+                write!(f, ", ")?;
+            }
+            write!(f, "settings changed")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default, alias = "hook")]
+    pub direction: Direction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iifname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oifname: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_networks",
+        serialize_with = "serialize_networks"
+    )]
+    pub sources: Vec<IpNet>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_networks",
+        serialize_with = "serialize_networks"
+    )]
+    pub destinations: Vec<IpNet>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sports: Vec<Port>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dports: Vec<Port>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<Limit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meter: Option<Meter>,
+    pub verdict: Verdict,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub log: bool,
+    // Prefix of the packets the rule logs instead of one naming the rule,
+    // kept for imported rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_prefix: Option<String>,
+    // Compiled rules always have a counter, this only says whether an
+    // imported rule had one
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub counter: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl Rule {
+    // Protocols the rule matches, None meaning any protocol. Ports without
+    // a protocol match both tcp and udp.
+    pub fn protocols(&self) -> Option<Vec<Protocol>> {
+        if let Some(protocol) = self.protocol {
+            return Some(vec![protocol]);
+        }
+
+        let ports: Vec<&Port> = self.sports.iter().chain(&self.dports).collect();
+        if ports.is_empty() {
+            return None;
+        }
+
+        let protocols = [Protocol::Tcp, Protocol::Udp]
+            .into_iter()
+            .filter(|p| {
+                let applies =
+                    |list: &[Port]| list.is_empty() || list.iter().any(|port| port.applies_to(*p));
+                applies(&self.sports) && applies(&self.dports)
+            })
+            .collect();
+
+        Some(protocols)
+    }
+
+    // Rules with a limit, quota or meter only match part of the traffic
+    // they describe
+    pub fn is_limited(&self) -> bool {
+        self.limit.is_some() || self.quota.is_some() || self.meter.is_some()
+    }
+
+    fn needs_services(&self) -> bool {
+        ports::needs_services(&self.sports) || ports::needs_services(&self.dports)
+    }
+
+    fn resolve_services(&mut self, services: &Services) -> Result<()> {
+        self.sports = services.resolve_all(&self.sports)?;
+        self.dports = services.resolve_all(&self.dports)?;
+
+        Ok(())
+    }
+
+    pub fn new(name: String, direction: Direction, verdict: Verdict, log: bool) -> Self {
+        Self {
+            name,
+            direction,
+            protocol: None,
+            iifname: None,
+            oifname: None,
+            sources: vec![],
+            destinations: vec![],
+            sports: vec![],
+            dports: vec![],
+            limit: None,
+            quota: None,
+            meter: None,
+            verdict,
+            log,
+            log_prefix: None,
+            counter: false,
+            comment: None,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            bail!("rule names can't be empty");
+        }
+        check_unquoted("rule names", &self.name)?;
+        let strings = [
+            ("comments", &self.comment),
+            ("iifname", &self.iifname),
+            ("oifname", &self.oifname),
+            ("log_prefix", &self.log_prefix),
+        ];
+        for (what, value) in strings {
+            if let Some(value) = value {
+                check_unquoted(what, value)?;
+            }
+        }
+
+        let has_ports = !self.sports.is_empty() || !self.dports.is_empty();
+        if has_ports && !matches!(self.protocol, None | Some(Protocol::Tcp | Protocol::Udp)) {
+            bail!("ports can only be used with tcp or udp");
+        }
+        for port in self.sports.iter().chain(&self.dports) {
+            if port.first == 0 {
+                bail!("invalid port {port}, ports must be between 1 and 65535");
+            }
+            if port.first > port.last {
+                bail!("invalid port range {port}, the first port is above the last");
+            }
+            if let (Some(protocol), Some(port_protocol)) = (self.protocol, port.protocol)
+                && protocol != port_protocol
+            {
+                bail!("port {port} can't be used in a {protocol} rule");
+            }
+        }
+        if self.protocols().is_some_and(|p| p.is_empty()) {
+            bail!("sports and dports are for different protocols, the rule can never match");
+        }
+
+        // Each nftables rule matches addresses of a single family
+        let has_family = |ipv4: bool| {
+            let matches =
+                |nets: &[IpNet]| nets.is_empty() || nets.iter().any(|n| n.addr().is_ipv4() == ipv4);
+            matches(&self.sources) && matches(&self.destinations)
+        };
+        if !has_family(true) && !has_family(false) {
+            bail!(
+                "sources and destinations have no address family in common, the rule can never match"
+            );
+        }
+
+        if self.log_prefix.is_some() && !self.log {
+            bail!("log_prefix is only used with log = true");
+        }
+
+        if self.iifname.is_some() && self.direction == Direction::Output {
+            bail!("iifname can't be used on output rules");
+        }
+        if self.oifname.is_some() && self.direction == Direction::Input {
+            bail!("oifname can't be used on input rules");
+        }
+
+        if let Some(limit) = &self.limit {
+            limit.validate()?;
+        }
+        if let Some(quota) = &self.quota {
+            quota.validate()?;
+        }
+        if let Some(meter) = &self.meter {
+            meter.rate.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Input,
+    Output,
+    Forward,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Input => write!(f, "input"),
+            Direction::Output => write!(f, "output"),
+            Direction::Forward => write!(f, "forward"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Icmpv6,
+}
+
+impl Protocol {
+    // IANA protocol number, as matched by `meta l4proto`
+    pub fn number(&self) -> u8 {
+        match self {
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Icmp => 1,
+            Protocol::Icmpv6 => 58,
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Icmp => write!(f, "icmp"),
+            Protocol::Icmpv6 => write!(f, "icmpv6"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Reject,
+    // Keep evaluating the following rules, used for log only rules
+    Continue,
+    // Hand the packet to the queue worker, which decides its fate
+    Queue,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Accept => write!(f, "accept"),
+            Verdict::Drop => write!(f, "drop"),
+            Verdict::Reject => write!(f, "reject"),
+            Verdict::Continue => write!(f, "continue"),
+            Verdict::Queue => write!(f, "queue"),
+        }
+    }
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<RulesFile> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read rules file {}", path.display()))?;
+
+    parse(path, &contents).with_context(|| format!("Invalid rules file {}", path.display()))
+}
+
+// Parses the contents of the rules file at path, files it includes are
+// looked up relative to it
+pub fn parse(path: &Path, contents: &str) -> Result<RulesFile> {
+    let table = defines::resolve(path, contents)?;
+    let rules = RulesFile::deserialize(toml::Value::Table(table))?;
+
+    Ok(rules)
+}
+
+pub fn to_toml(rules: &RulesFile) -> Result<String> {
+    let contents = toml::to_string(rules)?;
+
+    Ok(contents)
+}
+
+// Accepts both plain addresses and CIDR networks
+pub fn parse_network(value: &str) -> Result<IpNet> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("invalid address or network \"{value}\""))
+}
+
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Vec::<String>::deserialize(deserializer)?;
+
+    values
+        .iter()
+        .map(|value| parse_network(value).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// Single addresses are written without their prefix length
+pub fn format_network(net: &IpNet) -> String {
+    if net.prefix_len() == net.max_prefix_len() {
+        net.addr().to_string()
+    } else {
+        net.trunc().to_string()
+    }
+}
+
+fn serialize_networks<S>(networks: &[IpNet], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    networks
+        .iter()
+        .map(format_network)
+        .collect::<Vec<String>>()
+        .serialize(serializer)
+}
+
+// Strings end up quoted in the nft script, which has no way to escape a
+// double quote. The dry-run could only show them differently from the rules
+// the batch applies.
+pub(crate) fn check_unquoted(what: &str, value: &str) -> Result<()> {
+    if value.contains('"') {
+        bail!("{what} can't contain double quotes, found {value}");
+    }
+
+    Ok(())
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(contents: &str) -> Result<RulesFile> {
+        parse(Path::new("rules.toml"), contents)
+    }
+
+    fn parse_error(contents: &str) -> String {
+        format!("{:#}", parse_str(contents).unwrap_err())
+    }
+
+    const RULES: &str = r#"
+version = 2
+
+[nflog]
+group = 5
+
+[[rules]]
+name = "ssh-from-lan"
+hook = "input"
+protocol = "tcp"
+iifname = "eth0"
+sources = ["10.0.0.1", "10.0.0.0/8", "fd00::/8"]
+dports = [22, "tcp/2222", "6000-6010"]
+verdict = "accept"
+comment = "ssh from the LAN only"
+
+[[rules]]
+name = "log-out"
+direction = "output"
+destinations = ["192.0.2.1"]
+verdict = "continue"
+log = true
+log_prefix = "out: "
+"#;
+
+    #[test]
+    fn ordered_rules() {
+        let rules = parse_str(RULES).unwrap();
+        assert_eq!(rules.log_settings(), LogSettings { group: 5 });
+        assert_eq!(rules.queue_settings(), QueueSettings::default());
+
+        let [ssh, log] = rules.rules.as_slice() else {
+            panic!("{:?}", rules.rules);
+        };
+        assert_eq!(ssh.direction, Direction::Input);
+        assert_eq!(ssh.protocols(), Some(vec![Protocol::Tcp]));
+        assert_eq!(
+            ssh.sources,
+            vec![
+                parse_network("10.0.0.1").unwrap(),
+                parse_network("10.0.0.0/8").unwrap(),
+                parse_network("fd00::/8").unwrap(),
+            ]
+        );
+        assert_eq!(
+            ssh.dports,
+            vec![
+                Port::from(22),
+                "tcp/2222".parse().unwrap(),
+                "6000-6010".parse().unwrap()
+            ]
+        );
+        assert_eq!(log.verdict, Verdict::Continue);
+        assert_eq!(log.log_prefix.as_deref(), Some("out: "));
+    }
+
+    #[test]
+    fn written_back_unchanged() {
+        let rules = parse_str(RULES).unwrap();
+        let written = to_toml(&rules).unwrap();
+        assert_eq!(parse_str(&written).unwrap(), rules);
+        assert!(written.contains("sources = [\"10.0.0.1\", \"10.0.0.0/8\", \"fd00::/8\"]"));
+    }
+
+    #[test]
+    fn legacy_sections_are_upgraded() {
+        let rules = parse_str(include_str!("../../sample-rules-file-toml")).unwrap();
+        assert_eq!(rules.version, SCHEMA_VERSION);
+
+        let rules: Vec<_> = rules
+            .rules
+            .iter()
+            .map(|r| (r.name.as_str(), r.direction, r.verdict, r.log))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                ("log-sources", Direction::Input, Verdict::Continue, true),
+                (
+                    "log-destinations",
+                    Direction::Output,
+                    Verdict::Continue,
+                    true
+                ),
+                ("log-dports", Direction::Input, Verdict::Continue, true),
+                ("log-sports", Direction::Output, Verdict::Continue, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_files() {
+        let rule =
+            |fields: &str| format!("[[rules]]\nname = \"r\"\nverdict = \"accept\"\n{fields}");
+
+        for (contents, error) in [
+            (
+                format!("{}\n{}", rule(""), rule("")),
+                "duplicate rule name \"r\"",
+            ),
+            (
+                "version = 3".to_string(),
+                "unsupported rules file version 3",
+            ),
+            (
+                format!("[allow]\ndports = [22]\n{}", rule("")),
+                "[[rules]] can't be mixed",
+            ),
+            (
+                rule("protocol = \"icmp\"\ndports = [22]"),
+                "ports can only be used with tcp or udp",
+            ),
+            (
+                rule("protocol = \"udp\"\ndports = [\"tcp/22\"]"),
+                "port tcp/22 can't be used in a udp rule",
+            ),
+            (
+                rule("sports = [\"udp/53\"]\ndports = [\"tcp/22\"]"),
+                "sports and dports are for different protocols",
+            ),
+            (
+                rule("dports = [\"8100-8000\"]"),
+                "the first port is above the last",
+            ),
+            (
+                rule("direction = \"output\"\niifname = \"eth0\""),
+                "iifname can't be used on output rules",
+            ),
+            (
+                rule("log_prefix = \"r: \""),
+                "log_prefix is only used with log = true",
+            ),
+            (
+                rule("sources = [\"10.0.0.300\"]"),
+                "invalid address or network",
+            ),
+            (rule("colour = \"red\""), "unknown field `colour`"),
+            (
+                rule("comment = 'say \"hi\"'"),
+                "comments can't contain double quotes",
+            ),
+        ] {
+            let e = parse_error(&contents);
+            assert!(e.contains(error), "{contents}: {e}");
+        }
+    }
+
+    #[test]
+    fn changes_between_files() {
+        let old = parse_str(RULES).unwrap();
+
+        let mut new = old.clone();
+        new.rules.reverse();
+        let changes = RulesChanges::between(&old, &new);
+        assert!(changes.reordered);
+        assert_eq!(changes.to_string(), "rules reordered");
+
+        let mut new = old.clone();
+        new.rules[0].dports.push(Port::from(23));
+        new.rules.pop();
+        new.rules.push(Rule::new(
+            "new".to_string(),
+            Direction::Input,
+            Verdict::Drop,
+            false,
+        ));
+        new.nflog = None;
+        let changes = RulesChanges::between(&old, &new);
+        assert_eq!(changes.added, ["new"]);
+        assert_eq!(changes.removed, ["log-out"]);
+        assert_eq!(changes.changed, ["ssh-from-lan"]);
+        assert_eq!(
+            changes.to_string(),
+            "1 added, 1 removed, 1 changed, settings changed"
+        );
+
+        assert!(RulesChanges::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn sources_and_destinations_of_different_families() {
+        let rule = |sources: &str, destinations: &str| {
+            format!(
+                r#"
+[[rules]]
+name = "mixed"
+direction = "forward"
+sources = {sources}
+destinations = {destinations}
+verdict = "accept"
+"#
+            )
+        };
+
+        let e = parse_str(&rule(r#"["10.0.0.0/8"]"#, r#"["fd00::/8"]"#)).unwrap_err();
+        assert!(
+            format!("{e:#}").contains("no address family in common"),
+            "{e:#}"
+        );
+
+        parse_str(&rule(r#"["10.0.0.0/8", "fd00::1"]"#, r#"["fd00::/8"]"#)).unwrap();
+        parse_str(&rule(r#"["10.0.0.0/8"]"#, "[]")).unwrap();
+    }
+
+    #[test]
+    fn queue_decision() {
+        let rules = parse_str(
+            r#"
+[queue]
+number = 3
+decision = { repeat = 7 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.queue_settings(),
+            QueueSettings {
+                number: 3,
+                fail_open: false,
+                decision: QueueDecision::Repeat(7),
+            }
+        );
+        assert_eq!(parse_str(&to_toml(&rules).unwrap()).unwrap(), rules);
+
+        let rules = parse_str("[queue]\ndecision = \"drop\"\n").unwrap();
+        assert_eq!(rules.queue_settings().decision, QueueDecision::Drop);
+    }
+}
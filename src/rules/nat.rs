@@ -0,0 +1,230 @@
+use super::{Port, check_unquoted, deserialize_networks, serialize_networks};
+
+use anyhow::{Result, anyhow, bail};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+// Address translation of the [nat] section, for small gateways. Masquerade
+// and SNAT apply to traffic leaving an interface, port forwards to traffic
+// coming in.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NatSettings {
+    // Interfaces whose outgoing traffic takes their address
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masquerade: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snat: Vec<Snat>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dnat: Vec<Dnat>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snat {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oifname: Option<String>,
+    // Only traffic from these networks is translated, all of it when empty
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_networks",
+        serialize_with = "serialize_networks"
+    )]
+    pub sources: Vec<IpNet>,
+    pub to: IpAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dnat {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iifname: Option<String>,
+    pub forward: PortForward,
+}
+
+// Like "tcp/8080 -> 10.0.0.5:80". Without a protocol both tcp and udp are
+// forwarded, without a port on the right the port is kept. IPv6 targets
+// are written in brackets when they have a port, "[2001:db8::5]:80".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    pub port: Port,
+    pub to: IpAddr,
+    pub to_port: Option<u16>,
+}
+
+impl NatSettings {
+    pub fn is_empty(&self) -> bool {
+        self.masquerade.is_empty() && self.snat.is_empty() && self.dnat.is_empty()
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        for name in &self.masquerade {
+            if name.is_empty() {
+                bail!("masquerade needs an interface name");
+            }
+            check_unquoted("interface names", name)?;
+        }
+        let interfaces = self.snat.iter().map(|s| &s.oifname);
+        for name in interfaces
+            .chain(self.dnat.iter().map(|d| &d.iifname))
+            .flatten()
+        {
+            check_unquoted("interface names", name)?;
+        }
+
+        for snat in &self.snat {
+            let family_matches = |net: &IpNet| net.addr().is_ipv4() == snat.to.is_ipv4();
+            if !snat.sources.is_empty() && !snat.sources.iter().any(family_matches) {
+                bail!(
+                    "snat to {} has no sources of the same family, it can never match",
+                    snat.to
+                );
+            }
+        }
+
+        for dnat in &self.dnat {
+            let port = &dnat.forward.port;
+            if port.first == 0 || port.first > port.last {
+                bail!("invalid port {port} in forward \"{}\"", dnat.forward);
+            }
+            if dnat.forward.to_port.is_some() && port.first != port.last {
+                bail!(
+                    "forward \"{}\" maps a range of ports to a single one",
+                    dnat.forward
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for PortForward {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid =
+            || anyhow!("invalid forward \"{value}\", expected like \"tcp/8080 -> 10.0.0.5:80\"");
+
+        let (port, target) = value.split_once("->").ok_or_else(invalid)?;
+        let port: Port = port.trim().parse()?;
+        if port.service.is_some() {
+            bail!("invalid forward \"{value}\", forwarded ports have to be numbers");
+        }
+
+        let target = target.trim();
+        let (to, to_port) = if let Ok(addr) = target.parse::<IpAddr>() {
+            (addr, None)
+        } else {
+            let (addr, to_port) = target.rsplit_once(':').ok_or_else(invalid)?;
+            let addr = addr.trim_start_matches('[').trim_end_matches(']');
+            let to_port = to_port.parse::<u16>().ok().filter(|p| *p != 0);
+            (
+                addr.parse().map_err(|_| invalid())?,
+                Some(to_port.ok_or_else(invalid)?),
+            )
+        };
+
+        Ok(Self { port, to, to_port })
+    }
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.to, self.to_port) {
+            (to, None) => write!(f, "{} -> {to}", self.port),
+            (IpAddr::V4(to), Some(port)) => write!(f, "{} -> {to}:{port}", self.port),
+            (IpAddr::V6(to), Some(port)) => write!(f, "{} -> [{to}]:{port}", self.port),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PortForward {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for PortForward {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Protocol;
+
+    #[test]
+    fn forward_with_protocol_and_port() {
+        let forward: PortForward = "tcp/8080 -> 10.0.0.5:80".parse().unwrap();
+        assert_eq!(forward.port.protocol, Some(Protocol::Tcp));
+        assert_eq!((forward.port.first, forward.port.last), (8080, 8080));
+        assert_eq!(forward.to, "10.0.0.5".parse::<IpAddr>().unwrap());
+        assert_eq!(forward.to_port, Some(80));
+        assert_eq!(forward.to_string(), "tcp/8080 -> 10.0.0.5:80");
+    }
+
+    #[test]
+    fn forward_keeping_the_port() {
+        let forward: PortForward = "8000-8100->10.0.0.5".parse().unwrap();
+        assert_eq!(forward.port.protocol, None);
+        assert_eq!((forward.port.first, forward.port.last), (8000, 8100));
+        assert_eq!(forward.to_port, None);
+        assert_eq!(forward.to_string(), "8000-8100 -> 10.0.0.5");
+    }
+
+    #[test]
+    fn forward_to_ipv6() {
+        let forward: PortForward = "udp/53 -> [2001:db8::5]:5353".parse().unwrap();
+        assert_eq!(forward.to, "2001:db8::5".parse::<IpAddr>().unwrap());
+        assert_eq!(forward.to_port, Some(5353));
+        assert_eq!(forward.to_string(), "udp/53 -> [2001:db8::5]:5353");
+
+        let forward: PortForward = "443 -> 2001:db8::5".parse().unwrap();
+        assert_eq!(forward.to_port, None);
+    }
+
+    #[test]
+    fn invalid_forwards() {
+        for value in [
+            "8080 10.0.0.5:80",
+            "ssh -> 10.0.0.5",
+            "8080 -> 10.0.0.5:0",
+            "8080 -> host:80",
+            "icmp/1 -> 10.0.0.5",
+        ] {
+            assert!(value.parse::<PortForward>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn snat_needs_sources_of_its_family() {
+        let nat: NatSettings = toml::from_str(
+            r#"
+snat = [{ sources = ["fd00::/8"], to = "192.0.2.1" }]
+"#,
+        )
+        .unwrap();
+        assert!(nat.validate().is_err());
+
+        let nat: NatSettings = toml::from_str(
+            r#"
+snat = [{ sources = ["fd00::/8", "10.0.0.0/8"], to = "192.0.2.1" }]
+"#,
+        )
+        .unwrap();
+        nat.validate().unwrap();
+    }
+}
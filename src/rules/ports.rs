@@ -0,0 +1,327 @@
+use super::Protocol;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, fmt, fs, path::Path, str::FromStr};
+
+// Services file used to resolve named ports unless the rules file sets one
+pub const DEFAULT_SERVICES_FILE: &str = "/etc/services";
+
+// Entry of sports or dports. Either a port number, a range like "8000-8100"
+// or a service name like "ssh", each optionally limited to one protocol
+// with a prefix like "udp/53".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Port {
+    pub protocol: Option<Protocol>,
+    pub first: u16,
+    pub last: u16,
+    // Service the port was resolved from, kept so it is written back by name.
+    // Unresolved services have both ports set to 0.
+    pub service: Option<String>,
+}
+
+impl Port {
+    pub fn contains(&self, other: &Port) -> bool {
+        self.first <= other.first
+            && other.last <= self.last
+            && (self.protocol.is_none() || self.protocol == other.protocol)
+    }
+
+    pub fn overlaps(&self, other: &Port) -> bool {
+        self.first <= other.last
+            && other.first <= self.last
+            && (self.protocol.is_none()
+                || other.protocol.is_none()
+                || self.protocol == other.protocol)
+    }
+
+    // Whether the port can be matched for traffic of the given protocol
+    pub fn applies_to(&self, protocol: Protocol) -> bool {
+        self.protocol.is_none_or(|p| p == protocol)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.service.is_none() || self.first != 0
+    }
+}
+
+impl From<u16> for Port {
+    fn from(port: u16) -> Self {
+        Self {
+            protocol: None,
+            first: port,
+            last: port,
+            service: None,
+        }
+    }
+}
+
+impl FromStr for Port {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (protocol, port) = match value.split_once('/') {
+            Some(("tcp", port)) => (Some(Protocol::Tcp), port),
+            Some(("udp", port)) => (Some(Protocol::Udp), port),
+            Some((protocol, _)) => bail!("invalid port \"{value}\", unknown protocol {protocol}"),
+            None => (None, value),
+        };
+
+        let number = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map_err(|_| anyhow!("invalid port \"{value}\""))
+        };
+
+        // Service names like "netbios-ns" contain hyphens too, only two
+        // numbers make a range
+        let range = port
+            .split_once('-')
+            .and_then(|(first, last)| Some((number(first).ok()?, number(last).ok()?)));
+
+        let port = if let Some((first, last)) = range {
+            Self {
+                protocol,
+                first,
+                last,
+                service: None,
+            }
+        } else if port.chars().all(|c| c.is_ascii_digit()) {
+            Self {
+                protocol,
+                ..Self::from(number(port)?)
+            }
+        } else if !port.is_empty() {
+            Self {
+                protocol,
+                first: 0,
+                last: 0,
+                service: Some(port.to_string()),
+            }
+        } else {
+            bail!("invalid port \"{value}\"");
+        };
+
+        Ok(port)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(protocol) = self.protocol {
+            write!(f, "{protocol}/")?;
+        }
+
+        match &self.service {
+            Some(service) => write!(f, "{service}"),
+            None if self.first == self.last => write!(f, "{}", self.first),
+            None => write!(f, "{}-{}", self.first, self.last),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Port {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawPort {
+            Number(i64),
+            String(String),
+        }
+
+        match RawPort::deserialize(deserializer)? {
+            RawPort::Number(port) => u16::try_from(port).map(Port::from).map_err(|_| {
+                serde::de::Error::custom(format!(
+                    "invalid port {port}, ports must be between 1 and 65535"
+                ))
+            }),
+            RawPort::String(port) => port.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// Plain ports are written back as numbers, everything else as strings
+impl Serialize for Port {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.protocol.is_none() && self.service.is_none() && self.first == self.last {
+            serializer.serialize_u16(self.first)
+        } else {
+            serializer.serialize_str(&self.to_string())
+        }
+    }
+}
+
+// Service names and aliases from an /etc/services style file
+#[derive(Debug, Default)]
+pub struct Services {
+    ports: HashMap<(String, Protocol), u16>,
+}
+
+impl Services {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read services file {}", path.display()))?;
+
+        Ok(Self::parse(&contents))
+    }
+
+    // Lines look like "ssh 22/tcp # comment", lines that can't be parsed and
+    // protocols other than tcp and udp are skipped
+    pub fn parse(contents: &str) -> Self {
+        let mut ports = HashMap::new();
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(port)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let Some((port, protocol)) = port.split_once('/') else {
+                continue;
+            };
+            let protocol = match protocol {
+                "tcp" => Protocol::Tcp,
+                "udp" => Protocol::Udp,
+                _ => continue,
+            };
+            let Ok(port) = port.parse::<u16>() else {
+                continue;
+            };
+
+            for name in std::iter::once(name).chain(fields) {
+                ports.entry((name.to_string(), protocol)).or_insert(port);
+            }
+        }
+
+        Self { ports }
+    }
+
+    pub fn lookup(&self, name: &str, protocol: Protocol) -> Option<u16> {
+        self.ports.get(&(name.to_string(), protocol)).copied()
+    }
+
+    // Services without a protocol are looked up for both tcp and udp. When
+    // these differ, the service becomes one port per protocol.
+    pub fn resolve(&self, port: &Port) -> Result<Vec<Port>> {
+        let Some(service) = port.service.as_ref().filter(|_| !port.is_resolved()) else {
+            return Ok(vec![port.clone()]);
+        };
+
+        let protocols = match port.protocol {
+            Some(protocol) => vec![protocol],
+            None => vec![Protocol::Tcp, Protocol::Udp],
+        };
+        let found: Vec<(Protocol, u16)> = protocols
+            .into_iter()
+            .filter_map(|p| Some((p, self.lookup(service, p)?)))
+            .collect();
+
+        let resolved = |protocol, number| Port {
+            protocol,
+            first: number,
+            last: number,
+            service: Some(service.clone()),
+        };
+
+        match found.as_slice() {
+            [] => bail!("unknown service \"{port}\""),
+            [(_, tcp), (_, udp)] if tcp == udp => Ok(vec![resolved(port.protocol, *tcp)]),
+            found => Ok(found
+                .iter()
+                .map(|(protocol, number)| resolved(Some(*protocol), *number))
+                .collect()),
+        }
+    }
+
+    pub fn resolve_all(&self, ports: &[Port]) -> Result<Vec<Port>> {
+        let mut resolved = vec![];
+        for port in ports {
+            resolved.extend(self.resolve(port)?);
+        }
+
+        Ok(resolved)
+    }
+}
+
+pub fn needs_services(ports: &[Port]) -> bool {
+    ports.iter().any(|p| !p.is_resolved())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICES: &str = "\
+ssh             22/tcp
+domain          53/tcp
+domain          53/udp
+netbios-ns      137/tcp         # NETBIOS Name Service
+netbios-ns      137/udp
+";
+
+    #[test]
+    fn range() {
+        let port: Port = "8000-8100".parse().unwrap();
+        assert_eq!((port.protocol, port.first, port.last), (None, 8000, 8100));
+        assert_eq!(port.service, None);
+        assert_eq!(port.to_string(), "8000-8100");
+    }
+
+    #[test]
+    fn single_port() {
+        let port: Port = "443".parse().unwrap();
+        assert_eq!(port, Port::from(443));
+        assert!("65536".parse::<Port>().is_err());
+        assert!("".parse::<Port>().is_err());
+    }
+
+    #[test]
+    fn hyphenated_service() {
+        let port: Port = "netbios-ns".parse().unwrap();
+        assert_eq!(port.service.as_deref(), Some("netbios-ns"));
+        assert!(needs_services(std::slice::from_ref(&port)));
+
+        let resolved = Services::parse(SERVICES).resolve(&port).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!((resolved[0].first, resolved[0].last), (137, 137));
+        assert_eq!(resolved[0].to_string(), "netbios-ns");
+    }
+
+    #[test]
+    fn protocol_prefix() {
+        let port: Port = "udp/53".parse().unwrap();
+        assert_eq!((port.protocol, port.first), (Some(Protocol::Udp), 53));
+        assert_eq!(port.to_string(), "udp/53");
+
+        let range: Port = "tcp/6000-6010".parse().unwrap();
+        assert_eq!(
+            (range.protocol, range.first, range.last),
+            (Some(Protocol::Tcp), 6000, 6010)
+        );
+
+        let service: Port = "tcp/ssh".parse().unwrap();
+        let resolved = Services::parse(SERVICES).resolve(&service).unwrap();
+        assert_eq!(resolved[0].protocol, Some(Protocol::Tcp));
+        assert_eq!(resolved[0].first, 22);
+
+        assert!("icmp/1".parse::<Port>().is_err());
+    }
+
+    #[test]
+    fn service_without_protocol_differs_per_protocol() {
+        let services = Services::parse("foo 100/tcp\nfoo 200/udp\n");
+        let resolved = services.resolve(&"foo".parse().unwrap()).unwrap();
+        let resolved: Vec<_> = resolved.iter().map(|p| (p.protocol, p.first)).collect();
+        assert_eq!(
+            resolved,
+            vec![(Some(Protocol::Tcp), 100), (Some(Protocol::Udp), 200)]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{Context, Result, anyhow};
+use cli_log::debug;
+use futures::StreamExt;
+use inotify::{Inotify, WatchMask};
+use std::{ffi::OsString, path::Path, time::Duration};
+use tokio::sync::mpsc::{self};
+
+// Editors often write a file in several steps, events arriving within this
+// window are reported as a single change
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Watches the rules file for changes, a message is sent on the returned
+// channel every time the file has been written or replaced
+pub fn watch(path: impl AsRef<Path>) -> Result<mpsc::UnboundedReceiver<()>> {
+    let path = path.as_ref();
+    let file_name: OsString = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid rules file path {}", path.display()))?
+        .into();
+
+    // The parent directory is watched instead of the file itself since
+    // editors usually save by renaming a new file over the old one, which
+    // would silently drop a watch on the file
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let inotify = Inotify::init().context("Unable to initialize inotify")?;
+    inotify
+        .watches()
+        .add(
+            dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+        )
+        .with_context(|| format!("Unable to watch {}", dir.display()))?;
+
+    let mut events = inotify.into_event_stream([0; 4096])?;
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    debug!("Watching {} for changes", path.display());
+
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            let Ok(event) = event else {
+                continue;
+            };
+            if event.name.as_ref() != Some(&file_name) {
+                continue;
+            }
+
+            // Drain whatever else arrives while the file is being written
+            while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE, events.next()).await {}
+
+            if tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}